@@ -0,0 +1,1053 @@
+//! Analog to Digital Converter (ADC)
+
+use embedded_hal::adc::{Channel, OneShot};
+use nb;
+use stm32l0x3::ADC;
+
+use crate::dma::{self, Direction, WordSize};
+use crate::gpio::gpioa::{PA0, PA1, PA2, PA3, PA4, PA5, PA6, PA7};
+use crate::gpio::gpiob::{PB0, PB1};
+use crate::gpio::gpioc::{PC0, PC1, PC2, PC3, PC4, PC5};
+use crate::gpio::Analog;
+use crate::rcc::{Clocks, APB2};
+use crate::signature::{TemperatureCal, VRefintCal, CALIBRATION_VDDA_MV};
+use crate::signature::{TS_CAL1_TEMP_C, TS_CAL2_TEMP_C};
+use crate::syscfg::{disable_buffer, enable_buffer, wait_for_vrefint};
+use crate::syscfg::{ENBUF_SENSOR_ADC, ENBUF_VREFINT_ADC};
+
+/// ADC error
+#[derive(Debug)]
+pub enum Error {
+    /// A conversion result was overwritten before it was read
+    Overrun,
+    /// DMA transfer error
+    Dma,
+    #[doc(hidden)]
+    _Extensible,
+}
+
+macro_rules! adc_pins {
+    ($($PIN:ty: $channel:expr,)+) => {
+        $(
+            impl Channel<ADC> for $PIN {
+                type ID = u8;
+
+                fn channel() -> u8 {
+                    $channel
+                }
+            }
+        )+
+    };
+}
+
+adc_pins! {
+    PA0<Analog>: 0,
+    PA1<Analog>: 1,
+    PA2<Analog>: 2,
+    PA3<Analog>: 3,
+    PA4<Analog>: 4,
+    PA5<Analog>: 5,
+    PA6<Analog>: 6,
+    PA7<Analog>: 7,
+    PB0<Analog>: 8,
+    PB1<Analog>: 9,
+    PC0<Analog>: 10,
+    PC1<Analog>: 11,
+    PC2<Analog>: 12,
+    PC3<Analog>: 13,
+    PC4<Analog>: 14,
+    PC5<Analog>: 15,
+}
+
+/// Internal voltage reference channel
+pub struct VRef {
+    _0: (),
+}
+
+impl Channel<ADC> for VRef {
+    type ID = u8;
+
+    fn channel() -> u8 {
+        17
+    }
+}
+
+/// Internal temperature sensor channel
+pub struct TemperatureSensor {
+    _0: (),
+}
+
+impl Channel<ADC> for TemperatureSensor {
+    type ID = u8;
+
+    fn channel() -> u8 {
+        18
+    }
+}
+
+/// Sampling time, in ADC clock cycles
+pub enum SampleTime {
+    Cycles1_5,
+    Cycles3_5,
+    Cycles7_5,
+    Cycles12_5,
+    Cycles19_5,
+    Cycles39_5,
+    Cycles79_5,
+    Cycles160_5,
+}
+
+impl SampleTime {
+    fn bits(&self) -> u8 {
+        match self {
+            SampleTime::Cycles1_5 => 0b000,
+            SampleTime::Cycles3_5 => 0b001,
+            SampleTime::Cycles7_5 => 0b010,
+            SampleTime::Cycles12_5 => 0b011,
+            SampleTime::Cycles19_5 => 0b100,
+            SampleTime::Cycles39_5 => 0b101,
+            SampleTime::Cycles79_5 => 0b110,
+            SampleTime::Cycles160_5 => 0b111,
+        }
+    }
+}
+
+/// Conversion resolution
+pub enum Resolution {
+    Bits12,
+    Bits10,
+    Bits8,
+    Bits6,
+}
+
+/// Alignment of the results within 16 bits
+pub enum Align {
+    Right,
+    Left,
+}
+
+/// ADC clock source
+pub enum ClockSource {
+    /// HSI16 oscillator, which has to be running, divided by `prescaler`
+    Hsi16 { prescaler: Prescaler },
+    /// APB clock, which must not be divided by the APB prescaler
+    Pclk,
+    /// APB clock divided by 2
+    PclkDiv2,
+    /// APB clock divided by 4
+    PclkDiv4,
+}
+
+/// Divider of the HSI16 ADC clock
+pub enum Prescaler {
+    Div1,
+    Div2,
+    Div4,
+    Div6,
+    Div8,
+    Div10,
+    Div12,
+    Div16,
+    Div32,
+    Div64,
+    Div128,
+    Div256,
+}
+
+/// Number of conversions accumulated by the hardware oversampler
+pub enum OversamplingRatio {
+    X2,
+    X4,
+    X8,
+    X16,
+    X32,
+    X64,
+    X128,
+    X256,
+}
+
+impl OversamplingRatio {
+    fn bits(&self) -> u8 {
+        match self {
+            OversamplingRatio::X2 => 0b000,
+            OversamplingRatio::X4 => 0b001,
+            OversamplingRatio::X8 => 0b010,
+            OversamplingRatio::X16 => 0b011,
+            OversamplingRatio::X32 => 0b100,
+            OversamplingRatio::X64 => 0b101,
+            OversamplingRatio::X128 => 0b110,
+            OversamplingRatio::X256 => 0b111,
+        }
+    }
+}
+
+/// External event starting conversions
+pub enum Trigger {
+    /// TIM6 TRGO
+    Tim6Trgo,
+    /// TIM21 capture compare 2
+    Tim21Ch2,
+    /// TIM2 TRGO
+    Tim2Trgo,
+    /// TIM2 capture compare 4
+    Tim2Ch4,
+    /// TIM22 TRGO
+    Tim22Trgo,
+    /// EXTI line 11
+    Exti11,
+}
+
+impl Trigger {
+    fn bits(&self) -> u8 {
+        match self {
+            Trigger::Tim6Trgo => 0b000,
+            Trigger::Tim21Ch2 => 0b001,
+            Trigger::Tim2Trgo => 0b010,
+            Trigger::Tim2Ch4 => 0b011,
+            Trigger::Tim22Trgo => 0b100,
+            Trigger::Exti11 => 0b111,
+        }
+    }
+}
+
+/// Trigger edge
+pub enum TriggerEdge {
+    Rising,
+    Falling,
+    Both,
+}
+
+/// ADC interrupt event
+pub enum Event {
+    /// A conversion result is ready
+    EndOfConversion,
+    /// The last channel of a sequence has been converted
+    EndOfSequence,
+    /// A conversion result was overwritten before it was read
+    Overrun,
+}
+
+/// Order in which the channels of a sequence are converted
+pub enum ScanDirection {
+    /// From the lowest to the highest channel number
+    Upward,
+    /// From the highest to the lowest channel number
+    Backward,
+}
+
+/// Set of channels converted one after another by a single trigger
+#[derive(Clone, Copy)]
+pub struct Sequence {
+    channels: u32,
+}
+
+impl Sequence {
+    /// Creates an empty sequence
+    pub fn new() -> Self {
+        Sequence { channels: 0 }
+    }
+
+    /// Adds the channel of `pin` to the sequence
+    pub fn add<PIN>(mut self, _pin: &PIN) -> Self
+    where
+        PIN: Channel<ADC, ID = u8>,
+    {
+        self.channels |= 1 << PIN::channel();
+        self
+    }
+
+    /// Returns the number of channels in the sequence
+    pub fn len(&self) -> usize {
+        self.channels.count_ones() as usize
+    }
+
+    /// Returns true if the sequence has no channels
+    pub fn is_empty(&self) -> bool {
+        self.channels == 0
+    }
+}
+
+/// Results of the conversion of a sequence, by channel
+pub struct SequenceResults {
+    channels: u32,
+    values: [u16; 19],
+}
+
+impl SequenceResults {
+    /// Returns the result for the channel of `pin`, if it was part of the sequence
+    pub fn get<PIN>(&self, _pin: &PIN) -> Option<u16>
+    where
+        PIN: Channel<ADC, ID = u8>,
+    {
+        self.channel(PIN::channel())
+    }
+
+    /// Returns the result for the given channel number, if it was part of the sequence
+    pub fn channel(&self, channel: u8) -> Option<u16> {
+        if (channel as usize) < self.values.len() && self.channels & (1 << channel) != 0 {
+            Some(self.values[channel as usize])
+        } else {
+            None
+        }
+    }
+}
+
+/// Analog to digital converter
+pub struct Adc {
+    adc: ADC,
+    sysclk: u32,
+}
+
+impl Adc {
+    /// Powers up the ADC, clocked from PCLK / 2, with 12-bit resolution
+    pub fn adc(adc: ADC, clocks: &Clocks, apb2: &mut APB2) -> Self {
+        apb2.enr().modify(|_, w| w.adcen().set_bit());
+        apb2.rstr().modify(|_, w| w.adcrst().set_bit());
+        apb2.rstr().modify(|_, w| w.adcrst().clear_bit());
+
+        // synchronous clock from PCLK / 2, which keeps ADCCLK within 16 MHz
+        adc.cfgr2.write(|w| unsafe { w.ckmode().bits(0b01) });
+        // single conversion mode, 12-bit right aligned data
+        adc.cfgr1.reset();
+        // longest sampling time (160.5 ADC clock cycles)
+        adc.smpr.write(|w| unsafe { w.smp().bits(0b111) });
+
+        let mut adc = Adc {
+            adc,
+            sysclk: clocks.sysclk().0,
+        };
+        adc.enable();
+        adc
+    }
+
+    /// Sets the sampling time of all channels
+    ///
+    /// The internal reference and temperature sensor need at least 10 us.
+    pub fn set_sample_time(&mut self, sample_time: SampleTime) {
+        self.stop();
+        self.adc
+            .smpr
+            .write(|w| unsafe { w.smp().bits(sample_time.bits()) });
+    }
+
+    /// Sets the conversion resolution
+    pub fn set_resolution(&mut self, resolution: Resolution) {
+        let res = match resolution {
+            Resolution::Bits12 => 0b00,
+            Resolution::Bits10 => 0b01,
+            Resolution::Bits8 => 0b10,
+            Resolution::Bits6 => 0b11,
+        };
+
+        self.stop();
+        self.adc.cfgr1.modify(|_, w| unsafe { w.res().bits(res) });
+    }
+
+    /// Sets the alignment of the results
+    pub fn set_align(&mut self, align: Align) {
+        self.stop();
+        self.adc.cfgr1.modify(|_, w| {
+            w.align().bit(match align {
+                Align::Right => false,
+                Align::Left => true,
+            })
+        });
+    }
+
+    /// Selects the ADC clock
+    ///
+    /// The ADC clock must not exceed 16 MHz. Below 3.5 MHz the low frequency mode has to be
+    /// enabled with `set_low_frequency_mode`.
+    pub fn set_clock_source(&mut self, source: ClockSource) {
+        let (ckmode, presc) = match source {
+            ClockSource::Hsi16 { prescaler } => (
+                0b00,
+                match prescaler {
+                    Prescaler::Div1 => 0b0000,
+                    Prescaler::Div2 => 0b0001,
+                    Prescaler::Div4 => 0b0010,
+                    Prescaler::Div6 => 0b0011,
+                    Prescaler::Div8 => 0b0100,
+                    Prescaler::Div10 => 0b0101,
+                    Prescaler::Div12 => 0b0110,
+                    Prescaler::Div16 => 0b0111,
+                    Prescaler::Div32 => 0b1000,
+                    Prescaler::Div64 => 0b1001,
+                    Prescaler::Div128 => 0b1010,
+                    Prescaler::Div256 => 0b1011,
+                },
+            ),
+            ClockSource::PclkDiv2 => (0b01, 0b0000),
+            ClockSource::PclkDiv4 => (0b10, 0b0000),
+            ClockSource::Pclk => (0b11, 0b0000),
+        };
+
+        // the clock may only be changed while the ADC is disabled
+        self.disable();
+        self.adc.ccr.modify(|_, w| unsafe { w.presc().bits(presc) });
+        self.adc
+            .cfgr2
+            .modify(|_, w| unsafe { w.ckmode().bits(ckmode) });
+        self.enable();
+    }
+
+    /// Enables / disables the low frequency mode, required for ADC clocks below 3.5 MHz
+    pub fn set_low_frequency_mode(&mut self, enabled: bool) {
+        self.disable();
+        self.adc.ccr.modify(|_, w| w.lfmen().bit(enabled));
+        self.enable();
+    }
+
+    /// Enables / disables the auto-off mode
+    ///
+    /// In auto-off mode the ADC is powered down between conversions, and powers up
+    /// automatically when a conversion starts.
+    pub fn set_auto_off(&mut self, enabled: bool) {
+        self.stop();
+        self.adc.cfgr1.modify(|_, w| w.autoff().bit(enabled));
+    }
+
+    /// Enables / disables the wait mode
+    ///
+    /// In wait mode a new conversion only starts once the previous result has been read,
+    /// which avoids overruns without polling when the CPU is slower than the ADC.
+    pub fn set_wait(&mut self, enabled: bool) {
+        self.stop();
+        self.adc.cfgr1.modify(|_, w| w.wait().bit(enabled));
+    }
+
+    /// Enables the hardware oversampler
+    ///
+    /// Each result is the sum of `ratio` conversions shifted right by `shift` bits (at most
+    /// 8), which has to keep the result within 16 bits. E.g. 16x with a 4-bit shift gives
+    /// a 16-bit result. With `triggered` set, each conversion of the burst needs its own
+    /// trigger instead of the whole burst being run from one trigger.
+    pub fn enable_oversampling(&mut self, ratio: OversamplingRatio, shift: u8, triggered: bool) {
+        assert!(shift <= 8);
+
+        // the oversampler may only be configured while the ADC is disabled
+        self.disable();
+        self.adc.cfgr2.modify(|_, w| unsafe {
+            w.ovsr()
+                .bits(ratio.bits())
+                .ovss()
+                .bits(shift)
+                .tovs()
+                .bit(triggered)
+                .ovse()
+                .set_bit()
+        });
+        self.enable();
+    }
+
+    /// Disables the hardware oversampler
+    pub fn disable_oversampling(&mut self) {
+        self.disable();
+        self.adc
+            .cfgr2
+            .modify(|_, w| w.ovse().clear_bit().tovs().clear_bit());
+        self.enable();
+    }
+
+    /// Starts conversions on an external trigger instead of software
+    ///
+    /// Conversions, sequences and DMA transfers started afterwards wait for the trigger,
+    /// and each trigger starts one conversion or one pass over a sequence.
+    pub fn set_external_trigger(&mut self, trigger: Trigger, edge: TriggerEdge) {
+        let exten = match edge {
+            TriggerEdge::Rising => 0b01,
+            TriggerEdge::Falling => 0b10,
+            TriggerEdge::Both => 0b11,
+        };
+
+        self.stop();
+        self.adc
+            .cfgr1
+            .modify(|_, w| unsafe { w.extsel().bits(trigger.bits()).exten().bits(exten) });
+    }
+
+    /// Starts conversions from software, as soon as they are requested
+    pub fn set_software_trigger(&mut self) {
+        self.stop();
+        self.adc
+            .cfgr1
+            .modify(|_, w| unsafe { w.exten().bits(0b00) });
+    }
+
+    /// Enables the analog watchdog on the channel of `pin`
+    ///
+    /// The watchdog flags a conversion outside of `low..=high`, given as 12-bit values.
+    pub fn enable_watchdog<PIN>(&mut self, _pin: &PIN, low: u16, high: u16)
+    where
+        PIN: Channel<ADC, ID = u8>,
+    {
+        self.configure_watchdog(Some(PIN::channel()), low, high);
+    }
+
+    /// Enables the analog watchdog on all converted channels
+    pub fn enable_watchdog_all(&mut self, low: u16, high: u16) {
+        self.configure_watchdog(None, low, high);
+    }
+
+    fn configure_watchdog(&mut self, channel: Option<u8>, low: u16, high: u16) {
+        assert!(low <= high && high <= 0xFFF);
+
+        // the watchdog may only be configured while no conversion is ongoing
+        self.stop();
+        self.adc
+            .tr
+            .write(|w| unsafe { w.lt().bits(low).ht().bits(high) });
+        self.adc.cfgr1.modify(|_, w| unsafe {
+            w.awdch()
+                .bits(channel.unwrap_or(0))
+                .awdsgl()
+                .bit(channel.is_some())
+                .awden()
+                .set_bit()
+        });
+    }
+
+    /// Disables the analog watchdog
+    pub fn disable_watchdog(&mut self) {
+        self.stop();
+        self.adc.cfgr1.modify(|_, w| w.awden().clear_bit());
+        self.adc.ier.modify(|_, w| w.awdie().clear_bit());
+    }
+
+    /// Starts listening for analog watchdog events
+    pub fn listen_watchdog(&mut self) {
+        self.adc.ier.modify(|_, w| w.awdie().set_bit());
+    }
+
+    /// Stops listening for analog watchdog events
+    pub fn unlisten_watchdog(&mut self) {
+        self.adc.ier.modify(|_, w| w.awdie().clear_bit());
+    }
+
+    /// Returns true if a conversion fell outside of the watchdog thresholds
+    pub fn is_watchdog_triggered(&self) -> bool {
+        self.adc.isr.read().awd().bit_is_set()
+    }
+
+    /// Clears the analog watchdog flag
+    pub fn clear_watchdog(&mut self) {
+        // AWD is cleared by writing 1
+        self.adc.isr.write(|w| w.awd().set_bit());
+    }
+
+    /// Enables the internal voltage reference channel
+    pub fn enable_vref(&mut self, apb2: &mut APB2) -> VRef {
+        enable_buffer(ENBUF_VREFINT_ADC, apb2);
+        self.adc.ccr.modify(|_, w| w.vrefen().set_bit());
+
+        VRef { _0: () }
+    }
+
+    /// Disables the internal voltage reference channel
+    pub fn disable_vref(&mut self, _vref: VRef) {
+        self.adc.ccr.modify(|_, w| w.vrefen().clear_bit());
+        disable_buffer(ENBUF_VREFINT_ADC);
+    }
+
+    /// Enables the internal temperature sensor channel
+    ///
+    /// The sensor needs a sampling time of at least 10 us.
+    pub fn enable_temperature_sensor(&mut self, apb2: &mut APB2) -> TemperatureSensor {
+        enable_buffer(ENBUF_SENSOR_ADC, apb2);
+        self.adc.ccr.modify(|_, w| w.tsen().set_bit());
+        // wait for the sensor to start up (t_START = 10 us)
+        cortex_m::asm::delay(self.sysclk / 100_000);
+
+        TemperatureSensor { _0: () }
+    }
+
+    /// Disables the internal temperature sensor channel
+    pub fn disable_temperature_sensor(&mut self, _sensor: TemperatureSensor) {
+        self.adc.ccr.modify(|_, w| w.tsen().clear_bit());
+        disable_buffer(ENBUF_SENSOR_ADC);
+    }
+
+    /// Measures the die temperature in degrees Celsius
+    ///
+    /// The conversion is scaled to the supply voltage measured with `vref`, then
+    /// interpolated between the two factory calibration points.
+    pub fn read_temperature_c(
+        &mut self,
+        sensor: &mut TemperatureSensor,
+        vref: &mut VRef,
+    ) -> Result<i16, Error> {
+        let vdda = self.read_vdda_mv(vref)? as i32;
+        let data = nb::block!(OneShot::<ADC, u16, TemperatureSensor>::read(self, sensor))?;
+        let data = self.to_12_bits(data);
+        let cal = TemperatureCal::get();
+        let (cal1, cal2) = (cal.cal1() as i32, cal.cal2() as i32);
+        let (temp1, temp2) = (TS_CAL1_TEMP_C as i32, TS_CAL2_TEMP_C as i32);
+
+        // the result the sensor would have given at the calibration voltage
+        let data = data as i32 * vdda / CALIBRATION_VDDA_MV as i32;
+        let temperature = (data - cal1) * (temp2 - temp1) / (cal2 - cal1).max(1) + temp1;
+
+        Ok(temperature as i16)
+    }
+
+    /// Measures the analog supply voltage (VDDA) in millivolts
+    ///
+    /// The internal reference is converted and compared with its factory calibration.
+    pub fn read_vdda_mv(&mut self, vref: &mut VRef) -> Result<u16, Error> {
+        let data = nb::block!(OneShot::<ADC, u16, VRef>::read(self, vref))?;
+        let data = self.to_12_bits(data);
+        let cal = VRefintCal::get().value();
+
+        Ok((CALIBRATION_VDDA_MV as u32 * cal as u32 / data.max(1) as u32) as u16)
+    }
+
+    /// Scales a result to the 12-bit right aligned format of the factory calibration
+    fn to_12_bits(&self, data: u16) -> u16 {
+        let cfgr1 = self.adc.cfgr1.read();
+        let res = cfgr1.res().bits();
+        if cfgr1.align().bit_is_set() {
+            if res == 0b11 {
+                // 6-bit results are left aligned within the low byte
+                (data >> 2) << 6
+            } else {
+                data >> 4
+            }
+        } else {
+            data << (2 * res)
+        }
+    }
+
+    /// Powers down the ADC and releases the peripheral
+    pub fn free(mut self) -> ADC {
+        self.disable();
+        self.adc
+    }
+
+    /// Runs the self calibration, returning the calibration factor
+    ///
+    /// The factor is kept by the ADC until it is powered down or reset. It can be read back
+    /// with `calibration_factor` and restored later with `set_calibration_factor`.
+    pub fn calibrate(&mut self) -> u8 {
+        // ADCAL may only be set while the ADC is disabled and DMA is off
+        self.disable();
+        let dmaen = self.adc.cfgr1.read().dmaen().bit();
+        self.adc.cfgr1.modify(|_, w| w.dmaen().clear_bit());
+
+        // EOCAL is cleared by writing 1
+        self.adc.isr.write(|w| w.eocal().set_bit());
+        self.adc.cr.modify(|_, w| w.adcal().set_bit());
+        while self.adc.cr.read().adcal().bit_is_set() {}
+        self.adc.isr.write(|w| w.eocal().set_bit());
+
+        self.adc.cfgr1.modify(|_, w| w.dmaen().bit(dmaen));
+        self.enable();
+
+        self.calibration_factor()
+    }
+
+    /// Returns the calibration factor currently applied to conversions
+    pub fn calibration_factor(&self) -> u8 {
+        self.adc.calfact.read().calfact().bits()
+    }
+
+    /// Applies a calibration factor previously returned by `calibrate`
+    pub fn set_calibration_factor(&mut self, factor: u8) {
+        // CALFACT may only be written while the ADC is enabled and not converting
+        self.enable();
+        self.stop();
+        self.adc
+            .calfact
+            .write(|w| unsafe { w.calfact().bits(factor & 0x7F) });
+    }
+
+    /// Converts each channel of `sequence` once, in the given direction
+    pub fn read_sequence(
+        &mut self,
+        sequence: &Sequence,
+        direction: ScanDirection,
+    ) -> Result<SequenceResults, Error> {
+        let mut results = SequenceResults {
+            channels: sequence.channels,
+            values: [0; 19],
+        };
+        if sequence.is_empty() {
+            return Ok(results);
+        }
+
+        self.start_sequence(sequence, direction);
+
+        let direction = self.adc.cfgr1.read().scandir().bit();
+        let mut convert = |channel: usize| -> Result<(), Error> {
+            if sequence.channels & (1 << channel) != 0 {
+                results.values[channel] = nb::block!(self.read_result())?;
+            }
+            Ok(())
+        };
+        if direction {
+            for channel in (0..19).rev() {
+                convert(channel)?;
+            }
+        } else {
+            for channel in 0..19 {
+                convert(channel)?;
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Starts continuous conversions of `sequence`, written into `buffer` using DMA
+    ///
+    /// The results of each pass over the sequence are stored consecutively, in scan order.
+    pub fn sequence_dma(
+        mut self,
+        sequence: &Sequence,
+        direction: ScanDirection,
+        channel: dma::C1,
+        buffer: &'static mut [u16],
+        mode: dma::Mode,
+    ) -> AdcDma {
+        assert!(!sequence.is_empty());
+        self.set_scan_direction(&direction);
+        self.start_dma(sequence.channels, channel, buffer, mode)
+    }
+
+    fn set_scan_direction(&mut self, direction: &ScanDirection) {
+        // SCANDIR may only be changed while no conversion is ongoing
+        self.stop();
+        self.adc.cfgr1.modify(|_, w| {
+            w.scandir().bit(match direction {
+                ScanDirection::Upward => false,
+                ScanDirection::Backward => true,
+            })
+        });
+    }
+
+    /// Starts a conversion of `pin`, whose result is then read with `read_result`
+    pub fn start<PIN>(&mut self, _pin: &mut PIN)
+    where
+        PIN: Channel<ADC, ID = u8>,
+    {
+        self.start_conversion(1 << PIN::channel());
+    }
+
+    /// Starts a conversion of each channel of `sequence`, in the given direction
+    ///
+    /// The results are then read one by one with `read_result`, in scan order.
+    pub fn start_sequence(&mut self, sequence: &Sequence, direction: ScanDirection) {
+        self.set_scan_direction(&direction);
+        self.start_conversion(sequence.channels);
+    }
+
+    /// Selects the channels of `sequence`, converted in the given direction once a DMA
+    /// transfer from the ADC is started, e.g. with `dma::C1::receive`
+    pub fn select_sequence(&mut self, sequence: &Sequence, direction: ScanDirection) {
+        assert!(!sequence.is_empty());
+        self.set_scan_direction(&direction);
+        self.adc
+            .chselr
+            .write(|w| unsafe { w.bits(sequence.channels) });
+    }
+
+    fn start_conversion(&mut self, channels: u32) {
+        // the internal reference and its buffers restart after a wake up from Stop with
+        // `StopConfig::ultra_low_power` set
+        if channels & ((1 << 17) | (1 << 18)) != 0 {
+            wait_for_vrefint();
+        }
+
+        // CHSELR may only be written while no conversion is ongoing
+        self.stop();
+        self.adc.chselr.write(|w| unsafe { w.bits(channels) });
+
+        // clear stale flags, EOC, EOS and OVR are cleared by writing 1
+        self.adc
+            .isr
+            .write(|w| w.eoc().set_bit().eos().set_bit().ovr().set_bit());
+        self.adc.cr.modify(|_, w| w.adstart().set_bit());
+    }
+
+    /// Reads the result of the last conversion
+    pub fn read_result(&mut self) -> nb::Result<u16, Error> {
+        let isr = self.adc.isr.read();
+        if isr.ovr().bit_is_set() {
+            self.adc.isr.write(|w| w.ovr().set_bit());
+            Err(nb::Error::Other(Error::Overrun))
+        } else if isr.eoc().bit_is_set() {
+            // EOC is cleared by reading DR
+            Ok(self.adc.dr.read().data().bits())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+
+    /// Returns true once the last channel of a sequence has been converted
+    pub fn is_end_of_sequence(&self) -> bool {
+        self.adc.isr.read().eos().bit_is_set()
+    }
+
+    /// Clears the end of sequence flag
+    pub fn clear_end_of_sequence(&mut self) {
+        // EOS is cleared by writing 1
+        self.adc.isr.write(|w| w.eos().set_bit());
+    }
+
+    /// Starts listening for an interrupt event
+    pub fn listen(&mut self, event: Event) {
+        match event {
+            Event::EndOfConversion => self.adc.ier.modify(|_, w| w.eocie().set_bit()),
+            Event::EndOfSequence => self.adc.ier.modify(|_, w| w.eosie().set_bit()),
+            Event::Overrun => self.adc.ier.modify(|_, w| w.ovrie().set_bit()),
+        }
+    }
+
+    /// Stops listening for an interrupt event
+    pub fn unlisten(&mut self, event: Event) {
+        match event {
+            Event::EndOfConversion => self.adc.ier.modify(|_, w| w.eocie().clear_bit()),
+            Event::EndOfSequence => self.adc.ier.modify(|_, w| w.eosie().clear_bit()),
+            Event::Overrun => self.adc.ier.modify(|_, w| w.ovrie().clear_bit()),
+        }
+    }
+
+    /// Starts continuous conversions of `pin`, written into `buffer` using DMA
+    ///
+    /// The transfer progress can be followed with the flags of the returned `AdcDma`,
+    /// and their interrupts enabled with `AdcDma::listen`.
+    pub fn continuous_dma<PIN>(
+        mut self,
+        _pin: &mut PIN,
+        channel: dma::C1,
+        buffer: &'static mut [u16],
+        mode: dma::Mode,
+    ) -> AdcDma
+    where
+        PIN: Channel<ADC, ID = u8>,
+    {
+        self.set_scan_direction(&ScanDirection::Upward);
+        self.start_dma(1 << PIN::channel(), channel, buffer, mode)
+    }
+
+    fn start_dma(
+        mut self,
+        channels: u32,
+        mut channel: dma::C1,
+        buffer: &'static mut [u16],
+        mode: dma::Mode,
+    ) -> AdcDma {
+        assert!(buffer.len() > 0 && buffer.len() <= u16::max_value() as usize);
+
+        self.stop();
+        self.adc.chselr.write(|w| unsafe { w.bits(channels) });
+
+        channel.route(dma::C1Request::Adc);
+        channel.set_peripheral_address(&self.adc.dr as *const _ as u32);
+        channel.set_memory_address(buffer.as_mut_ptr() as u32);
+        channel.set_transfer_length(buffer.len() as u16);
+
+        let circular = match mode {
+            dma::Mode::OneShot => false,
+            dma::Mode::Circular => true,
+        };
+        if circular {
+            channel.start_circular(Direction::PeripheralToMemory, WordSize::Bits16);
+        } else {
+            channel.start(Direction::PeripheralToMemory, true, WordSize::Bits16);
+        }
+
+        // DMACFG keeps the DMA requests going after the last transfer in circular mode.
+        // With an external trigger each trigger starts one conversion (or sequence)
+        // instead of the conversions running back to back.
+        let triggered = self.adc.cfgr1.read().exten().bits() != 0b00;
+        self.adc.cfgr1.modify(|_, w| {
+            w.cont()
+                .bit(!triggered)
+                .dmaen()
+                .set_bit()
+                .dmacfg()
+                .bit(circular)
+        });
+        self.adc.isr.write(|w| w.eoc().set_bit().ovr().set_bit());
+        self.adc.cr.modify(|_, w| w.adstart().set_bit());
+
+        AdcDma {
+            adc: self,
+            channel,
+            buffer,
+        }
+    }
+
+    fn stop(&mut self) {
+        if self.adc.cr.read().adstart().bit_is_set() {
+            self.adc.cr.modify(|_, w| w.adstp().set_bit());
+            while self.adc.cr.read().adstp().bit_is_set() {}
+        }
+    }
+
+    fn enable(&mut self) {
+        if self.adc.cr.read().aden().bit_is_set() {
+            return;
+        }
+
+        self.adc.cr.modify(|_, w| w.advregen().set_bit());
+        // wait for the internal voltage regulator to stabilize (t_STAB = 10 us)
+        cortex_m::asm::delay(self.sysclk / 100_000);
+
+        // ADRDY is cleared by writing 1
+        self.adc.isr.write(|w| w.adrdy().set_bit());
+        self.adc.cr.modify(|_, w| w.aden().set_bit());
+        // in auto-off mode the ADC only powers up when a conversion starts
+        if self.adc.cfgr1.read().autoff().bit_is_clear() {
+            while self.adc.isr.read().adrdy().bit_is_clear() {}
+        }
+    }
+
+    fn disable(&mut self) {
+        if self.adc.cr.read().aden().bit_is_clear() {
+            return;
+        }
+
+        self.stop();
+        self.adc.cr.modify(|_, w| w.addis().set_bit());
+        while self.adc.cr.read().aden().bit_is_set() {}
+    }
+}
+
+macro_rules! dma {
+    ($($CX:ident: $Request:ident,)+) => {
+        $(
+            /// Conversion results of the channels selected with `select_sequence`, converted
+            /// back to back, or on each external trigger
+            unsafe impl dma::DmaRx<dma::$CX> for Adc {
+                type Word = u16;
+
+                const REQUEST: dma::$Request = dma::$Request::Adc;
+
+                fn rx_address(&self) -> u32 {
+                    &self.adc.dr as *const _ as u32
+                }
+
+                fn enable_rx_dma(&mut self) {
+                    // DMACFG keeps the requests going for circular transfers
+                    self.adc.cfgr1.modify(|_, w| w.dmaen().set_bit().dmacfg().set_bit());
+                }
+
+                fn disable_rx_dma(&mut self) {
+                    self.stop();
+                    self.adc.cfgr1.modify(|_, w| w.cont().clear_bit().dmaen().clear_bit());
+                }
+
+                fn start_rx(&mut self, _len: u16) {
+                    // the internal reference and its buffers restart after a wake up from Stop
+                    // with `StopConfig::ultra_low_power` set
+                    if self.adc.chselr.read().bits() & ((1 << 17) | (1 << 18)) != 0 {
+                        wait_for_vrefint();
+                    }
+
+                    let triggered = self.adc.cfgr1.read().exten().bits() != 0b00;
+                    self.adc.cfgr1.modify(|_, w| w.cont().bit(!triggered));
+                    self.adc.isr.write(|w| w.eoc().set_bit().ovr().set_bit());
+                    self.adc.cr.modify(|_, w| w.adstart().set_bit());
+                }
+            }
+        )+
+    }
+}
+
+dma! {
+    C1: C1Request,
+    C2: C2Request,
+}
+
+impl<WORD, PIN> OneShot<ADC, WORD, PIN> for Adc
+where
+    WORD: From<u16>,
+    PIN: Channel<ADC, ID = u8>,
+{
+    type Error = Error;
+
+    fn read(&mut self, pin: &mut PIN) -> nb::Result<WORD, Error> {
+        self.start(pin);
+
+        Ok(WORD::from(nb::block!(self.read_result())?))
+    }
+}
+
+/// ADC, DMA channel and buffer released by `AdcDma`
+type Released = (Adc, dma::C1, &'static mut [u16]);
+
+/// Continuous ADC conversions written into a buffer using DMA
+pub struct AdcDma {
+    adc: Adc,
+    channel: dma::C1,
+    buffer: &'static mut [u16],
+}
+
+impl AdcDma {
+    /// Enables / disables the DMA half transfer and transfer complete interrupts
+    pub fn listen(&mut self, half_complete: bool, complete: bool) {
+        self.channel.set_interrupts(half_complete, complete);
+    }
+
+    /// Returns true once the first half of the buffer has been filled
+    pub fn is_half_complete(&self) -> bool {
+        self.channel.is_half_complete()
+    }
+
+    /// Returns true once the whole buffer has been filled
+    pub fn is_complete(&self) -> bool {
+        self.channel.is_complete()
+    }
+
+    /// Clears the half complete flag
+    pub fn clear_half_complete(&mut self) {
+        self.channel.clear_half_complete();
+    }
+
+    /// Clears the complete flag
+    pub fn clear_complete(&mut self) {
+        self.channel.clear_complete();
+    }
+
+    /// Returns true if a DMA transfer error occurred
+    pub fn has_error(&self) -> bool {
+        self.channel.has_error()
+    }
+
+    /// Blocks until the buffer has been filled, then stops the conversions
+    ///
+    /// The ADC, DMA channel and buffer are released on error too.
+    pub fn wait(self) -> Result<Released, (Error, Released)> {
+        let mut error = None;
+        while !self.channel.is_complete() {
+            if self.channel.has_error() {
+                error = Some(Error::Dma);
+                break;
+            }
+        }
+
+        let released = self.stop();
+        match error {
+            None => Ok(released),
+            Some(error) => Err((error, released)),
+        }
+    }
+
+    /// Stops the conversions, releasing the ADC, DMA channel and buffer
+    pub fn stop(mut self) -> Released {
+        self.adc.stop();
+        self.adc.adc.cfgr1.modify(|_, w| {
+            w.cont()
+                .clear_bit()
+                .dmaen()
+                .clear_bit()
+                .dmacfg()
+                .clear_bit()
+        });
+        self.channel.set_interrupts(false, false);
+        self.channel.stop();
+        self.channel.clear_flags();
+
+        (self.adc, self.channel, self.buffer)
+    }
+}
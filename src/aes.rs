@@ -0,0 +1,340 @@
+//! AES-128 hardware accelerator
+//!
+//! Only on the parts with the AES peripheral (STM32L063, STM32L083), with the `aes` feature.
+//!
+//! Blocks are processed one at a time, in place, in about 200 AHB clock cycles each, and can be
+//! fed by DMA with `Aes::process_dma`. ECB and CBC decryption need the decryption key, the last
+//! round key of the key schedule: `Direction::DeriveAndDecrypt` derives it from the encryption
+//! key before each message, and `Aes::derive_decryption_key` computes it once for
+//! `Direction::Decrypt`.
+
+use core::sync::atomic::{self, Ordering};
+
+use stm32l0x3::AES;
+
+use crate::dma::{self, Direction as DmaDirection, DmaRx, DmaTx, WordSize};
+use crate::rcc::AHB;
+
+/// AES key
+pub type Key = [u8; 16];
+
+/// AES block, also used for the initialization vector
+pub type Block = [u8; 16];
+
+// CR bits
+const EN: u32 = 1 << 0;
+const CCFC: u32 = 1 << 7;
+const ERRC: u32 = 1 << 8;
+const DMAINEN: u32 = 1 << 11;
+const DMAOUTEN: u32 = 1 << 12;
+
+// SR bits
+const CCF: u32 = 1 << 0;
+const RDERR: u32 = 1 << 1;
+const WRERR: u32 = 1 << 2;
+
+/// AES error
+#[derive(Debug)]
+pub enum Error {
+    /// The output was read while a block was being processed
+    Read,
+    /// The input was written while a block was being processed
+    Write,
+    /// DMA transfer error
+    Dma,
+    #[doc(hidden)]
+    _Extensible,
+}
+
+/// Processing direction
+#[derive(Clone, Copy)]
+pub enum Direction {
+    Encrypt,
+    /// Decrypts with the decryption key, see `Aes::derive_decryption_key`
+    Decrypt,
+    /// Decrypts with the encryption key, from which the decryption key is derived first, in
+    /// about 80 more AHB clock cycles
+    DeriveAndDecrypt,
+}
+
+/// Chaining mode
+#[derive(Clone, Copy)]
+pub enum Chaining {
+    /// Electronic codebook, each block on its own
+    Ecb,
+    /// Cipher block chaining, from the initialization vector
+    Cbc(Block),
+    /// Counter, from the initial counter block, whose last 32 bits are incremented for each
+    /// block
+    ///
+    /// Encryption and decryption are the same. A final partial block is processed with
+    /// `Aes::process_partial`.
+    Ctr(Block),
+}
+
+/// AES accelerator
+pub struct Aes {
+    aes: AES,
+}
+
+impl Aes {
+    pub fn new(aes: AES, ahb: &mut AHB) -> Self {
+        ahb.enr().modify(|_, w| w.crypen().set_bit());
+        ahb.rstr().modify(|_, w| w.cryprst().set_bit());
+        ahb.rstr().modify(|_, w| w.cryprst().clear_bit());
+
+        Aes { aes }
+    }
+
+    /// Loads the key and the initialization vector, and starts processing blocks with
+    /// `process_block` until `stop`
+    pub fn start(&mut self, key: &Key, chaining: Chaining, direction: Direction) {
+        self.stop();
+
+        let (chmod, iv) = match chaining {
+            Chaining::Ecb => (0b00, None),
+            Chaining::Cbc(iv) => (0b01, Some(iv)),
+            Chaining::Ctr(iv) => (0b10, Some(iv)),
+        };
+        // the counter mode only uses the encryption
+        let mode = match (direction, chaining) {
+            (_, Chaining::Ctr(_)) | (Direction::Encrypt, _) => 0b00,
+            (Direction::Decrypt, _) => 0b10,
+            (Direction::DeriveAndDecrypt, _) => 0b11,
+        };
+
+        self.load_key(key);
+        if let Some(iv) = iv {
+            self.load_iv(&iv);
+        }
+        // DATATYPE: byte swapping, so that the blocks are read as little-endian words, also by
+        // the DMA; MODE, CHMOD
+        self.aes.cr.write(|w| unsafe {
+            w.bits((chmod << 5) | (mode << 3) | (0b10 << 1) | CCFC | ERRC | EN)
+        });
+    }
+
+    /// Derives the ECB and CBC decryption key from the encryption key
+    ///
+    /// This stops processing blocks.
+    pub fn derive_decryption_key(&mut self, key: &Key) -> Key {
+        self.stop();
+        self.load_key(key);
+        // MODE: key derivation
+        self.aes
+            .cr
+            .write(|w| unsafe { w.bits((0b01 << 3) | CCFC | ERRC | EN) });
+        while self.aes.sr.read().bits() & CCF == 0 {}
+        self.aes
+            .cr
+            .modify(|r, w| unsafe { w.bits(r.bits() | CCFC) });
+        self.stop();
+
+        // the key registers hold the derived key
+        let mut derived = [0; 16];
+        derived[0..4].copy_from_slice(&self.aes.keyr3.read().bits().to_be_bytes());
+        derived[4..8].copy_from_slice(&self.aes.keyr2.read().bits().to_be_bytes());
+        derived[8..12].copy_from_slice(&self.aes.keyr1.read().bits().to_be_bytes());
+        derived[12..16].copy_from_slice(&self.aes.keyr0.read().bits().to_be_bytes());
+        derived
+    }
+
+    /// Encrypts or decrypts a block in place
+    pub fn process_block(&mut self, block: &mut Block) -> Result<(), Error> {
+        for word in block.chunks(4) {
+            let word = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+            self.aes.dinr.write(|w| unsafe { w.bits(word) });
+        }
+
+        let sr = loop {
+            let sr = self.aes.sr.read().bits();
+            if sr & (CCF | RDERR | WRERR) != 0 {
+                break sr;
+            }
+        };
+        if sr & (RDERR | WRERR) != 0 {
+            self.aes
+                .cr
+                .modify(|r, w| unsafe { w.bits(r.bits() | ERRC | CCFC) });
+            return Err(if sr & WRERR != 0 {
+                Error::Write
+            } else {
+                Error::Read
+            });
+        }
+
+        for word in block.chunks_mut(4) {
+            word.copy_from_slice(&self.aes.doutr.read().bits().to_le_bytes());
+        }
+        self.aes
+            .cr
+            .modify(|r, w| unsafe { w.bits(r.bits() | CCFC) });
+        Ok(())
+    }
+
+    /// Encrypts or decrypts the final partial block of a message in place, of up to 16 bytes
+    ///
+    /// The block is padded with zeros, and the padding of the result discarded, which is only
+    /// meaningful in the counter mode.
+    pub fn process_partial(&mut self, data: &mut [u8]) -> Result<(), Error> {
+        assert!(data.len() <= 16);
+        let mut block = [0; 16];
+        block[..data.len()].copy_from_slice(data);
+        self.process_block(&mut block)?;
+        data.copy_from_slice(&block[..data.len()]);
+        Ok(())
+    }
+
+    /// Encrypts or decrypts blocks in place, chained according to `start`
+    pub fn process(&mut self, blocks: &mut [Block]) -> Result<(), Error> {
+        for block in blocks {
+            self.process_block(block)?;
+        }
+        Ok(())
+    }
+
+    /// Encrypts or decrypts blocks in place using DMA, chained according to `start`, blocking
+    /// until done
+    ///
+    /// The blocks must be word-aligned.
+    pub fn process_dma(
+        &mut self,
+        in_channel: &mut dma::C1,
+        out_channel: &mut dma::C2,
+        blocks: &mut [Block],
+    ) -> Result<(), Error> {
+        let words = blocks.len() * 4;
+        assert!(words <= u16::max_value() as usize);
+        let buffer = blocks.as_mut_ptr() as u32;
+        assert!(buffer % 4 == 0);
+
+        out_channel.route(dma::C2Request::AesOut);
+        out_channel.set_peripheral_address(&self.aes.doutr as *const _ as u32);
+        out_channel.set_memory_address(buffer);
+        out_channel.set_transfer_length(words as u16);
+
+        in_channel.route(dma::C1Request::AesIn);
+        in_channel.set_peripheral_address(&self.aes.dinr as *const _ as u32);
+        in_channel.set_memory_address(buffer);
+        in_channel.set_transfer_length(words as u16);
+
+        // the blocks must be written before the DMA reads them
+        atomic::compiler_fence(Ordering::Release);
+        out_channel.start(DmaDirection::PeripheralToMemory, true, WordSize::Bits32);
+        in_channel.start(DmaDirection::MemoryToPeripheral, true, WordSize::Bits32);
+        // DMAINEN triggers the first input request
+        self.aes
+            .cr
+            .modify(|r, w| unsafe { w.bits(r.bits() | DMAOUTEN | DMAINEN) });
+
+        // each block is read in place before its result is written
+        let mut result = Ok(());
+        while !out_channel.is_complete() {
+            let sr = self.aes.sr.read().bits();
+            if in_channel.has_error() || out_channel.has_error() || sr & (RDERR | WRERR) != 0 {
+                result = Err(if sr & RDERR != 0 {
+                    Error::Read
+                } else if sr & WRERR != 0 {
+                    Error::Write
+                } else {
+                    Error::Dma
+                });
+                break;
+            }
+        }
+
+        self.aes
+            .cr
+            .modify(|r, w| unsafe { w.bits((r.bits() & !(DMAOUTEN | DMAINEN)) | ERRC | CCFC) });
+        in_channel.stop();
+        out_channel.stop();
+        // the results must be read after the DMA wrote them
+        atomic::compiler_fence(Ordering::Acquire);
+        result
+    }
+
+    /// Stops processing blocks, which ends the chaining
+    pub fn stop(&mut self) {
+        self.aes.cr.modify(|r, w| unsafe { w.bits(r.bits() & !EN) });
+    }
+
+    /// Releases the peripheral
+    pub fn release(mut self) -> AES {
+        self.stop();
+        self.aes
+    }
+
+    fn load_key(&mut self, key: &Key) {
+        // KEYR3 holds the first bytes of the key
+        let word = |i: usize| u32::from_be_bytes([key[i], key[i + 1], key[i + 2], key[i + 3]]);
+        self.aes.keyr3.write(|w| unsafe { w.bits(word(0)) });
+        self.aes.keyr2.write(|w| unsafe { w.bits(word(4)) });
+        self.aes.keyr1.write(|w| unsafe { w.bits(word(8)) });
+        self.aes.keyr0.write(|w| unsafe { w.bits(word(12)) });
+    }
+
+    fn load_iv(&mut self, iv: &Block) {
+        // IVR3 holds the first bytes of the initialization vector
+        let word = |i: usize| u32::from_be_bytes([iv[i], iv[i + 1], iv[i + 2], iv[i + 3]]);
+        self.aes.ivr3.write(|w| unsafe { w.bits(word(0)) });
+        self.aes.ivr2.write(|w| unsafe { w.bits(word(4)) });
+        self.aes.ivr1.write(|w| unsafe { w.bits(word(8)) });
+        self.aes.ivr0.write(|w| unsafe { w.bits(word(12)) });
+    }
+}
+
+macro_rules! dma {
+    ([$($TXCH:ident: $TXREQ:ident),+], [$($RXCH:ident: $RXREQ:ident),+]) => {
+        $(
+            /// Input words of the blocks, read as little-endian words
+            unsafe impl DmaTx<dma::$TXCH> for Aes {
+                type Word = u32;
+
+                const REQUEST: dma::$TXREQ = dma::$TXREQ::AesIn;
+
+                fn tx_address(&self) -> u32 {
+                    &self.aes.dinr as *const _ as u32
+                }
+
+                fn enable_tx_dma(&mut self) {
+                    self.aes
+                        .cr
+                        .modify(|r, w| unsafe { w.bits(r.bits() | DMAINEN) });
+                }
+
+                fn disable_tx_dma(&mut self) {
+                    self.aes
+                        .cr
+                        .modify(|r, w| unsafe { w.bits(r.bits() & !DMAINEN) });
+                }
+            }
+        )+
+
+        $(
+            /// Output words of the blocks
+            unsafe impl DmaRx<dma::$RXCH> for Aes {
+                type Word = u32;
+
+                const REQUEST: dma::$RXREQ = dma::$RXREQ::AesOut;
+
+                fn rx_address(&self) -> u32 {
+                    &self.aes.doutr as *const _ as u32
+                }
+
+                fn enable_rx_dma(&mut self) {
+                    self.aes
+                        .cr
+                        .modify(|r, w| unsafe { w.bits(r.bits() | DMAOUTEN) });
+                }
+
+                fn disable_rx_dma(&mut self) {
+                    self.aes
+                        .cr
+                        .modify(|r, w| unsafe { w.bits(r.bits() & !DMAOUTEN) });
+                }
+            }
+        )+
+    };
+}
+
+dma!([C1: C1Request, C5: C5Request], [C2: C2Request, C3: C3Request]);
@@ -0,0 +1,278 @@
+//! Interrupt-driven, ring-buffered serial communication
+//!
+//! `buffered` splits a UART into a receive half and a transmit half for use from thread
+//! context, plus an interrupt half whose `on_interrupt` method has to be called from
+//! the UART's interrupt handler. The halves communicate through single-producer,
+//! single-consumer queues, so no locking is needed.
+
+use core::fmt;
+
+use embedded_hal::serial;
+use heapless::spsc::{Consumer, Producer, Queue};
+use heapless::ArrayLength;
+use nb;
+
+/// Buffered serial error
+#[derive(Debug)]
+pub enum Error {
+    /// A line did not fit into the provided buffer
+    LineTooLong,
+    #[doc(hidden)]
+    _Extensible,
+}
+
+/// A UART that can be driven from its interrupt handler
+pub trait BufferedUart {
+    /// Returns the received byte, if any, clearing any reception errors
+    fn read_byte(&mut self) -> Option<u8>;
+
+    /// Writes a byte to the transmit data register
+    ///
+    /// Only call this when `is_tx_empty` returns true.
+    fn write_byte(&mut self, byte: u8);
+
+    /// Returns true if the transmit data register can accept another byte
+    fn is_tx_empty(&self) -> bool;
+
+    fn enable_rx_interrupt(&mut self);
+
+    fn disable_tx_interrupt(&mut self);
+
+    /// Enables the transmit data register empty interrupt from thread context
+    fn listen_tx();
+
+    /// Returns true if the transmit data register empty interrupt is enabled
+    fn is_listening_tx() -> bool;
+
+    /// Returns true once the last byte has been shifted out (TC)
+    fn is_tx_complete() -> bool;
+}
+
+/// Splits a UART into buffered receive, transmit and interrupt halves
+pub fn buffered<UART, RXN, TXN>(
+    mut uart: UART,
+    rx_queue: &'static mut Queue<u8, RXN>,
+    tx_queue: &'static mut Queue<u8, TXN>,
+) -> (
+    BufferedRx<RXN>,
+    BufferedTx<UART, TXN>,
+    BufferedIsr<UART, RXN, TXN>,
+)
+where
+    UART: BufferedUart,
+    RXN: ArrayLength<u8>,
+    TXN: ArrayLength<u8>,
+{
+    let (rx_producer, rx_consumer) = rx_queue.split();
+    let (tx_producer, tx_consumer) = tx_queue.split();
+
+    uart.enable_rx_interrupt();
+
+    (
+        BufferedRx {
+            rx: rx_consumer,
+            line_len: 0,
+        },
+        BufferedTx {
+            tx: tx_producer,
+            _uart: core::marker::PhantomData,
+        },
+        BufferedIsr {
+            uart,
+            rx: rx_producer,
+            tx: tx_consumer,
+            dropped: 0,
+        },
+    )
+}
+
+/// Receive half of a buffered UART
+pub struct BufferedRx<RXN>
+where
+    RXN: ArrayLength<u8>,
+{
+    rx: Consumer<'static, u8, RXN>,
+    line_len: usize,
+}
+
+impl<RXN> BufferedRx<RXN>
+where
+    RXN: ArrayLength<u8>,
+{
+    /// Returns the next received byte, if any
+    pub fn read_byte(&mut self) -> Option<u8> {
+        self.rx.dequeue()
+    }
+
+    /// Copies received bytes into `buffer`, returning how many were copied
+    pub fn read(&mut self, buffer: &mut [u8]) -> usize {
+        let mut count = 0;
+        for byte in buffer.iter_mut() {
+            match self.rx.dequeue() {
+                Some(b) => *byte = b,
+                None => break,
+            }
+            count += 1;
+        }
+        count
+    }
+
+    /// Accumulates received bytes into `buffer` until a `\n` is received
+    ///
+    /// Call this repeatedly with the same buffer. Once a full line has been received it
+    /// returns its length, including the `\n`.
+    pub fn read_line(&mut self, buffer: &mut [u8]) -> nb::Result<usize, Error> {
+        while let Some(byte) = self.rx.dequeue() {
+            if self.line_len >= buffer.len() {
+                self.line_len = 0;
+                return Err(nb::Error::Other(Error::LineTooLong));
+            }
+
+            buffer[self.line_len] = byte;
+            self.line_len += 1;
+
+            if byte == b'\n' {
+                let len = self.line_len;
+                self.line_len = 0;
+                return Ok(len);
+            }
+        }
+
+        Err(nb::Error::WouldBlock)
+    }
+
+    /// Returns true if there are no bytes waiting to be read
+    pub fn is_empty(&self) -> bool {
+        !self.rx.ready()
+    }
+}
+
+impl<RXN> serial::Read<u8> for BufferedRx<RXN>
+where
+    RXN: ArrayLength<u8>,
+{
+    type Error = Error;
+
+    fn read(&mut self) -> nb::Result<u8, Error> {
+        self.rx.dequeue().ok_or(nb::Error::WouldBlock)
+    }
+}
+
+/// Transmit half of a buffered UART
+pub struct BufferedTx<UART, TXN>
+where
+    TXN: ArrayLength<u8>,
+{
+    tx: Producer<'static, u8, TXN>,
+    _uart: core::marker::PhantomData<UART>,
+}
+
+impl<UART, TXN> BufferedTx<UART, TXN>
+where
+    UART: BufferedUart,
+    TXN: ArrayLength<u8>,
+{
+    /// Queues as many bytes of `bytes` as fit, returning how many were queued
+    pub fn write(&mut self, bytes: &[u8]) -> usize {
+        let mut count = 0;
+        for byte in bytes.iter() {
+            if self.tx.enqueue(*byte).is_err() {
+                break;
+            }
+            count += 1;
+        }
+        if count > 0 {
+            UART::listen_tx();
+        }
+        count
+    }
+
+    /// Queues all of `bytes`, blocking while the transmit queue is full
+    pub fn write_all(&mut self, bytes: &[u8]) {
+        let mut bytes = bytes;
+        while !bytes.is_empty() {
+            let count = self.write(bytes);
+            bytes = &bytes[count..];
+        }
+    }
+}
+
+impl<UART, TXN> serial::Write<u8> for BufferedTx<UART, TXN>
+where
+    UART: BufferedUart,
+    TXN: ArrayLength<u8>,
+{
+    type Error = Error;
+
+    fn write(&mut self, byte: u8) -> nb::Result<(), Error> {
+        self.tx.enqueue(byte).map_err(|_| nb::Error::WouldBlock)?;
+        UART::listen_tx();
+        Ok(())
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Error> {
+        // the interrupt half stops listening once the transmit queue has been drained, the
+        // last byte is then still being shifted out
+        if !UART::is_listening_tx() && UART::is_tx_complete() {
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+}
+
+impl<UART, TXN> fmt::Write for BufferedTx<UART, TXN>
+where
+    UART: BufferedUart,
+    TXN: ArrayLength<u8>,
+{
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_all(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Interrupt half of a buffered UART
+pub struct BufferedIsr<UART, RXN, TXN>
+where
+    RXN: ArrayLength<u8>,
+    TXN: ArrayLength<u8>,
+{
+    uart: UART,
+    rx: Producer<'static, u8, RXN>,
+    tx: Consumer<'static, u8, TXN>,
+    dropped: u32,
+}
+
+impl<UART, RXN, TXN> BufferedIsr<UART, RXN, TXN>
+where
+    UART: BufferedUart,
+    RXN: ArrayLength<u8>,
+    TXN: ArrayLength<u8>,
+{
+    /// Moves data between the UART and the queues; call from the UART interrupt handler
+    pub fn on_interrupt(&mut self) {
+        while let Some(byte) = self.uart.read_byte() {
+            if self.rx.enqueue(byte).is_err() {
+                self.dropped = self.dropped.wrapping_add(1);
+            }
+        }
+
+        if self.uart.is_tx_empty() {
+            match self.tx.dequeue() {
+                Some(byte) => self.uart.write_byte(byte),
+                None => self.uart.disable_tx_interrupt(),
+            }
+        }
+    }
+
+    /// Returns the number of received bytes dropped because the receive queue was full
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    /// Returns the UART, e.g. to reconfigure it from the interrupt handler
+    pub fn uart(&mut self) -> &mut UART {
+        &mut self.uart
+    }
+}
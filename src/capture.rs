@@ -0,0 +1,315 @@
+//! Input capture on the general purpose timers
+
+use core::marker::PhantomData;
+
+use nb;
+use stm32l0x3::{TIM2, TIM21, TIM22};
+
+use crate::pwm::{ChannelPin, C1, C2, C3, C4};
+use crate::rcc::{Clocks, APB1, APB2};
+use crate::time::Hertz;
+pub use crate::timer::Edge;
+
+/// Input capture error
+#[derive(Debug)]
+pub enum Error {
+    /// A capture was overwritten before it was read
+    Overcapture,
+    #[doc(hidden)]
+    _Extensible,
+}
+
+/// Input capture channel configuration
+pub struct Config {
+    edge: Edge,
+    prescaler: u8,
+    filter: u8,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            edge: Edge::Rising,
+            prescaler: 0b00,
+            filter: 0b0000,
+        }
+    }
+}
+
+impl Config {
+    /// Sets the input edge triggering a capture
+    pub fn edge(mut self, edge: Edge) -> Self {
+        self.edge = edge;
+        self
+    }
+
+    /// Captures once every `events` edges, one of 1, 2, 4 or 8
+    pub fn prescaler(mut self, events: u8) -> Self {
+        self.prescaler = match events {
+            1 => 0b00,
+            2 => 0b01,
+            4 => 0b10,
+            8 => 0b11,
+            _ => panic!("invalid input capture prescaler"),
+        };
+        self
+    }
+
+    /// Sets the digital input filter (ICxF), from 0 (no filter) to 15
+    ///
+    /// See the reference manual for the sampling frequency and number of samples of each
+    /// setting.
+    pub fn filter(mut self, filter: u8) -> Self {
+        assert!(filter <= 0b1111);
+        self.filter = filter;
+        self
+    }
+}
+
+/// Timer operating in input capture mode
+pub struct Capture<TIM> {
+    pub(crate) tim: TIM,
+    clk: Hertz,
+}
+
+/// Timer measuring the period and pulse width of a signal on its channel 1 input
+pub struct PwmInput<TIM, PIN> {
+    tim: TIM,
+    pin: PIN,
+    clk: Hertz,
+}
+
+/// Input capture channel
+pub struct CaptureChannel<TIM, CHANNEL, PIN> {
+    pin: PIN,
+    _tim: PhantomData<TIM>,
+    _channel: PhantomData<CHANNEL>,
+}
+
+macro_rules! hal {
+    ($(
+        $TIMX:ident: (
+            $timX:ident,
+            $APB:ident,
+            $timXen:ident,
+            $timXrst:ident,
+            $pclkX:ident,
+            $ppreX:ident,
+            [$($CX:ident: ($chX:ident, $i:expr, $ccmrX:ident, $ccrX:ident),)+]
+        ),
+    )+) => {
+        $(
+            impl Capture<$TIMX> {
+                /// Configures the timer as a free running counter at `freq`, used to time
+                /// the captures
+                ///
+                /// The inputs are then set up with `ch1`, `ch2`, etc.
+                pub fn $timX<F>(tim: $TIMX, freq: F, clocks: &Clocks, apb: &mut $APB) -> Self
+                where
+                    F: Into<Hertz>,
+                {
+                    apb.enr().modify(|_, w| w.$timXen().set_bit());
+                    apb.rstr().modify(|_, w| w.$timXrst().set_bit());
+                    apb.rstr().modify(|_, w| w.$timXrst().clear_bit());
+
+                    // the timer clock is doubled when the APB clock is divided
+                    let clk = if clocks.$ppreX() == 1 {
+                        clocks.$pclkX()
+                    } else {
+                        Hertz(clocks.$pclkX().0 * 2)
+                    };
+
+                    let psc = (clk.0 / freq.into().0).max(1) - 1;
+                    assert!(psc <= 0xFFFF);
+                    tim.psc.write(|w| unsafe { w.psc().bits(psc as u16) });
+                    tim.arr.write(|w| unsafe { w.bits(0xFFFF) });
+                    tim.egr.write(|w| w.ug().set_bit());
+                    tim.cr1.modify(|_, w| w.cen().set_bit());
+
+                    Capture { tim, clk }
+                }
+
+                /// Returns the counter frequency
+                pub fn frequency(&self) -> Hertz {
+                    Hertz(self.clk.0 / (self.tim.psc.read().bits() + 1))
+                }
+
+                /// Stops the timer and releases the peripheral
+                pub fn release(self) -> $TIMX {
+                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    self.tim
+                }
+            }
+
+            impl<PIN> PwmInput<$TIMX, PIN>
+            where
+                PIN: ChannelPin<$TIMX, C1>,
+            {
+                /// Configures the timer to measure the signal on `pin`, counting at `freq`
+                ///
+                /// The counter is reset on each rising edge, so the signal period must be shorter
+                /// than 2^16 ticks.
+                pub fn $timX<F>(
+                    tim: $TIMX,
+                    pin: PIN,
+                    freq: F,
+                    clocks: &Clocks,
+                    apb: &mut $APB,
+                ) -> Self
+                where
+                    F: Into<Hertz>,
+                {
+                    let Capture { tim, clk } = Capture::$timX(tim, freq, clocks, apb);
+
+                    // CC1S = 01 (IC1 on TI1), CC2S = 10 (IC2 on TI1)
+                    tim.ccmr1_input.write(|w| unsafe { w.bits(0b01 | (0b10 << 8)) });
+                    // IC1 captures the rising edges, IC2 the falling edges
+                    tim.ccer.write(|w| unsafe { w.bits(0b0001 | (0b0011 << 4)) });
+                    // TS = 101 (TI1FP1), SMS = 100 (reset mode): the period starts at 0
+                    tim.smcr.modify(|_, w| unsafe { w.ts().bits(0b101).sms().bits(0b100) });
+
+                    PwmInput { tim, pin, clk }
+                }
+
+                /// Returns the counter frequency
+                pub fn frequency(&self) -> Hertz {
+                    Hertz(self.clk.0 / (self.tim.psc.read().bits() + 1))
+                }
+
+                /// Returns the period and the pulse width of the signal, in ticks, after each
+                /// period
+                ///
+                /// Returns an error if a period has been lost since the last read.
+                pub fn read(&mut self) -> nb::Result<(u16, u16), Error> {
+                    let sr = self.tim.sr.read();
+                    if sr.cc1if().bit_is_clear() {
+                        return Err(nb::Error::WouldBlock);
+                    }
+
+                    // reading CCR1 clears CC1IF
+                    let width = self.tim.ccr2.read().bits() as u16;
+                    let period = self.tim.ccr1.read().bits() as u16;
+                    if sr.cc1of().bit_is_set() {
+                        // CC1OF is cleared by writing 0, the other flags are unaffected by 1
+                        self.tim.sr.write(|w| unsafe { w.bits(!(1 << 9)) });
+                        return Err(nb::Error::Other(Error::Overcapture));
+                    }
+                    Ok((period, width))
+                }
+
+                /// Stops the timer and releases the peripheral and the pin
+                pub fn release(self) -> ($TIMX, PIN) {
+                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    (self.tim, self.pin)
+                }
+            }
+
+            $(
+                impl Capture<$TIMX> {
+                    /// Configures `pin` as capture input
+                    pub fn $chX<PIN>(
+                        &mut self,
+                        pin: PIN,
+                        config: Config,
+                    ) -> CaptureChannel<$TIMX, $CX, PIN>
+                    where
+                        PIN: ChannelPin<$TIMX, $CX>,
+                    {
+                        let offset = 8 * (($i - 1) % 2);
+                        // CCxS = 01, ICx mapped on TIx
+                        let ccmr = 0b01 | (config.prescaler << 2) | (config.filter << 4);
+                        self.tim.$ccmrX.modify(|r, w| unsafe {
+                            w.bits((r.bits() & !(0xFF << offset)) | ((ccmr as u32) << offset))
+                        });
+
+                        // CCxP / CCxNP select the edge, CCxE enables the capture
+                        let ccer = match config.edge {
+                            Edge::Rising => 0b0001,
+                            Edge::Falling => 0b0011,
+                            Edge::Both => 0b1011,
+                        };
+                        let offset = 4 * ($i - 1);
+                        self.tim.ccer.modify(|r, w| unsafe {
+                            w.bits((r.bits() & !(0b1111 << offset)) | (ccer << offset))
+                        });
+
+                        CaptureChannel {
+                            pin,
+                            _tim: PhantomData,
+                            _channel: PhantomData,
+                        }
+                    }
+                }
+
+                impl<PIN> CaptureChannel<$TIMX, $CX, PIN> {
+                    // NOTE(unsafe) the channel only accesses its own CCR register and SR flags,
+                    // and the shared CCER register atomically
+
+                    /// Returns the counter value captured at the last edge
+                    ///
+                    /// Returns an error if a capture has been lost since the last read.
+                    pub fn read(&mut self) -> nb::Result<u16, Error> {
+                        let tim = unsafe { &(*$TIMX::ptr()) };
+                        let sr = tim.sr.read().bits();
+                        if sr & (1 << $i) == 0 {
+                            return Err(nb::Error::WouldBlock);
+                        }
+
+                        // reading CCR clears CCxIF
+                        let capture = tim.$ccrX.read().bits() as u16;
+                        if sr & (1 << (8 + $i)) != 0 {
+                            // CCxOF is cleared by writing 0, the other flags are unaffected by 1
+                            tim.sr.write(|w| unsafe { w.bits(!(1 << (8 + $i))) });
+                            return Err(nb::Error::Other(Error::Overcapture));
+                        }
+                        Ok(capture)
+                    }
+
+                    /// Returns true if a capture has been lost since the last read
+                    pub fn is_overcapture(&self) -> bool {
+                        unsafe { (*$TIMX::ptr()).sr.read().bits() & (1 << (8 + $i)) != 0 }
+                    }
+
+                    /// Enables the capture
+                    pub fn enable(&mut self) {
+                        let cce = 1 << (4 * ($i - 1));
+                        cortex_m::interrupt::free(|_| unsafe {
+                            (*$TIMX::ptr()).ccer.modify(|r, w| w.bits(r.bits() | cce))
+                        });
+                    }
+
+                    /// Disables the capture
+                    pub fn disable(&mut self) {
+                        let cce = 1 << (4 * ($i - 1));
+                        cortex_m::interrupt::free(|_| unsafe {
+                            (*$TIMX::ptr()).ccer.modify(|r, w| w.bits(r.bits() & !cce))
+                        });
+                    }
+
+                    /// Disables the capture and releases the pin
+                    pub fn free(mut self) -> PIN {
+                        self.disable();
+                        self.pin
+                    }
+                }
+            )+
+        )+
+    }
+}
+
+hal! {
+    TIM2: (tim2, APB1, tim2en, tim2rst, pclk1, ppre1, [
+        C1: (ch1, 1, ccmr1_input, ccr1),
+        C2: (ch2, 2, ccmr1_input, ccr2),
+        C3: (ch3, 3, ccmr2_input, ccr3),
+        C4: (ch4, 4, ccmr2_input, ccr4),
+    ]),
+    TIM21: (tim21, APB2, tim21en, tim21rst, pclk2, ppre2, [
+        C1: (ch1, 1, ccmr1_input, ccr1),
+        C2: (ch2, 2, ccmr1_input, ccr2),
+    ]),
+    TIM22: (tim22, APB2, tim22en, tim22rst, pclk2, ppre2, [
+        C1: (ch1, 1, ccmr1_input, ccr1),
+        C2: (ch2, 2, ccmr1_input, ccr2),
+    ]),
+}
@@ -0,0 +1,466 @@
+//! Comparators (COMP1 / COMP2)
+
+use core::marker::PhantomData;
+
+use stm32l0x3::{SYSCFG_COMP, TIM2, TIM21, TIM22};
+
+use crate::exti::{ExtiTrigger, Internal, InternalLine};
+use crate::gpio::gpioa::{PA0, PA1, PA2, PA3};
+use crate::gpio::gpiob::{PB3, PB4, PB5, PB6, PB7};
+use crate::gpio::Analog;
+use crate::rcc::APB2;
+use crate::syscfg::{self, ENBUF_VREFINT_COMP2};
+
+/// COMP1 (type state)
+pub struct COMP1;
+
+/// COMP2 (type state)
+pub struct COMP2;
+
+/// Internal reference voltage input
+pub struct VRefint;
+
+/// 1/4 of the internal reference voltage input
+pub struct VRefintDiv4;
+
+/// 1/2 of the internal reference voltage input
+pub struct VRefintDiv2;
+
+/// 3/4 of the internal reference voltage input
+pub struct VRefint3Div4;
+
+/// DAC channel 1 output (PA4) input
+pub struct DacOut1;
+
+/// DAC channel 2 output (PA5) input
+#[cfg(feature = "dual-dac")]
+pub struct DacOut2;
+
+/// Non-inverting input -- DO NOT IMPLEMENT THIS TRAIT
+pub unsafe trait NonInvertingInput<COMP> {
+    #[doc(hidden)]
+    const INPSEL: u8;
+}
+
+/// Inverting input -- DO NOT IMPLEMENT THIS TRAIT
+pub unsafe trait InvertingInput<COMP> {
+    #[doc(hidden)]
+    const INNSEL: u8;
+    #[doc(hidden)]
+    const SCALER: bool = false;
+    #[doc(hidden)]
+    const VREFINT: bool = false;
+}
+
+unsafe impl NonInvertingInput<COMP1> for PA1<Analog> {
+    const INPSEL: u8 = 0;
+}
+
+unsafe impl InvertingInput<COMP1> for VRefint {
+    const INNSEL: u8 = 0b00;
+    const VREFINT: bool = true;
+}
+unsafe impl InvertingInput<COMP1> for PA0<Analog> {
+    const INNSEL: u8 = 0b01;
+}
+unsafe impl InvertingInput<COMP1> for DacOut1 {
+    const INNSEL: u8 = 0b10;
+}
+#[cfg(feature = "dual-dac")]
+unsafe impl InvertingInput<COMP1> for DacOut2 {
+    const INNSEL: u8 = 0b11;
+}
+
+unsafe impl NonInvertingInput<COMP2> for PA3<Analog> {
+    const INPSEL: u8 = 0b000;
+}
+unsafe impl NonInvertingInput<COMP2> for PB4<Analog> {
+    const INPSEL: u8 = 0b001;
+}
+unsafe impl NonInvertingInput<COMP2> for PB5<Analog> {
+    const INPSEL: u8 = 0b010;
+}
+unsafe impl NonInvertingInput<COMP2> for PB6<Analog> {
+    const INPSEL: u8 = 0b011;
+}
+unsafe impl NonInvertingInput<COMP2> for PB7<Analog> {
+    const INPSEL: u8 = 0b100;
+}
+
+unsafe impl InvertingInput<COMP2> for VRefint {
+    const INNSEL: u8 = 0b000;
+    const VREFINT: bool = true;
+}
+unsafe impl InvertingInput<COMP2> for PA2<Analog> {
+    const INNSEL: u8 = 0b001;
+}
+unsafe impl InvertingInput<COMP2> for DacOut1 {
+    const INNSEL: u8 = 0b010;
+}
+#[cfg(feature = "dual-dac")]
+unsafe impl InvertingInput<COMP2> for DacOut2 {
+    const INNSEL: u8 = 0b011;
+}
+unsafe impl InvertingInput<COMP2> for VRefintDiv4 {
+    const INNSEL: u8 = 0b100;
+    const SCALER: bool = true;
+    const VREFINT: bool = true;
+}
+unsafe impl InvertingInput<COMP2> for VRefintDiv2 {
+    const INNSEL: u8 = 0b101;
+    const SCALER: bool = true;
+    const VREFINT: bool = true;
+}
+unsafe impl InvertingInput<COMP2> for VRefint3Div4 {
+    const INNSEL: u8 = 0b110;
+    const SCALER: bool = true;
+    const VREFINT: bool = true;
+}
+unsafe impl InvertingInput<COMP2> for PB3<Analog> {
+    const INNSEL: u8 = 0b111;
+}
+
+/// Timer inputs that can be driven by a comparator output instead of a pin
+///
+/// Routing to an ETR input also provides the OCREF clear signal of that timer, for
+/// cycle-by-cycle current limiting of PWM outputs.
+pub enum TimerInput {
+    /// TIM2 external trigger
+    Tim2Etr,
+    /// TIM2 channel 4 input
+    Tim2Ch4,
+    /// TIM21 external trigger
+    Tim21Etr,
+    /// TIM21 channel 1 input (COMP1 only)
+    Tim21Ch1,
+    /// TIM21 channel 2 input (COMP2 only)
+    Tim21Ch2,
+    /// TIM22 external trigger
+    Tim22Etr,
+    /// TIM22 channel 1 input
+    Tim22Ch1,
+    /// LPTIM1 input 1
+    Lptim1In1,
+    /// LPTIM1 input 2 (COMP2 only)
+    Lptim1In2,
+}
+
+/// Output polarity
+pub enum Polarity {
+    /// The output is high while the non-inverting input is above the inverting input
+    NonInverted,
+    /// The output is low while the non-inverting input is above the inverting input
+    Inverted,
+}
+
+/// COMP2 power mode
+pub enum PowerMode {
+    /// Slow, lowest consumption
+    LowSpeed,
+    /// Fast, higher consumption
+    HighSpeed,
+}
+
+/// Comparator configuration
+pub struct Config {
+    polarity: Polarity,
+    power_mode: PowerMode,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            polarity: Polarity::NonInverted,
+            power_mode: PowerMode::LowSpeed,
+        }
+    }
+}
+
+impl Config {
+    pub fn polarity(mut self, polarity: Polarity) -> Self {
+        self.polarity = polarity;
+        self
+    }
+
+    /// Sets the power mode, which only applies to COMP2 as COMP1 is always ultra low power
+    pub fn power_mode(mut self, power_mode: PowerMode) -> Self {
+        self.power_mode = power_mode;
+        self
+    }
+}
+
+/// Comparator
+pub struct Comp<COMP, INP, INN> {
+    inp: INP,
+    inn: INN,
+    _comp: PhantomData<COMP>,
+}
+
+impl<INP, INN> Comp<COMP1, INP, INN>
+where
+    INP: NonInvertingInput<COMP1>,
+    INN: InvertingInput<COMP1>,
+{
+    /// Configures and enables COMP1
+    pub fn comp1(
+        _syscfg: &mut SYSCFG_COMP,
+        inp: INP,
+        inn: INN,
+        config: Config,
+        apb2: &mut APB2,
+    ) -> Self {
+        apb2.enr().modify(|_, w| w.syscfgen().set_bit());
+
+        let syscfg = unsafe { &(*SYSCFG_COMP::ptr()) };
+        syscfg.comp1_csr.modify(|_, w| unsafe {
+            w.comp1innsel()
+                .bits(INN::INNSEL)
+                .comp1polarity()
+                .bit(match config.polarity {
+                    Polarity::NonInverted => false,
+                    Polarity::Inverted => true,
+                })
+                .comp1en()
+                .set_bit()
+        });
+
+        Comp {
+            inp,
+            inn,
+            _comp: PhantomData,
+        }
+    }
+}
+
+impl<INP, INN> Comp<COMP2, INP, INN>
+where
+    INP: NonInvertingInput<COMP2>,
+    INN: InvertingInput<COMP2>,
+{
+    /// Configures and enables COMP2
+    pub fn comp2(
+        _syscfg: &mut SYSCFG_COMP,
+        inp: INP,
+        inn: INN,
+        config: Config,
+        apb2: &mut APB2,
+    ) -> Self {
+        apb2.enr().modify(|_, w| w.syscfgen().set_bit());
+
+        let syscfg = unsafe { &(*SYSCFG_COMP::ptr()) };
+        if INN::SCALER {
+            syscfg::enable_buffer(ENBUF_VREFINT_COMP2, apb2);
+        }
+
+        syscfg.comp2_csr.modify(|_, w| unsafe {
+            w.comp2inpsel()
+                .bits(INP::INPSEL)
+                .comp2innsel()
+                .bits(INN::INNSEL)
+                .comp2speed()
+                .bit(match config.power_mode {
+                    PowerMode::LowSpeed => false,
+                    PowerMode::HighSpeed => true,
+                })
+                .comp2polarity()
+                .bit(match config.polarity {
+                    Polarity::NonInverted => false,
+                    Polarity::Inverted => true,
+                })
+                .comp2en()
+                .set_bit()
+        });
+
+        Comp {
+            inp,
+            inn,
+            _comp: PhantomData,
+        }
+    }
+}
+
+macro_rules! comp {
+    ($COMPX:ident: (
+        $CSR:ident,
+        $line:ident,
+        $id:expr,
+        $compX_csr:ident,
+        $compXen:ident,
+        $compXvalue:ident,
+        $compXlock:ident
+    )) => {
+        impl<INP, INN> Comp<$COMPX, INP, INN>
+        where
+            INN: InvertingInput<$COMPX>,
+        {
+            /// Returns true unless the inverting input is the internal reference, and it isn't
+            /// ready yet
+            ///
+            /// The reference restarts after a wake up from Stop with `StopConfig::ultra_low_power`
+            /// set, which takes up to 3 ms with `StopConfig::fast_wake_up`.
+            pub fn is_reference_ready(&self) -> bool {
+                !INN::VREFINT || syscfg::is_vrefint_ready()
+            }
+        }
+
+        impl<INP, INN> Comp<$COMPX, INP, INN> {
+            fn csr(&self) -> &stm32l0x3::syscfg_comp::$CSR {
+                // NOTE(unsafe) the comparator only accesses its own CSR register
+                unsafe { &(*SYSCFG_COMP::ptr()).$compX_csr }
+            }
+
+            /// Returns the output level of the comparator
+            ///
+            /// The output is only valid once `is_reference_ready` returns true.
+            pub fn output(&self) -> bool {
+                self.csr().read().$compXvalue().bit_is_set()
+            }
+
+            /// Enables the comparator
+            pub fn enable(&mut self) {
+                self.csr().modify(|_, w| w.$compXen().set_bit());
+            }
+
+            /// Disables the comparator
+            pub fn disable(&mut self) {
+                self.csr().modify(|_, w| w.$compXen().clear_bit());
+            }
+
+            /// Routes output edges to the comparator's EXTI line
+            ///
+            /// The EXTI interrupt also wakes the device from Stop, e.g. on a voltage excursion.
+            pub fn listen(&mut self, exti: &mut Internal, trigger: ExtiTrigger) {
+                exti.listen(InternalLine::$line, trigger);
+            }
+
+            /// Stops routing output edges to the comparator's EXTI line
+            pub fn unlisten(&mut self, exti: &mut Internal) {
+                exti.unlisten(InternalLine::$line);
+            }
+
+            /// Returns true if an output edge was detected on the EXTI line
+            pub fn is_pending(&self, exti: &Internal) -> bool {
+                exti.is_pending(InternalLine::$line)
+            }
+
+            /// Clears the pending EXTI flag
+            pub fn clear_pending(&mut self, exti: &mut Internal) {
+                exti.clear_pending(InternalLine::$line);
+            }
+
+            /// Connects the comparator output to a timer input
+            pub fn connect(&mut self, input: TimerInput) {
+                route(&input, $id, true);
+            }
+
+            /// Connects a timer input back to its pin
+            pub fn disconnect(&mut self, input: TimerInput) {
+                route(&input, $id, false);
+            }
+
+            /// Makes the comparator configuration read-only until the next reset
+            pub fn lock(&mut self) {
+                self.csr().modify(|_, w| w.$compXlock().set_bit());
+            }
+
+            /// Disables the comparator and releases the inputs
+            ///
+            /// This has no effect once the configuration is locked.
+            pub fn free(mut self) -> (INP, INN) {
+                self.disable();
+                // a locked comparator keeps using the scaler
+                if INN::SCALER && self.csr().read().$compXlock().bit_is_clear() {
+                    syscfg::disable_buffer(ENBUF_VREFINT_COMP2);
+                }
+                (self.inp, self.inn)
+            }
+        }
+    };
+}
+
+comp!(COMP1: (COMP1_CSR, Comp1, 1, comp1_csr, comp1en, comp1value, comp1lock));
+comp!(COMP2: (COMP2_CSR, Comp2, 2, comp2_csr, comp2en, comp2value, comp2lock));
+
+impl<INP, INN> Comp<COMP1, INP, INN> {
+    /// Enables / disables the window mode
+    ///
+    /// In window mode the COMP1 non-inverting input is connected to the COMP2 one, so
+    /// both comparators watch the same signal and their inverting inputs set the bounds
+    /// of the window. The signal is within the window when exactly one output is high.
+    pub fn set_window_mode(&mut self, enabled: bool) {
+        self.csr().modify(|_, w| w.comp1wm().bit(enabled));
+    }
+}
+
+/// Routes the output of COMP`comp` to a timer input, or back to the pin
+fn route(input: &TimerInput, comp: u8, connect: bool) {
+    let comp1 = comp == 1;
+
+    // updates the `mask` remap field of a timer option register with the value selecting
+    // this comparator, or with 0 (the pin)
+    let or = |mask: u32, comp1_value: u32, comp2_value: u32| {
+        let value = if !connect {
+            0
+        } else if comp1 {
+            comp1_value
+        } else {
+            comp2_value
+        };
+        move |bits: u32| (bits & !mask) | value
+    };
+
+    // NOTE(unsafe) the timer drivers never touch the option registers, and the
+    // read-modify-writes are atomic
+    cortex_m::interrupt::free(|_| unsafe {
+        match input {
+            TimerInput::Tim2Etr => {
+                let f = or(0b111, 0b101, 0b100);
+                (*TIM2::ptr()).or.modify(|r, w| w.bits(f(r.bits())));
+            }
+            TimerInput::Tim2Ch4 => {
+                let f = or(0b11 << 3, 0b10 << 3, 0b01 << 3);
+                (*TIM2::ptr()).or.modify(|r, w| w.bits(f(r.bits())));
+            }
+            TimerInput::Tim21Etr => {
+                let f = or(0b11, 0b10, 0b01);
+                (*TIM21::ptr()).or.modify(|r, w| w.bits(f(r.bits())));
+            }
+            TimerInput::Tim21Ch1 => {
+                assert!(comp1);
+                let f = or(0b111 << 2, 0b110 << 2, 0);
+                (*TIM21::ptr()).or.modify(|r, w| w.bits(f(r.bits())));
+            }
+            TimerInput::Tim21Ch2 => {
+                assert!(!comp1);
+                let f = or(1 << 5, 0, 1 << 5);
+                (*TIM21::ptr()).or.modify(|r, w| w.bits(f(r.bits())));
+            }
+            TimerInput::Tim22Etr => {
+                let f = or(0b11, 0b10, 0b01);
+                (*TIM22::ptr()).or.modify(|r, w| w.bits(f(r.bits())));
+            }
+            TimerInput::Tim22Ch1 => {
+                let f = or(0b11 << 2, 0b10 << 2, 0b01 << 2);
+                (*TIM22::ptr()).or.modify(|r, w| w.bits(f(r.bits())));
+            }
+            TimerInput::Lptim1In1 => {
+                let syscfg = &(*SYSCFG_COMP::ptr());
+                if comp1 {
+                    syscfg
+                        .comp1_csr
+                        .modify(|_, w| w.comp1lptimin1().bit(connect));
+                } else {
+                    syscfg
+                        .comp2_csr
+                        .modify(|_, w| w.comp2lptimin1().bit(connect));
+                }
+            }
+            TimerInput::Lptim1In2 => {
+                assert!(!comp1);
+                (*SYSCFG_COMP::ptr())
+                    .comp2_csr
+                    .modify(|_, w| w.comp2lptimin2().bit(connect));
+            }
+        }
+    });
+}
@@ -0,0 +1,207 @@
+//! Pulse counting with the timers clocked by an external signal
+
+use stm32l0x3::{TIM2, TIM21, TIM22};
+
+use crate::gpio::gpioa::{PA0, PA1, PA15, PA4, PA5};
+use crate::gpio::{AF2, AF5};
+use crate::pwm::{ChannelPin, C1, C2};
+use crate::rcc::{APB1, APB2};
+pub use crate::timer::Edge;
+
+/// Timer external trigger (ETR) pin -- DO NOT IMPLEMENT THIS TRAIT
+pub unsafe trait EtrPin<TIM> {}
+
+unsafe impl EtrPin<TIM2> for PA0<AF5> {}
+unsafe impl EtrPin<TIM2> for PA5<AF2> {}
+unsafe impl EtrPin<TIM2> for PA15<AF2> {}
+unsafe impl EtrPin<TIM21> for PA1<AF5> {}
+unsafe impl EtrPin<TIM22> for PA4<AF5> {}
+
+/// Counter input configuration
+pub struct Config {
+    edge: Edge,
+    prescaler: u8,
+    filter: u8,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            edge: Edge::Rising,
+            prescaler: 0b00,
+            filter: 0b0000,
+        }
+    }
+}
+
+impl Config {
+    /// Sets the counted input edge
+    ///
+    /// The ETR input only counts a single edge, not `Edge::Both`.
+    pub fn edge(mut self, edge: Edge) -> Self {
+        self.edge = edge;
+        self
+    }
+
+    /// Counts once every `edges` edges, one of 1, 2, 4 or 8
+    ///
+    /// The prescaler is only available on the ETR input, whose frequency must be below a quarter
+    /// of the timer clock after the prescaler.
+    pub fn prescaler(mut self, edges: u8) -> Self {
+        self.prescaler = match edges {
+            1 => 0b00,
+            2 => 0b01,
+            4 => 0b10,
+            8 => 0b11,
+            _ => panic!("invalid external trigger prescaler"),
+        };
+        self
+    }
+
+    /// Sets the digital input filter, from 0 (no filter) to 15
+    pub fn filter(mut self, filter: u8) -> Self {
+        assert!(filter <= 0b1111);
+        self.filter = filter;
+        self
+    }
+}
+
+/// Timer counting the edges of an input signal
+pub struct PulseCounter<TIM, PIN> {
+    tim: TIM,
+    pin: PIN,
+}
+
+macro_rules! hal {
+    ($(
+        $TIMX:ident: (
+            $timX_etr:ident,
+            $timX_ti1:ident,
+            $timX_ti2:ident,
+            $APB:ident,
+            $timXen:ident,
+            $timXrst:ident
+        ),
+    )+) => {
+        $(
+            impl<PIN> PulseCounter<$TIMX, PIN> {
+                fn enable(apb: &mut $APB) {
+                    apb.enr().modify(|_, w| w.$timXen().set_bit());
+                    apb.rstr().modify(|_, w| w.$timXrst().set_bit());
+                    apb.rstr().modify(|_, w| w.$timXrst().clear_bit());
+                }
+
+                fn start(tim: $TIMX, pin: PIN) -> Self {
+                    tim.arr.write(|w| unsafe { w.bits(0xFFFF) });
+                    tim.cr1.modify(|_, w| w.cen().set_bit());
+
+                    PulseCounter { tim, pin }
+                }
+
+                /// Returns the number of counted edges, modulo 2^16
+                pub fn count(&self) -> u16 {
+                    self.tim.cnt.read().bits() as u16
+                }
+
+                /// Resets the count to zero
+                pub fn reset(&mut self) {
+                    self.tim.cnt.write(|w| unsafe { w.bits(0) });
+                }
+
+                /// Stops counting and releases the peripheral and the pin
+                pub fn release(self) -> ($TIMX, PIN) {
+                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    (self.tim, self.pin)
+                }
+            }
+
+            impl<PIN> PulseCounter<$TIMX, PIN>
+            where
+                PIN: EtrPin<$TIMX>,
+            {
+                /// Counts the edges on the external trigger pin (external clock mode 2)
+                pub fn $timX_etr(tim: $TIMX, pin: PIN, config: Config, apb: &mut $APB) -> Self {
+                    Self::enable(apb);
+
+                    let etp = match config.edge {
+                        Edge::Rising => false,
+                        Edge::Falling => true,
+                        Edge::Both => panic!("the ETR input counts a single edge"),
+                    };
+                    tim.smcr.modify(|_, w| unsafe {
+                        w.etp()
+                            .bit(etp)
+                            .etps()
+                            .bits(config.prescaler)
+                            .etf()
+                            .bits(config.filter)
+                            .ece()
+                            .set_bit()
+                    });
+
+                    PulseCounter::start(tim, pin)
+                }
+            }
+
+            impl<PIN> PulseCounter<$TIMX, PIN>
+            where
+                PIN: ChannelPin<$TIMX, C1>,
+            {
+                /// Counts the edges on the channel 1 pin (external clock mode 1)
+                ///
+                /// The prescaler of `config` is ignored.
+                pub fn $timX_ti1(tim: $TIMX, pin: PIN, config: Config, apb: &mut $APB) -> Self {
+                    Self::enable(apb);
+
+                    // CC1S = 01, IC1F = filter
+                    tim.ccmr1_input
+                        .write(|w| unsafe { w.bits(0b01 | (config.filter << 4) as u32) });
+                    // CC1P, CC1NP
+                    let ccer = match config.edge {
+                        Edge::Rising => 0,
+                        Edge::Falling => 1 << 1,
+                        Edge::Both => (1 << 1) | (1 << 3),
+                    };
+                    tim.ccer.write(|w| unsafe { w.bits(ccer) });
+                    // TS = 101 (TI1FP1), SMS = 111 (external clock mode 1)
+                    tim.smcr.modify(|_, w| unsafe { w.ts().bits(0b101).sms().bits(0b111) });
+
+                    PulseCounter::start(tim, pin)
+                }
+            }
+
+            impl<PIN> PulseCounter<$TIMX, PIN>
+            where
+                PIN: ChannelPin<$TIMX, C2>,
+            {
+                /// Counts the edges on the channel 2 pin (external clock mode 1)
+                ///
+                /// The prescaler of `config` is ignored.
+                pub fn $timX_ti2(tim: $TIMX, pin: PIN, config: Config, apb: &mut $APB) -> Self {
+                    Self::enable(apb);
+
+                    // CC2S = 01, IC2F = filter
+                    tim.ccmr1_input
+                        .write(|w| unsafe { w.bits((0b01 | (config.filter << 4) as u32) << 8) });
+                    // CC2P, CC2NP
+                    let ccer = match config.edge {
+                        Edge::Rising => 0,
+                        Edge::Falling => 1 << 5,
+                        Edge::Both => (1 << 5) | (1 << 7),
+                    };
+                    tim.ccer.write(|w| unsafe { w.bits(ccer) });
+                    // TS = 110 (TI2FP2), SMS = 111 (external clock mode 1)
+                    tim.smcr.modify(|_, w| unsafe { w.ts().bits(0b110).sms().bits(0b111) });
+
+                    PulseCounter::start(tim, pin)
+                }
+            }
+        )+
+    }
+}
+
+hal! {
+    TIM2: (tim2_etr, tim2_ti1, tim2_ti2, APB1, tim2en, tim2rst),
+    TIM21: (tim21_etr, tim21_ti1, tim21_ti2, APB2, tim21en, tim21rst),
+    TIM22: (tim22_etr, tim22_ti1, tim22_ti2, APB2, tim22en, tim22rst),
+}
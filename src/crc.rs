@@ -0,0 +1,213 @@
+//! Cyclic Redundancy Check (CRC) calculation unit
+//!
+//! The unit computes a CRC over the data written to it, a word in 4 AHB clock cycles. Out of
+//! reset, it is configured for CRC-32/MPEG-2: the CRC-32 polynomial 0x04C11DB7, an initial
+//! value of 0xFFFFFFFF, and neither reflection nor final XOR. `Crc::configure` selects another
+//! algorithm, such as one of the `Config` presets.
+
+use core::ptr;
+
+use stm32l0x3::CRC;
+
+use crate::rcc::AHB;
+
+/// Polynomial size
+#[derive(Clone, Copy)]
+pub enum PolySize {
+    Bits7,
+    Bits8,
+    Bits16,
+    Bits32,
+}
+
+impl PolySize {
+    /// Returns the POLYSIZE bits, and the mask of the CRC bits
+    fn bits(self) -> (u32, u32) {
+        match self {
+            PolySize::Bits32 => (0b00, 0xFFFF_FFFF),
+            PolySize::Bits16 => (0b01, 0xFFFF),
+            PolySize::Bits8 => (0b10, 0xFF),
+            PolySize::Bits7 => (0b11, 0x7F),
+        }
+    }
+}
+
+/// Bit reversal of the input data
+#[derive(Clone, Copy)]
+pub enum Reverse {
+    None,
+    /// Each byte is reflected
+    Byte,
+    /// Each half-word is reflected
+    HalfWord,
+    /// Each word is reflected
+    Word,
+}
+
+/// CRC algorithm
+pub struct Config {
+    polynomial: u32,
+    size: PolySize,
+    initial_value: u32,
+    reverse_input: Reverse,
+    reverse_output: bool,
+    final_xor: u32,
+}
+
+impl Default for Config {
+    /// CRC-32/MPEG-2, the reset configuration
+    fn default() -> Config {
+        Config {
+            polynomial: 0x04C1_1DB7,
+            size: PolySize::Bits32,
+            initial_value: 0xFFFF_FFFF,
+            reverse_input: Reverse::None,
+            reverse_output: false,
+            final_xor: 0,
+        }
+    }
+}
+
+impl Config {
+    /// Sets the polynomial, in normal representation without the highest term, which must be
+    /// odd
+    pub fn polynomial(mut self, polynomial: u32, size: PolySize) -> Self {
+        assert!(polynomial & 1 == 1);
+        self.polynomial = polynomial;
+        self.size = size;
+        self
+    }
+
+    /// Sets the initial value of the calculation
+    pub fn initial_value(mut self, initial_value: u32) -> Self {
+        self.initial_value = initial_value;
+        self
+    }
+
+    /// Reflects the input data (REV_IN)
+    pub fn reverse_input(mut self, reverse: Reverse) -> Self {
+        self.reverse_input = reverse;
+        self
+    }
+
+    /// Reflects the result (REV_OUT)
+    pub fn reverse_output(mut self, enabled: bool) -> Self {
+        self.reverse_output = enabled;
+        self
+    }
+
+    /// Sets the value XORed with the result, in software
+    pub fn final_xor(mut self, final_xor: u32) -> Self {
+        self.final_xor = final_xor;
+        self
+    }
+
+    /// CRC-32, as used by Ethernet, zlib and PNG, for data fed as bytes
+    pub fn crc32() -> Config {
+        Config::default()
+            .reverse_input(Reverse::Byte)
+            .reverse_output(true)
+            .final_xor(0xFFFF_FFFF)
+    }
+
+    /// CRC-16/CCITT-FALSE, polynomial 0x1021 with an initial value of 0xFFFF, as used by
+    /// XMODEM-like protocols and many radio links
+    pub fn crc16_ccitt() -> Config {
+        Config::default()
+            .polynomial(0x1021, PolySize::Bits16)
+            .initial_value(0xFFFF)
+    }
+
+    /// CRC-16/MODBUS, polynomial 0x8005 reflected with an initial value of 0xFFFF, for data fed
+    /// as bytes
+    pub fn crc16_modbus() -> Config {
+        Config::default()
+            .polynomial(0x8005, PolySize::Bits16)
+            .initial_value(0xFFFF)
+            .reverse_input(Reverse::Byte)
+            .reverse_output(true)
+    }
+
+    /// CRC-8, polynomial 0x07 with an initial value of 0, as used by SMBus
+    pub fn crc8() -> Config {
+        Config::default()
+            .polynomial(0x07, PolySize::Bits8)
+            .initial_value(0)
+    }
+}
+
+/// CRC calculation unit
+pub struct Crc {
+    crc: CRC,
+    mask: u32,
+    final_xor: u32,
+}
+
+impl Crc {
+    pub fn new(crc: CRC, ahb: &mut AHB) -> Self {
+        ahb.enr().modify(|_, w| w.crcen().set_bit());
+        ahb.rstr().modify(|_, w| w.crcrst().set_bit());
+        ahb.rstr().modify(|_, w| w.crcrst().clear_bit());
+
+        Crc {
+            crc,
+            mask: 0xFFFF_FFFF,
+            final_xor: 0,
+        }
+    }
+
+    /// Selects the CRC algorithm, and starts a new calculation
+    pub fn configure(&mut self, config: &Config) {
+        let (polysize, mask) = config.size.bits();
+        let rev_in = match config.reverse_input {
+            Reverse::None => 0b00,
+            Reverse::Byte => 0b01,
+            Reverse::HalfWord => 0b10,
+            Reverse::Word => 0b11,
+        };
+        self.mask = mask;
+        self.final_xor = config.final_xor & mask;
+
+        self.crc
+            .pol
+            .write(|w| unsafe { w.bits(config.polynomial & mask) });
+        self.crc
+            .init
+            .write(|w| unsafe { w.bits(config.initial_value & mask) });
+        // POLYSIZE, REV_IN, REV_OUT, RESET
+        self.crc.cr.write(|w| unsafe {
+            w.bits((polysize << 3) | (rev_in << 5) | ((config.reverse_output as u32) << 7) | 1)
+        });
+    }
+
+    /// Starts a new calculation from the initial value
+    pub fn reset(&mut self) {
+        self.crc.cr.modify(|r, w| unsafe { w.bits(r.bits() | 1) });
+    }
+
+    /// Feeds bytes to the calculation
+    pub fn feed_bytes(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            // NOTE(write_volatile) 8-bit write that's not possible through the svd2rust API
+            unsafe { ptr::write_volatile(&self.crc.dr as *const _ as *mut u8, *byte) }
+        }
+    }
+
+    /// Feeds words to the calculation, most significant byte first unless the input is
+    /// reflected by word
+    pub fn feed_words(&mut self, words: &[u32]) {
+        for word in words {
+            self.crc.dr.write(|w| unsafe { w.bits(*word) });
+        }
+    }
+
+    /// Returns the CRC of the data fed since the last reset
+    pub fn result(&self) -> u32 {
+        (self.crc.dr.read().bits() & self.mask) ^ self.final_xor
+    }
+
+    /// Releases the peripheral
+    pub fn release(self) -> CRC {
+        self.crc
+    }
+}
@@ -0,0 +1,511 @@
+//! Digital to Analog Converter (DAC)
+
+use stm32l0x3::DAC;
+
+use crate::dma::{self, Direction, WordSize};
+use crate::gpio::gpioa::PA4;
+#[cfg(feature = "dual-dac")]
+use crate::gpio::gpioa::PA5;
+use crate::gpio::Analog;
+use crate::rcc::APB1;
+
+/// DAC error
+#[derive(Debug)]
+pub enum Error {
+    /// A trigger arrived before the DMA delivered the previous sample
+    Underrun,
+    /// DMA transfer error
+    Dma,
+    #[doc(hidden)]
+    _Extensible,
+}
+
+/// Event loading the next value into the output
+pub enum Trigger {
+    /// TIM6 TRGO
+    Tim6Trgo,
+    /// TIM21 TRGO
+    Tim21Trgo,
+    /// TIM2 TRGO
+    Tim2Trgo,
+    /// TIM7 TRGO (STM32L072/L073 only)
+    Tim7Trgo,
+    /// EXTI line 9
+    Exti9,
+    /// Software trigger, see `trigger`
+    Software,
+}
+
+impl Trigger {
+    fn bits(&self) -> u8 {
+        match self {
+            Trigger::Tim6Trgo => 0b000,
+            Trigger::Tim21Trgo => 0b011,
+            Trigger::Tim2Trgo => 0b100,
+            Trigger::Tim7Trgo => 0b101,
+            Trigger::Exti9 => 0b110,
+            Trigger::Software => 0b111,
+        }
+    }
+}
+
+/// DAC channel, DMA channel and samples released by `Waveform`
+type Released<CH, DMA> = (CH, DMA, &'static [u16]);
+
+/// Waveform played from a sample buffer using DMA
+pub struct Waveform<CH, DMA> {
+    channel: CH,
+    dma: DMA,
+    samples: &'static [u16],
+}
+
+/// Extension trait to split the DAC peripheral into independent channels
+pub trait DacExt {
+    /// Splits the DAC into the channels of the given output pins
+    fn split<PINS>(self, pins: PINS, apb1: &mut APB1) -> PINS::Channels
+    where
+        PINS: Pins;
+}
+
+/// DAC output pins -- DO NOT IMPLEMENT THIS TRAIT
+pub unsafe trait Pins {
+    /// The channels driving the pins
+    type Channels;
+
+    #[doc(hidden)]
+    fn channels() -> Self::Channels;
+}
+
+unsafe impl Pins for PA4<Analog> {
+    type Channels = C1;
+
+    fn channels() -> C1 {
+        C1 { _0: () }
+    }
+}
+
+#[cfg(feature = "dual-dac")]
+unsafe impl Pins for PA5<Analog> {
+    type Channels = C2;
+
+    fn channels() -> C2 {
+        C2 { _0: () }
+    }
+}
+
+#[cfg(feature = "dual-dac")]
+unsafe impl Pins for (PA4<Analog>, PA5<Analog>) {
+    type Channels = (C1, C2);
+
+    fn channels() -> (C1, C2) {
+        (C1 { _0: () }, C2 { _0: () })
+    }
+}
+
+impl DacExt for DAC {
+    fn split<PINS>(self, _pins: PINS, apb1: &mut APB1) -> PINS::Channels
+    where
+        PINS: Pins,
+    {
+        apb1.enr().modify(|_, w| w.dacen().set_bit());
+        apb1.rstr().modify(|_, w| w.dacrst().set_bit());
+        apb1.rstr().modify(|_, w| w.dacrst().clear_bit());
+
+        PINS::channels()
+    }
+}
+
+macro_rules! dac_channels {
+    ($(
+        $CX:ident: (
+            $i:expr,
+            $DMACH:ident,
+            $request:expr,
+            $enX:ident,
+            $boffX:ident,
+            $dhr12rX:ident,
+            $dhr12lX:ident,
+            $dhr8rX:ident,
+            $dorX:ident,
+            $tenX:ident,
+            $tselX:ident,
+            $dmaenX:ident,
+            $dmaudrieX:ident,
+            $dmaudrX:ident,
+            $waveX:ident,
+            $mampX:ident
+        ),
+    )+) => {
+        $(
+            /// DAC channel
+            pub struct $CX {
+                _0: (),
+            }
+
+            impl $CX {
+                fn dac(&self) -> &stm32l0x3::dac::RegisterBlock {
+                    // NOTE(unsafe) the channel only accesses its own data registers, and
+                    // the shared CR register atomically
+                    unsafe { &(*DAC::ptr()) }
+                }
+
+                /// Enables the channel output
+                pub fn enable(&mut self) {
+                    cortex_m::interrupt::free(|_| {
+                        self.dac().cr.modify(|_, w| w.$enX().set_bit())
+                    });
+                }
+
+                /// Disables the channel output
+                pub fn disable(&mut self) {
+                    cortex_m::interrupt::free(|_| {
+                        self.dac().cr.modify(|_, w| w.$enX().clear_bit())
+                    });
+                }
+
+                /// Enables / disables the output buffer
+                ///
+                /// The buffer is enabled after reset. It reduces the output impedance so
+                /// loads can be driven directly, at the cost of some supply current.
+                pub fn set_output_buffer(&mut self, enabled: bool) {
+                    // BOFF may only be changed while the channel is disabled
+                    cortex_m::interrupt::free(|_| {
+                        self.dac().cr.modify(|_, w| w.$boffX().bit(!enabled))
+                    });
+                }
+
+                /// Sets the output to a 12-bit right aligned value
+                pub fn set_value(&mut self, value: u16) {
+                    self.dac()
+                        .$dhr12rX
+                        .write(|w| unsafe { w.bits(value as u32 & 0xFFF) });
+                }
+
+                /// Sets the output to a 12-bit left aligned value (bits 15:4)
+                pub fn set_value_left(&mut self, value: u16) {
+                    self.dac()
+                        .$dhr12lX
+                        .write(|w| unsafe { w.bits(value as u32 & 0xFFF0) });
+                }
+
+                /// Sets the output to an 8-bit value
+                pub fn set_value_8bit(&mut self, value: u8) {
+                    self.dac().$dhr8rX.write(|w| unsafe { w.bits(value as u32) });
+                }
+
+                /// Returns the 12-bit value currently output
+                pub fn get_value(&self) -> u16 {
+                    self.dac().$dorX.read().bits() as u16
+                }
+
+                /// Only loads written values into the output on `trigger`
+                pub fn enable_trigger(&mut self, trigger: Trigger) {
+                    cortex_m::interrupt::free(|_| {
+                        self.dac().cr.modify(|_, w| unsafe {
+                            w.$tselX().bits(trigger.bits()).$tenX().set_bit()
+                        })
+                    });
+                }
+
+                /// Loads written values into the output right away
+                pub fn disable_trigger(&mut self) {
+                    cortex_m::interrupt::free(|_| {
+                        self.dac().cr.modify(|_, w| w.$tenX().clear_bit())
+                    });
+                }
+
+                /// Triggers the channel from software, see `Trigger::Software`
+                pub fn trigger(&mut self) {
+                    // NOTE(unsafe) SWTRIGR bits are cleared by hardware, writing 0 is no-op
+                    self.dac().swtrigr.write(|w| unsafe { w.bits(1 << ($i - 1)) });
+                }
+
+                /// Adds pseudo-noise to the output value on each trigger
+                ///
+                /// The noise comes from the `bits` (1 to 12) lowest bits of an LFSR.
+                pub fn enable_noise(&mut self, bits: u8, trigger: Trigger) {
+                    self.enable_wave(0b01, bits, trigger);
+                }
+
+                /// Adds a triangle wave to the output value, stepping on each trigger
+                ///
+                /// The triangle counts up to 2^`amplitude_bits` - 1 and back down to 0,
+                /// with `amplitude_bits` from 1 to 12.
+                pub fn enable_triangle(&mut self, amplitude_bits: u8, trigger: Trigger) {
+                    self.enable_wave(0b10, amplitude_bits, trigger);
+                }
+
+                fn enable_wave(&mut self, wave: u8, bits: u8, trigger: Trigger) {
+                    assert!(bits >= 1 && bits <= 12);
+
+                    cortex_m::interrupt::free(|_| {
+                        self.dac().cr.modify(|_, w| unsafe {
+                            w.$waveX()
+                                .bits(wave)
+                                .$mampX()
+                                .bits(bits - 1)
+                                .$tselX()
+                                .bits(trigger.bits())
+                                .$tenX()
+                                .set_bit()
+                        })
+                    });
+                }
+
+                /// Disables the noise or triangle wave generator
+                pub fn disable_wave(&mut self) {
+                    cortex_m::interrupt::free(|_| {
+                        self.dac().cr.modify(|_, w| unsafe { w.$waveX().bits(0b00) })
+                    });
+                }
+
+                /// Plays 12-bit right aligned `samples` using DMA, one sample per trigger
+                ///
+                /// E.g. a timer TRGO sets the sample rate, and a circular transfer repeats
+                /// a wavetable until stopped.
+                pub fn play_dma(
+                    mut self,
+                    mut dma: dma::$DMACH,
+                    samples: &'static [u16],
+                    trigger: Trigger,
+                    mode: dma::Mode,
+                ) -> Waveform<$CX, dma::$DMACH> {
+                    assert!(samples.len() > 0 && samples.len() <= u16::max_value() as usize);
+
+                    dma.route($request);
+                    dma.set_peripheral_address(&self.dac().$dhr12rX as *const _ as u32);
+                    dma.set_memory_address(samples.as_ptr() as u32);
+                    dma.set_transfer_length(samples.len() as u16);
+                    match mode {
+                        dma::Mode::OneShot => {
+                            dma.start(Direction::MemoryToPeripheral, true, WordSize::Bits16)
+                        }
+                        dma::Mode::Circular => {
+                            dma.start_circular(Direction::MemoryToPeripheral, WordSize::Bits16)
+                        }
+                    }
+
+                    // DMAUDR is cleared by writing 1
+                    self.dac().sr.write(|w| w.$dmaudrX().set_bit());
+                    self.enable_trigger(trigger);
+                    cortex_m::interrupt::free(|_| {
+                        self.dac().cr.modify(|_, w| w.$dmaenX().set_bit())
+                    });
+                    self.enable();
+
+                    Waveform {
+                        channel: self,
+                        dma,
+                        samples,
+                    }
+                }
+            }
+
+            unsafe impl dma::DmaTx<dma::$DMACH> for $CX {
+                type Word = u16;
+
+                const REQUEST: <dma::$DMACH as dma::Channel>::Request = $request;
+
+                /// 12-bit right aligned data register
+                fn tx_address(&self) -> u32 {
+                    &self.dac().$dhr12rX as *const _ as u32
+                }
+
+                /// Enables the DMA requests, issued on each trigger set with `enable_trigger`
+                fn enable_tx_dma(&mut self) {
+                    cortex_m::interrupt::free(|_| {
+                        self.dac().cr.modify(|_, w| w.$dmaenX().set_bit())
+                    });
+                }
+
+                fn disable_tx_dma(&mut self) {
+                    cortex_m::interrupt::free(|_| {
+                        self.dac().cr.modify(|_, w| w.$dmaenX().clear_bit())
+                    });
+                }
+            }
+
+            impl Waveform<$CX, dma::$DMACH> {
+                /// Enables / disables the DMA underrun interrupt
+                pub fn listen_underrun(&mut self, enabled: bool) {
+                    cortex_m::interrupt::free(|_| {
+                        self.channel
+                            .dac()
+                            .cr
+                            .modify(|_, w| w.$dmaudrieX().bit(enabled))
+                    });
+                }
+
+                /// Enables / disables the DMA half transfer and transfer complete interrupts
+                pub fn listen(&mut self, half_complete: bool, complete: bool) {
+                    self.dma.set_interrupts(half_complete, complete);
+                }
+
+                /// Returns true once the first half of the samples have been played
+                pub fn is_half_complete(&self) -> bool {
+                    self.dma.is_half_complete()
+                }
+
+                /// Returns true once all the samples have been played
+                pub fn is_complete(&self) -> bool {
+                    self.dma.is_complete()
+                }
+
+                /// Clears the half complete flag
+                pub fn clear_half_complete(&mut self) {
+                    self.dma.clear_half_complete();
+                }
+
+                /// Clears the complete flag
+                pub fn clear_complete(&mut self) {
+                    self.dma.clear_complete();
+                }
+
+                /// Returns the error that stopped the playback, if any
+                pub fn error(&self) -> Option<Error> {
+                    if self.channel.dac().sr.read().$dmaudrX().bit_is_set() {
+                        Some(Error::Underrun)
+                    } else if self.dma.has_error() {
+                        Some(Error::Dma)
+                    } else {
+                        None
+                    }
+                }
+
+                /// Blocks until all the samples have been played, then stops the playback
+                ///
+                /// The channel, DMA channel and samples are released on error too.
+                pub fn wait(
+                    self,
+                ) -> Result<Released<$CX, dma::$DMACH>, (Error, Released<$CX, dma::$DMACH>)> {
+                    let mut error = None;
+                    while !self.dma.is_complete() {
+                        error = self.error();
+                        if error.is_some() {
+                            break;
+                        }
+                    }
+
+                    let released = self.stop();
+                    match error {
+                        None => Ok(released),
+                        Some(error) => Err((error, released)),
+                    }
+                }
+
+                /// Stops the playback, releasing the channel, DMA channel and samples
+                ///
+                /// The output keeps the last value played.
+                pub fn stop(mut self) -> Released<$CX, dma::$DMACH> {
+                    cortex_m::interrupt::free(|_| {
+                        self.channel.dac().cr.modify(|_, w| {
+                            w.$dmaenX().clear_bit().$dmaudrieX().clear_bit()
+                        })
+                    });
+                    self.channel.disable_trigger();
+                    self.dma.set_interrupts(false, false);
+                    self.dma.stop();
+                    self.dma.clear_flags();
+
+                    (self.channel, self.dma, self.samples)
+                }
+            }
+        )+
+    }
+}
+
+dac_channels! {
+    C1: (
+        1, C2, dma::C2Request::Tim6UpDacCh1,
+        en1, boff1, dhr12r1, dhr12l1, dhr8r1, dor1, ten1, tsel1, dmaen1, dmaudrie1, dmaudr1,
+        wave1, mamp1
+    ),
+}
+
+#[cfg(feature = "dual-dac")]
+dac_channels! {
+    C2: (
+        2, C4, dma::C4Request::Tim7UpDacCh2,
+        en2, boff2, dhr12r2, dhr12l2, dhr8r2, dor2, ten2, tsel2, dmaen2, dmaudrie2, dmaudr2,
+        wave2, mamp2
+    ),
+}
+
+/// Both DAC channels, updated simultaneously
+#[cfg(feature = "dual-dac")]
+pub struct DualChannel {
+    c1: C1,
+    c2: C2,
+}
+
+#[cfg(feature = "dual-dac")]
+impl DualChannel {
+    /// Combines the two channels
+    pub fn new(c1: C1, c2: C2) -> Self {
+        DualChannel { c1, c2 }
+    }
+
+    /// Enables both channel outputs
+    pub fn enable(&mut self) {
+        self.c1.enable();
+        self.c2.enable();
+    }
+
+    /// Disables both channel outputs
+    pub fn disable(&mut self) {
+        self.c1.disable();
+        self.c2.disable();
+    }
+
+    /// Sets both outputs to 12-bit right aligned values
+    pub fn set_values(&mut self, value1: u16, value2: u16) {
+        let bits = (value1 as u32 & 0xFFF) | ((value2 as u32 & 0xFFF) << 16);
+        self.c1.dac().dhr12rd.write(|w| unsafe { w.bits(bits) });
+    }
+
+    /// Sets both outputs to 12-bit left aligned values (bits 15:4)
+    pub fn set_values_left(&mut self, value1: u16, value2: u16) {
+        let bits = (value1 as u32 & 0xFFF0) | ((value2 as u32 & 0xFFF0) << 16);
+        self.c1.dac().dhr12ld.write(|w| unsafe { w.bits(bits) });
+    }
+
+    /// Sets both outputs to 8-bit values
+    pub fn set_values_8bit(&mut self, value1: u8, value2: u8) {
+        let bits = value1 as u32 | ((value2 as u32) << 8);
+        self.c1.dac().dhr8rd.write(|w| unsafe { w.bits(bits) });
+    }
+
+    /// Only loads written values into both outputs on `trigger`
+    pub fn enable_trigger(&mut self, trigger: Trigger) {
+        let tsel = trigger.bits();
+        cortex_m::interrupt::free(|_| {
+            self.c1.dac().cr.modify(|_, w| unsafe {
+                w.tsel1()
+                    .bits(tsel)
+                    .ten1()
+                    .set_bit()
+                    .tsel2()
+                    .bits(tsel)
+                    .ten2()
+                    .set_bit()
+            })
+        });
+    }
+
+    /// Loads written values into both outputs right away
+    pub fn disable_trigger(&mut self) {
+        self.c1.disable_trigger();
+        self.c2.disable_trigger();
+    }
+
+    /// Triggers both channels from software at once, see `Trigger::Software`
+    pub fn trigger(&mut self) {
+        // NOTE(unsafe) SWTRIGR bits are cleared by hardware
+        self.c1.dac().swtrigr.write(|w| unsafe { w.bits(0b11) });
+    }
+
+    /// Releases the two channels
+    pub fn release(self) -> (C1, C2) {
+        (self.c1, self.c2)
+    }
+}
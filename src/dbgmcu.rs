@@ -0,0 +1,106 @@
+//! Debug support (DBG)
+//!
+//! Peripherals can be frozen while the core is halted by the debugger, so that e.g. the
+//! watchdogs don't reset the device at a breakpoint, and the timers don't keep counting.
+
+use stm32l0x3::DBG;
+
+use crate::rcc::APB2;
+
+/// Peripheral that can be frozen while the core is halted
+#[derive(Clone, Copy)]
+pub enum Freeze {
+    Tim2,
+    Tim6,
+    Tim7,
+    Tim21,
+    Tim22,
+    Rtc,
+    Wwdg,
+    Iwdg,
+    I2c1,
+    I2c2,
+    Lptim,
+}
+
+impl Freeze {
+    /// Returns true for the APB2 peripherals, and the bit in the freeze register
+    fn bit(self) -> (bool, u32) {
+        match self {
+            Freeze::Tim2 => (false, 0),
+            Freeze::Tim6 => (false, 4),
+            Freeze::Tim7 => (false, 5),
+            Freeze::Rtc => (false, 10),
+            Freeze::Wwdg => (false, 11),
+            Freeze::Iwdg => (false, 12),
+            Freeze::I2c1 => (false, 21),
+            Freeze::I2c2 => (false, 22),
+            Freeze::Lptim => (false, 31),
+            Freeze::Tim21 => (true, 2),
+            Freeze::Tim22 => (true, 5),
+        }
+    }
+}
+
+/// Debug configuration
+pub struct Dbgmcu {
+    dbg: DBG,
+}
+
+impl Dbgmcu {
+    pub fn new(dbg: DBG, apb2: &mut APB2) -> Self {
+        apb2.enr().modify(|_, w| w.dbgen().set_bit());
+
+        Dbgmcu { dbg }
+    }
+
+    /// Stops `periph` while the core is halted by the debugger
+    pub fn freeze(&mut self, periph: Freeze) {
+        match periph.bit() {
+            (false, bit) => self
+                .dbg
+                .apb1_fz
+                .modify(|r, w| unsafe { w.bits(r.bits() | (1 << bit)) }),
+            (true, bit) => self
+                .dbg
+                .apb2_fz
+                .modify(|r, w| unsafe { w.bits(r.bits() | (1 << bit)) }),
+        }
+    }
+
+    /// Keeps `periph` running while the core is halted by the debugger, the default
+    pub fn unfreeze(&mut self, periph: Freeze) {
+        match periph.bit() {
+            (false, bit) => self
+                .dbg
+                .apb1_fz
+                .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << bit)) }),
+            (true, bit) => self
+                .dbg
+                .apb2_fz
+                .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << bit)) }),
+        }
+    }
+
+    /// Keeps the debug connection alive in the Sleep, Stop and Standby modes
+    ///
+    /// This increases the consumption in the low-power modes.
+    pub fn enable_low_power_debug(&mut self) {
+        // DBG_SLEEP, DBG_STOP, DBG_STANDBY
+        self.dbg
+            .cr
+            .modify(|r, w| unsafe { w.bits(r.bits() | 0b111) });
+    }
+
+    /// Stops the debug connection in the low-power modes, the default
+    pub fn disable_low_power_debug(&mut self) {
+        self.dbg
+            .cr
+            .modify(|r, w| unsafe { w.bits(r.bits() & !0b111) });
+    }
+
+    /// Releases the peripheral
+    pub fn release(self) -> DBG {
+        self.dbg
+    }
+}
@@ -0,0 +1,970 @@
+//! Direct Memory Access (DMA) controller
+//!
+//! The channels are used by the peripheral drivers, or directly to start transfers between a
+//! peripheral and a static buffer with `send` and `receive`.
+
+use core::cell::{Cell, RefCell};
+use core::slice;
+use core::sync::atomic::{self, Ordering};
+use core::task::Waker;
+
+use cortex_m::interrupt::{self, Mutex};
+
+use embedded_dma::{StaticReadBuffer, StaticWriteBuffer};
+use stm32l0x3::DMA1;
+
+use crate::rcc::AHB;
+
+/// Extension trait to split the DMA1 peripheral into independent channels
+pub trait DmaExt {
+    /// The channels to split the DMA into
+    type Channels;
+
+    /// Splits the DMA into independent channels
+    fn split(self, ahb: &mut AHB) -> Self::Channels;
+}
+
+/// Transfer direction
+pub enum Direction {
+    /// Read from the peripheral, write to memory
+    PeripheralToMemory,
+    /// Read from memory, write to the peripheral
+    MemoryToPeripheral,
+}
+
+/// How a transfer uses its memory buffer
+pub enum Mode {
+    /// Stop once the whole buffer has been transferred
+    OneShot,
+    /// Keep going, wrapping around to the start of the buffer when done
+    Circular,
+}
+
+/// DMA error
+#[derive(Debug)]
+pub enum Error {
+    /// Bus error on a transfer, e.g. to an invalid address
+    Transfer,
+    /// A circular transfer overwrote data that hadn't been read yet
+    Overrun,
+    #[doc(hidden)]
+    _Extensible,
+}
+
+// CCR bits kept when a transfer starts: TCIE, HTIE, TEIE and PL
+const CCR_KEPT: u32 = 0b1110 | (0b11 << 12);
+
+/// Channel interrupt event
+#[derive(Clone, Copy)]
+pub enum Event {
+    /// Half of the data items have been transferred (HTIF)
+    HalfTransfer,
+    /// All the data items have been transferred (TCIF)
+    TransferComplete,
+    /// Bus error, which also disables the channel (TEIF)
+    TransferError,
+}
+
+impl Event {
+    /// Returns the mask of the event, in CCR and in the channel flags of ISR
+    fn mask(self) -> u32 {
+        match self {
+            Event::TransferComplete => 1 << 1,
+            Event::HalfTransfer => 1 << 2,
+            Event::TransferError => 1 << 3,
+        }
+    }
+}
+
+/// Channel priority, arbitrating between channels with pending requests
+///
+/// Channels of equal priority are served in channel number order.
+#[derive(Clone, Copy)]
+pub enum Priority {
+    Low,
+    Medium,
+    High,
+    VeryHigh,
+}
+
+impl Priority {
+    fn bits(self) -> u8 {
+        match self {
+            Priority::Low => 0b00,
+            Priority::Medium => 0b01,
+            Priority::High => 0b10,
+            Priority::VeryHigh => 0b11,
+        }
+    }
+}
+
+/// Size of each data item
+pub enum WordSize {
+    Bits8,
+    Bits16,
+    Bits32,
+}
+
+impl WordSize {
+    fn bits(&self) -> u8 {
+        match self {
+            WordSize::Bits8 => 0b00,
+            WordSize::Bits16 => 0b01,
+            WordSize::Bits32 => 0b10,
+        }
+    }
+}
+
+/// DMA1 channel -- DO NOT IMPLEMENT THIS TRAIT
+pub unsafe trait Channel {
+    /// Peripheral requests that can be routed to the channel
+    type Request;
+}
+
+macro_rules! requests {
+    ($($CX:ident: $Request:ident: [$($Variant:ident = $bits:expr),+],)+) => {
+        $(
+            /// Peripheral request that can be routed to the channel
+            #[derive(Clone, Copy)]
+            pub enum $Request {
+                $($Variant,)+
+            }
+
+            impl $Request {
+                fn bits(self) -> u8 {
+                    match self {
+                        $($Request::$Variant => $bits,)+
+                    }
+                }
+            }
+
+            unsafe impl Channel for $CX {
+                type Request = $Request;
+            }
+        )+
+    }
+}
+
+requests! {
+    C1: C1Request: [Adc = 0b0000, Tim2Ch3 = 0b1000, AesIn = 0b1011],
+    C2: C2Request: [
+        Adc = 0b0000,
+        Spi1Rx = 0b0001,
+        Usart1Tx = 0b0011,
+        Lpuart1Tx = 0b0101,
+        I2c1Tx = 0b0110,
+        Tim2Up = 0b1000,
+        I2c3Tx = 0b1110,
+        Tim6UpDacCh1 = 0b1001,
+        AesOut = 0b1011
+    ],
+    C3: C3Request: [
+        Spi1Tx = 0b0001,
+        Usart1Rx = 0b0011,
+        Lpuart1Rx = 0b0101,
+        I2c1Rx = 0b0110,
+        Tim2Ch2 = 0b1000,
+        AesOut = 0b1011,
+        I2c3Rx = 0b1110
+    ],
+    C4: C4Request: [
+        Spi2Rx = 0b0010,
+        Usart1Tx = 0b0011,
+        Usart2Tx = 0b0100,
+        I2c2Tx = 0b0111,
+        Tim2Ch4 = 0b1000,
+        I2c3Tx = 0b1110,
+        Tim7UpDacCh2 = 0b1111
+    ],
+    C5: C5Request: [
+        Spi2Tx = 0b0010,
+        Usart1Rx = 0b0011,
+        Usart2Rx = 0b0100,
+        I2c2Rx = 0b0111,
+        Tim2Ch1 = 0b1000,
+        AesIn = 0b1011,
+        I2c3Rx = 0b1110
+    ],
+    C6: C6Request: [Spi2Rx = 0b0010, Usart2Rx = 0b0100, Lpuart1Rx = 0b0101, I2c1Tx = 0b0110],
+    C7: C7Request: [
+        Spi2Tx = 0b0010,
+        Usart2Tx = 0b0100,
+        Lpuart1Tx = 0b0101,
+        I2c1Rx = 0b0110,
+        Tim2Ch2Ch4 = 0b1000
+    ],
+}
+
+/// Data item of a transfer -- DO NOT IMPLEMENT THIS TRAIT
+pub unsafe trait Word {
+    #[doc(hidden)]
+    fn size() -> WordSize;
+}
+
+unsafe impl Word for u8 {
+    fn size() -> WordSize {
+        WordSize::Bits8
+    }
+}
+
+unsafe impl Word for u16 {
+    fn size() -> WordSize {
+        WordSize::Bits16
+    }
+}
+
+unsafe impl Word for u32 {
+    fn size() -> WordSize {
+        WordSize::Bits32
+    }
+}
+
+/// Peripheral sending the data written by channel `CH` -- DO NOT IMPLEMENT THIS TRAIT
+pub unsafe trait DmaTx<CH>
+where
+    CH: Channel,
+{
+    /// Data item
+    type Word: Word;
+
+    #[doc(hidden)]
+    const REQUEST: CH::Request;
+
+    /// Returns the address of the data register written by the channel
+    fn tx_address(&self) -> u32;
+
+    /// Enables the DMA requests of the peripheral
+    fn enable_tx_dma(&mut self);
+
+    /// Disables the DMA requests of the peripheral
+    fn disable_tx_dma(&mut self);
+
+    /// Starts the peripheral side of a transfer of `len` items, once the requests are enabled
+    ///
+    /// Nothing to do for the peripherals requesting the data on their own.
+    fn start_tx(&mut self, _len: u16) {}
+}
+
+/// Peripheral receiving the data read by channel `CH` -- DO NOT IMPLEMENT THIS TRAIT
+pub unsafe trait DmaRx<CH>
+where
+    CH: Channel,
+{
+    /// Data item
+    type Word: Word;
+
+    #[doc(hidden)]
+    const REQUEST: CH::Request;
+
+    /// Returns the address of the data register read by the channel
+    fn rx_address(&self) -> u32;
+
+    /// Enables the DMA requests of the peripheral
+    fn enable_rx_dma(&mut self);
+
+    /// Disables the DMA requests of the peripheral
+    fn disable_rx_dma(&mut self);
+
+    /// Starts the peripheral side of a transfer of `len` items, once the requests are enabled
+    ///
+    /// Nothing to do for the peripherals providing the data on their own.
+    fn start_rx(&mut self, _len: u16) {}
+}
+
+/// Transfer in progress between a peripheral and a static buffer, or between two static buffers
+///
+/// The transfer owns the channel, the peripheral and the buffer, and only gives them back once
+/// done, so the buffer can't be accessed or freed while the DMA uses it.
+pub struct Transfer<CH, PERIPH, BUF> {
+    channel: CH,
+    periph: PERIPH,
+    buffer: BUF,
+    stop: fn(&mut PERIPH),
+}
+
+/// Half of a circular buffer
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Half {
+    First,
+    Second,
+}
+
+/// Circular transfer continuously filling a static buffer from a peripheral
+///
+/// While the DMA fills one half of the buffer, the other half can be read.
+pub struct CircularTransfer<CH, PERIPH, BUF> {
+    channel: CH,
+    periph: PERIPH,
+    buffer: BUF,
+    stop: fn(&mut PERIPH),
+    address: usize,
+    len: usize,
+}
+
+/// Channel configuration
+pub struct Config {
+    direction: Direction,
+    circular: bool,
+    memory_increment: bool,
+    peripheral_increment: bool,
+    memory_size: WordSize,
+    peripheral_size: WordSize,
+    priority: Priority,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            direction: Direction::PeripheralToMemory,
+            circular: false,
+            memory_increment: true,
+            peripheral_increment: false,
+            memory_size: WordSize::Bits8,
+            peripheral_size: WordSize::Bits8,
+            priority: Priority::Low,
+        }
+    }
+}
+
+impl Config {
+    pub fn direction(mut self, direction: Direction) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    /// Restarts from the beginning of the buffer when done
+    pub fn circular(mut self, circular: bool) -> Self {
+        self.circular = circular;
+        self
+    }
+
+    /// Increments the memory address after each item
+    pub fn memory_increment(mut self, increment: bool) -> Self {
+        self.memory_increment = increment;
+        self
+    }
+
+    /// Increments the peripheral address after each item
+    pub fn peripheral_increment(mut self, increment: bool) -> Self {
+        self.peripheral_increment = increment;
+        self
+    }
+
+    pub fn memory_size(mut self, size: WordSize) -> Self {
+        self.memory_size = size;
+        self
+    }
+
+    pub fn peripheral_size(mut self, size: WordSize) -> Self {
+        self.peripheral_size = size;
+        self
+    }
+
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+}
+
+/// DMA1 channels
+pub struct Channels {
+    pub ch1: C1,
+    pub ch2: C2,
+    pub ch3: C3,
+    pub ch4: C4,
+    pub ch5: C5,
+    pub ch6: C6,
+    pub ch7: C7,
+}
+
+impl DmaExt for DMA1 {
+    type Channels = Channels;
+
+    fn split(self, ahb: &mut AHB) -> Channels {
+        ahb.enr().modify(|_, w| w.dmaen().set_bit());
+        ahb.rstr().modify(|_, w| w.dmarst().set_bit());
+        ahb.rstr().modify(|_, w| w.dmarst().clear_bit());
+
+        Channels {
+            ch1: C1 { _0: () },
+            ch2: C2 { _0: () },
+            ch3: C3 { _0: () },
+            ch4: C4 { _0: () },
+            ch5: C5 { _0: () },
+            ch6: C6 { _0: () },
+            ch7: C7 { _0: () },
+        }
+    }
+}
+
+macro_rules! dma_channels {
+    ($(
+        $CX:ident: (
+            $i:expr,
+            $Request:ident,
+            $ccrX:ident,
+            $cndtrX:ident,
+            $cparX:ident,
+            $cmarX:ident
+        ),
+    )+) => {
+        $(
+            /// DMA1 channel
+            pub struct $CX {
+                _0: (),
+            }
+
+            impl $CX {
+                fn dma(&self) -> &stm32l0x3::dma1::RegisterBlock {
+                    // NOTE(unsafe) the channel only accesses its own registers and the
+                    // write-only IFCR flags of its own channel
+                    unsafe { &(*DMA1::ptr()) }
+                }
+
+                /// Sets the peripheral data register address
+                pub fn set_peripheral_address(&mut self, address: u32) {
+                    self.dma().$cparX.write(|w| unsafe { w.pa().bits(address) });
+                }
+
+                /// Sets the memory buffer address
+                pub fn set_memory_address(&mut self, address: u32) {
+                    self.dma().$cmarX.write(|w| unsafe { w.ma().bits(address) });
+                }
+
+                /// Sets the number of data items to transfer
+                ///
+                /// The length can't be changed while the channel is enabled.
+                pub fn set_transfer_length(&mut self, len: u16) {
+                    self.dma().$cndtrX.write(|w| unsafe { w.ndt().bits(len) });
+                }
+
+                /// Routes a peripheral request to this channel
+                pub fn route(&mut self, request: $Request) {
+                    let request = request.bits();
+                    let offset = 4 * ($i - 1);
+                    // NOTE the channels share CSELR, so the update has to be atomic
+                    interrupt::free(|_| {
+                        self.dma().cselr.modify(|r, w| unsafe {
+                            w.bits(
+                                (r.bits() & !(0b1111 << offset)) | ((request as u32) << offset),
+                            )
+                        })
+                    });
+                }
+
+                /// Starts a transfer with a fixed peripheral address
+                pub(crate) fn start(
+                    &mut self,
+                    direction: Direction,
+                    memory_increment: bool,
+                    size: WordSize,
+                ) {
+                    self.enable_transfer(direction, memory_increment, size, false);
+                }
+
+                /// Starts a transfer that restarts from the beginning of the buffer when done
+                pub(crate) fn start_circular(&mut self, direction: Direction, size: WordSize) {
+                    self.enable_transfer(direction, true, size, true);
+                }
+
+                fn enable_transfer(
+                    &mut self,
+                    direction: Direction,
+                    memory_increment: bool,
+                    size: WordSize,
+                    circular: bool,
+                ) {
+                    let size = size.bits();
+                    self.clear_flags();
+                    // keep the priority set with `set_priority`, and the interrupt enables;
+                    // NOTE `on_interrupt` also modifies the interrupt enables
+                    interrupt::free(|_| {
+                        self.dma().$ccrX.modify(|r, w| unsafe {
+                            w.bits(r.bits() & CCR_KEPT)
+                                .dir()
+                                .bit(match direction {
+                                    Direction::PeripheralToMemory => false,
+                                    Direction::MemoryToPeripheral => true,
+                                })
+                                .circ()
+                                .bit(circular)
+                                .minc()
+                                .bit(memory_increment)
+                                .msize()
+                                .bits(size)
+                                .psize()
+                                .bits(size)
+                                .en()
+                                .set_bit()
+                        })
+                    });
+                }
+
+                /// Configures the channel, which must be disabled
+                ///
+                /// The interrupt enables are kept.
+                pub fn configure(&mut self, config: &Config) {
+                    assert!(!self.is_enabled());
+                    let (msize, psize) = (config.memory_size.bits(), config.peripheral_size.bits());
+                    self.dma().$ccrX.modify(|_, w| unsafe {
+                        w.mem2mem()
+                            .clear_bit()
+                            .pl()
+                            .bits(config.priority.bits())
+                            .msize()
+                            .bits(msize)
+                            .psize()
+                            .bits(psize)
+                            .minc()
+                            .bit(config.memory_increment)
+                            .pinc()
+                            .bit(config.peripheral_increment)
+                            .circ()
+                            .bit(config.circular)
+                            .dir()
+                            .bit(match config.direction {
+                                Direction::PeripheralToMemory => false,
+                                Direction::MemoryToPeripheral => true,
+                            })
+                    });
+                }
+
+                /// Sets the channel priority, also used by the transfers of the other drivers
+                pub fn set_priority(&mut self, priority: Priority) {
+                    self.dma()
+                        .$ccrX
+                        .modify(|_, w| unsafe { w.pl().bits(priority.bits()) });
+                }
+
+                /// Enables the channel, starting the transfer on the next request
+                ///
+                /// # Safety
+                ///
+                /// The addresses and length must describe memory that stays valid, and isn't
+                /// otherwise accessed, until the transfer is done or the channel is disabled.
+                pub unsafe fn enable(&mut self) {
+                    self.clear_flags();
+                    self.dma().$ccrX.modify(|_, w| w.en().set_bit());
+                }
+
+                /// Disables the channel, aborting any transfer in progress
+                pub fn disable(&mut self) {
+                    self.stop();
+                }
+
+                /// Returns true while the channel is enabled
+                pub fn is_enabled(&self) -> bool {
+                    self.dma().$ccrX.read().en().bit_is_set()
+                }
+
+                /// Returns the number of data items left to transfer
+                pub fn transfer_count(&self) -> u16 {
+                    self.dma().$cndtrX.read().ndt().bits()
+                }
+
+                /// Starts sending `buffer` through `periph`
+                pub fn send<P, B>(mut self, mut periph: P, buffer: B) -> Transfer<$CX, P, B>
+                where
+                    P: DmaTx<$CX>,
+                    B: StaticReadBuffer<Word = P::Word>,
+                {
+                    // NOTE(unsafe) the buffer is owned by the transfer until it's done
+                    let (ptr, len) = unsafe { buffer.static_read_buffer() };
+                    assert!(len <= u16::max_value() as usize);
+
+                    self.route(P::REQUEST);
+                    self.set_peripheral_address(periph.tx_address());
+                    self.set_memory_address(ptr as u32);
+                    self.set_transfer_length(len as u16);
+                    // the buffer must be written before the DMA reads it
+                    atomic::compiler_fence(Ordering::Release);
+                    self.start(Direction::MemoryToPeripheral, true, P::Word::size());
+                    periph.enable_tx_dma();
+                    periph.start_tx(len as u16);
+
+                    Transfer {
+                        channel: self,
+                        periph,
+                        buffer,
+                        stop: <P as DmaTx<$CX>>::disable_tx_dma,
+                    }
+                }
+
+                /// Starts filling `buffer` with the data received by `periph`
+                pub fn receive<P, B>(mut self, mut periph: P, mut buffer: B) -> Transfer<$CX, P, B>
+                where
+                    P: DmaRx<$CX>,
+                    B: StaticWriteBuffer<Word = P::Word>,
+                {
+                    // NOTE(unsafe) the buffer is owned by the transfer until it's done
+                    let (ptr, len) = unsafe { buffer.static_write_buffer() };
+                    assert!(len <= u16::max_value() as usize);
+
+                    self.route(P::REQUEST);
+                    self.set_peripheral_address(periph.rx_address());
+                    self.set_memory_address(ptr as u32);
+                    self.set_transfer_length(len as u16);
+                    atomic::compiler_fence(Ordering::Release);
+                    self.start(Direction::PeripheralToMemory, true, P::Word::size());
+                    periph.enable_rx_dma();
+                    periph.start_rx(len as u16);
+
+                    Transfer {
+                        channel: self,
+                        periph,
+                        buffer,
+                        stop: <P as DmaRx<$CX>>::disable_rx_dma,
+                    }
+                }
+
+                /// Starts copying `src` to the beginning of `dst`, which must be at least as long
+                ///
+                /// The copy runs as fast as the bus allows, without waiting for requests.
+                pub fn copy<S, D>(mut self, src: S, mut dst: D) -> Transfer<$CX, S, D>
+                where
+                    S: StaticReadBuffer,
+                    S::Word: Word,
+                    D: StaticWriteBuffer<Word = S::Word>,
+                {
+                    // NOTE(unsafe) the buffers are owned by the transfer until it's done
+                    let (src_ptr, len) = unsafe { src.static_read_buffer() };
+                    let (dst_ptr, dst_len) = unsafe { dst.static_write_buffer() };
+                    assert!(len <= dst_len && len <= u16::max_value() as usize);
+
+                    // the source is on the peripheral side
+                    self.set_peripheral_address(src_ptr as u32);
+                    self.set_memory_address(dst_ptr as u32);
+                    self.set_transfer_length(len as u16);
+                    atomic::compiler_fence(Ordering::Release);
+
+                    let size = S::Word::size().bits();
+                    self.clear_flags();
+                    // NOTE `on_interrupt` also modifies the interrupt enables
+                    interrupt::free(|_| {
+                        self.dma().$ccrX.modify(|r, w| unsafe {
+                            w.bits(r.bits() & CCR_KEPT)
+                                .mem2mem()
+                                .set_bit()
+                                .minc()
+                                .set_bit()
+                                .pinc()
+                                .set_bit()
+                                .msize()
+                                .bits(size)
+                                .psize()
+                                .bits(size)
+                                .en()
+                                .set_bit()
+                        })
+                    });
+
+                    Transfer {
+                        channel: self,
+                        periph: src,
+                        buffer: dst,
+                        stop: |_| {},
+                    }
+                }
+
+                /// Starts filling `buffer` continuously with the data received by `periph`,
+                /// wrapping around to the start of the buffer when full
+                ///
+                /// The buffer length must be even.
+                pub fn receive_circular<P, B>(
+                    mut self,
+                    mut periph: P,
+                    mut buffer: B,
+                ) -> CircularTransfer<$CX, P, B>
+                where
+                    P: DmaRx<$CX>,
+                    B: StaticWriteBuffer<Word = P::Word>,
+                {
+                    // NOTE(unsafe) the buffer is owned by the transfer until it's stopped
+                    let (ptr, len) = unsafe { buffer.static_write_buffer() };
+                    assert!(len > 0 && len % 2 == 0 && len <= u16::max_value() as usize);
+
+                    self.route(P::REQUEST);
+                    self.set_peripheral_address(periph.rx_address());
+                    self.set_memory_address(ptr as u32);
+                    self.set_transfer_length(len as u16);
+                    atomic::compiler_fence(Ordering::Release);
+                    self.start_circular(Direction::PeripheralToMemory, P::Word::size());
+                    periph.enable_rx_dma();
+                    periph.start_rx(len as u16);
+
+                    CircularTransfer {
+                        channel: self,
+                        periph,
+                        buffer,
+                        stop: <P as DmaRx<$CX>>::disable_rx_dma,
+                        address: ptr as usize,
+                        len,
+                    }
+                }
+
+                /// Disables the channel
+                pub(crate) fn stop(&mut self) {
+                    self.dma().$ccrX.modify(|_, w| w.en().clear_bit());
+                }
+
+                /// Enables / disables the half transfer and transfer complete interrupts
+                pub(crate) fn set_interrupts(&mut self, half_complete: bool, complete: bool) {
+                    // NOTE `on_interrupt` also modifies the interrupt enables
+                    interrupt::free(|_| {
+                        self.dma()
+                            .$ccrX
+                            .modify(|_, w| w.htie().bit(half_complete).tcie().bit(complete))
+                    });
+                }
+
+                /// Starts listening for an interrupt event
+                ///
+                /// The transfers started afterwards keep listening.
+                pub fn listen(&mut self, event: Event) {
+                    interrupt::free(|_| {
+                        self.dma()
+                            .$ccrX
+                            .modify(|r, w| unsafe { w.bits(r.bits() | event.mask()) })
+                    });
+                }
+
+                /// Stops listening for an interrupt event
+                pub fn unlisten(&mut self, event: Event) {
+                    interrupt::free(|_| {
+                        self.dma()
+                            .$ccrX
+                            .modify(|r, w| unsafe { w.bits(r.bits() & !event.mask()) })
+                    });
+                }
+
+                /// Returns true if the event flag is set
+                pub fn is_pending(&self, event: Event) -> bool {
+                    self.dma().isr.read().bits() & (event.mask() << (4 * ($i - 1))) != 0
+                }
+
+                /// Clears the event flag
+                pub fn clear_interrupt(&mut self, event: Event) {
+                    // NOTE(unsafe) atomic write to a stateless register
+                    self.dma()
+                        .ifcr
+                        .write(|w| unsafe { w.bits(event.mask() << (4 * ($i - 1))) });
+                }
+
+                /// Registers a waker, woken once by `on_interrupt`
+                ///
+                /// An async task registers its waker, listens to the events it waits for, and
+                /// checks the event flags when polled again.
+                pub fn register_waker(&mut self, waker: &Waker) {
+                    interrupt::free(|cs| {
+                        $CX::waker().borrow(cs).replace(Some(waker.clone()));
+                    });
+                }
+
+                /// Sets a function called by `on_interrupt`, or removes it
+                pub fn set_callback(&mut self, callback: Option<fn()>) {
+                    interrupt::free(|cs| $CX::callback().borrow(cs).set(callback));
+                }
+
+                /// Handles the channel interrupt, to be called from the DMA1 channel interrupt
+                /// handler
+                ///
+                /// The pending events are unlistened, so that the interrupt doesn't fire again,
+                /// but their flags are left set. The callback is then called, and the registered
+                /// waker is woken.
+                pub fn on_interrupt() {
+                    // NOTE(unsafe) only the interrupt enables of this channel are written, in a
+                    // critical section
+                    let dma = unsafe { &(*DMA1::ptr()) };
+                    let (callback, waker) = interrupt::free(|cs| {
+                        let flags = dma.isr.read().bits() >> (4 * ($i - 1));
+                        let pending = flags & dma.$ccrX.read().bits() & 0b1110;
+                        if pending == 0 {
+                            return (None, None);
+                        }
+                        dma.$ccrX.modify(|r, w| unsafe { w.bits(r.bits() & !pending) });
+                        (
+                            $CX::callback().borrow(cs).get(),
+                            $CX::waker().borrow(cs).borrow_mut().take(),
+                        )
+                    });
+                    if let Some(callback) = callback {
+                        callback();
+                    }
+                    if let Some(waker) = waker {
+                        waker.wake();
+                    }
+                }
+
+                fn waker() -> &'static Mutex<RefCell<Option<Waker>>> {
+                    static WAKER: Mutex<RefCell<Option<Waker>>> = Mutex::new(RefCell::new(None));
+                    &WAKER
+                }
+
+                fn callback() -> &'static Mutex<Cell<Option<fn()>>> {
+                    static CALLBACK: Mutex<Cell<Option<fn()>>> = Mutex::new(Cell::new(None));
+                    &CALLBACK
+                }
+
+                /// Returns true once the first half of the transfer has completed
+                pub(crate) fn is_half_complete(&self) -> bool {
+                    self.dma().isr.read().bits() & (0b0100 << (4 * ($i - 1))) != 0
+                }
+
+                /// Clears the half transfer flag
+                pub(crate) fn clear_half_complete(&mut self) {
+                    // NOTE(unsafe) atomic write to a stateless register
+                    self.dma()
+                        .ifcr
+                        .write(|w| unsafe { w.bits(0b0100 << (4 * ($i - 1))) });
+                }
+
+                /// Clears the transfer complete flag
+                pub(crate) fn clear_complete(&mut self) {
+                    // NOTE(unsafe) atomic write to a stateless register
+                    self.dma()
+                        .ifcr
+                        .write(|w| unsafe { w.bits(0b0010 << (4 * ($i - 1))) });
+                }
+
+                /// Returns true once the transfer has completed
+                pub(crate) fn is_complete(&self) -> bool {
+                    self.dma().isr.read().bits() & (0b0010 << (4 * ($i - 1))) != 0
+                }
+
+                /// Returns true if a transfer error occurred
+                pub(crate) fn has_error(&self) -> bool {
+                    self.dma().isr.read().bits() & (0b1000 << (4 * ($i - 1))) != 0
+                }
+
+                /// Clears all the interrupt flags of the channel
+                pub(crate) fn clear_flags(&mut self) {
+                    // NOTE(unsafe) atomic write to a stateless register
+                    self.dma()
+                        .ifcr
+                        .write(|w| unsafe { w.bits(0b0001 << (4 * ($i - 1))) });
+                }
+            }
+
+            impl<P, B> CircularTransfer<$CX, P, B>
+            where
+                B: StaticWriteBuffer,
+            {
+                /// Returns the half of the buffer that has just been filled
+                ///
+                /// Each half is returned once. `Error::Overrun` is returned if both halves were
+                /// filled since the last call, in which case the data is lost.
+                pub fn readable_half(&mut self) -> nb::Result<Half, Error> {
+                    if self.channel.has_error() {
+                        return Err(nb::Error::Other(Error::Transfer));
+                    }
+                    match (self.channel.is_half_complete(), self.channel.is_complete()) {
+                        (true, true) => {
+                            self.channel.clear_half_complete();
+                            self.channel.clear_complete();
+                            Err(nb::Error::Other(Error::Overrun))
+                        }
+                        (true, false) => {
+                            self.channel.clear_half_complete();
+                            Ok(Half::First)
+                        }
+                        (false, true) => {
+                            self.channel.clear_complete();
+                            Ok(Half::Second)
+                        }
+                        (false, false) => Err(nb::Error::WouldBlock),
+                    }
+                }
+
+                /// Waits for the next half of the buffer to be filled, and calls `f` with it
+                ///
+                /// `Error::Overrun` is returned if the DMA came back to this half while `f` was
+                /// reading it, in which case the result of `f` can't be trusted.
+                pub fn peek<R, F>(&mut self, f: F) -> Result<R, Error>
+                where
+                    F: FnOnce(&[B::Word], Half) -> R,
+                {
+                    let half = nb::block!(self.readable_half())?;
+                    let half_len = self.len / 2;
+                    let offset = match half {
+                        Half::First => 0,
+                        Half::Second => half_len,
+                    };
+                    // the DMA writes must be visible before the buffer is read
+                    atomic::compiler_fence(Ordering::Acquire);
+                    let ptr = self.address as *const B::Word;
+                    // NOTE(unsafe) the DMA is filling the other half
+                    let data = unsafe { slice::from_raw_parts(ptr.add(offset), half_len) };
+                    let result = f(data, half);
+                    atomic::compiler_fence(Ordering::Acquire);
+
+                    // the flag of the other half is set once the DMA starts writing this one
+                    let overrun = match half {
+                        Half::First => self.channel.is_complete(),
+                        Half::Second => self.channel.is_half_complete(),
+                    };
+                    if overrun {
+                        Err(Error::Overrun)
+                    } else {
+                        Ok(result)
+                    }
+                }
+
+                /// Stops the transfer, and releases the channel, the peripheral and the buffer
+                pub fn stop(mut self) -> ($CX, P, B) {
+                    (self.stop)(&mut self.periph);
+                    self.channel.stop();
+                    self.channel.clear_flags();
+                    atomic::compiler_fence(Ordering::Acquire);
+                    (self.channel, self.periph, self.buffer)
+                }
+            }
+
+            impl<P, B> Transfer<$CX, P, B> {
+                /// Returns true once the transfer is done, or failed
+                pub fn is_done(&self) -> bool {
+                    self.channel.is_complete() || self.channel.has_error()
+                }
+
+                /// Returns the number of data items left to transfer
+                pub fn remaining(&self) -> u16 {
+                    self.channel.transfer_count()
+                }
+
+                /// Waits for the transfer to be done, and releases the channel, the peripheral
+                /// and the buffer
+                ///
+                /// They are released along with the error if the transfer failed.
+                pub fn wait(self) -> Result<($CX, P, B), (Error, ($CX, P, B))> {
+                    while !self.is_done() {}
+                    let error = self.channel.has_error();
+                    let released = self.stop();
+                    if error {
+                        Err((Error::Transfer, released))
+                    } else {
+                        Ok(released)
+                    }
+                }
+
+                /// Aborts the transfer, and releases the channel, the peripheral and the buffer
+                pub fn stop(mut self) -> ($CX, P, B) {
+                    (self.stop)(&mut self.periph);
+                    self.channel.stop();
+                    self.channel.clear_flags();
+                    // the DMA writes must be visible before the buffer is read
+                    atomic::compiler_fence(Ordering::Acquire);
+                    (self.channel, self.periph, self.buffer)
+                }
+            }
+        )+
+    }
+}
+
+dma_channels! {
+    C1: (1, C1Request, ccr1, cndtr1, cpar1, cmar1),
+    C2: (2, C2Request, ccr2, cndtr2, cpar2, cmar2),
+    C3: (3, C3Request, ccr3, cndtr3, cpar3, cmar3),
+    C4: (4, C4Request, ccr4, cndtr4, cpar4, cmar4),
+    C5: (5, C5Request, ccr5, cndtr5, cpar5, cmar5),
+    C6: (6, C6Request, ccr6, cndtr6, cpar6, cmar6),
+    C7: (7, C7Request, ccr7, cndtr7, cpar7, cmar7),
+}
@@ -28,6 +28,7 @@ impl ExtiExt for EXTI {
             exti13: EXTI13 {},
             exti14: EXTI14 {},
             exti15: EXTI15 {},
+            internal: Internal {},
         }
     }
 }
@@ -50,6 +51,7 @@ pub struct Exti {
     pub exti13: EXTI13,
     pub exti14: EXTI14,
     pub exti15: EXTI15,
+    pub internal: Internal,
 }
 
 pub enum GpioExtiSource {
@@ -173,3 +175,105 @@ exti_gpio_line!(EXTI12, exti12, exticr4, im12, rt12, ft12, pif12);
 exti_gpio_line!(EXTI13, exti13, exticr4, im13, rt13, ft13, pif13);
 exti_gpio_line!(EXTI14, exti14, exticr4, im14, rt14, ft14, pif14);
 exti_gpio_line!(EXTI15, exti15, exticr4, im15, rt15, ft15, pif15);
+
+/// EXTI lines connected to peripherals
+#[derive(Clone, Copy)]
+pub enum InternalLine {
+    Pvd,
+    RtcAlarm,
+    Usb,
+    RtcTamperTimestamp,
+    RtcWakeup,
+    Comp1,
+    Comp2,
+    I2c1,
+    Usart1,
+    Usart2,
+    Lpuart1,
+    Lptim1,
+}
+
+impl InternalLine {
+    fn line(self) -> u8 {
+        match self {
+            InternalLine::Pvd => 16,
+            InternalLine::RtcAlarm => 17,
+            InternalLine::Usb => 18,
+            InternalLine::RtcTamperTimestamp => 19,
+            InternalLine::RtcWakeup => 20,
+            InternalLine::Comp1 => 21,
+            InternalLine::Comp2 => 22,
+            InternalLine::I2c1 => 23,
+            InternalLine::Usart1 => 25,
+            InternalLine::Usart2 => 26,
+            InternalLine::Lpuart1 => 28,
+            InternalLine::Lptim1 => 29,
+        }
+    }
+
+    /// Returns true if the line has configurable edges, otherwise it follows the
+    /// peripheral's own wakeup interrupt
+    fn is_configurable(self) -> bool {
+        self.line() <= 22 && self.line() != 18
+    }
+}
+
+/// Internal EXTI lines
+pub struct Internal {}
+
+impl Internal {
+    /// Unmasks the interrupt of an internal line, which also lets it wake the device from Stop
+    ///
+    /// `trigger` is ignored for the lines without configurable edges (USB, I2C1, USART1,
+    /// USART2, LPUART1 and LPTIM1).
+    pub fn listen(&mut self, line: InternalLine, trigger: ExtiTrigger) {
+        let mask = 1 << line.line();
+        let exti = unsafe { &(*EXTI::ptr()) };
+
+        cortex_m::interrupt::free(|_| unsafe {
+            if line.is_configurable() {
+                let (rising, falling) = match trigger {
+                    ExtiTrigger::Rising => (true, false),
+                    ExtiTrigger::Falling => (false, true),
+                    ExtiTrigger::RisingAndFalling => (true, true),
+                };
+                exti.rtsr.modify(|r, w| {
+                    w.bits(if rising {
+                        r.bits() | mask
+                    } else {
+                        r.bits() & !mask
+                    })
+                });
+                exti.ftsr.modify(|r, w| {
+                    w.bits(if falling {
+                        r.bits() | mask
+                    } else {
+                        r.bits() & !mask
+                    })
+                });
+            }
+            exti.imr.modify(|r, w| w.bits(r.bits() | mask));
+        });
+    }
+
+    /// Masks the interrupt of an internal line
+    pub fn unlisten(&mut self, line: InternalLine) {
+        let mask = 1 << line.line();
+        cortex_m::interrupt::free(|_| unsafe {
+            (*EXTI::ptr()).imr.modify(|r, w| w.bits(r.bits() & !mask))
+        });
+    }
+
+    /// Returns true if an edge was detected on a configurable internal line
+    pub fn is_pending(&self, line: InternalLine) -> bool {
+        unsafe { (*EXTI::ptr()).pr.read().bits() & (1 << line.line()) != 0 }
+    }
+
+    /// Clears the pending flag of a configurable internal line
+    ///
+    /// The lines without configurable edges are cleared in their peripheral instead.
+    pub fn clear_pending(&mut self, line: InternalLine) {
+        // NOTE(unsafe) PR bits are cleared by writing 1, writing 0 has no effect
+        unsafe { (*EXTI::ptr()).pr.write(|w| w.bits(1 << line.line())) }
+    }
+}
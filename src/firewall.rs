@@ -0,0 +1,175 @@
+//! Firewall (FW)
+//!
+//! The firewall protects a code segment and a non-volatile data segment in flash, and a
+//! volatile data segment in SRAM, from the rest of the application: any access from outside
+//! the protected code resets the device. Once enabled, it can't be disabled nor reconfigured
+//! until the next reset.
+//!
+//! The protected code is entered through its call gate only, 4 bytes after the start of the
+//! code segment, see `Firewall::call`. Before returning, the protected code calls
+//! `Firewall::prearm`, so that the firewall closes again instead of resetting the device.
+
+use core::mem;
+
+use stm32l0x3::{FIREWALL, SYSCFG_COMP};
+
+use crate::rcc::APB2;
+
+// CR bits
+const FPA: u32 = 1 << 0;
+const VDS: u32 = 1 << 1;
+const VDE: u32 = 1 << 2;
+
+/// Firewall error
+#[derive(Debug)]
+pub enum Error {
+    /// The firewall is already enabled, and keeps its configuration until the next reset
+    AlreadyEnabled,
+    #[doc(hidden)]
+    _Extensible,
+}
+
+/// Protected segments
+pub struct Config {
+    code: (u32, u32),
+    non_volatile_data: (u32, u32),
+    volatile_data: (u32, u32),
+    volatile_data_shared: bool,
+    volatile_data_executable: bool,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            code: (0x0800_0000, 0),
+            non_volatile_data: (0x0800_0000, 0),
+            volatile_data: (0x2000_0000, 0),
+            volatile_data_shared: false,
+            volatile_data_executable: false,
+        }
+    }
+}
+
+impl Config {
+    /// Sets the code segment in flash, with a 256 bytes granularity
+    ///
+    /// The segment starts with the call gate.
+    pub fn code_segment(mut self, start: u32, len: u32) -> Self {
+        assert!(start % 256 == 0 && len % 256 == 0 && len < 0x40_0000);
+        self.code = (start, len);
+        self
+    }
+
+    /// Sets the non-volatile data segment in flash, e.g. for key material, with a 256 bytes
+    /// granularity
+    pub fn non_volatile_data_segment(mut self, start: u32, len: u32) -> Self {
+        assert!(start % 256 == 0 && len % 256 == 0 && len < 0x40_0000);
+        self.non_volatile_data = (start, len);
+        self
+    }
+
+    /// Sets the volatile data segment in SRAM, with a 64 bytes granularity
+    pub fn volatile_data_segment(mut self, start: u32, len: u32) -> Self {
+        assert!(start % 64 == 0 && len % 64 == 0 && len < 0x1_0000);
+        self.volatile_data = (start, len);
+        self
+    }
+
+    /// Lets the code outside the firewall access the volatile data segment (VDS)
+    pub fn volatile_data_shared(mut self, shared: bool) -> Self {
+        self.volatile_data_shared = shared;
+        self
+    }
+
+    /// Lets the volatile data segment be executed, the segment then starting with its own
+    /// call gate (VDE)
+    pub fn volatile_data_executable(mut self, executable: bool) -> Self {
+        self.volatile_data_executable = executable;
+        self
+    }
+}
+
+/// Enabled firewall
+pub struct Firewall {
+    fw: FIREWALL,
+    code_start: u32,
+}
+
+impl Firewall {
+    /// Configures the segments, and enables the firewall until the next reset
+    pub fn enable(
+        fw: FIREWALL,
+        config: &Config,
+        syscfg: &mut SYSCFG_COMP,
+        apb2: &mut APB2,
+    ) -> Result<Self, Error> {
+        apb2.enr().modify(|_, w| w.syscfgen().set_bit());
+        if Firewall::is_enabled(syscfg) {
+            return Err(Error::AlreadyEnabled);
+        }
+        // FWEN
+        apb2.enr()
+            .modify(|r, w| unsafe { w.bits(r.bits() | (1 << 7)) });
+
+        let (start, len) = config.code;
+        fw.cssa.write(|w| unsafe { w.bits(start & 0x00FF_FF00) });
+        fw.csl.write(|w| unsafe { w.bits(len) });
+        let (start, len) = config.non_volatile_data;
+        fw.nvdssa.write(|w| unsafe { w.bits(start & 0x00FF_FF00) });
+        fw.nvdsl.write(|w| unsafe { w.bits(len) });
+        let (start, len) = config.volatile_data;
+        fw.vdssa.write(|w| unsafe { w.bits(start & 0xFFC0) });
+        fw.vdsl.write(|w| unsafe { w.bits(len) });
+
+        let mut cr = 0;
+        if config.volatile_data_shared {
+            cr |= VDS;
+        }
+        if config.volatile_data_executable {
+            cr |= VDE;
+        }
+        fw.cr.write(|w| unsafe { w.bits(cr) });
+
+        // FWDISEN, which can only be cleared, and is set again by a reset
+        syscfg.cfgr2.modify(|r, w| unsafe { w.bits(r.bits() & !1) });
+
+        Ok(Firewall {
+            fw,
+            code_start: config.code.0,
+        })
+    }
+
+    /// Returns true if the firewall has been enabled since the last reset
+    pub fn is_enabled(syscfg: &SYSCFG_COMP) -> bool {
+        syscfg.cfgr2.read().bits() & 1 == 0
+    }
+
+    /// Calls the protected code through its call gate, passing two arguments and returning
+    /// its result, according to the C calling convention
+    ///
+    /// # Safety
+    ///
+    /// The code segment must start with a call gate of that signature.
+    pub unsafe fn call(&self, arg0: u32, arg1: u32) -> u32 {
+        // the thumb bit is set in the call gate address
+        let gate: extern "C" fn(u32, u32) -> u32 =
+            mem::transmute((self.code_start + 4) as usize | 1);
+        gate(arg0, arg1)
+    }
+
+    /// Lets the protected code be left without resetting the device, the firewall closing
+    /// again (FPA)
+    ///
+    /// To be called by the protected code, just before returning. The firewall clears the
+    /// bit when it's opened.
+    pub fn prearm() {
+        // NOTE(unsafe) only the protected code can access the firewall registers while the
+        // firewall is open
+        unsafe { (*FIREWALL::ptr()).cr.modify(|r, w| w.bits(r.bits() | FPA)) };
+    }
+
+    /// Returns the peripheral; the firewall stays enabled
+    pub fn release(self) -> FIREWALL {
+        self.fw
+    }
+}
@@ -2,10 +2,13 @@
 
 use stm32l0x3::{I2C1, I2C3};
 
+use crate::dma::{self, DmaRx, DmaTx};
 use crate::gpio::gpioa::{PA10, PA9};
 use crate::gpio::gpiob::{PB6, PB7, PB8, PB9};
 use crate::gpio::{AF1, AF4, AF6};
 use crate::rcc::{Clocks, APB1};
+use crate::syscfg::{self, I2C1_FMP, I2C3_FMP};
+use crate::syscfg::{I2C_PB6_FMP, I2C_PB7_FMP, I2C_PB8_FMP, I2C_PB9_FMP};
 use crate::time::Hertz;
 use core::cmp;
 use embedded_hal::blocking::i2c::{Write, WriteRead};
@@ -27,18 +30,38 @@ pub enum Error {
 
 // FIXME these should be "closed" traits
 /// SCL pin -- DO NOT IMPLEMENT THIS TRAIT
-pub unsafe trait SclPin<I2C> {}
+pub unsafe trait SclPin<I2C> {
+    /// Fast-mode Plus drive bit of the pin, 0 if the pin has none
+    #[doc(hidden)]
+    const FMP: u32;
+}
 
 /// SDA pin -- DO NOT IMPLEMENT THIS TRAIT
-pub unsafe trait SdaPin<I2C> {}
+pub unsafe trait SdaPin<I2C> {
+    /// Fast-mode Plus drive bit of the pin, 0 if the pin has none
+    #[doc(hidden)]
+    const FMP: u32;
+}
 
-unsafe impl SclPin<I2C1> for PA9<AF6> {}
-unsafe impl SclPin<I2C1> for PB6<AF1> {}
-unsafe impl SclPin<I2C1> for PB8<AF4> {}
+unsafe impl SclPin<I2C1> for PA9<AF6> {
+    const FMP: u32 = 0;
+}
+unsafe impl SclPin<I2C1> for PB6<AF1> {
+    const FMP: u32 = I2C_PB6_FMP;
+}
+unsafe impl SclPin<I2C1> for PB8<AF4> {
+    const FMP: u32 = I2C_PB8_FMP;
+}
 
-unsafe impl SdaPin<I2C1> for PA10<AF6> {}
-unsafe impl SdaPin<I2C1> for PB7<AF1> {}
-unsafe impl SdaPin<I2C1> for PB9<AF4> {}
+unsafe impl SdaPin<I2C1> for PA10<AF6> {
+    const FMP: u32 = 0;
+}
+unsafe impl SdaPin<I2C1> for PB7<AF1> {
+    const FMP: u32 = I2C_PB7_FMP;
+}
+unsafe impl SdaPin<I2C1> for PB9<AF4> {
+    const FMP: u32 = I2C_PB9_FMP;
+}
 
 /// I2C peripheral operating in master mode
 pub struct I2c<I2C, PINS> {
@@ -65,10 +88,15 @@ macro_rules! busy_wait {
 }
 
 macro_rules! hal {
-    ($($I2CX:ident: ($i2cX:ident, $i2cXen:ident, $i2cXrst:ident),)+) => {
+    ($($I2CX:ident: ($i2cX:ident, $i2cXen:ident, $i2cXrst:ident, $fmp:expr),)+) => {
         $(
             impl<SCL, SDA> I2c<$I2CX, (SCL, SDA)> {
                 /// Configures the I2C peripheral to work in master mode
+                ///
+                /// Up to 100 kHz (standard mode), 400 kHz (fast mode), or 1 MHz (fast mode
+                /// plus). Fast mode needs PCLK1 of at least 8 MHz, and fast mode plus of at
+                /// least 16 MHz, and the Fast-mode Plus drive of both pins, or of all the pins
+                /// of the peripheral, see `SysCfgExt::enable_fast_mode_plus`.
                 pub fn $i2cX<F>(
                     i2c: $I2CX,
                     pins: (SCL, SDA),
@@ -86,7 +114,13 @@ macro_rules! hal {
 
                     let freq = freq.into().0;
 
-                    assert!(freq <= 100_000);
+                    assert!(freq > 0 && freq <= 1_000_000);
+                    if freq > 400_000 {
+                        let pins_fmp = SCL::FMP != 0
+                            && SDA::FMP != 0
+                            && syscfg::is_fast_mode_plus_enabled(SCL::FMP | SDA::FMP);
+                        assert!(pins_fmp || syscfg::is_fast_mode_plus_enabled($fmp));
+                    }
 
                     // TODO review compliance with the timing requirements of I2C
                     // t_I2CCLK = 1 / PCLK1
@@ -98,14 +132,25 @@ macro_rules! hal {
                     // t_SCL ~= t_SYNC1 + t_SYNC2 + t_SCLL + t_SCLH
                     let i2cclk = clocks.pclk1().0;
 
-                    // // standard-mode only
-                    let presc = 1;
-                    let scll = ((((i2cclk >> presc) >> 1) / freq) - 1) as u8;
-                    let sclh = scll - 4;
-                    let sdadel = 2;
-                    let scldel = 4;
+                    let (presc, scll, sclh, sdadel, scldel) = if freq <= 100_000 {
+                        let presc = 1;
+                        let scll = ((((i2cclk >> presc) >> 1) / freq) - 1) as u8;
+                        (presc, scll, scll - 4, 2, 4)
+                    } else {
+                        // t_PRESC of 125 ns in fast mode, and 62.5 ns in fast mode plus, for
+                        // the data hold and setup times; SCL low for about 2/3 of the period
+                        let (tpresc, sdadel, scldel) = if freq <= 400_000 {
+                            (8_000_000, 2, 3)
+                        } else {
+                            (16_000_000, 0, 2)
+                        };
+                        assert!(i2cclk >= tpresc);
+                        let presc = cmp::min(i2cclk / tpresc - 1, 15);
+                        let period = i2cclk / (presc + 1) / freq;
+                        let low = period * 2 / 3;
+                        (presc as u8, (low - 1) as u8, (period - low - 1) as u8, sdadel, scldel)
+                    };
 
-                    // Configure for "standard mode" (100 KHz)
                     i2c.timingr.write(|w| unsafe {
                         w.presc()
                             .bits(presc)
@@ -250,6 +295,108 @@ macro_rules! hal {
 }
 
 hal! {
-    I2C1: (i2c1, i2c1en, i2c1rst),
-    I2C3: (i2c3, i2c3en, i2c3rst),
+    I2C1: (i2c1, i2c1en, i2c1rst, I2C1_FMP),
+    I2C3: (i2c3, i2c3en, i2c3rst, I2C3_FMP),
+}
+
+macro_rules! dma {
+    ($(
+        $I2CX:ident: (
+            $tx_request:ident,
+            $rx_request:ident,
+            [$($TXCH:ident: $TXREQ:ident),+],
+            [$($RXCH:ident: $RXREQ:ident),+]
+        ),
+    )+) => {
+        $(
+            impl<PINS> I2c<$I2CX, PINS> {
+                /// Sets the slave address of the next DMA transfers
+                ///
+                /// Each transfer then starts with a START and the address, and ends with a
+                /// STOP, up to 255 bytes.
+                pub fn set_dma_address(&mut self, addr: u8) {
+                    self.i2c.cr2.write(|w| unsafe { w.sadd().bits(addr as u16) });
+                }
+            }
+
+            $(
+                /// Data bytes of a write to the address set with `set_dma_address`
+                unsafe impl<PINS> DmaTx<dma::$TXCH> for I2c<$I2CX, PINS> {
+                    type Word = u8;
+
+                    const REQUEST: dma::$TXREQ = dma::$TXREQ::$tx_request;
+
+                    fn tx_address(&self) -> u32 {
+                        &self.i2c.txdr as *const _ as u32
+                    }
+
+                    fn enable_tx_dma(&mut self) {
+                        self.i2c.cr1.modify(|_, w| w.txdmaen().set_bit());
+                    }
+
+                    fn disable_tx_dma(&mut self) {
+                        self.i2c.cr1.modify(|_, w| w.txdmaen().clear_bit());
+                    }
+
+                    fn start_tx(&mut self, len: u16) {
+                        // TODO support transfers of more than 255 bytes
+                        assert!(len < 256 && len > 0);
+                        // START and send `len` bytes, then automatic STOP
+                        self.i2c.cr2.modify(|_, w| unsafe {
+                            w.rd_wrn()
+                                .clear_bit()
+                                .nbytes()
+                                .bits(len as u8)
+                                .start()
+                                .set_bit()
+                                .autoend()
+                                .set_bit()
+                        });
+                    }
+                }
+            )+
+
+            $(
+                /// Data bytes of a read from the address set with `set_dma_address`
+                unsafe impl<PINS> DmaRx<dma::$RXCH> for I2c<$I2CX, PINS> {
+                    type Word = u8;
+
+                    const REQUEST: dma::$RXREQ = dma::$RXREQ::$rx_request;
+
+                    fn rx_address(&self) -> u32 {
+                        &self.i2c.rxdr as *const _ as u32
+                    }
+
+                    fn enable_rx_dma(&mut self) {
+                        self.i2c.cr1.modify(|_, w| w.rxdmaen().set_bit());
+                    }
+
+                    fn disable_rx_dma(&mut self) {
+                        self.i2c.cr1.modify(|_, w| w.rxdmaen().clear_bit());
+                    }
+
+                    fn start_rx(&mut self, len: u16) {
+                        // TODO support transfers of more than 255 bytes
+                        assert!(len < 256 && len > 0);
+                        // START and receive `len` bytes, then automatic STOP
+                        self.i2c.cr2.modify(|_, w| unsafe {
+                            w.rd_wrn()
+                                .set_bit()
+                                .nbytes()
+                                .bits(len as u8)
+                                .start()
+                                .set_bit()
+                                .autoend()
+                                .set_bit()
+                        });
+                    }
+                }
+            )+
+        )+
+    }
+}
+
+dma! {
+    I2C1: (I2c1Tx, I2c1Rx, [C2: C2Request, C6: C6Request], [C3: C3Request, C7: C7Request]),
+    I2C3: (I2c3Tx, I2c3Rx, [C2: C2Request, C4: C4Request], [C3: C3Request, C5: C5Request]),
 }
@@ -0,0 +1,493 @@
+//! Liquid Crystal Display (LCD) controller
+//!
+//! The controller drives a segment glass with up to 8 common (COM) and 44 segment (SEG) lines,
+//! refreshing it on its own from a display RAM, also in the Stop mode. It is clocked by the RTC
+//! clock, which it shares with the RTC.
+//!
+//! The COM and SEG pins must be configured as alternate function 1 (AF1).
+//!
+//! Text is displayed with `Lcd::display_str`, from a `Glass` describing which COM and SEG lines
+//! drive the segments of each character, and the 14-segment font of `font::segments14`.
+
+use core::ptr;
+
+use stm32l0x3::LCD;
+
+use crate::pwr::with_backup_domain_access;
+use crate::rcc::{APB1, CSR};
+use crate::rtc::ClockSource;
+use crate::time::Hertz;
+
+// CR bits
+const LCDEN: u32 = 1 << 0;
+const VSEL: u32 = 1 << 1;
+
+// SR bits
+const ENS: u32 = 1 << 0;
+const UDR: u32 = 1 << 2;
+const UDD: u32 = 1 << 3;
+const RDY: u32 = 1 << 4;
+const FCRSF: u32 = 1 << 5;
+
+// CLR bits
+const UDDC: u32 = 1 << 3;
+
+/// Duty ratio, the number of common lines
+#[derive(Clone, Copy)]
+pub enum Duty {
+    /// COM0 only
+    Static,
+    /// COM0 to COM1
+    Half,
+    /// COM0 to COM2
+    Third,
+    /// COM0 to COM3
+    Quarter,
+    /// COM0 to COM7, using SEG28 to SEG31 as COM4 to COM7
+    Eighth,
+}
+
+impl Duty {
+    /// Returns the DUTY bits, and the number of common lines
+    fn bits(self) -> (u32, u32) {
+        match self {
+            Duty::Static => (0b000, 1),
+            Duty::Half => (0b001, 2),
+            Duty::Third => (0b010, 3),
+            Duty::Quarter => (0b011, 4),
+            Duty::Eighth => (0b100, 8),
+        }
+    }
+}
+
+/// Bias, the number of voltage levels
+#[derive(Clone, Copy)]
+pub enum Bias {
+    Quarter,
+    Half,
+    Third,
+}
+
+/// LCD supply
+#[derive(Clone, Copy)]
+pub enum VoltageSource {
+    /// The internal step-up converter, which allows a VLCD higher than VDD
+    Internal,
+    /// The VLCD pin
+    External,
+}
+
+/// Blinking segments
+#[derive(Clone, Copy)]
+pub enum Blink {
+    Off,
+    /// SEG0 on COM0
+    Seg0Com0,
+    /// SEG0 on all the common lines
+    Seg0,
+    /// All the segments
+    All,
+}
+
+/// Blink frequency, as a divider of the LCD clock (`frame rate * COMs`)
+///
+/// At a 30 Hz frame rate with 4 COMs, `Div64` blinks at about 2 Hz.
+#[derive(Clone, Copy)]
+pub enum BlinkFrequency {
+    Div8,
+    Div16,
+    Div32,
+    Div64,
+    Div128,
+    Div256,
+    Div512,
+    Div1024,
+}
+
+/// LCD configuration
+pub struct Config {
+    duty: Duty,
+    bias: Bias,
+    voltage_source: VoltageSource,
+    frame_rate: Hertz,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            duty: Duty::Quarter,
+            bias: Bias::Third,
+            voltage_source: VoltageSource::Internal,
+            frame_rate: Hertz(30),
+        }
+    }
+}
+
+impl Config {
+    pub fn duty(mut self, duty: Duty) -> Self {
+        self.duty = duty;
+        self
+    }
+
+    pub fn bias(mut self, bias: Bias) -> Self {
+        self.bias = bias;
+        self
+    }
+
+    pub fn voltage_source(mut self, voltage_source: VoltageSource) -> Self {
+        self.voltage_source = voltage_source;
+        self
+    }
+
+    /// Sets the frame rate, typically 30 to 100 Hz, the closest one possible being used
+    pub fn frame_rate<F>(mut self, frame_rate: F) -> Self
+    where
+        F: Into<Hertz>,
+    {
+        self.frame_rate = frame_rate.into();
+        self
+    }
+}
+
+/// LCD controller
+pub struct Lcd {
+    lcd: LCD,
+    coms: u32,
+}
+
+impl Lcd {
+    /// Starts driving the glass from the RTC clock
+    ///
+    /// The RTC clock source is selected if needed, which resets the RTC if it ran from
+    /// another source. The display RAM is blank.
+    pub fn new(
+        lcd: LCD,
+        config: &Config,
+        source: ClockSource,
+        csr: &mut CSR,
+        apb1: &mut APB1,
+    ) -> Self {
+        apb1.enr()
+            .modify(|_, w| w.pwren().set_bit().lcden().set_bit());
+        let (rtcsel, clk, _, _) = source.config();
+        with_backup_domain_access(|| csr.enable_rtc(rtcsel));
+
+        let (duty, coms) = config.duty.bits();
+        let bias = match config.bias {
+            Bias::Quarter => 0b00,
+            Bias::Half => 0b01,
+            Bias::Third => 0b10,
+        };
+
+        // the frame rate is the RTC clock / (2^PS * (16 + DIV)) / the number of COMs
+        let target = config.frame_rate.0 * coms;
+        let mut best = (0, 0, u32::max_value());
+        for ps in 0..16 {
+            for div in 0..16 {
+                let freq = clk.0 / ((1 << ps) * (16 + div));
+                let error = if freq > target {
+                    freq - target
+                } else {
+                    target - freq
+                };
+                if error < best.2 {
+                    best = (ps, div, error);
+                }
+            }
+        }
+        let (ps, div, _) = best;
+
+        // FCR is written in the LCD clock domain, PS, DIV, and PON: 4 pulses, for a
+        // reasonable contrast with the internal converter
+        lcd.fcr
+            .write(|w| unsafe { w.bits((ps << 22) | (div << 18) | (0b100 << 4)) });
+        while lcd.sr.read().bits() & FCRSF == 0 {}
+
+        let vsel = match config.voltage_source {
+            VoltageSource::Internal => 0,
+            VoltageSource::External => VSEL,
+        };
+        lcd.cr
+            .write(|w| unsafe { w.bits((bias << 5) | (duty << 2) | vsel) });
+        lcd.cr.modify(|r, w| unsafe { w.bits(r.bits() | LCDEN) });
+        while lcd.sr.read().bits() & ENS == 0 {}
+        if vsel == 0 {
+            // the step-up converter is ready
+            while lcd.sr.read().bits() & RDY == 0 {}
+        }
+
+        Lcd { lcd, coms }
+    }
+
+    /// Blinks segments
+    pub fn set_blink(&mut self, blink: Blink, frequency: BlinkFrequency) {
+        let blink = match blink {
+            Blink::Off => 0b00,
+            Blink::Seg0Com0 => 0b01,
+            Blink::Seg0 => 0b10,
+            Blink::All => 0b11,
+        };
+        // BLINK, BLINKF
+        self.modify_fcr(
+            (0b11 << 16) | (0b111 << 13),
+            (blink << 16) | ((frequency as u32) << 13),
+        );
+    }
+
+    /// Sets the contrast, the maximum VLCD voltage level, from 0 (2.60 V) to 7 (3.51 V) (CC)
+    ///
+    /// With an external supply, this has no effect on the voltage.
+    pub fn set_contrast(&mut self, level: u8) {
+        assert!(level <= 7);
+        self.modify_fcr(0b111 << 10, (level as u32) << 10);
+    }
+
+    /// Inserts `phases`, from 0 to 7, with the segments unpowered between the frames (DEAD)
+    ///
+    /// This lowers the contrast without changing VLCD.
+    pub fn set_dead_time(&mut self, phases: u8) {
+        assert!(phases <= 7);
+        self.modify_fcr(0b111 << 7, (phases as u32) << 7);
+    }
+
+    /// Sets how long the low resistance divider drives each phase, from 0 to 7 pulses of the
+    /// prescaled clock (PON)
+    ///
+    /// Longer pulses improve the contrast with large glasses, and increase the consumption.
+    pub fn set_pulse_on_duration(&mut self, pulses: u8) {
+        assert!(pulses <= 7);
+        self.modify_fcr(0b111 << 4, (pulses as u32) << 4);
+    }
+
+    /// Updates FCR, which is synchronized to the LCD clock domain
+    fn modify_fcr(&mut self, mask: u32, bits: u32) {
+        while self.lcd.sr.read().bits() & FCRSF == 0 {}
+        self.lcd
+            .fcr
+            .modify(|r, w| unsafe { w.bits((r.bits() & !mask) | bits) });
+        while self.lcd.sr.read().bits() & FCRSF == 0 {}
+    }
+
+    /// Returns the display RAM word address
+    fn ram(&self, index: usize) -> *mut u32 {
+        // RAM0 to RAM15 follow each other from offset 0x14
+        unsafe { (&self.lcd.cr as *const _ as *mut u32).add(5 + index) }
+    }
+
+    /// Sets the segments of a common line in the display RAM, SEG0 in bit 0 up to SEG43
+    ///
+    /// This waits for the previous update to be done, and the segments are only displayed on
+    /// the next `update`.
+    pub fn write_com(&mut self, com: u8, segments: u64) {
+        assert!((com as u32) < self.coms);
+        // the RAM is write protected until the update request is served
+        while self.lcd.sr.read().bits() & UDR != 0 {}
+
+        let index = 2 * com as usize;
+        // NOTE(write_volatile) the display RAM isn't accessible through the svd2rust API
+        unsafe {
+            ptr::write_volatile(self.ram(index), segments as u32);
+            ptr::write_volatile(self.ram(index + 1), (segments >> 32) as u32 & 0xFFF);
+        }
+    }
+
+    /// Turns a single segment on or off in the display RAM
+    ///
+    /// See `write_com`.
+    pub fn set_segment(&mut self, com: u8, segment: u8, on: bool) {
+        assert!((com as u32) < self.coms && segment < 44);
+        while self.lcd.sr.read().bits() & UDR != 0 {}
+
+        let index = 2 * com as usize + (segment as usize / 32);
+        let bit = 1 << (segment % 32);
+        // NOTE(read_volatile, write_volatile) see `write_com`
+        unsafe {
+            let word = ptr::read_volatile(self.ram(index));
+            ptr::write_volatile(self.ram(index), if on { word | bit } else { word & !bit });
+        }
+    }
+
+    /// Blanks the display RAM
+    ///
+    /// See `write_com`.
+    pub fn clear(&mut self) {
+        for com in 0..self.coms as u8 {
+            self.write_com(com, 0);
+        }
+    }
+
+    /// Displays `text` on the characters of `glass`, and updates the glass
+    ///
+    /// A `.` following a character lights the decimal point of that character. The characters
+    /// beyond the text are blanked, and the characters missing from the font too. The segments
+    /// not used by the characters are left as they are.
+    pub fn display_str(&mut self, glass: &Glass, text: &str) {
+        let mut mask = [0u64; 8];
+        let mut value = [0u64; 8];
+
+        let mut chars = text.chars().peekable();
+        for digit in glass.digits {
+            let mut segments = chars.next().map(font::segments14).unwrap_or(0);
+            if chars.peek() == Some(&'.') {
+                chars.next();
+                segments |= font::DP;
+            }
+            for (bit, segment) in digit.iter().enumerate() {
+                if let Some(Segment { com, seg }) = *segment {
+                    let com = com as usize;
+                    mask[com] |= 1 << seg;
+                    if segments & (1 << bit) != 0 {
+                        value[com] |= 1 << seg;
+                    }
+                }
+            }
+        }
+
+        for com in 0..self.coms as u8 {
+            let i = com as usize;
+            if mask[i] != 0 {
+                let segments = self.read_com(com);
+                self.write_com(com, (segments & !mask[i]) | value[i]);
+            }
+        }
+        self.update();
+    }
+
+    /// Returns the segments of a common line in the display RAM
+    fn read_com(&self, com: u8) -> u64 {
+        let index = 2 * com as usize;
+        // NOTE(read_volatile) see `write_com`
+        unsafe {
+            ptr::read_volatile(self.ram(index)) as u64
+                | (ptr::read_volatile(self.ram(index + 1)) as u64) << 32
+        }
+    }
+
+    /// Requests the display RAM to be transferred to the glass, at the start of the next frame
+    ///
+    /// The RAM can't be written until the update is done, see `is_update_done`.
+    pub fn update(&mut self) {
+        self.lcd.clr.write(|w| unsafe { w.bits(UDDC) });
+        self.lcd.sr.modify(|r, w| unsafe { w.bits(r.bits() | UDR) });
+    }
+
+    /// Returns true once the last update is done
+    pub fn is_update_done(&self) -> bool {
+        self.lcd.sr.read().bits() & UDD != 0
+    }
+
+    /// Stops driving the glass at the end of the frame, and releases the peripheral
+    pub fn release(self) -> LCD {
+        self.lcd
+            .cr
+            .modify(|r, w| unsafe { w.bits(r.bits() & !LCDEN) });
+        while self.lcd.sr.read().bits() & ENS != 0 {}
+        self.lcd
+    }
+}
+
+/// Segment of the glass, driven by a common and a segment line
+#[derive(Clone, Copy)]
+pub struct Segment {
+    pub com: u8,
+    pub seg: u8,
+}
+
+impl Segment {
+    pub const fn new(com: u8, seg: u8) -> Self {
+        Segment { com, seg }
+    }
+}
+
+/// Segments of a character, indexed by the bits of `font::segments14`, `None` for the segments the
+/// glass doesn't have
+///
+/// A 7-segment character maps `A` to `F`, `G1` as its middle segment, and `DP`.
+pub type Digit = [Option<Segment>; 15];
+
+/// Layout of the characters of a glass, from left to right
+pub struct Glass<'a> {
+    pub digits: &'a [Digit],
+}
+
+/// 14-segment font
+pub mod font {
+    // 14-segment character bits
+    //
+    //  ---A---
+    // |\  |  /|
+    // F H  I J B
+    // |  \|/  |
+    //  -G1 G2-
+    // |  /|\  |
+    // E K  L M C
+    // |/  |  \|
+    //  ---D---  DP
+    pub const A: u16 = 1 << 0;
+    pub const B: u16 = 1 << 1;
+    pub const C: u16 = 1 << 2;
+    pub const D: u16 = 1 << 3;
+    pub const E: u16 = 1 << 4;
+    pub const F: u16 = 1 << 5;
+    pub const G1: u16 = 1 << 6;
+    pub const G2: u16 = 1 << 7;
+    pub const H: u16 = 1 << 8;
+    pub const I: u16 = 1 << 9;
+    pub const J: u16 = 1 << 10;
+    pub const K: u16 = 1 << 11;
+    pub const L: u16 = 1 << 12;
+    pub const M: u16 = 1 << 13;
+    pub const DP: u16 = 1 << 14;
+
+    /// Returns the 14-segment representation of a character, 0 if it isn't in the font
+    ///
+    /// The font has the digits, the letters, upper case for both cases, and a few symbols.
+    pub fn segments14(c: char) -> u16 {
+        match c.to_ascii_uppercase() {
+            '0' => A | B | C | D | E | F | J | K,
+            '1' => B | C | J,
+            '2' => A | B | D | E | G1 | G2,
+            '3' => A | B | C | D | G2,
+            '4' => B | C | F | G1 | G2,
+            '5' => A | C | D | F | G1 | G2,
+            '6' => A | C | D | E | F | G1 | G2,
+            '7' => A | B | C,
+            '8' => A | B | C | D | E | F | G1 | G2,
+            '9' => A | B | C | D | F | G1 | G2,
+            'A' => A | B | C | E | F | G1 | G2,
+            'B' => A | B | C | D | G2 | I | L,
+            'C' => A | D | E | F,
+            'D' => A | B | C | D | I | L,
+            'E' => A | D | E | F | G1,
+            'F' => A | E | F | G1,
+            'G' => A | C | D | E | F | G2,
+            'H' => B | C | E | F | G1 | G2,
+            'I' => A | D | I | L,
+            'J' => B | C | D | E,
+            'K' => E | F | G1 | J | M,
+            'L' => D | E | F,
+            'M' => B | C | E | F | H | J,
+            'N' => B | C | E | F | H | M,
+            'O' => A | B | C | D | E | F,
+            'P' => A | B | E | F | G1 | G2,
+            'Q' => A | B | C | D | E | F | M,
+            'R' => A | B | E | F | G1 | G2 | M,
+            'S' => A | C | D | G2 | H,
+            'T' => A | I | L,
+            'U' => B | C | D | E | F,
+            'V' => E | F | J | K,
+            'W' => B | C | E | F | K | M,
+            'X' => H | J | K | M,
+            'Y' => H | J | L,
+            'Z' => A | D | J | K,
+            '-' => G1 | G2,
+            '_' => D,
+            '+' => G1 | G2 | I | L,
+            '*' => G1 | G2 | H | I | J | K | L | M,
+            '/' => J | K,
+            '\\' => H | M,
+            '=' => D | G1 | G2,
+            _ => 0,
+        }
+    }
+}
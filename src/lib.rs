@@ -4,11 +4,40 @@
 
 pub use stm32l0x3;
 
+pub mod adc;
+#[cfg(feature = "aes")]
+pub mod aes;
+pub mod buffered_serial;
+pub mod capture;
+pub mod comp;
+pub mod counter;
+pub mod crc;
+pub mod dac;
+pub mod dbgmcu;
+pub mod dma;
 pub mod exti;
+pub mod firewall;
 pub mod flash;
 pub mod gpio;
 pub mod i2c;
+pub mod lcd;
+pub mod lptim;
 pub mod lpusart;
+pub mod opm;
 pub mod prelude;
+pub mod pwm;
+pub mod pwr;
+pub mod qei;
 pub mod rcc;
+pub mod rng;
+pub mod rtc;
+pub mod serial;
+pub mod signature;
+pub mod spi;
+pub mod sync;
+pub mod syscfg;
 pub mod time;
+pub mod timer;
+pub mod tsc;
+pub mod usb;
+pub mod watchdog;
@@ -0,0 +1,617 @@
+//! Low Power Timer (LPTIM)
+//!
+//! Clocked by the LSE, the LSI or the HSI16, LPTIM1 keeps running in Stop mode, and its
+//! interrupts can wake the core up.
+
+use core::cell::Cell;
+
+use embedded_hal::timer::{CountDown, Periodic};
+use embedded_hal::{Direction, PwmPin, Qei};
+use nb;
+use stm32l0x3::{LPTIM, RCC};
+use void::Void;
+
+use crate::exti::{ExtiTrigger, Internal, InternalLine};
+use crate::gpio::gpioa::PA14;
+use crate::gpio::gpiob::{PB2, PB5, PB7};
+use crate::gpio::gpioc::{PC0, PC1, PC2};
+use crate::gpio::{AF0, AF1, AF2};
+use crate::rcc::{Clocks, LptimClock, APB1, CCIPR, HSI, LSE, LSI};
+use crate::time::{Hertz, MicroSeconds};
+use crate::timer::Timeout;
+
+/// LPTIM kernel clock
+pub enum ClockSource {
+    /// APB1 clock, stopped in Stop mode
+    Apb,
+    /// LSI oscillator, enabled with `Rcc.csr.enable_lsi`
+    Lsi,
+    /// HSI16 oscillator, enabled by the driver and kept running in Stop mode
+    Hsi16,
+    /// LSE oscillator, enabled with `Rcc.csr.enable_lse`
+    Lse,
+}
+
+/// LPTIM1 output pin -- DO NOT IMPLEMENT THIS TRAIT
+pub unsafe trait OutPin {}
+
+unsafe impl OutPin for PA14<AF1> {}
+unsafe impl OutPin for PB2<AF2> {}
+unsafe impl OutPin for PC1<AF0> {}
+
+/// LPTIM1 input 1 pin -- DO NOT IMPLEMENT THIS TRAIT
+pub unsafe trait In1Pin {}
+
+unsafe impl In1Pin for PB5<AF2> {}
+unsafe impl In1Pin for PC0<AF0> {}
+
+/// LPTIM1 input 2 pin -- DO NOT IMPLEMENT THIS TRAIT
+pub unsafe trait In2Pin {}
+
+unsafe impl In2Pin for PB7<AF2> {}
+unsafe impl In2Pin for PC2<AF0> {}
+
+/// PWM output polarity
+pub enum Polarity {
+    /// The output is high during the duty cycle
+    ActiveHigh,
+    /// The output is low during the duty cycle
+    ActiveLow,
+}
+
+/// Counted input edges
+pub enum Edge {
+    Rising,
+    Falling,
+    Both,
+}
+
+/// Digital input filter, in consecutive samples at the kernel clock
+pub enum Filter {
+    None,
+    Samples2,
+    Samples4,
+    Samples8,
+}
+
+/// Pulse counter input configuration
+pub struct CounterConfig {
+    edge: Edge,
+    filter: Filter,
+}
+
+impl Default for CounterConfig {
+    fn default() -> CounterConfig {
+        CounterConfig {
+            edge: Edge::Rising,
+            filter: Filter::None,
+        }
+    }
+}
+
+impl CounterConfig {
+    pub fn edge(mut self, edge: Edge) -> Self {
+        self.edge = edge;
+        self
+    }
+
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+}
+
+/// Interrupt events
+pub enum Event {
+    /// Timer timed out / count down ended
+    TimeOut,
+}
+
+/// Enables and resets LPTIM1, clocked by `clock`, and returns the kernel clock frequency
+fn enable(clock: &ClockSource, clocks: &Clocks, ccipr: &mut CCIPR, apb1: &mut APB1) -> Hertz {
+    let (source, freq) = match clock {
+        ClockSource::Apb => (LptimClock::ApbClock, clocks.pclk1()),
+        ClockSource::Lsi => (LptimClock::LSIClock, Hertz(LSI)),
+        ClockSource::Hsi16 => {
+            // NOTE(unsafe) atomic read-modify-write of the HSI16 enable bits
+            cortex_m::interrupt::free(|_| unsafe {
+                let rcc = &(*RCC::ptr());
+                rcc.cr
+                    .modify(|_, w| w.hsi16on().set_bit().hsi16keron().set_bit());
+                while rcc.cr.read().hsi16rdyf().bit_is_clear() {}
+            });
+            (LptimClock::HSI16Clock, Hertz(HSI))
+        }
+        ClockSource::Lse => (LptimClock::LSEClock, Hertz(LSE)),
+    };
+    ccipr.set_lptim_clock(source);
+
+    apb1.enr().modify(|_, w| w.lptim1en().set_bit());
+    apb1.rstr().modify(|_, w| w.lptim1rst().set_bit());
+    apb1.rstr().modify(|_, w| w.lptim1rst().clear_bit());
+
+    freq
+}
+
+/// Returns the PRESC bits and auto-reload value counting `ticks` of the kernel clock
+fn presc_arr(ticks: u64) -> (u8, u16) {
+    let ticks = ticks.max(2).min(128 << 16);
+    let mut presc = 0;
+    while ticks > (1 << (16 + presc)) {
+        presc += 1;
+    }
+    let arr = (ticks >> presc).max(2) - 1;
+    (presc as u8, arr as u16)
+}
+
+/// Writes ARR, which is only possible while the timer is enabled
+fn set_arr(lptim: &LPTIM, arr: u16) {
+    // the write takes a few kernel clock cycles
+    lptim.arr.write(|w| unsafe { w.bits(arr as u32) });
+    while lptim.isr.read().arrok().bit_is_clear() {}
+    lptim.icr.write(|w| w.arrokcf().set_bit());
+}
+
+/// Writes CMP, which is only possible while the timer is enabled
+fn set_cmp(lptim: &LPTIM, cmp: u16) {
+    // the write takes a few kernel clock cycles
+    lptim.cmp.write(|w| unsafe { w.bits(cmp as u32) });
+    while lptim.isr.read().cmpok().bit_is_clear() {}
+    lptim.icr.write(|w| w.cmpokcf().set_bit());
+}
+
+/// Reads CNT
+fn read_count(lptim: &LPTIM) -> u16 {
+    // the counter runs asynchronously to the APB clock, so it's only reliable when two
+    // consecutive reads match
+    loop {
+        let count = lptim.cnt.read().bits();
+        if lptim.cnt.read().bits() == count {
+            return count as u16;
+        }
+    }
+}
+
+/// Low power count down timer
+pub struct LpTimer {
+    lptim: LPTIM,
+    clk: Hertz,
+}
+
+impl LpTimer {
+    /// Configures LPTIM1 as a periodic count down timer clocked by `clock`, and starts it
+    pub fn lptim1<T>(
+        lptim: LPTIM,
+        clock: ClockSource,
+        timeout: T,
+        clocks: &Clocks,
+        ccipr: &mut CCIPR,
+        apb1: &mut APB1,
+    ) -> Self
+    where
+        T: Into<Timeout>,
+    {
+        let clk = enable(&clock, clocks, ccipr, apb1);
+
+        let mut timer = LpTimer { lptim, clk };
+        timer.start(timeout);
+        timer
+    }
+
+    /// Returns the frequency of the timer kernel clock
+    pub fn clock(&self) -> Hertz {
+        self.clk
+    }
+
+    /// Starts listening for an `event`
+    ///
+    /// The interrupts can only be changed while the timer is disabled, so this restarts the
+    /// count.
+    pub fn listen(&mut self, event: Event) {
+        match event {
+            Event::TimeOut => self.set_interrupt(true),
+        }
+    }
+
+    /// Stops listening for an `event`
+    ///
+    /// The interrupts can only be changed while the timer is disabled, so this restarts the
+    /// count.
+    pub fn unlisten(&mut self, event: Event) {
+        match event {
+            Event::TimeOut => self.set_interrupt(false),
+        }
+    }
+
+    fn set_interrupt(&mut self, enabled: bool) {
+        let arr = self.lptim.arr.read().bits() as u16;
+        self.lptim.cr.reset();
+        self.lptim.ier.modify(|_, w| w.arrmie().bit(enabled));
+        self.resume(arr);
+    }
+
+    /// Clears the timeout (auto-reload match) interrupt flag
+    pub fn clear_timeout(&mut self) {
+        self.lptim.icr.write(|w| w.arrmcf().set_bit());
+    }
+
+    /// Sleeps for `timeout`, and returns the time actually slept
+    ///
+    /// The timer is armed for a single count, its EXTI line is unmasked, and `stop` is called to
+    /// enter the Stop mode, e.g. with `Pwr::stop`. The LPTIM1 interrupt can stay masked in the
+    /// NVIC with `WakeUp::Event` and SEVONPEND set, as a pending interrupt is then enough to
+    /// wake the core up. If the core is woken up early by another event, the time
+    /// slept so far is returned.
+    ///
+    /// The count down is stopped afterwards, and has to be restarted with `start`.
+    pub fn sleep_for<T, F>(&mut self, timeout: T, exti: &mut Internal, stop: F) -> MicroSeconds
+    where
+        T: Into<Timeout>,
+        F: FnOnce(),
+    {
+        // the configuration and interrupts can only be changed while disabled
+        self.lptim.cr.reset();
+        let (presc, arr) = presc_arr(timeout.into().ticks(self.clk));
+        self.lptim
+            .cfgr
+            .modify(|_, w| unsafe { w.presc().bits(presc) });
+        let arrmie = self.lptim.ier.read().arrmie().bit_is_set();
+        self.lptim.ier.modify(|_, w| w.arrmie().set_bit());
+
+        self.lptim.cr.write(|w| w.enable().set_bit());
+        set_arr(&self.lptim, arr);
+        self.clear_timeout();
+        // LPTIM1 wakes the device up from Stop through its interrupt on EXTI line 29
+        exti.listen(InternalLine::Lptim1, ExtiTrigger::Rising);
+        self.lptim.cr.modify(|_, w| w.sngstrt().set_bit());
+
+        stop();
+
+        let ticks = if self.lptim.isr.read().arrm().bit_is_set() {
+            arr as u64 + 1
+        } else {
+            read_count(&self.lptim) as u64
+        };
+
+        exti.unlisten(InternalLine::Lptim1);
+        self.clear_timeout();
+        self.lptim.cr.reset();
+        self.lptim.ier.modify(|_, w| w.arrmie().bit(arrmie));
+
+        MicroSeconds(((ticks << presc) * 1_000_000 / self.clk.0 as u64) as u32)
+    }
+
+    /// Stops the timer and releases the peripheral
+    pub fn release(self) -> LPTIM {
+        self.lptim.cr.reset();
+        self.lptim.ier.reset();
+        self.lptim
+    }
+
+    /// Enables the timer, loads `arr` and starts counting continuously
+    fn resume(&mut self, arr: u16) {
+        self.lptim.cr.write(|w| w.enable().set_bit());
+        set_arr(&self.lptim, arr);
+        self.clear_timeout();
+        self.lptim.cr.modify(|_, w| w.cntstrt().set_bit());
+    }
+}
+
+impl CountDown for LpTimer {
+    type Time = Timeout;
+
+    fn start<T>(&mut self, timeout: T)
+    where
+        T: Into<Timeout>,
+    {
+        // the configuration can only be changed while disabled
+        self.lptim.cr.reset();
+
+        let (presc, arr) = presc_arr(timeout.into().ticks(self.clk));
+        self.lptim
+            .cfgr
+            .modify(|_, w| unsafe { w.presc().bits(presc) });
+        self.resume(arr);
+    }
+
+    fn wait(&mut self) -> nb::Result<(), Void> {
+        if self.lptim.isr.read().arrm().bit_is_clear() {
+            Err(nb::Error::WouldBlock)
+        } else {
+            self.clear_timeout();
+            Ok(())
+        }
+    }
+}
+
+impl Periodic for LpTimer {}
+
+/// Low power PWM output
+///
+/// Clocked by the LSE or the LSI, the output keeps running in Stop mode.
+pub struct LpPwm<PIN> {
+    lptim: LPTIM,
+    pin: PIN,
+    clk: Hertz,
+}
+
+impl<PIN> LpPwm<PIN>
+where
+    PIN: OutPin,
+{
+    /// Configures LPTIM1 to generate a PWM signal at `freq` on `pin`, initially disabled with a
+    /// 0 duty cycle
+    pub fn lptim1<F>(
+        lptim: LPTIM,
+        pin: PIN,
+        clock: ClockSource,
+        freq: F,
+        clocks: &Clocks,
+        ccipr: &mut CCIPR,
+        apb1: &mut APB1,
+    ) -> Self
+    where
+        F: Into<Hertz>,
+    {
+        let clk = enable(&clock, clocks, ccipr, apb1);
+
+        // the compare and auto-reload values are loaded at the end of the period
+        lptim.cfgr.write(|w| w.preload().set_bit());
+
+        let mut pwm = LpPwm { lptim, pin, clk };
+        pwm.set_frequency(freq);
+        pwm
+    }
+
+    /// Sets the PWM frequency
+    ///
+    /// The maximum duty cycle changes with the frequency, so the duty cycle needs to be set
+    /// again.
+    pub fn set_frequency<F>(&mut self, freq: F)
+    where
+        F: Into<Hertz>,
+    {
+        let (presc, arr) = presc_arr((self.clk.0 / freq.into().0) as u64);
+
+        let running = self.is_enabled();
+        self.lptim.cr.reset();
+        self.lptim
+            .cfgr
+            .modify(|_, w| unsafe { w.presc().bits(presc) });
+        self.lptim.cr.write(|w| w.enable().set_bit());
+        set_arr(&self.lptim, arr);
+        // CMP = ARR for a 0 duty cycle
+        set_cmp(&self.lptim, arr);
+        if running {
+            self.lptim.cr.modify(|_, w| w.cntstrt().set_bit());
+        } else {
+            self.lptim.cr.reset();
+        }
+    }
+
+    /// Returns the PWM frequency
+    pub fn frequency(&self) -> Hertz {
+        let presc = 1 << self.lptim.cfgr.read().presc().bits();
+        let arr = self.lptim.arr.read().bits() + 1;
+        Hertz(self.clk.0 / presc / arr)
+    }
+
+    /// Sets the output polarity
+    pub fn set_polarity(&mut self, polarity: Polarity) {
+        let running = self.is_enabled();
+        // the configuration can only be changed while disabled
+        self.lptim.cr.reset();
+        self.lptim.cfgr.modify(|_, w| match polarity {
+            Polarity::ActiveHigh => w.wavpol().clear_bit(),
+            Polarity::ActiveLow => w.wavpol().set_bit(),
+        });
+        if running {
+            self.enable();
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.lptim.cr.read().enable().bit_is_set()
+    }
+
+    /// Stops the timer and releases the peripheral and the pin
+    pub fn release(self) -> (LPTIM, PIN) {
+        self.lptim.cr.reset();
+        (self.lptim, self.pin)
+    }
+}
+
+impl<PIN> PwmPin for LpPwm<PIN>
+where
+    PIN: OutPin,
+{
+    type Duty = u16;
+
+    fn disable(&mut self) {
+        self.lptim.cr.reset();
+    }
+
+    fn enable(&mut self) {
+        self.lptim.cr.write(|w| w.enable().set_bit());
+        self.lptim.cr.modify(|_, w| w.cntstrt().set_bit());
+    }
+
+    fn get_duty(&self) -> u16 {
+        // the output is active from the CMP match to the end of the period
+        let arr = self.lptim.arr.read().bits() as u16;
+        arr - self.lptim.cmp.read().bits() as u16
+    }
+
+    fn get_max_duty(&self) -> u16 {
+        self.lptim.arr.read().bits() as u16
+    }
+
+    fn set_duty(&mut self, duty: u16) {
+        let arr = self.lptim.arr.read().bits() as u16;
+        let cmp = arr - duty.min(arr);
+
+        if self.is_enabled() {
+            set_cmp(&self.lptim, cmp);
+        } else {
+            // CMP can only be written while enabled, the counter stays stopped
+            self.lptim.cr.write(|w| w.enable().set_bit());
+            set_cmp(&self.lptim, cmp);
+            self.lptim.cr.reset();
+        }
+    }
+}
+
+/// Low power pulse counter
+///
+/// Clocked by the LSE or the LSI, the counter keeps counting in Stop mode.
+pub struct LpCounter<PIN> {
+    lptim: LPTIM,
+    pin: PIN,
+}
+
+impl<PIN> LpCounter<PIN>
+where
+    PIN: In1Pin,
+{
+    /// Configures LPTIM1 to count the edges on `pin`, sampled by the `clock` kernel clock
+    ///
+    /// The input pulses must last at least two kernel clock periods, more with filtering.
+    pub fn lptim1(
+        lptim: LPTIM,
+        pin: PIN,
+        clock: ClockSource,
+        config: CounterConfig,
+        clocks: &Clocks,
+        ccipr: &mut CCIPR,
+        apb1: &mut APB1,
+    ) -> Self {
+        enable(&clock, clocks, ccipr, apb1);
+
+        let ckpol = match config.edge {
+            Edge::Rising => 0b00,
+            Edge::Falling => 0b01,
+            Edge::Both => 0b10,
+        };
+        let ckflt = match config.filter {
+            Filter::None => 0b00,
+            Filter::Samples2 => 0b01,
+            Filter::Samples4 => 0b10,
+            Filter::Samples8 => 0b11,
+        };
+        // COUNTMODE: the counter is incremented by the edges on input 1
+        lptim.cfgr.write(|w| unsafe {
+            w.countmode()
+                .set_bit()
+                .ckpol()
+                .bits(ckpol)
+                .ckflt()
+                .bits(ckflt)
+        });
+
+        let mut counter = LpCounter { lptim, pin };
+        counter.reset();
+        counter
+    }
+
+    /// Returns the number of counted edges, modulo 2^16
+    pub fn count(&self) -> u16 {
+        read_count(&self.lptim)
+    }
+
+    /// Resets the count to zero
+    pub fn reset(&mut self) {
+        // disabling the timer resets the counter
+        self.lptim.cr.reset();
+        self.lptim.cr.write(|w| w.enable().set_bit());
+        set_arr(&self.lptim, 0xFFFF);
+        self.lptim.cr.modify(|_, w| w.cntstrt().set_bit());
+    }
+
+    /// Stops counting and releases the peripheral and the pin
+    pub fn release(self) -> (LPTIM, PIN) {
+        self.lptim.cr.reset();
+        (self.lptim, self.pin)
+    }
+}
+
+/// Low power quadrature encoder interface
+///
+/// Clocked by the LSE or the LSI, the encoder keeps being tracked in Stop mode.
+pub struct LpEncoder<IN1, IN2> {
+    lptim: LPTIM,
+    pins: (IN1, IN2),
+    down: Cell<bool>,
+}
+
+impl<IN1, IN2> LpEncoder<IN1, IN2>
+where
+    IN1: In1Pin,
+    IN2: In2Pin,
+{
+    /// Configures LPTIM1 to count the quadrature signals on input 1 and 2, sampled by the
+    /// `clock` kernel clock
+    ///
+    /// The count wraps around at `max`, and changes on every edge of both inputs. The kernel
+    /// clock must be at least 4 times faster than the input edges.
+    pub fn lptim1(
+        lptim: LPTIM,
+        pins: (IN1, IN2),
+        clock: ClockSource,
+        max: u16,
+        clocks: &Clocks,
+        ccipr: &mut CCIPR,
+        apb1: &mut APB1,
+    ) -> Self {
+        enable(&clock, clocks, ccipr, apb1);
+
+        // ENC with CKPOL = 10: counts on both edges of both inputs
+        lptim
+            .cfgr
+            .write(|w| unsafe { w.enc().set_bit().ckpol().bits(0b10) });
+        lptim.cr.write(|w| w.enable().set_bit());
+        set_arr(&lptim, max.max(1));
+        lptim.cr.modify(|_, w| w.cntstrt().set_bit());
+
+        LpEncoder {
+            lptim,
+            pins,
+            down: Cell::new(false),
+        }
+    }
+
+    /// Stops the timer and releases the peripheral and the pins
+    pub fn release(self) -> (LPTIM, (IN1, IN2)) {
+        self.lptim.cr.reset();
+        (self.lptim, self.pins)
+    }
+}
+
+impl<IN1, IN2> Qei for LpEncoder<IN1, IN2> {
+    type Count = u16;
+
+    fn count(&self) -> u16 {
+        read_count(&self.lptim)
+    }
+
+    /// Returns the direction of the last count
+    ///
+    /// The timer only flags direction changes, so if the direction changed back and forth since
+    /// the last call, the previous direction is returned.
+    fn direction(&self) -> Direction {
+        let isr = self.lptim.isr.read();
+        let up = isr.up().bit_is_set();
+        let down = isr.down().bit_is_set();
+        if up != down {
+            self.down.set(down);
+        }
+        self.lptim
+            .icr
+            .write(|w| w.upcf().bit(up).downcf().bit(down));
+
+        if self.down.get() {
+            Direction::Downcounting
+        } else {
+            Direction::Upcounting
+        }
+    }
+}
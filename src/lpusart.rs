@@ -1,8 +1,15 @@
+use crate::buffered_serial::BufferedUart;
+use crate::dma::{self, DmaRx, DmaTx};
 use crate::gpio::gpioa::{PA13, PA14, PA2, PA3};
 use crate::gpio::gpiob::{PB10, PB11};
 use crate::gpio::gpioc::{PC0, PC1, PC10, PC11, PC4, PC5};
 use crate::gpio::{AF0, AF2, AF4, AF6, AF7};
 use crate::rcc::{Clocks, LpUsartClock, APB1, CCIPR};
+use core::convert::Infallible;
+
+use cortex_m::interrupt;
+use embedded_hal::serial;
+use nb;
 use stm32l0x3::LPUSART1;
 
 pub trait LpUsartExt {
@@ -123,6 +130,227 @@ where
     }
 }
 
+impl<TX, RX> serial::Read<u8> for LpUsart<TX, RX>
+where
+    TX: LpUsartTxPin,
+    RX: LpUsartRxPin,
+{
+    type Error = Infallible;
+
+    fn read(&mut self) -> nb::Result<u8, Infallible> {
+        self.get_received_byte().ok_or(nb::Error::WouldBlock)
+    }
+}
+
+impl<TX, RX> serial::Write<u8> for LpUsart<TX, RX>
+where
+    TX: LpUsartTxPin,
+    RX: LpUsartRxPin,
+{
+    type Error = Infallible;
+
+    fn write(&mut self, byte: u8) -> nb::Result<(), Infallible> {
+        if self.is_transmitting() {
+            Err(nb::Error::WouldBlock)
+        } else {
+            self.transmit_byte(byte);
+            Ok(())
+        }
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Infallible> {
+        if unsafe { (*LPUSART1::ptr()).isr.read().tc().bit_is_set() } {
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+}
+
+impl<TX, RX> embedded_io::ErrorType for LpUsart<TX, RX>
+where
+    TX: LpUsartTxPin,
+    RX: LpUsartRxPin,
+{
+    type Error = Infallible;
+}
+
+impl<TX, RX> embedded_io::Read for LpUsart<TX, RX>
+where
+    TX: LpUsartTxPin,
+    RX: LpUsartRxPin,
+{
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Infallible> {
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+
+        buffer[0] = nb::block!(serial::Read::read(self))?;
+
+        let mut count = 1;
+        while count < buffer.len() {
+            match self.get_received_byte() {
+                Some(byte) => buffer[count] = byte,
+                None => break,
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+impl<TX, RX> embedded_io::ReadReady for LpUsart<TX, RX>
+where
+    TX: LpUsartTxPin,
+    RX: LpUsartRxPin,
+{
+    fn read_ready(&mut self) -> Result<bool, Infallible> {
+        Ok(unsafe { (*LPUSART1::ptr()).isr.read().rxne().bit_is_set() })
+    }
+}
+
+impl<TX, RX> embedded_io::Write for LpUsart<TX, RX>
+where
+    TX: LpUsartTxPin,
+    RX: LpUsartRxPin,
+{
+    fn write(&mut self, bytes: &[u8]) -> Result<usize, Infallible> {
+        if bytes.is_empty() {
+            return Ok(0);
+        }
+
+        nb::block!(serial::Write::write(self, bytes[0]))?;
+
+        let mut count = 1;
+        while count < bytes.len() && !self.is_transmitting() {
+            self.transmit_byte(bytes[count]);
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> Result<(), Infallible> {
+        nb::block!(serial::Write::flush(self))
+    }
+}
+
+impl<TX, RX> embedded_io::WriteReady for LpUsart<TX, RX>
+where
+    TX: LpUsartTxPin,
+    RX: LpUsartRxPin,
+{
+    fn write_ready(&mut self) -> Result<bool, Infallible> {
+        Ok(!self.is_transmitting())
+    }
+}
+
+impl<TX, RX> BufferedUart for LpUsart<TX, RX>
+where
+    TX: LpUsartTxPin,
+    RX: LpUsartRxPin,
+{
+    fn read_byte(&mut self) -> Option<u8> {
+        self.get_received_byte()
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        self.transmit_byte(byte);
+    }
+
+    fn is_tx_empty(&self) -> bool {
+        !self.is_transmitting()
+    }
+
+    fn enable_rx_interrupt(&mut self) {
+        self.enable_rx_interrupt();
+    }
+
+    fn disable_tx_interrupt(&mut self) {
+        self.disable_tx_interrupt();
+    }
+
+    fn listen_tx() {
+        // NOTE(unsafe) the read-modify-write is made atomic by the critical section
+        interrupt::free(|_| unsafe {
+            (*LPUSART1::ptr()).cr1.modify(|_, w| w.txeie().set_bit())
+        });
+    }
+
+    fn is_listening_tx() -> bool {
+        unsafe { (*LPUSART1::ptr()).cr1.read().txeie().bit_is_set() }
+    }
+
+    fn is_tx_complete() -> bool {
+        unsafe { (*LPUSART1::ptr()).isr.read().tc().bit_is_set() }
+    }
+}
+
+macro_rules! dma {
+    ([$($TXCH:ident: $TXREQ:ident),+], [$($RXCH:ident: $RXREQ:ident),+]) => {
+        $(
+            unsafe impl<TX, RX> DmaTx<dma::$TXCH> for LpUsart<TX, RX>
+            where
+                TX: LpUsartTxPin,
+                RX: LpUsartRxPin,
+            {
+                type Word = u8;
+
+                const REQUEST: dma::$TXREQ = dma::$TXREQ::Lpuart1Tx;
+
+                fn tx_address(&self) -> u32 {
+                    unsafe { &(*LPUSART1::ptr()).tdr as *const _ as u32 }
+                }
+
+                fn enable_tx_dma(&mut self) {
+                    // NOTE(unsafe) the read-modify-write is made atomic by the critical section
+                    interrupt::free(|_| unsafe {
+                        (*LPUSART1::ptr()).cr3.modify(|_, w| w.dmat().set_bit())
+                    });
+                }
+
+                fn disable_tx_dma(&mut self) {
+                    interrupt::free(|_| unsafe {
+                        (*LPUSART1::ptr()).cr3.modify(|_, w| w.dmat().clear_bit())
+                    });
+                }
+            }
+        )+
+
+        $(
+            unsafe impl<TX, RX> DmaRx<dma::$RXCH> for LpUsart<TX, RX>
+            where
+                TX: LpUsartTxPin,
+                RX: LpUsartRxPin,
+            {
+                type Word = u8;
+
+                const REQUEST: dma::$RXREQ = dma::$RXREQ::Lpuart1Rx;
+
+                fn rx_address(&self) -> u32 {
+                    unsafe { &(*LPUSART1::ptr()).rdr as *const _ as u32 }
+                }
+
+                fn enable_rx_dma(&mut self) {
+                    interrupt::free(|_| unsafe {
+                        (*LPUSART1::ptr()).cr3.modify(|_, w| w.dmar().set_bit())
+                    });
+                }
+
+                fn disable_rx_dma(&mut self) {
+                    interrupt::free(|_| unsafe {
+                        (*LPUSART1::ptr()).cr3.modify(|_, w| w.dmar().clear_bit())
+                    });
+                }
+            }
+        )+
+    }
+}
+
+dma! {
+    [C2: C2Request, C7: C7Request],
+    [C3: C3Request, C6: C6Request]
+}
+
 pub enum WordLength {
     Word8Bits,
     Word9Bits,
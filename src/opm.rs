@@ -0,0 +1,172 @@
+//! One-pulse mode on the general purpose timers
+//!
+//! The pulse is output on channel 1, after a delay, when the timer is triggered by software or
+//! by an edge on the channel 2 input.
+
+use stm32l0x3::{TIM2, TIM21, TIM22};
+
+use crate::pwm::{ChannelPin, C1, C2};
+use crate::rcc::{Clocks, APB1, APB2};
+use crate::time::Hertz;
+pub use crate::timer::Edge;
+
+/// Timer operating in one-pulse mode
+pub struct OnePulse<TIM, PINS> {
+    tim: TIM,
+    pins: PINS,
+    clk: Hertz,
+}
+
+macro_rules! hal {
+    ($(
+        $TIMX:ident: (
+            $timX:ident,
+            $timX_triggered:ident,
+            $APB:ident,
+            $timXen:ident,
+            $timXrst:ident,
+            $pclkX:ident,
+            $ppreX:ident
+        ),
+    )+) => {
+        $(
+            impl<OUT> OnePulse<$TIMX, OUT>
+            where
+                OUT: ChannelPin<$TIMX, C1>,
+            {
+                /// Configures the timer to output a pulse on `out` each time it is triggered by
+                /// software
+                ///
+                /// The delay and width of the pulse are counted in ticks at `freq`.
+                pub fn $timX<F>(
+                    tim: $TIMX,
+                    out: OUT,
+                    freq: F,
+                    clocks: &Clocks,
+                    apb: &mut $APB,
+                ) -> Self
+                where
+                    F: Into<Hertz>,
+                {
+                    apb.enr().modify(|_, w| w.$timXen().set_bit());
+                    apb.rstr().modify(|_, w| w.$timXrst().set_bit());
+                    apb.rstr().modify(|_, w| w.$timXrst().clear_bit());
+
+                    // the timer clock is doubled when the APB clock is divided
+                    let clk = if clocks.$ppreX() == 1 {
+                        clocks.$pclkX()
+                    } else {
+                        Hertz(clocks.$pclkX().0 * 2)
+                    };
+
+                    let psc = (clk.0 / freq.into().0).max(1) - 1;
+                    assert!(psc <= 0xFFFF);
+                    tim.psc.write(|w| unsafe { w.psc().bits(psc as u16) });
+
+                    // PWM mode 2 on channel 1: the output goes active once the counter
+                    // reaches the delay in CCR1, until the update event at ARR
+                    tim.ccmr1_output.write(|w| unsafe { w.bits(0b0111_1000) });
+                    tim.ccer.write(|w| unsafe { w.bits(1) });
+                    tim.cr1.modify(|_, w| w.opm().set_bit());
+
+                    let mut opm = OnePulse { tim, pins: out, clk };
+                    opm.set_pulse(1, 1);
+                    opm
+                }
+            }
+
+            impl<OUT, TRIG> OnePulse<$TIMX, (OUT, TRIG)>
+            where
+                OUT: ChannelPin<$TIMX, C1>,
+                TRIG: ChannelPin<$TIMX, C2>,
+            {
+                /// Configures the timer to output a pulse on `out` each time `edge` is detected
+                /// on the `trig` input
+                ///
+                /// The delay and width of the pulse are counted in ticks at `freq`. The timer
+                /// can't be triggered again before the end of the pulse.
+                pub fn $timX_triggered<F>(
+                    tim: $TIMX,
+                    (out, trig): (OUT, TRIG),
+                    edge: Edge,
+                    freq: F,
+                    clocks: &Clocks,
+                    apb: &mut $APB,
+                ) -> Self
+                where
+                    F: Into<Hertz>,
+                {
+                    let OnePulse { tim, pins: out, clk } =
+                        OnePulse::<$TIMX, OUT>::$timX(tim, out, freq, clocks, apb);
+
+                    // CC2S = 01, IC2 mapped on TI2
+                    tim.ccmr1_output.modify(|r, w| unsafe { w.bits(r.bits() | (0b01 << 8)) });
+                    // CC2P, CC2NP
+                    let ccer = match edge {
+                        Edge::Rising => 0,
+                        Edge::Falling => 1 << 5,
+                        Edge::Both => (1 << 5) | (1 << 7),
+                    };
+                    tim.ccer.modify(|r, w| unsafe { w.bits(r.bits() | ccer) });
+                    // TS = 110 (TI2FP2), SMS = 110 (trigger mode): the edge starts the counter
+                    tim.smcr.modify(|_, w| unsafe { w.ts().bits(0b110).sms().bits(0b110) });
+
+                    OnePulse {
+                        tim,
+                        pins: (out, trig),
+                        clk,
+                    }
+                }
+            }
+
+            impl<PINS> OnePulse<$TIMX, PINS> {
+                /// Sets the delay from the trigger to the start of the pulse, and the width of
+                /// the pulse, in ticks
+                ///
+                /// The delay must be at least 1 tick, and the sum at most 65536 ticks.
+                pub fn set_pulse(&mut self, delay: u16, width: u16) {
+                    assert!(delay > 0 && width > 0);
+                    // the output is active from CCR1 to ARR included
+                    let arr = delay as u32 + width as u32 - 1;
+                    assert!(arr <= 0xFFFF);
+
+                    self.tim.ccr1.write(|w| unsafe { w.bits(delay as u32) });
+                    self.tim.arr.write(|w| unsafe { w.bits(arr) });
+                    // load the prescaler and compare value without raising the update interrupt
+                    self.tim.cr1.modify(|_, w| w.urs().set_bit());
+                    self.tim.egr.write(|w| w.ug().set_bit());
+                    self.tim.cr1.modify(|_, w| w.urs().clear_bit());
+                }
+
+                /// Returns the tick frequency
+                pub fn frequency(&self) -> Hertz {
+                    Hertz(self.clk.0 / (self.tim.psc.read().bits() + 1))
+                }
+
+                /// Starts a pulse
+                pub fn trigger(&mut self) {
+                    self.tim.cr1.modify(|_, w| w.cen().set_bit());
+                }
+
+                /// Returns true while a pulse, including its delay, is in progress
+                pub fn is_running(&self) -> bool {
+                    self.tim.cr1.read().cen().bit_is_set()
+                }
+
+                /// Stops the timer and releases the peripheral and the pins
+                pub fn release(self) -> ($TIMX, PINS) {
+                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    self.tim.smcr.reset();
+                    self.tim.ccer.reset();
+                    (self.tim, self.pins)
+                }
+            }
+        )+
+    }
+}
+
+hal! {
+    TIM2: (tim2, tim2_triggered, APB1, tim2en, tim2rst, pclk1, ppre1),
+    TIM21: (tim21, tim21_triggered, APB2, tim21en, tim21rst, pclk2, ppre2),
+    TIM22: (tim22, tim22_triggered, APB2, tim22en, tim22rst, pclk2, ppre2),
+}
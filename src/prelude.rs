@@ -1,5 +1,8 @@
 pub use embedded_hal::prelude::*;
 
+pub use crate::dac::DacExt as _stm32l0x3_hal_dac_DacExt;
+pub use crate::dma::DmaExt as _stm32l0x3_hal_dma_DmaExt;
 pub use crate::flash::FlashExt as _stm32l0x3_hal_flash_FlashExt;
 pub use crate::gpio::GpioExt as _stm32l0x3_hal_gpio_GpioExt;
 pub use crate::rcc::RccExt as _stm32l0x3_hal_rcc_RccExt;
+pub use crate::syscfg::SysCfgExt as _stm32l0x3_hal_syscfg_SysCfgExt;
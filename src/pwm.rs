@@ -0,0 +1,350 @@
+//! Pulse Width Modulation (PWM) on the general purpose timers
+
+use core::marker::PhantomData;
+
+use embedded_hal::PwmPin;
+use stm32l0x3::{TIM2, TIM21, TIM22};
+
+use crate::dma::{self, Direction, WordSize};
+use crate::gpio::gpioa::{PA0, PA1, PA15, PA2, PA3, PA5, PA6, PA7};
+use crate::gpio::gpiob::{PB10, PB11, PB13, PB14, PB3, PB4, PB5};
+use crate::gpio::gpioc::{PC6, PC7};
+use crate::gpio::{AF0, AF2, AF4, AF5, AF6};
+use crate::rcc::{Clocks, APB1, APB2};
+use crate::time::Hertz;
+use crate::timer;
+
+/// PWM error
+#[derive(Debug)]
+pub enum Error {
+    /// DMA transfer error
+    Dma,
+    #[doc(hidden)]
+    _Extensible,
+}
+
+/// Channel 1 (type state)
+pub struct C1;
+/// Channel 2 (type state)
+pub struct C2;
+/// Channel 3 (type state)
+pub struct C3;
+/// Channel 4 (type state)
+pub struct C4;
+
+/// Timer channel pin -- DO NOT IMPLEMENT THIS TRAIT
+pub unsafe trait ChannelPin<TIM, CHANNEL> {}
+
+unsafe impl ChannelPin<TIM2, C1> for PA0<AF2> {}
+unsafe impl ChannelPin<TIM2, C1> for PA5<AF5> {}
+unsafe impl ChannelPin<TIM2, C1> for PA15<AF5> {}
+unsafe impl ChannelPin<TIM2, C2> for PA1<AF2> {}
+unsafe impl ChannelPin<TIM2, C2> for PB3<AF2> {}
+unsafe impl ChannelPin<TIM2, C3> for PA2<AF2> {}
+unsafe impl ChannelPin<TIM2, C3> for PB10<AF2> {}
+unsafe impl ChannelPin<TIM2, C4> for PA3<AF2> {}
+unsafe impl ChannelPin<TIM2, C4> for PB11<AF2> {}
+
+unsafe impl ChannelPin<TIM21, C1> for PA2<AF0> {}
+unsafe impl ChannelPin<TIM21, C1> for PB13<AF6> {}
+unsafe impl ChannelPin<TIM21, C2> for PA3<AF0> {}
+unsafe impl ChannelPin<TIM21, C2> for PB14<AF6> {}
+
+unsafe impl ChannelPin<TIM22, C1> for PA6<AF5> {}
+unsafe impl ChannelPin<TIM22, C1> for PB4<AF4> {}
+unsafe impl ChannelPin<TIM22, C1> for PC6<AF0> {}
+unsafe impl ChannelPin<TIM22, C2> for PA7<AF5> {}
+unsafe impl ChannelPin<TIM22, C2> for PB5<AF4> {}
+unsafe impl ChannelPin<TIM22, C2> for PC7<AF0> {}
+
+/// Timer operating in PWM mode
+pub struct Pwm<TIM> {
+    pub(crate) tim: TIM,
+    clk: Hertz,
+}
+
+/// Duty cycles streamed to the PWM channels using DMA
+pub struct DutyStream<TIM, DMA> {
+    pwm: Pwm<TIM>,
+    dma: DMA,
+    duties: &'static [u16],
+}
+
+/// PWM output channel
+pub struct PwmChannel<TIM, CHANNEL, PIN> {
+    pin: PIN,
+    _tim: PhantomData<TIM>,
+    _channel: PhantomData<CHANNEL>,
+}
+
+macro_rules! hal {
+    ($(
+        $TIMX:ident: (
+            $timX:ident,
+            $APB:ident,
+            $timXen:ident,
+            $timXrst:ident,
+            $pclkX:ident,
+            $ppreX:ident,
+            [$($CX:ident: ($chX:ident, $i:expr, $ccmrX:ident, $ccrX:ident),)+]
+        ),
+    )+) => {
+        $(
+            impl Pwm<$TIMX> {
+                /// Configures the timer to generate PWM signals at `freq`
+                ///
+                /// The outputs are then set up with `ch1`, `ch2`, etc.
+                pub fn $timX<F>(tim: $TIMX, freq: F, clocks: &Clocks, apb: &mut $APB) -> Self
+                where
+                    F: Into<Hertz>,
+                {
+                    apb.enr().modify(|_, w| w.$timXen().set_bit());
+                    apb.rstr().modify(|_, w| w.$timXrst().set_bit());
+                    apb.rstr().modify(|_, w| w.$timXrst().clear_bit());
+
+                    // the timer clock is doubled when the APB clock is divided
+                    let clk = if clocks.$ppreX() == 1 {
+                        clocks.$pclkX()
+                    } else {
+                        Hertz(clocks.$pclkX().0 * 2)
+                    };
+
+                    let mut pwm = Pwm { tim, clk };
+                    pwm.set_frequency(freq);
+                    // auto-reload preload, so frequency changes take effect at the next period
+                    pwm.tim.cr1.modify(|_, w| w.arpe().set_bit().cen().set_bit());
+                    pwm
+                }
+
+                /// Sets the PWM frequency
+                ///
+                /// The maximum duty cycle changes with the frequency, so the duty cycles of
+                /// the channels need to be set again.
+                pub fn set_frequency<F>(&mut self, freq: F)
+                where
+                    F: Into<Hertz>,
+                {
+                    let freq = freq.into().0;
+                    assert!(freq > 0);
+                    // ARR stays below 0xFFFF so that a fully on duty cycle (ARR + 1) fits in
+                    // 16 bits
+                    let (psc, arr) = timer::psc_arr(u64::from(self.clk.0 / freq), 0xFFFE);
+
+                    self.tim.psc.write(|w| unsafe { w.psc().bits(psc) });
+                    self.tim.arr.write(|w| unsafe { w.bits(u32::from(arr)) });
+                    // load the prescaler without raising the update interrupt
+                    self.tim.cr1.modify(|_, w| w.urs().set_bit());
+                    self.tim.egr.write(|w| w.ug().set_bit());
+                    self.tim.cr1.modify(|_, w| w.urs().clear_bit());
+                }
+
+                /// Returns the PWM frequency
+                pub fn frequency(&self) -> Hertz {
+                    let psc = self.tim.psc.read().bits() + 1;
+                    let arr = self.tim.arr.read().bits() + 1;
+                    Hertz(self.clk.0 / psc / arr)
+                }
+
+                /// Returns the duty cycle of a fully on output
+                pub fn get_max_duty(&self) -> u16 {
+                    (self.tim.arr.read().bits() + 1) as u16
+                }
+
+                /// Stops the timer and releases the peripheral
+                pub fn release(self) -> $TIMX {
+                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    self.tim
+                }
+            }
+
+            $(
+                impl Pwm<$TIMX> {
+                    /// Configures `pin` as PWM output, initially disabled with a 0 duty cycle
+                    pub fn $chX<PIN>(&mut self, pin: PIN) -> PwmChannel<$TIMX, $CX, PIN>
+                    where
+                        PIN: ChannelPin<$TIMX, $CX>,
+                    {
+                        let offset = 8 * (($i - 1) % 2);
+                        self.tim.$ccrX.write(|w| unsafe { w.bits(0) });
+                        // PWM mode 1 with preload
+                        self.tim.$ccmrX.modify(|r, w| unsafe {
+                            w.bits((r.bits() & !(0xFF << offset)) | (0b0110_1000 << offset))
+                        });
+
+                        PwmChannel {
+                            pin,
+                            _tim: PhantomData,
+                            _channel: PhantomData,
+                        }
+                    }
+                }
+
+                impl<PIN> PwmChannel<$TIMX, $CX, PIN> {
+                    /// Disables the output and releases the pin
+                    pub fn free(mut self) -> PIN {
+                        self.disable();
+                        self.pin
+                    }
+                }
+
+                impl<PIN> PwmPin for PwmChannel<$TIMX, $CX, PIN> {
+                    type Duty = u16;
+
+                    // NOTE(unsafe) the channel only accesses its own CCR register, and the shared
+                    // CCER register atomically
+
+                    fn disable(&mut self) {
+                        let cce = 1 << (4 * ($i - 1));
+                        cortex_m::interrupt::free(|_| unsafe {
+                            (*$TIMX::ptr()).ccer.modify(|r, w| w.bits(r.bits() & !cce))
+                        });
+                    }
+
+                    fn enable(&mut self) {
+                        let cce = 1 << (4 * ($i - 1));
+                        cortex_m::interrupt::free(|_| unsafe {
+                            (*$TIMX::ptr()).ccer.modify(|r, w| w.bits(r.bits() | cce))
+                        });
+                    }
+
+                    fn get_duty(&self) -> u16 {
+                        unsafe { (*$TIMX::ptr()).$ccrX.read().bits() as u16 }
+                    }
+
+                    fn get_max_duty(&self) -> u16 {
+                        unsafe { ((*$TIMX::ptr()).arr.read().bits() + 1) as u16 }
+                    }
+
+                    fn set_duty(&mut self, duty: u16) {
+                        unsafe { (*$TIMX::ptr()).$ccrX.write(|w| w.bits(duty as u32)) }
+                    }
+                }
+            )+
+        )+
+    }
+}
+
+hal! {
+    TIM2: (tim2, APB1, tim2en, tim2rst, pclk1, ppre1, [
+        C1: (ch1, 1, ccmr1_output, ccr1),
+        C2: (ch2, 2, ccmr1_output, ccr2),
+        C3: (ch3, 3, ccmr2_output, ccr3),
+        C4: (ch4, 4, ccmr2_output, ccr4),
+    ]),
+    TIM21: (tim21, APB2, tim21en, tim21rst, pclk2, ppre2, [
+        C1: (ch1, 1, ccmr1_output, ccr1),
+        C2: (ch2, 2, ccmr1_output, ccr2),
+    ]),
+    TIM22: (tim22, APB2, tim22en, tim22rst, pclk2, ppre2, [
+        C1: (ch1, 1, ccmr1_output, ccr1),
+        C2: (ch2, 2, ccmr1_output, ccr2),
+    ]),
+}
+
+impl Pwm<TIM2> {
+    /// Streams `duties` to `channels` consecutive channels starting from channel `first`, using
+    /// a DMA burst at the start of each PWM period
+    ///
+    /// `duties` holds `channels` duty cycles per period, e.g. for a WS2812 bitstream on a single
+    /// channel, or sine-modulated three phase PWM on channels 1 to 3. A circular transfer repeats
+    /// the table until stopped. The channels have to be set up and enabled separately.
+    pub fn stream_duty(
+        self,
+        mut dma: dma::C2,
+        first: u8,
+        channels: u8,
+        duties: &'static [u16],
+        mode: dma::Mode,
+    ) -> DutyStream<TIM2, dma::C2> {
+        assert!(first >= 1 && channels >= 1 && first + channels <= 5);
+        assert!(duties.len() > 0 && duties.len() <= u16::max_value() as usize);
+
+        // DBA = offset of the first CCR register in words, DBL = transfers per burst - 1
+        let dba = (0x34 / 4) + (first - 1);
+        self.tim
+            .dcr
+            .write(|w| unsafe { w.dba().bits(dba).dbl().bits(channels - 1) });
+
+        // TIM2_UP request
+        dma.route(dma::C2Request::Tim2Up);
+        dma.set_peripheral_address(&self.tim.dmar as *const _ as u32);
+        dma.set_memory_address(duties.as_ptr() as u32);
+        dma.set_transfer_length(duties.len() as u16);
+        match mode {
+            dma::Mode::OneShot => dma.start(Direction::MemoryToPeripheral, true, WordSize::Bits16),
+            dma::Mode::Circular => {
+                dma.start_circular(Direction::MemoryToPeripheral, WordSize::Bits16)
+            }
+        }
+
+        self.tim.dier.modify(|_, w| w.ude().set_bit());
+
+        DutyStream {
+            pwm: self,
+            dma,
+            duties,
+        }
+    }
+}
+
+/// Timer, DMA channel and duty cycles released by `DutyStream`
+type Released = (Pwm<TIM2>, dma::C2, &'static [u16]);
+
+impl DutyStream<TIM2, dma::C2> {
+    /// Enables / disables the DMA half transfer and transfer complete interrupts
+    pub fn listen(&mut self, half_complete: bool, complete: bool) {
+        self.dma.set_interrupts(half_complete, complete);
+    }
+
+    /// Returns true once the first half of the duty cycles have been transferred
+    pub fn is_half_complete(&self) -> bool {
+        self.dma.is_half_complete()
+    }
+
+    /// Returns true once all the duty cycles have been transferred
+    pub fn is_complete(&self) -> bool {
+        self.dma.is_complete()
+    }
+
+    /// Clears the half complete flag
+    pub fn clear_half_complete(&mut self) {
+        self.dma.clear_half_complete();
+    }
+
+    /// Clears the complete flag
+    pub fn clear_complete(&mut self) {
+        self.dma.clear_complete();
+    }
+
+    /// Blocks until all the duty cycles have been transferred, then stops the stream
+    ///
+    /// The timer, DMA channel and duty cycles are released on error too.
+    pub fn wait(self) -> Result<Released, (Error, Released)> {
+        let mut error = None;
+        while !self.dma.is_complete() {
+            if self.dma.has_error() {
+                error = Some(Error::Dma);
+                break;
+            }
+        }
+
+        let released = self.stop();
+        match error {
+            None => Ok(released),
+            Some(error) => Err((error, released)),
+        }
+    }
+
+    /// Stops the stream, releasing the timer, DMA channel and duty cycles
+    ///
+    /// The channels keep the last duty cycles transferred.
+    pub fn stop(mut self) -> Released {
+        self.pwm.tim.dier.modify(|_, w| w.ude().clear_bit());
+        self.pwm.tim.dcr.reset();
+        self.dma.set_interrupts(false, false);
+        self.dma.stop();
+        self.dma.clear_flags();
+
+        (self.pwm, self.dma, self.duties)
+    }
+}
@@ -0,0 +1,331 @@
+//! Power control (PWR) and low-power modes
+
+use cortex_m::peripheral::SCB;
+use cortex_m::{asm, interrupt};
+use stm32l0x3::{EXTI, PWR, RCC};
+
+use crate::exti::{ExtiTrigger, Internal, InternalLine};
+use crate::rcc::{Clocks, APB1};
+
+/// Instruction used to enter a low-power mode, and what wakes the core up
+pub enum WakeUp {
+    /// `wfi`: an enabled interrupt
+    Interrupt,
+    /// `wfe`: an event, or a pending interrupt with SEVONPEND set
+    Event,
+}
+
+/// PWR error
+#[derive(Debug)]
+pub enum Error {
+    /// No EXTI line is unmasked, so nothing could wake the device up
+    NoWakeUpSource,
+    #[doc(hidden)]
+    _Extensible,
+}
+
+/// Stop mode configuration
+pub struct StopConfig {
+    low_power_regulator: bool,
+    ultra_low_power: bool,
+    fast_wake_up: bool,
+}
+
+impl Default for StopConfig {
+    fn default() -> StopConfig {
+        StopConfig {
+            low_power_regulator: true,
+            ultra_low_power: false,
+            fast_wake_up: false,
+        }
+    }
+}
+
+impl StopConfig {
+    /// Switches the voltage regulator to its low-power mode, lowering the consumption but
+    /// lengthening the wake up
+    pub fn low_power_regulator(mut self, enabled: bool) -> Self {
+        self.low_power_regulator = enabled;
+        self
+    }
+
+    /// Stops the internal voltage reference (ULP)
+    ///
+    /// This saves a few µA, but the reference then takes up to 3 ms to restart on wake up,
+    /// which the wake up waits for unless `fast_wake_up` is set. The ADC internal channels and
+    /// the comparators using the reference aren't accurate until it's ready, and the BOR and
+    /// PVD are off meanwhile.
+    pub fn ultra_low_power(mut self, enabled: bool) -> Self {
+        self.ultra_low_power = enabled;
+        self
+    }
+
+    /// Doesn't wait for the internal voltage reference to be ready on wake up (FWU), with
+    /// `ultra_low_power`
+    ///
+    /// The wake up then takes a few µs instead of up to 3 ms. The ADC waits for the reference
+    /// before converting an internal channel, and `Comp::is_reference_ready` tells when the
+    /// comparator output is valid.
+    pub fn fast_wake_up(mut self, enabled: bool) -> Self {
+        self.fast_wake_up = enabled;
+        self
+    }
+}
+
+/// Pin waking the device up from the Standby mode on a rising edge
+#[derive(Clone, Copy)]
+pub enum WakeUpPin {
+    /// WKUP1, on PA0
+    Pin1,
+    /// WKUP2, on PC13
+    Pin2,
+    /// WKUP3, on PE6
+    Pin3,
+}
+
+impl WakeUpPin {
+    /// Returns the EWUPx mask in CSR
+    fn mask(self) -> u32 {
+        match self {
+            WakeUpPin::Pin1 => 1 << 8,
+            WakeUpPin::Pin2 => 1 << 9,
+            WakeUpPin::Pin3 => 1 << 10,
+        }
+    }
+}
+
+/// Programmable voltage detector (PVD) threshold, on the falling edge of VDD
+///
+/// The thresholds on the rising edge are about 100 mV higher.
+#[derive(Clone, Copy)]
+pub enum PvdLevel {
+    V1_9,
+    V2_1,
+    V2_3,
+    V2_5,
+    V2_7,
+    V2_9,
+    V3_1,
+    /// The PVD_IN pin (PB7) compared to the internal voltage reference
+    External,
+}
+
+/// Power control
+pub struct Pwr {
+    pwr: PWR,
+}
+
+impl Pwr {
+    pub fn new(pwr: PWR, apb1: &mut APB1) -> Self {
+        apb1.enr().modify(|_, w| w.pwren().set_bit());
+
+        Pwr { pwr }
+    }
+
+    /// Enters the Sleep mode until woken up
+    ///
+    /// Only the core is stopped, the peripherals keep running.
+    pub fn sleep(&mut self, scb: &mut SCB, wake_up: WakeUp) {
+        scb.clear_sleepdeep();
+        self.pwr.cr.modify(|_, w| w.lpsdsr().clear_bit());
+        wait(wake_up);
+    }
+
+    /// Enters the Low-power sleep mode until woken up
+    ///
+    /// The voltage regulator is switched to its low-power mode while the core is stopped, which
+    /// requires the system clock to run at 131.072 kHz at most, e.g. from the MSI range 1. The
+    /// regulator is back in its main mode on wake up.
+    pub fn low_power_sleep(&mut self, scb: &mut SCB, clocks: &Clocks, wake_up: WakeUp) {
+        assert!(clocks.sysclk().0 <= 131_072);
+        scb.clear_sleepdeep();
+        self.pwr.cr.modify(|_, w| w.lpsdsr().set_bit());
+        wait(wake_up);
+        // LPSDSR also selects the regulator mode of the Stop mode
+        self.pwr.cr.modify(|_, w| w.lpsdsr().clear_bit());
+    }
+
+    /// Enters the Stop mode until woken up, and restores the system clock
+    ///
+    /// All the clocks but LSE and LSI are stopped, and the device wakes up on an unmasked EXTI
+    /// line: a GPIO, or an internal line such as the RTC, LPTIM1 or LPUART1. The system clock
+    /// is HSI16 on wake up, and is then switched back to the frozen configuration.
+    pub fn stop(
+        &mut self,
+        scb: &mut SCB,
+        config: &StopConfig,
+        clocks: &Clocks,
+        wake_up: WakeUp,
+    ) -> Result<(), Error> {
+        // NOTE(unsafe) read-only access
+        let exti = unsafe { &*EXTI::ptr() };
+        if exti.imr.read().bits() | exti.emr.read().bits() == 0 {
+            return Err(Error::NoWakeUpSource);
+        }
+
+        // STOPWUCK, HSI16 on wake up
+        // NOTE(unsafe) CFGR is only written by `CFGR::freeze` before the clocks exist
+        unsafe { (*RCC::ptr()).cfgr.modify(|_, w| w.stopwuck().set_bit()) };
+
+        self.pwr.cr.modify(|_, w| {
+            w.pdds()
+                .clear_bit()
+                .lpsdsr()
+                .bit(config.low_power_regulator)
+                .ulp()
+                .bit(config.ultra_low_power)
+                .fwu()
+                .bit(config.fast_wake_up)
+                .cwuf()
+                .set_bit()
+        });
+        while self.pwr.csr.read().wuf().bit_is_set() {}
+
+        scb.set_sleepdeep();
+        wait(wake_up);
+        scb.clear_sleepdeep();
+
+        self.pwr.cr.modify(|_, w| w.lpsdsr().clear_bit());
+        clocks.restore();
+        Ok(())
+    }
+
+    /// Enters the Standby mode, which only ends with a reset
+    ///
+    /// Only the backup domain (RTC, LSE) and LSI, IWDG and the wake up pins keep running, and
+    /// the RAM and registers are lost. The device resets on the rising edge of an enabled wake
+    /// up pin, on an RTC alarm, wake up, tamper or timestamp event, on an IWDG timeout, or on
+    /// NRST. The RTC flags must be cleared beforehand, otherwise the device wakes up at once.
+    pub fn standby(&mut self, scb: &mut SCB) -> ! {
+        self.pwr
+            .cr
+            .modify(|_, w| w.pdds().set_bit().cwuf().set_bit());
+        while self.pwr.csr.read().wuf().bit_is_set() {}
+
+        scb.set_sleepdeep();
+        loop {
+            asm::wfi();
+        }
+    }
+
+    /// Stops the internal voltage reference in the Standby mode (ULP), and doesn't wait for it
+    /// on wake up (FWU)
+    ///
+    /// See `StopConfig::ultra_low_power` and `StopConfig::fast_wake_up`, which are used instead
+    /// in the Stop mode.
+    pub fn set_standby_ultra_low_power(&mut self, ultra_low_power: bool, fast_wake_up: bool) {
+        self.pwr
+            .cr
+            .modify(|_, w| w.ulp().bit(ultra_low_power).fwu().bit(fast_wake_up));
+    }
+
+    /// Wakes the device up from the Standby mode on a rising edge of `pin`
+    ///
+    /// The pin is then used as an input with a pull-down, whatever its GPIO configuration.
+    pub fn enable_wake_up_pin(&mut self, pin: WakeUpPin) {
+        self.pwr
+            .csr
+            .modify(|r, w| unsafe { w.bits(r.bits() | pin.mask()) });
+    }
+
+    /// Gives a wake up pin back to its GPIO configuration
+    pub fn disable_wake_up_pin(&mut self, pin: WakeUpPin) {
+        self.pwr
+            .csr
+            .modify(|r, w| unsafe { w.bits(r.bits() & !pin.mask()) });
+    }
+
+    /// Returns true if the device has been in the Standby mode since the flag was cleared
+    ///
+    /// After a reset, this tells a wake up from Standby apart from a power-on.
+    pub fn is_standby_flag_set(&self) -> bool {
+        self.pwr.csr.read().sbf().bit_is_set()
+    }
+
+    /// Returns true if a wake up event (wake up pin or RTC) occurred since the flag was cleared
+    pub fn is_wake_up_flag_set(&self) -> bool {
+        self.pwr.csr.read().wuf().bit_is_set()
+    }
+
+    /// Clears the standby and wake up flags
+    pub fn clear_flags(&mut self) {
+        self.pwr
+            .cr
+            .modify(|_, w| w.csbf().set_bit().cwuf().set_bit());
+    }
+
+    /// Enables the programmable voltage detector, monitoring VDD
+    pub fn enable_pvd(&mut self, level: PvdLevel) {
+        let pls = match level {
+            PvdLevel::V1_9 => 0b000,
+            PvdLevel::V2_1 => 0b001,
+            PvdLevel::V2_3 => 0b010,
+            PvdLevel::V2_5 => 0b011,
+            PvdLevel::V2_7 => 0b100,
+            PvdLevel::V2_9 => 0b101,
+            PvdLevel::V3_1 => 0b110,
+            PvdLevel::External => 0b111,
+        };
+        self.pwr
+            .cr
+            .modify(|_, w| unsafe { w.pls().bits(pls).pvde().set_bit() });
+    }
+
+    /// Disables the programmable voltage detector
+    pub fn disable_pvd(&mut self) {
+        self.pwr.cr.modify(|_, w| w.pvde().clear_bit());
+    }
+
+    /// Returns true while VDD is below the PVD threshold
+    pub fn is_vdd_low(&self) -> bool {
+        self.pwr.csr.read().pvdo().bit_is_set()
+    }
+
+    /// Starts listening for the PVD output on its EXTI line (16)
+    ///
+    /// The rising edge is VDD dropping below the threshold, and the falling edge VDD rising
+    /// back above it. The PVD also wakes the device up from the Stop mode.
+    pub fn listen_pvd(&mut self, trigger: ExtiTrigger, exti: &mut Internal) {
+        exti.clear_pending(InternalLine::Pvd);
+        exti.listen(InternalLine::Pvd, trigger);
+    }
+
+    /// Stops listening for the PVD output
+    pub fn unlisten_pvd(&mut self, exti: &mut Internal) {
+        exti.unlisten(InternalLine::Pvd);
+        exti.clear_pending(InternalLine::Pvd);
+    }
+
+    /// Releases the peripheral
+    pub fn release(self) -> PWR {
+        self.pwr
+    }
+}
+
+/// Runs `f` with the backup domain writable: the RTC, and the LSE and RTC configuration in
+/// RCC_CSR
+///
+/// PWR_CR.DBP is set before `f` runs, and restored afterwards, so that calls can be nested. The
+/// PWR clock must be enabled, e.g. by `Pwr::new`.
+pub fn with_backup_domain_access<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    // NOTE(unsafe) only DBP is written, atomically, and it's restored before returning
+    let pwr = unsafe { &(*PWR::ptr()) };
+    let dbp = interrupt::free(|_| {
+        let dbp = pwr.cr.read().dbp().bit_is_set();
+        pwr.cr.modify(|_, w| w.dbp().set_bit());
+        dbp
+    });
+    let result = f();
+    interrupt::free(|_| pwr.cr.modify(|_, w| w.dbp().bit(dbp)));
+    result
+}
+
+fn wait(wake_up: WakeUp) {
+    match wake_up {
+        WakeUp::Interrupt => asm::wfi(),
+        WakeUp::Event => asm::wfe(),
+    }
+}
@@ -0,0 +1,103 @@
+//! Quadrature Encoder Interface
+
+use embedded_hal::{Direction, Qei as QeiTrait};
+use stm32l0x3::{TIM2, TIM21, TIM22};
+
+use crate::pwm::{ChannelPin, C1, C2};
+use crate::rcc::{APB1, APB2};
+
+/// Encoder edges counted by the timer
+pub enum Mode {
+    /// Counts the edges of TI1 only
+    Ti1,
+    /// Counts the edges of TI2 only
+    Ti2,
+    /// Counts the edges of both TI1 and TI2
+    Ti1AndTi2,
+}
+
+/// Quadrature encoder read by a timer
+///
+/// The counter is 16 bits wide on all the timers of the STM32L0x3, so it wraps around at
+/// `0xFFFF` in both directions.
+pub struct Qei<TIM, PINS> {
+    tim: TIM,
+    pins: PINS,
+}
+
+macro_rules! hal {
+    ($(
+        $TIMX:ident: (
+            $timX:ident,
+            $APB:ident,
+            $timXen:ident,
+            $timXrst:ident
+        ),
+    )+) => {
+        $(
+            impl<PA, PB> Qei<$TIMX, (PA, PB)>
+            where
+                PA: ChannelPin<$TIMX, C1>,
+                PB: ChannelPin<$TIMX, C2>,
+            {
+                /// Configures the timer to count the quadrature signals on channels 1 and 2
+                pub fn $timX(tim: $TIMX, pins: (PA, PB), mode: Mode, apb: &mut $APB) -> Self {
+                    apb.enr().modify(|_, w| w.$timXen().set_bit());
+                    apb.rstr().modify(|_, w| w.$timXrst().set_bit());
+                    apb.rstr().modify(|_, w| w.$timXrst().clear_bit());
+
+                    // CC1S = 01, CC2S = 01: IC1 mapped on TI1, IC2 mapped on TI2
+                    tim.ccmr1_input.write(|w| unsafe { w.bits(0b01 | (0b01 << 8)) });
+                    // rising edges, no inversion
+                    tim.ccer.write(|w| unsafe { w.bits(0) });
+
+                    let sms = match mode {
+                        Mode::Ti1 => 0b010,
+                        Mode::Ti2 => 0b001,
+                        Mode::Ti1AndTi2 => 0b011,
+                    };
+                    tim.smcr.modify(|_, w| unsafe { w.sms().bits(sms) });
+
+                    tim.arr.write(|w| unsafe { w.bits(0xFFFF) });
+                    tim.cnt.write(|w| unsafe { w.bits(0) });
+                    tim.cr1.modify(|_, w| w.cen().set_bit());
+
+                    Qei { tim, pins }
+                }
+
+                /// Sets the counter value
+                pub fn set_count(&mut self, count: u16) {
+                    self.tim.cnt.write(|w| unsafe { w.bits(count as u32) });
+                }
+
+                /// Stops the timer and releases the peripheral and the pins
+                pub fn release(self) -> ($TIMX, (PA, PB)) {
+                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    (self.tim, self.pins)
+                }
+            }
+
+            impl<PINS> QeiTrait for Qei<$TIMX, PINS> {
+                type Count = u16;
+
+                fn count(&self) -> u16 {
+                    self.tim.cnt.read().bits() as u16
+                }
+
+                fn direction(&self) -> Direction {
+                    if self.tim.cr1.read().dir().bit_is_clear() {
+                        Direction::Upcounting
+                    } else {
+                        Direction::Downcounting
+                    }
+                }
+            }
+        )+
+    }
+}
+
+hal! {
+    TIM2: (tim2, APB1, tim2en, tim2rst),
+    TIM21: (tim21, APB2, tim21en, tim21rst),
+    TIM22: (tim22, APB2, tim22en, tim22rst),
+}
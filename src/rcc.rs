@@ -1,6 +1,8 @@
 //! Reset and Clock Control
 
 use crate::flash::ACR;
+use crate::pwr::with_backup_domain_access;
+use crate::syscfg::{self, ENREF_HSI48};
 use crate::time::Hertz;
 use stm32l0x3::{rcc, RCC};
 
@@ -19,6 +21,9 @@ impl RccExt for RCC {
             gpio: GPIO { _0: () },
             cfgr: CFGR::new(),
             ccipr: CCIPR::new(),
+            csr: CSR { _0: () },
+            crrcr: CRRCR { _0: () },
+            mco: MCO { _0: () },
         }
     }
 }
@@ -37,6 +42,12 @@ pub struct Rcc {
     pub cfgr: CFGR,
     /// Clock configuration
     pub ccipr: CCIPR,
+    /// Low speed oscillators
+    pub csr: CSR,
+    /// HSI48 oscillator
+    pub crrcr: CRRCR,
+    /// Microcontroller clock output
+    pub mco: MCO,
 }
 
 /// AMBA High-performance Bus (AHB) registers
@@ -125,6 +136,32 @@ impl LpUsartClock {
     }
 }
 
+pub enum LptimClock {
+    ApbClock,
+    LSIClock,
+    HSI16Clock,
+    LSEClock,
+}
+
+impl LptimClock {
+    fn ccipr_bits(&self) -> (bool, bool) {
+        match self {
+            LptimClock::ApbClock => (false, false),
+            LptimClock::LSIClock => (false, true),
+            LptimClock::HSI16Clock => (true, false),
+            LptimClock::LSEClock => (true, true),
+        }
+    }
+}
+
+/// 48 MHz clock of the USB and RNG
+pub enum Clock48 {
+    /// The PLL at 96 MHz, divided by 2, see `CFGR::usb_pll`
+    PLLClock,
+    /// The HSI48 oscillator, see `CRRCR::enable_hsi48`
+    HSI48Clock,
+}
+
 pub struct CCIPR {}
 
 impl CCIPR {
@@ -140,9 +177,208 @@ impl CCIPR {
                 .modify(|_, w| w.lpuart1sel1().bit(sel1).lpuart1sel0().bit(sel0));
         }
     }
+
+    pub fn set_lptim_clock(&mut self, source: LptimClock) {
+        let (sel1, sel0) = source.ccipr_bits();
+        unsafe {
+            &(*RCC::ptr())
+                .ccipr
+                .modify(|_, w| w.lptim1sel1().bit(sel1).lptim1sel0().bit(sel0));
+        }
+    }
+
+    pub fn set_clock48(&mut self, source: Clock48) {
+        // HSI48SEL
+        let mask = 1 << 26;
+        unsafe {
+            (*RCC::ptr()).ccipr.modify(|r, w| match source {
+                Clock48::PLLClock => w.bits(r.bits() & !mask),
+                Clock48::HSI48Clock => w.bits(r.bits() | mask),
+            });
+        }
+    }
+
+    /// Returns true if the 48 MHz clock is HSI48
+    pub(crate) fn is_clock48_hsi48() -> bool {
+        // NOTE(unsafe) read-only access
+        unsafe { (*RCC::ptr()).ccipr.read().bits() & (1 << 26) != 0 }
+    }
+}
+
+/// HSI48 oscillator, clocking the USB and RNG without a crystal
+pub struct CRRCR {
+    _0: (),
+}
+
+impl CRRCR {
+    fn crrcr(&mut self) -> &rcc::CRRCR {
+        // NOTE(unsafe) this proxy grants exclusive access to this register
+        unsafe { &(*RCC::ptr()).crrcr }
+    }
+
+    /// Starts the HSI48 oscillator and waits until it is ready
+    ///
+    /// The oscillator needs the internal voltage reference, which is enabled in SYSCFG_CFGR3.
+    pub fn enable_hsi48(&mut self, apb2: &mut APB2) {
+        syscfg::enable_buffer(ENREF_HSI48, apb2);
+
+        // HSI48ON, HSI48RDY
+        self.crrcr().modify(|r, w| unsafe { w.bits(r.bits() | 1) });
+        while self.crrcr().read().bits() & (1 << 1) == 0 {}
+    }
+
+    /// Stops the HSI48 oscillator
+    pub fn disable_hsi48(&mut self) {
+        self.crrcr().modify(|r, w| unsafe { w.bits(r.bits() & !1) });
+        syscfg::disable_buffer(ENREF_HSI48);
+    }
+
+    /// Restarts the HSI48 oscillator after a wake up from the Stop mode, which stops it
+    pub(crate) fn restore_hsi48() {
+        // NOTE(unsafe) CRRCR is only written by this proxy, which `enable_hsi48` already used
+        let crrcr = unsafe { &(*RCC::ptr()).crrcr };
+        crrcr.modify(|r, w| unsafe { w.bits(r.bits() | 1) });
+        while crrcr.read().bits() & (1 << 1) == 0 {}
+    }
+
+    /// Returns true if the HSI48 oscillator is running
+    pub(crate) fn is_hsi48_ready() -> bool {
+        // NOTE(unsafe) read-only access
+        unsafe { (*RCC::ptr()).crrcr.read().bits() & (1 << 1) != 0 }
+    }
+}
+
+/// Low speed oscillators (LSE, LSI)
+///
+/// The oscillators keep running in the Stop and Standby modes, to clock the RTC, LPTIM and
+/// LPUART.
+pub struct CSR {
+    _0: (),
 }
 
-const HSI: u32 = 16_000_000; // Hz
+impl CSR {
+    fn csr(&mut self) -> &rcc::CSR {
+        // NOTE(unsafe) this proxy grants exclusive access to this register
+        unsafe { &(*RCC::ptr()).csr }
+    }
+
+    /// Starts the 32.768 kHz LSE oscillator, or takes an external clock on OSC32_IN when
+    /// `bypass` is set, and waits until it is ready
+    ///
+    /// The LSE is in the backup domain, so its configuration survives a reset.
+    pub fn enable_lse(&mut self, bypass: bool, apb1: &mut APB1) {
+        if self.csr().read().lserdy().bit_is_set() {
+            return;
+        }
+
+        // the backup domain is write protected
+        apb1.enr().modify(|_, w| w.pwren().set_bit());
+        with_backup_domain_access(|| {
+            self.csr().modify(|_, w| w.lsebyp().bit(bypass));
+            self.csr().modify(|_, w| w.lseon().set_bit());
+            while self.csr().read().lserdy().bit_is_clear() {}
+        });
+    }
+
+    /// Returns true if the LSE oscillator is running
+    pub fn is_lse_ready(&mut self) -> bool {
+        self.csr().read().lserdy().bit_is_set()
+    }
+
+    /// Starts the ~37 kHz LSI oscillator and waits until it is ready
+    pub fn enable_lsi(&mut self) {
+        self.csr().modify(|_, w| w.lsion().set_bit());
+        while self.csr().read().lsirdy().bit_is_clear() {}
+    }
+
+    /// Stops the LSI oscillator
+    pub fn disable_lsi(&mut self) {
+        self.csr().modify(|_, w| w.lsion().clear_bit());
+    }
+
+    /// Clocks the RTC from the given RTCSEL source, and returns true if it was already running
+    /// from it
+    ///
+    /// Changing the source resets the RTC and the backup registers, the LSE is restarted if it
+    /// was running. The backup domain must be writable.
+    pub(crate) fn enable_rtc(&mut self, rtcsel: u8) -> bool {
+        let csr = self.csr().read();
+        if csr.rtcen().bit_is_set() && csr.rtcsel().bits() == rtcsel {
+            return true;
+        }
+        if csr.rtcsel().bits() != 0b00 {
+            // the source can only be selected once after a backup domain reset, which also
+            // stops the LSE
+            self.csr().modify(|_, w| w.rtcrst().set_bit());
+            self.csr().modify(|_, w| w.rtcrst().clear_bit());
+            if csr.lseon().bit_is_set() {
+                self.csr().modify(|_, w| w.lsebyp().bit(csr.lsebyp().bit()));
+                self.csr().modify(|_, w| w.lseon().set_bit());
+                while self.csr().read().lserdy().bit_is_clear() {}
+            }
+        }
+        self.csr()
+            .modify(|_, w| unsafe { w.rtcsel().bits(rtcsel).rtcen().set_bit() });
+        false
+    }
+}
+
+/// Clock output on the MCO pin
+pub enum McoSource {
+    Sysclk,
+    HSI16,
+    MSI,
+    HSE,
+    PLL,
+    LSI,
+    LSE,
+}
+
+/// Microcontroller clock output (MCO), on PA8 or PA9 in alternate function 0
+pub struct MCO {
+    _0: (),
+}
+
+impl MCO {
+    /// Outputs `source` divided by `div`, one of 1, 2, 4, 8 or 16
+    ///
+    /// E.g. the LSE output clocks external chips, or allows to trim the crystal.
+    pub fn enable(&mut self, source: McoSource, div: u8) {
+        let mcosel = match source {
+            McoSource::Sysclk => 0b0001,
+            McoSource::HSI16 => 0b0010,
+            McoSource::MSI => 0b0011,
+            McoSource::HSE => 0b0100,
+            McoSource::PLL => 0b0101,
+            McoSource::LSI => 0b0110,
+            McoSource::LSE => 0b0111,
+        };
+        let mcopre = match div {
+            1 => 0b000,
+            2 => 0b001,
+            4 => 0b010,
+            8 => 0b011,
+            16 => 0b100,
+            _ => panic!("invalid MCO prescaler"),
+        };
+        // NOTE(unsafe) CFGR is only written by `CFGR::freeze`, which consumes the builder before
+        // this proxy can be used
+        unsafe {
+            (*RCC::ptr())
+                .cfgr
+                .modify(|_, w| w.mcosel().bits(mcosel).mcopre().bits(mcopre));
+        }
+    }
+
+    /// Stops the clock output
+    pub fn disable(&mut self) {
+        unsafe { (*RCC::ptr()).cfgr.modify(|_, w| w.mcosel().bits(0b0000)) }
+    }
+}
+
+pub(crate) const HSI: u32 = 16_000_000; // Hz
+pub(crate) const LSI: u32 = 37_000; // Hz, typical
+pub(crate) const LSE: u32 = 32_768; // Hz
 const USB_PLL_FREQ: u32 = 96_000_000; // Hz
 
 pub enum ExternalHseType {
@@ -183,6 +419,7 @@ impl CFGR {
         self
     }
 
+    /// Runs the PLL at 96 MHz, so that it can clock the USB
     pub fn usb_pll(mut self, enabled: bool) -> Self {
         self.usb_pll = enabled;
         self
@@ -427,6 +664,9 @@ impl CFGR {
             ppre1,
             ppre2,
             sysclk: Hertz(sysclk_freq),
+            hse: hse_en,
+            sw: rcc.cfgr.read().sw().bits(),
+            usb_pll: self.usb_pll,
         }
     }
 }
@@ -442,9 +682,37 @@ pub struct Clocks {
     ppre1: u8,
     ppre2: u8,
     sysclk: Hertz,
+    hse: bool,
+    sw: u8,
+    usb_pll: bool,
 }
 
 impl Clocks {
+    /// Restores the system clock after a wake up from the Stop mode, which stops the HSE and
+    /// the PLL, and leaves the system clocked by HSI16
+    ///
+    /// The PLL is restarted when it clocks the system or the USB.
+    pub(crate) fn restore(&self) {
+        // NOTE(unsafe) CR and CFGR are only written by `CFGR::freeze`, which consumes the
+        // builder before the clocks exist
+        let rcc = unsafe { &*RCC::ptr() };
+        if self.hse {
+            rcc.cr.modify(|_, w| w.hseon().set_bit());
+            while rcc.cr.read().hserdy().bit_is_clear() {}
+        }
+        if self.sw == 0b11 || self.usb_pll {
+            rcc.cr.modify(|_, w| w.pllon().set_bit());
+            while rcc.cr.read().pllrdy().bit_is_clear() {}
+        }
+        rcc.cfgr.modify(|_, w| unsafe { w.sw().bits(self.sw) });
+        while rcc.cfgr.read().sws().bits() != self.sw {}
+    }
+
+    /// Returns true if the PLL runs at 96 MHz, and can clock the USB
+    pub(crate) fn usb_pll(&self) -> bool {
+        self.usb_pll
+    }
+
     /// Returns the frequency of the AHB
     pub fn hclk(&self) -> Hertz {
         self.hclk
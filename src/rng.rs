@@ -0,0 +1,133 @@
+//! True Random Number Generator (RNG)
+//!
+//! The generator samples analog noise, and outputs a 32-bit random number every 46 cycles of
+//! its 48 MHz clock. That clock comes from the PLL at 96 MHz (`CFGR::usb_pll`) or from the HSI48
+//! oscillator (`CRRCR::enable_hsi48`), selected with `CCIPR::set_clock48`.
+
+use core::num::NonZeroU32;
+
+use nb;
+use rand_core::{impls, CryptoRng, RngCore};
+use stm32l0x3::RNG;
+
+use crate::rcc::{Clocks, AHB, CCIPR, CRRCR};
+
+// CR bits
+const RNGEN: u32 = 1 << 2;
+
+// SR bits
+const DRDY: u32 = 1 << 0;
+const CECS: u32 = 1 << 1;
+const SECS: u32 = 1 << 2;
+const CEIS: u32 = 1 << 5;
+const SEIS: u32 = 1 << 6;
+
+/// RNG error
+#[derive(Debug)]
+pub enum Error {
+    /// The noise source failed its health check; the generator has been restarted, and the
+    /// next numbers are fine if the error doesn't persist
+    Seed,
+    /// The RNG clock is too slow compared to the AHB clock, at most 16 times slower
+    Clock,
+    #[doc(hidden)]
+    _Extensible,
+}
+
+impl From<Error> for rand_core::Error {
+    fn from(error: Error) -> rand_core::Error {
+        let code = match error {
+            Error::Seed => 1,
+            _ => 2,
+        };
+        rand_core::Error::from(NonZeroU32::new(rand_core::Error::CUSTOM_START + code).unwrap())
+    }
+}
+
+/// Random number generator
+pub struct Rng {
+    rng: RNG,
+}
+
+impl Rng {
+    /// Starts the generator, once its 48 MHz clock is running
+    pub fn new(rng: RNG, clocks: &Clocks, ahb: &mut AHB) -> Self {
+        if CCIPR::is_clock48_hsi48() {
+            assert!(CRRCR::is_hsi48_ready());
+        } else {
+            assert!(clocks.usb_pll());
+        }
+        ahb.enr().modify(|_, w| w.rngen().set_bit());
+        rng.cr.write(|w| unsafe { w.bits(RNGEN) });
+
+        Rng { rng }
+    }
+
+    /// Reads a random number
+    pub fn read(&mut self) -> nb::Result<u32, Error> {
+        let sr = self.rng.sr.read().bits();
+        if sr & (SECS | SEIS) != 0 {
+            // clear SEIS, and restart the generator, which discards the numbers produced
+            self.rng.sr.write(|w| unsafe { w.bits(sr & !SEIS) });
+            self.rng
+                .cr
+                .modify(|r, w| unsafe { w.bits(r.bits() & !RNGEN) });
+            self.rng
+                .cr
+                .modify(|r, w| unsafe { w.bits(r.bits() | RNGEN) });
+            Err(nb::Error::Other(Error::Seed))
+        } else if sr & (CECS | CEIS) != 0 {
+            self.rng.sr.write(|w| unsafe { w.bits(sr & !CEIS) });
+            Err(nb::Error::Other(Error::Clock))
+        } else if sr & DRDY != 0 {
+            Ok(self.rng.dr.read().bits())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+
+    /// Blocks until a random number is read, retrying after the seed errors
+    fn read_seeded(&mut self) -> Result<u32, Error> {
+        loop {
+            match nb::block!(self.read()) {
+                Err(Error::Seed) => continue,
+                result => return result,
+            }
+        }
+    }
+
+    /// Stops the generator, and releases the peripheral
+    pub fn release(self) -> RNG {
+        self.rng.cr.write(|w| unsafe { w.bits(0) });
+        self.rng
+    }
+}
+
+impl RngCore for Rng {
+    /// Returns a random number, retrying after the seed errors, and panics on a clock error
+    fn next_u32(&mut self) -> u32 {
+        self.read_seeded().expect("RNG clock error")
+    }
+
+    /// Returns a random number, retrying after the seed errors, and panics on a clock error
+    fn next_u64(&mut self) -> u64 {
+        impls::next_u64_via_u32(self)
+    }
+
+    /// Fills `dest` with random bytes, retrying after the seed errors, and panics on a clock
+    /// error
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.try_fill_bytes(dest).expect("RNG clock error")
+    }
+
+    /// Fills `dest` with random bytes, retrying after the seed errors
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        for chunk in dest.chunks_mut(4) {
+            let random = self.read_seeded()?.to_le_bytes();
+            chunk.copy_from_slice(&random[..chunk.len()]);
+        }
+        Ok(())
+    }
+}
+
+impl CryptoRng for Rng {}
@@ -0,0 +1,1150 @@
+//! Real Time Clock (RTC)
+//!
+//! The RTC is in the backup domain, so it keeps running through resets and in the Stop and
+//! Standby modes.
+
+use stm32l0x3::RTC;
+
+use crate::exti::{ExtiTrigger, Internal, InternalLine};
+use crate::pwr::with_backup_domain_access;
+use crate::rcc::{APB1, CSR, LSE, LSI};
+use crate::time::{Hertz, MicroSeconds};
+
+#[cfg(feature = "rtcc")]
+use core::convert::TryFrom;
+#[cfg(feature = "rtcc")]
+use rtcc::{Datelike, Hours, NaiveDate, NaiveDateTime, NaiveTime, Rtcc, Timelike};
+
+/// RTC clock
+pub enum ClockSource {
+    /// LSE oscillator, enabled with `Rcc.csr.enable_lse`
+    Lse,
+    /// LSI oscillator, enabled with `Rcc.csr.enable_lsi`
+    ///
+    /// The LSI frequency varies a lot between parts and with temperature, so the calendar will
+    /// drift.
+    Lsi,
+}
+
+impl ClockSource {
+    /// Returns the RTCSEL bits, the clock frequency, and the asynchronous / synchronous
+    /// prescalers dividing the clock down to 1 Hz
+    pub(crate) fn config(&self) -> (u8, Hertz, u8, u16) {
+        match self {
+            // 32768 Hz / 128 / 256
+            ClockSource::Lse => (0b01, Hertz(LSE), 127, 255),
+            // 37000 Hz / 125 / 296
+            ClockSource::Lsi => (0b10, Hertz(LSI), 124, 295),
+        }
+    }
+}
+
+/// Time of day, in 24 hour format
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Time {
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: u8,
+}
+
+/// Calendar date
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Date {
+    /// Year, from 2000 to 2099
+    pub year: u16,
+    /// Month, from 1 to 12
+    pub month: u8,
+    /// Day of the month, from 1 to 31
+    pub day: u8,
+    /// Day of the week, from 1 (Monday) to 7 (Sunday)
+    pub weekday: u8,
+}
+
+/// RTC alarm
+#[derive(Clone, Copy)]
+pub enum Alarm {
+    A,
+    B,
+}
+
+impl Alarm {
+    /// Returns the bit offset of the alarm in the CR enable bits and ISR flags
+    fn offset(self) -> u8 {
+        match self {
+            Alarm::A => 0,
+            Alarm::B => 1,
+        }
+    }
+}
+
+/// Alarm match fields
+///
+/// The fields left unset match any value, e.g. an alarm with only `seconds(0)` set triggers
+/// every minute.
+#[derive(Clone, Copy, Default)]
+pub struct AlarmConfig {
+    date: Option<u8>,
+    weekday: Option<u8>,
+    hours: Option<u8>,
+    minutes: Option<u8>,
+    seconds: Option<u8>,
+    subseconds: Option<(u16, u8)>,
+}
+
+impl AlarmConfig {
+    /// Matches the day of the month, from 1 to 31
+    pub fn date(mut self, date: u8) -> Self {
+        assert!(date >= 1 && date <= 31);
+        self.date = Some(date);
+        self.weekday = None;
+        self
+    }
+
+    /// Matches the day of the week, from 1 (Monday) to 7 (Sunday)
+    pub fn weekday(mut self, weekday: u8) -> Self {
+        assert!(weekday >= 1 && weekday <= 7);
+        self.weekday = Some(weekday);
+        self.date = None;
+        self
+    }
+
+    pub fn hours(mut self, hours: u8) -> Self {
+        assert!(hours < 24);
+        self.hours = Some(hours);
+        self
+    }
+
+    pub fn minutes(mut self, minutes: u8) -> Self {
+        assert!(minutes < 60);
+        self.minutes = Some(minutes);
+        self
+    }
+
+    pub fn seconds(mut self, seconds: u8) -> Self {
+        assert!(seconds < 60);
+        self.seconds = Some(seconds);
+        self
+    }
+
+    /// Matches the `bits` least significant bits of the sub-second counter, from 1 to 15
+    ///
+    /// The sub-second counter counts down from the synchronous prescaler value (255 with the
+    /// LSE) once per second.
+    pub fn subseconds(mut self, subseconds: u16, bits: u8) -> Self {
+        assert!(bits >= 1 && bits <= 15);
+        self.subseconds = Some((subseconds & 0x7FFF, bits));
+        self
+    }
+
+    fn alrmr(&self) -> u32 {
+        let mut alrmr = 0;
+        alrmr |= match (self.date, self.weekday) {
+            (Some(date), _) => bcd(date) << 24,
+            // WDSEL
+            (_, Some(weekday)) => (1 << 30) | ((weekday as u32) << 24),
+            // MSK4
+            (None, None) => 1 << 31,
+        };
+        alrmr |= self.hours.map_or(1 << 23, |hours| bcd(hours) << 16);
+        alrmr |= self.minutes.map_or(1 << 15, |minutes| bcd(minutes) << 8);
+        alrmr |= self.seconds.map_or(1 << 7, bcd);
+        alrmr
+    }
+
+    fn alrmssr(&self) -> u32 {
+        self.subseconds.map_or(0, |(subseconds, bits)| {
+            ((bits as u32) << 24) | subseconds as u32
+        })
+    }
+}
+
+/// Wakeup timer clock
+#[derive(Clone, Copy)]
+pub enum WakeupClock {
+    /// RTC clock divided by 16
+    RtcDiv16,
+    /// RTC clock divided by 8
+    RtcDiv8,
+    /// RTC clock divided by 4
+    RtcDiv4,
+    /// RTC clock divided by 2
+    RtcDiv2,
+    /// 1 Hz calendar clock
+    Seconds,
+    /// 1 Hz calendar clock, with 2^16 added to the reload value for periods up to 36 hours
+    SecondsExtended,
+}
+
+impl WakeupClock {
+    fn wucksel(self) -> u32 {
+        match self {
+            WakeupClock::RtcDiv16 => 0b000,
+            WakeupClock::RtcDiv8 => 0b001,
+            WakeupClock::RtcDiv4 => 0b010,
+            WakeupClock::RtcDiv2 => 0b011,
+            WakeupClock::Seconds => 0b100,
+            WakeupClock::SecondsExtended => 0b110,
+        }
+    }
+}
+
+/// Tamper input
+#[derive(Clone, Copy)]
+pub enum Tamper {
+    /// RTC_TAMP1, on PC13
+    Tamper1,
+    /// RTC_TAMP2, on PA0
+    Tamper2,
+}
+
+impl Tamper {
+    fn index(self) -> u8 {
+        match self {
+            Tamper::Tamper1 => 0,
+            Tamper::Tamper2 => 1,
+        }
+    }
+}
+
+/// Tamper detection condition
+pub enum TamperTrigger {
+    RisingEdge,
+    FallingEdge,
+    /// The input stays low for the number of samples set with `TamperConfig::filter`
+    LowLevel,
+    /// The input stays high for the number of samples set with `TamperConfig::filter`
+    HighLevel,
+}
+
+/// Tamper input configuration
+///
+/// The filter, sampling, precharge and timestamp settings are shared by all the tamper inputs.
+pub struct TamperConfig {
+    trigger: TamperTrigger,
+    filter: u8,
+    frequency: u8,
+    precharge: Option<u8>,
+    timestamp: bool,
+    erase_backup: bool,
+}
+
+impl Default for TamperConfig {
+    fn default() -> TamperConfig {
+        TamperConfig {
+            trigger: TamperTrigger::RisingEdge,
+            filter: 0b01,
+            frequency: 0b000,
+            precharge: Some(0b00),
+            timestamp: false,
+            erase_backup: true,
+        }
+    }
+}
+
+impl TamperConfig {
+    pub fn trigger(mut self, trigger: TamperTrigger) -> Self {
+        self.trigger = trigger;
+        self
+    }
+
+    /// Sets the number of consecutive samples at the same level triggering a level tamper, one
+    /// of 2, 4 or 8
+    pub fn filter(mut self, samples: u8) -> Self {
+        self.filter = match samples {
+            2 => 0b01,
+            4 => 0b10,
+            8 => 0b11,
+            _ => panic!("invalid tamper filter"),
+        };
+        self
+    }
+
+    /// Sets the sampling frequency of a level tamper, as the RTC clock divided by `div`, a power
+    /// of 2 from 256 to 32768
+    pub fn sampling_divider(mut self, div: u16) -> Self {
+        assert!(div.is_power_of_two() && (256..=32768).contains(&div));
+        self.frequency = 15 - div.trailing_zeros() as u8;
+        self
+    }
+
+    /// Sets the time the internal pull-up precharges a level tamper input before each sample,
+    /// as 1, 2, 4 or 8 RTC clock cycles, or disables the pull-up with `None`
+    pub fn precharge(mut self, cycles: Option<u8>) -> Self {
+        self.precharge = cycles.map(|cycles| match cycles {
+            1 => 0b00,
+            2 => 0b01,
+            4 => 0b10,
+            8 => 0b11,
+            _ => panic!("invalid tamper precharge"),
+        });
+        self
+    }
+
+    /// Saves the date and time in the timestamp registers when a tamper is detected
+    pub fn timestamp(mut self, timestamp: bool) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    /// Erases the backup registers when a tamper is detected, enabled by default
+    pub fn erase_backup(mut self, erase: bool) -> Self {
+        self.erase_backup = erase;
+        self
+    }
+}
+
+/// Edge of the RTC_TS input (PC13) triggering a timestamp
+pub enum TimestampEdge {
+    Rising,
+    Falling,
+}
+
+/// Date and time captured by a timestamp event
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Timestamp {
+    /// Month, from 1 to 12
+    pub month: u8,
+    /// Day of the month, from 1 to 31
+    pub day: u8,
+    /// Day of the week, from 1 (Monday) to 7 (Sunday)
+    pub weekday: u8,
+    pub time: Time,
+    /// Sub-second counter, counting down from the synchronous prescaler value
+    pub subseconds: u16,
+    /// Another timestamp event occurred before this one was read, and was lost
+    pub overflow: bool,
+}
+
+/// Smooth calibration cycle length
+pub enum CalibrationWindow {
+    Seconds32,
+    Seconds16,
+    Seconds8,
+}
+
+/// Calibration output frequency, with the default prescalers
+pub enum CalibrationOutput {
+    /// RTC clock divided by 64, unaffected by the smooth calibration
+    Hz512,
+    /// Calendar clock, including the smooth calibration
+    Hz1,
+}
+
+/// Number of backup registers
+pub const BACKUP_REGISTERS: usize = 5;
+
+/// Converts a binary value to BCD
+fn bcd(value: u8) -> u32 {
+    (((value / 10) << 4) | (value % 10)) as u32
+}
+
+/// Converts a BCD value to binary
+fn from_bcd(value: u32) -> u8 {
+    ((value >> 4) * 10 + (value & 0xF)) as u8
+}
+
+impl Time {
+    fn tr(&self) -> u32 {
+        assert!(self.hours < 24 && self.minutes < 60 && self.seconds < 60);
+        (bcd(self.hours) << 16) | (bcd(self.minutes) << 8) | bcd(self.seconds)
+    }
+
+    fn from_tr(tr: u32) -> Time {
+        Time {
+            hours: from_bcd((tr >> 16) & 0x3F),
+            minutes: from_bcd((tr >> 8) & 0x7F),
+            seconds: from_bcd(tr & 0x7F),
+        }
+    }
+}
+
+impl Date {
+    fn dr(&self) -> u32 {
+        assert!(self.year >= 2000 && self.year <= 2099);
+        assert!(self.month >= 1 && self.month <= 12);
+        assert!(self.day >= 1 && self.day <= 31);
+        assert!(self.weekday >= 1 && self.weekday <= 7);
+        (bcd((self.year - 2000) as u8) << 16)
+            | ((self.weekday as u32) << 13)
+            | (bcd(self.month) << 8)
+            | bcd(self.day)
+    }
+
+    fn from_dr(dr: u32) -> Date {
+        Date {
+            year: 2000 + from_bcd((dr >> 16) & 0xFF) as u16,
+            month: from_bcd((dr >> 8) & 0x1F),
+            day: from_bcd(dr & 0x3F),
+            weekday: ((dr >> 13) & 0b111) as u8,
+        }
+    }
+}
+
+/// Real time clock
+pub struct Rtc {
+    rtc: RTC,
+    clk: Hertz,
+}
+
+impl Rtc {
+    /// Clocks the RTC from `source`, with the prescalers set for a 1 Hz calendar
+    ///
+    /// If the RTC was already running from `source`, e.g. after a reset, the calendar is kept.
+    pub fn new(rtc: RTC, source: ClockSource, csr: &mut CSR, apb1: &mut APB1) -> Self {
+        apb1.enr().modify(|_, w| w.pwren().set_bit());
+
+        let (rtcsel, clk, prediv_a, prediv_s) = source.config();
+        let mut rtc = Rtc { rtc, clk };
+        rtc.write_protected(|rtc| {
+            if !csr.enable_rtc(rtcsel) || rtc.isr.read().inits().bit_is_clear() {
+                Rtc::init_mode(rtc, |rtc| {
+                    // the synchronous prescaler has to be written first
+                    rtc.prer.write(|w| unsafe { w.prediv_s().bits(prediv_s) });
+                    rtc.prer
+                        .modify(|_, w| unsafe { w.prediv_a().bits(prediv_a) });
+                    rtc.cr.modify(|_, w| w.fmt().clear_bit());
+                });
+            }
+        });
+        rtc
+    }
+
+    /// Returns true if the calendar has been set since the last backup domain reset
+    pub fn is_initialized(&self) -> bool {
+        self.rtc.isr.read().inits().bit_is_set()
+    }
+
+    /// Sets the time of day
+    pub fn set_time(&mut self, time: &Time) {
+        let tr = time.tr();
+        self.write_protected(|rtc| {
+            Rtc::init_mode(rtc, |rtc| rtc.tr.write(|w| unsafe { w.bits(tr) }))
+        });
+    }
+
+    /// Sets the date
+    pub fn set_date(&mut self, date: &Date) {
+        let dr = date.dr();
+        self.write_protected(|rtc| {
+            Rtc::init_mode(rtc, |rtc| rtc.dr.write(|w| unsafe { w.bits(dr) }))
+        });
+    }
+
+    /// Sets the date and time of day at once
+    pub fn set_datetime(&mut self, date: &Date, time: &Time) {
+        let (dr, tr) = (date.dr(), time.tr());
+        self.write_protected(|rtc| {
+            Rtc::init_mode(rtc, |rtc| {
+                rtc.tr.write(|w| unsafe { w.bits(tr) });
+                rtc.dr.write(|w| unsafe { w.bits(dr) });
+            })
+        });
+    }
+
+    /// Returns the time of day
+    pub fn get_time(&mut self) -> Time {
+        self.get_datetime().1
+    }
+
+    /// Returns the date
+    pub fn get_date(&mut self) -> Date {
+        self.get_datetime().0
+    }
+
+    /// Returns the date and time of day
+    pub fn get_datetime(&mut self) -> (Date, Time) {
+        self.wait_for_sync();
+        // reading TR locks the DR shadow register until DR is read
+        let tr = self.rtc.tr.read().bits();
+        let dr = self.rtc.dr.read().bits();
+        (Date::from_dr(dr), Time::from_tr(tr))
+    }
+
+    /// Sets and enables an alarm
+    pub fn set_alarm(&mut self, alarm: Alarm, config: &AlarmConfig) {
+        let (alrmr, alrmssr) = (config.alrmr(), config.alrmssr());
+        let offset = alarm.offset();
+        self.write_protected(|rtc| {
+            // ALRxE
+            rtc.cr
+                .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << (8 + offset))) });
+            // ALRxWF, set once the alarm registers can be written
+            while rtc.isr.read().bits() & (1 << offset) == 0 {}
+            match alarm {
+                Alarm::A => {
+                    rtc.alrmar.write(|w| unsafe { w.bits(alrmr) });
+                    rtc.alrmassr.write(|w| unsafe { w.bits(alrmssr) });
+                }
+                Alarm::B => {
+                    rtc.alrmbr.write(|w| unsafe { w.bits(alrmr) });
+                    rtc.alrmbssr.write(|w| unsafe { w.bits(alrmssr) });
+                }
+            }
+            rtc.cr
+                .modify(|r, w| unsafe { w.bits(r.bits() | (1 << (8 + offset))) });
+        });
+        self.clear_alarm(alarm);
+    }
+
+    /// Disables an alarm
+    pub fn disable_alarm(&mut self, alarm: Alarm) {
+        let mask = 1 << (8 + alarm.offset());
+        self.write_protected(|rtc| rtc.cr.modify(|r, w| unsafe { w.bits(r.bits() & !mask) }));
+    }
+
+    /// Enables the interrupt of an alarm, and unmasks the RTC alarm EXTI line so that the alarm
+    /// wakes the device up from Stop and Standby mode
+    pub fn listen_alarm(&mut self, alarm: Alarm, exti: &mut Internal) {
+        // ALRxIE
+        let mask = 1 << (12 + alarm.offset());
+        self.write_protected(|rtc| rtc.cr.modify(|r, w| unsafe { w.bits(r.bits() | mask) }));
+        exti.listen(InternalLine::RtcAlarm, ExtiTrigger::Rising);
+    }
+
+    /// Disables the interrupt of an alarm
+    ///
+    /// The RTC alarm EXTI line is left unmasked, as it's shared by both alarms.
+    pub fn unlisten_alarm(&mut self, alarm: Alarm) {
+        let mask = 1 << (12 + alarm.offset());
+        self.write_protected(|rtc| rtc.cr.modify(|r, w| unsafe { w.bits(r.bits() & !mask) }));
+    }
+
+    /// Returns true if an alarm has triggered
+    pub fn is_alarm_triggered(&self, alarm: Alarm) -> bool {
+        // ALRxF
+        self.rtc.isr.read().bits() & (1 << (8 + alarm.offset())) != 0
+    }
+
+    /// Clears the flag of an alarm
+    ///
+    /// The RTC alarm EXTI line has to be cleared separately, with `Internal::clear_pending`.
+    pub fn clear_alarm(&mut self, alarm: Alarm) {
+        self.clear_flag(1 << (8 + alarm.offset()));
+    }
+
+    /// Starts the periodic wakeup timer, which triggers every `reload + 1` cycles of `clock`
+    pub fn enable_wakeup(&mut self, clock: WakeupClock, reload: u16) {
+        let wucksel = clock.wucksel();
+        self.write_protected(|rtc| {
+            // WUTE
+            rtc.cr
+                .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << 10)) });
+            // WUTWF, set once the wakeup timer registers can be written
+            while rtc.isr.read().bits() & (1 << 2) == 0 {}
+            rtc.wutr.write(|w| unsafe { w.bits(reload as u32) });
+            rtc.cr
+                .modify(|r, w| unsafe { w.bits((r.bits() & !0b111) | wucksel | (1 << 10)) });
+        });
+        self.clear_wakeup();
+    }
+
+    /// Starts the periodic wakeup timer with a period of `period`, up to 71 minutes
+    ///
+    /// Periods up to 32 seconds are counted in RTC clock cycles, the longer ones in seconds.
+    pub fn wakeup_every<T>(&mut self, period: T)
+    where
+        T: Into<MicroSeconds>,
+    {
+        let period = period.into().0 as u64;
+        for (clock, div) in &[
+            (WakeupClock::RtcDiv2, 2),
+            (WakeupClock::RtcDiv4, 4),
+            (WakeupClock::RtcDiv8, 8),
+            (WakeupClock::RtcDiv16, 16),
+        ] {
+            let ticks = period * self.clk.0 as u64 / div / 1_000_000;
+            if ticks <= 1 << 16 {
+                self.enable_wakeup(*clock, (ticks.max(1) - 1) as u16);
+                return;
+            }
+        }
+
+        let seconds = (period + 500_000) / 1_000_000;
+        self.enable_wakeup(WakeupClock::Seconds, (seconds.max(1) - 1) as u16);
+    }
+
+    /// Stops the periodic wakeup timer
+    pub fn disable_wakeup(&mut self) {
+        self.write_protected(|rtc| {
+            rtc.cr
+                .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << 10)) })
+        });
+    }
+
+    /// Enables the wakeup timer interrupt, and unmasks the RTC wakeup EXTI line so that the
+    /// timer wakes the device up from Stop and Standby mode
+    pub fn listen_wakeup(&mut self, exti: &mut Internal) {
+        // WUTIE
+        self.write_protected(|rtc| {
+            rtc.cr
+                .modify(|r, w| unsafe { w.bits(r.bits() | (1 << 14)) })
+        });
+        exti.listen(InternalLine::RtcWakeup, ExtiTrigger::Rising);
+    }
+
+    /// Disables the wakeup timer interrupt, and masks the RTC wakeup EXTI line
+    pub fn unlisten_wakeup(&mut self, exti: &mut Internal) {
+        self.write_protected(|rtc| {
+            rtc.cr
+                .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << 14)) })
+        });
+        exti.unlisten(InternalLine::RtcWakeup);
+    }
+
+    /// Returns true if the wakeup timer has triggered
+    pub fn is_wakeup_triggered(&self) -> bool {
+        // WUTF
+        self.rtc.isr.read().bits() & (1 << 10) != 0
+    }
+
+    /// Clears the wakeup timer flag
+    ///
+    /// The RTC wakeup EXTI line has to be cleared separately, with `Internal::clear_pending`.
+    pub fn clear_wakeup(&mut self) {
+        self.clear_flag(1 << 10);
+    }
+
+    /// Enables the detection of a tamper
+    pub fn enable_tamper(&mut self, tamper: Tamper, config: &TamperConfig) {
+        let offset = 3 * tamper.index() as u32;
+        let (trg, flt) = match config.trigger {
+            TamperTrigger::RisingEdge => (false, 0b00),
+            TamperTrigger::FallingEdge => (true, 0b00),
+            TamperTrigger::LowLevel => (false, config.filter as u32),
+            TamperTrigger::HighLevel => (true, config.filter as u32),
+        };
+        let (pudis, prch) = match config.precharge {
+            Some(prch) => (false, prch as u32),
+            None => (true, 0b00),
+        };
+
+        // TAMPxTRG, TAMPxNOERASE
+        let mask = (1 << (1 + offset)) | (1 << (17 + offset));
+        let bits =
+            ((trg as u32) << (1 + offset)) | ((!config.erase_backup as u32) << (17 + offset));
+        // TAMPTS, TAMPFREQ, TAMPFLT, TAMPPRCH, TAMPPUDIS
+        let shared = ((config.timestamp as u32) << 7)
+            | ((config.frequency as u32) << 8)
+            | (flt << 11)
+            | (prch << 13)
+            | ((pudis as u32) << 15);
+        self.write_protected(|rtc| {
+            // the tamper is disabled while its configuration changes
+            rtc.tampcr
+                .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << offset)) });
+            rtc.tampcr.modify(|r, w| unsafe {
+                w.bits((r.bits() & !(mask | (0x1FF << 7))) | bits | shared)
+            });
+            rtc.tampcr
+                .modify(|r, w| unsafe { w.bits(r.bits() | (1 << offset)) });
+        });
+        self.clear_tamper(tamper);
+    }
+
+    /// Disables the detection of a tamper
+    pub fn disable_tamper(&mut self, tamper: Tamper) {
+        let mask = 1 << (3 * tamper.index());
+        self.write_protected(|rtc| {
+            rtc.tampcr
+                .modify(|r, w| unsafe { w.bits(r.bits() & !mask) })
+        });
+    }
+
+    /// Enables the interrupt of a tamper, and unmasks the RTC tamper and timestamp EXTI line so
+    /// that the tamper wakes the device up from Stop and Standby mode
+    pub fn listen_tamper(&mut self, tamper: Tamper, exti: &mut Internal) {
+        // TAMPxIE
+        let mask = 1 << (16 + 3 * tamper.index());
+        self.write_protected(|rtc| rtc.tampcr.modify(|r, w| unsafe { w.bits(r.bits() | mask) }));
+        exti.listen(InternalLine::RtcTamperTimestamp, ExtiTrigger::Rising);
+    }
+
+    /// Disables the interrupt of a tamper
+    ///
+    /// The RTC tamper and timestamp EXTI line is left unmasked, as it's shared with the other
+    /// tamper inputs and the timestamp.
+    pub fn unlisten_tamper(&mut self, tamper: Tamper) {
+        let mask = 1 << (16 + 3 * tamper.index());
+        self.write_protected(|rtc| {
+            rtc.tampcr
+                .modify(|r, w| unsafe { w.bits(r.bits() & !mask) })
+        });
+    }
+
+    /// Returns true if a tamper has been detected
+    pub fn is_tamper_detected(&self, tamper: Tamper) -> bool {
+        // TAMPxF
+        self.rtc.isr.read().bits() & (1 << (13 + tamper.index())) != 0
+    }
+
+    /// Clears the flag of a tamper
+    ///
+    /// The RTC tamper and timestamp EXTI line has to be cleared separately, with
+    /// `Internal::clear_pending`.
+    pub fn clear_tamper(&mut self, tamper: Tamper) {
+        self.clear_flag(1 << (13 + tamper.index()));
+    }
+
+    /// Enables the timestamp on `edge` of the RTC_TS input
+    ///
+    /// Timestamps on tamper events are enabled with `TamperConfig::timestamp` instead.
+    pub fn enable_timestamp(&mut self, edge: TimestampEdge) {
+        let tsedge = match edge {
+            TimestampEdge::Rising => 0,
+            TimestampEdge::Falling => 1 << 3,
+        };
+        self.write_protected(|rtc| {
+            // TSE has to be cleared while TSEDGE changes
+            rtc.cr
+                .modify(|r, w| unsafe { w.bits(r.bits() & !((1 << 11) | (1 << 3))) });
+            rtc.cr
+                .modify(|r, w| unsafe { w.bits(r.bits() | tsedge | (1 << 11)) });
+        });
+    }
+
+    /// Disables the timestamp on the RTC_TS input
+    pub fn disable_timestamp(&mut self) {
+        self.write_protected(|rtc| {
+            rtc.cr
+                .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << 11)) })
+        });
+    }
+
+    /// Enables the timestamp interrupt, and unmasks the RTC tamper and timestamp EXTI line so
+    /// that a timestamp wakes the device up from Stop and Standby mode
+    pub fn listen_timestamp(&mut self, exti: &mut Internal) {
+        // TSIE
+        self.write_protected(|rtc| {
+            rtc.cr
+                .modify(|r, w| unsafe { w.bits(r.bits() | (1 << 15)) })
+        });
+        exti.listen(InternalLine::RtcTamperTimestamp, ExtiTrigger::Rising);
+    }
+
+    /// Disables the timestamp interrupt
+    ///
+    /// The RTC tamper and timestamp EXTI line is left unmasked, as it's shared with the tamper
+    /// inputs.
+    pub fn unlisten_timestamp(&mut self) {
+        self.write_protected(|rtc| {
+            rtc.cr
+                .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << 15)) })
+        });
+    }
+
+    /// Returns the last captured timestamp, if any, and clears it
+    pub fn read_timestamp(&mut self) -> Option<Timestamp> {
+        // TSF
+        if self.rtc.isr.read().bits() & (1 << 11) == 0 {
+            return None;
+        }
+
+        let subseconds = self.rtc.tsssr.read().bits() as u16;
+        let tstr = self.rtc.tstr.read().bits();
+        let tsdr = self.rtc.tsdr.read().bits();
+        self.clear_flag(1 << 11);
+        // TSOVF is checked after clearing TSF, so that an overflow can't go unnoticed
+        let overflow = self.rtc.isr.read().bits() & (1 << 12) != 0;
+        if overflow {
+            self.clear_flag(1 << 12);
+        }
+
+        Some(Timestamp {
+            month: from_bcd((tsdr >> 8) & 0x1F),
+            day: from_bcd(tsdr & 0x3F),
+            weekday: ((tsdr >> 13) & 0b111) as u8,
+            time: Time::from_tr(tstr),
+            subseconds,
+            overflow,
+        })
+    }
+
+    /// Sets the smooth digital calibration, adding 512 RTC clock pulses per `window` if `plus`
+    /// is set, and removing `minus` pulses (up to 511)
+    ///
+    /// With a 32 second window, each pulse corrects about 0.954 ppm. The shorter windows
+    /// calibrate faster, with a coarser resolution as the lower bits of `minus` are ignored.
+    pub fn set_smooth_calibration(&mut self, window: CalibrationWindow, plus: bool, minus: u16) {
+        assert!(minus < 512);
+        let calw = match window {
+            CalibrationWindow::Seconds32 => 0b00,
+            CalibrationWindow::Seconds16 => 0b01,
+            CalibrationWindow::Seconds8 => 0b10,
+        };
+        let calr = ((plus as u32) << 15) | (calw << 13) | minus as u32;
+        self.write_protected(|rtc| {
+            // RECALPF, set while a previous calibration is being applied
+            while rtc.isr.read().bits() & (1 << 16) != 0 {}
+            rtc.calr.write(|w| unsafe { w.bits(calr) });
+        });
+    }
+
+    /// Corrects the RTC clock frequency by `ppm` parts per million, from -487 to +488, using
+    /// the smooth calibration with a 32 second window
+    ///
+    /// A positive correction speeds the calendar up, e.g. for a crystal running slow.
+    pub fn set_calibration_ppm(&mut self, ppm: i32) {
+        // 2^20 RTC clock cycles in the 32 second window
+        let pulses = (ppm * (1 << 20) + ppm.signum() * 500_000) / 1_000_000;
+        assert!(pulses >= -511 && pulses <= 512);
+        if pulses > 0 {
+            self.set_smooth_calibration(CalibrationWindow::Seconds32, true, (512 - pulses) as u16);
+        } else {
+            self.set_smooth_calibration(CalibrationWindow::Seconds32, false, -pulses as u16);
+        }
+    }
+
+    /// Outputs the calibration clock on the RTC_OUT pin (PC13)
+    ///
+    /// The 512 Hz output allows measuring the crystal frequency for trimming, and the 1 Hz one
+    /// checks the result of the smooth calibration. The RTC overrides the GPIO configuration
+    /// of the pin, and the alarm output has priority over this one.
+    pub fn enable_calibration_output(&mut self, output: CalibrationOutput) {
+        let cosel = match output {
+            CalibrationOutput::Hz512 => false,
+            CalibrationOutput::Hz1 => true,
+        };
+        // COE, COSEL
+        let bits = (1 << 23) | ((cosel as u32) << 19);
+        self.write_protected(|rtc| {
+            rtc.cr
+                .modify(|r, w| unsafe { w.bits((r.bits() & !(1 << 19)) | bits) })
+        });
+    }
+
+    /// Stops the calibration output
+    pub fn disable_calibration_output(&mut self) {
+        self.write_protected(|rtc| {
+            rtc.cr
+                .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << 23)) })
+        });
+    }
+
+    /// Shifts the calendar by a fraction of a second, delaying it by `subtract` sub-second
+    /// counts, and advancing it by one second if `add_second` is set
+    ///
+    /// This synchronizes the calendar to a remote clock with sub-second precision, without
+    /// stopping it.
+    pub fn shift(&mut self, add_second: bool, subtract: u16) {
+        assert!(subtract < 1 << 15);
+        let shiftr = ((add_second as u32) << 31) | subtract as u32;
+        // a shift can't be applied while the sub-second counter overflows
+        while self.rtc.ssr.read().bits() & (1 << 15) != 0 {}
+        self.write_protected(|rtc| {
+            // SHPF, set while a previous shift is being applied
+            while rtc.isr.read().bits() & (1 << 3) != 0 {}
+            rtc.shiftr.write(|w| unsafe { w.bits(shiftr) });
+        });
+        // the shadow registers are only valid again once the shift has been applied
+        while self.rtc.isr.read().bits() & (1 << 3) != 0 {}
+        self.resync();
+    }
+
+    /// Returns the value of a backup register, from 0 to 4
+    ///
+    /// The backup registers keep their value through resets and in Standby mode, until a
+    /// backup domain reset or a tamper event.
+    pub fn read_backup(&self, index: usize) -> u32 {
+        match index {
+            0 => self.rtc.bkp0r.read().bits(),
+            1 => self.rtc.bkp1r.read().bits(),
+            2 => self.rtc.bkp2r.read().bits(),
+            3 => self.rtc.bkp3r.read().bits(),
+            4 => self.rtc.bkp4r.read().bits(),
+            _ => panic!("invalid backup register"),
+        }
+    }
+
+    /// Sets the value of a backup register, from 0 to 4
+    pub fn write_backup(&mut self, index: usize, value: u32) {
+        assert!(index < BACKUP_REGISTERS);
+        self.write_protected(|rtc| unsafe {
+            match index {
+                0 => rtc.bkp0r.write(|w| w.bits(value)),
+                1 => rtc.bkp1r.write(|w| w.bits(value)),
+                2 => rtc.bkp2r.write(|w| w.bits(value)),
+                3 => rtc.bkp3r.write(|w| w.bits(value)),
+                _ => rtc.bkp4r.write(|w| w.bits(value)),
+            }
+        });
+    }
+
+    /// Clears an ISR flag
+    fn clear_flag(&mut self, mask: u32) {
+        // the flags are cleared by writing 0, the other flags are unaffected by writing 1, and
+        // INIT is kept cleared; the RTC ignores the writes while the backup domain is
+        // write protected
+        let rtc = &self.rtc;
+        with_backup_domain_access(|| rtc.isr.write(|w| unsafe { w.bits(!mask & !(1 << 7)) }));
+    }
+
+    /// Returns the sub-second counter, counting down from the synchronous prescaler value to 0
+    /// during each second
+    pub fn get_subseconds(&mut self) -> u16 {
+        self.wait_for_sync();
+        let ssr = self.rtc.ssr.read().bits() as u16;
+        // reading SSR locks the TR and DR shadow registers until DR is read
+        self.rtc.dr.read();
+        ssr
+    }
+
+    /// Returns the date, the time of day and the microseconds elapsed in the current second
+    pub fn get_datetime_us(&mut self) -> (Date, Time, u32) {
+        self.wait_for_sync();
+        // reading SSR locks the TR and DR shadow registers until DR is read
+        let ssr = self.rtc.ssr.read().bits();
+        let tr = self.rtc.tr.read().bits();
+        let dr = self.rtc.dr.read().bits();
+
+        let prediv_s = self.prediv_s();
+        // SSR is above PREDIV_S right after a shift delaying the calendar, before the next second
+        let us = (prediv_s.saturating_sub(ssr) as u64 * 1_000_000 / (prediv_s + 1) as u64) as u32;
+        (Date::from_dr(dr), Time::from_tr(tr), us)
+    }
+
+    fn prediv_s(&self) -> u32 {
+        self.rtc.prer.read().bits() & 0x7FFF
+    }
+
+    /// Adjusts the calendar by `offset` microseconds, less than a second either way, to
+    /// synchronize it with an external time source, e.g. a GPS or network time
+    ///
+    /// A positive offset advances the calendar, a negative one delays it. The resolution is one
+    /// sub-second count, e.g. about 3.9 ms with the LSE.
+    pub fn adjust(&mut self, offset: i32) {
+        assert!(offset > -1_000_000 && offset < 1_000_000);
+        let counts = (self.prediv_s() + 1) as u64;
+        let shift = offset.abs() as u64 * counts / 1_000_000;
+        if shift == 0 {
+            return;
+        }
+        if offset > 0 {
+            // advancing by a fraction is done by adding a second and delaying by the rest
+            self.shift(true, (counts - shift) as u16);
+        } else {
+            self.shift(false, shift as u16);
+        }
+    }
+
+    /// Waits until the shadow registers are synchronized with the calendar
+    fn wait_for_sync(&mut self) {
+        while self.rtc.isr.read().rsf().bit_is_clear() {}
+    }
+
+    /// Invalidates the shadow registers, so that the next read waits for the calendar
+    ///
+    /// This has to be called after waking up from Stop or Standby mode.
+    pub fn resync(&mut self) {
+        self.write_protected(|rtc| rtc.isr.modify(|_, w| w.rsf().clear_bit()));
+    }
+
+    /// Runs `f` with the RTC registers unlocked
+    fn write_protected<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&RTC) -> R,
+    {
+        let rtc = &self.rtc;
+        with_backup_domain_access(|| {
+            rtc.wpr.write(|w| unsafe { w.key().bits(0xCA) });
+            rtc.wpr.write(|w| unsafe { w.key().bits(0x53) });
+            let result = f(rtc);
+            // any wrong key locks the registers again
+            rtc.wpr.write(|w| unsafe { w.key().bits(0xFF) });
+            result
+        })
+    }
+
+    /// Runs `f` with the calendar stopped in initialization mode
+    fn init_mode<F>(rtc: &RTC, f: F)
+    where
+        F: FnOnce(&RTC),
+    {
+        rtc.isr.modify(|_, w| w.init().set_bit());
+        while rtc.isr.read().initf().bit_is_clear() {}
+        f(rtc);
+        rtc.isr
+            .modify(|_, w| w.init().clear_bit().rsf().clear_bit());
+    }
+
+    /// Releases the peripheral
+    ///
+    /// The RTC keeps running.
+    pub fn release(self) -> RTC {
+        self.rtc
+    }
+}
+
+/// Error of the `Rtcc` implementation
+#[cfg(feature = "rtcc")]
+#[derive(Debug)]
+pub enum Error {
+    /// The value is out of range, or the year isn't between 2000 and 2099
+    InvalidInputData,
+}
+
+#[cfg(feature = "rtcc")]
+impl Rtc {
+    fn update<F>(&mut self, f: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut Date, &mut Time),
+    {
+        let (mut date, mut time) = Rtc::get_datetime(self);
+        f(&mut date, &mut time);
+        if time.hours > 23 || time.minutes > 59 || time.seconds > 59 {
+            return Err(Error::InvalidInputData);
+        }
+        if date.year < 2000
+            || date.year > 2099
+            || NaiveDate::try_from(date).is_err()
+            || date.weekday < 1
+            || date.weekday > 7
+        {
+            return Err(Error::InvalidInputData);
+        }
+        Rtc::set_datetime(self, &date, &time);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "rtcc")]
+impl From<&NaiveTime> for Time {
+    fn from(time: &NaiveTime) -> Time {
+        Time {
+            hours: time.hour() as u8,
+            minutes: time.minute() as u8,
+            seconds: time.second() as u8,
+        }
+    }
+}
+
+#[cfg(feature = "rtcc")]
+impl From<Time> for NaiveTime {
+    fn from(time: Time) -> NaiveTime {
+        NaiveTime::from_hms(time.hours as u32, time.minutes as u32, time.seconds as u32)
+    }
+}
+
+#[cfg(feature = "rtcc")]
+impl TryFrom<Date> for NaiveDate {
+    type Error = Error;
+
+    fn try_from(date: Date) -> Result<NaiveDate, Error> {
+        NaiveDate::from_ymd_opt(date.year as i32, date.month as u32, date.day as u32)
+            .ok_or(Error::InvalidInputData)
+    }
+}
+
+#[cfg(feature = "rtcc")]
+impl Rtcc for Rtc {
+    type Error = Error;
+
+    fn get_seconds(&mut self) -> Result<u8, Error> {
+        Ok(Rtc::get_time(self).seconds)
+    }
+
+    fn get_minutes(&mut self) -> Result<u8, Error> {
+        Ok(Rtc::get_time(self).minutes)
+    }
+
+    fn get_hours(&mut self) -> Result<Hours, Error> {
+        Ok(Hours::H24(Rtc::get_time(self).hours))
+    }
+
+    fn get_time(&mut self) -> Result<NaiveTime, Error> {
+        Ok(Rtc::get_time(self).into())
+    }
+
+    fn get_weekday(&mut self) -> Result<u8, Error> {
+        Ok(Rtc::get_date(self).weekday)
+    }
+
+    fn get_day(&mut self) -> Result<u8, Error> {
+        Ok(Rtc::get_date(self).day)
+    }
+
+    fn get_month(&mut self) -> Result<u8, Error> {
+        Ok(Rtc::get_date(self).month)
+    }
+
+    fn get_year(&mut self) -> Result<u16, Error> {
+        Ok(Rtc::get_date(self).year)
+    }
+
+    fn get_date(&mut self) -> Result<NaiveDate, Error> {
+        NaiveDate::try_from(Rtc::get_date(self))
+    }
+
+    fn get_datetime(&mut self) -> Result<NaiveDateTime, Error> {
+        let (date, time) = Rtc::get_datetime(self);
+        Ok(NaiveDate::try_from(date)?.and_time(time.into()))
+    }
+
+    fn set_seconds(&mut self, seconds: u8) -> Result<(), Error> {
+        self.update(|_, time| time.seconds = seconds)
+    }
+
+    fn set_minutes(&mut self, minutes: u8) -> Result<(), Error> {
+        self.update(|_, time| time.minutes = minutes)
+    }
+
+    fn set_hours(&mut self, hours: Hours) -> Result<(), Error> {
+        let hours = match hours {
+            Hours::H24(h) => h,
+            Hours::AM(h) if h >= 1 && h <= 12 => h % 12,
+            Hours::PM(h) if h >= 1 && h <= 12 => h % 12 + 12,
+            _ => return Err(Error::InvalidInputData),
+        };
+        self.update(|_, time| time.hours = hours)
+    }
+
+    fn set_time(&mut self, time: &NaiveTime) -> Result<(), Error> {
+        self.update(|_, t| *t = time.into())
+    }
+
+    fn set_weekday(&mut self, weekday: u8) -> Result<(), Error> {
+        self.update(|date, _| date.weekday = weekday)
+    }
+
+    fn set_day(&mut self, day: u8) -> Result<(), Error> {
+        self.update(|date, _| date.day = day)
+    }
+
+    fn set_month(&mut self, month: u8) -> Result<(), Error> {
+        self.update(|date, _| date.month = month)
+    }
+
+    fn set_year(&mut self, year: u16) -> Result<(), Error> {
+        self.update(|date, _| date.year = year)
+    }
+
+    fn set_date(&mut self, date: &NaiveDate) -> Result<(), Error> {
+        let (year, month, day) = (date.year(), date.month(), date.day());
+        let weekday = date.weekday().number_from_monday();
+        if year < 2000 || year > 2099 {
+            return Err(Error::InvalidInputData);
+        }
+        self.update(|d, _| {
+            *d = Date {
+                year: year as u16,
+                month: month as u8,
+                day: day as u8,
+                weekday: weekday as u8,
+            }
+        })
+    }
+
+    fn set_datetime(&mut self, datetime: &NaiveDateTime) -> Result<(), Error> {
+        let year = datetime.year();
+        if year < 2000 || year > 2099 {
+            return Err(Error::InvalidInputData);
+        }
+        let date = Date {
+            year: year as u16,
+            month: datetime.month() as u8,
+            day: datetime.day() as u8,
+            weekday: datetime.weekday().number_from_monday() as u8,
+        };
+        Rtc::set_datetime(self, &date, &(&datetime.time()).into());
+        Ok(())
+    }
+}
@@ -0,0 +1,1077 @@
+//! Serial communication using the USARTs
+
+use core::ptr;
+
+use cortex_m::interrupt;
+use embedded_hal::spi::{Mode, Phase, Polarity};
+use embedded_hal::{blocking, serial, spi};
+use nb;
+use stm32l0x3::{USART1, USART2};
+
+use crate::buffered_serial::BufferedUart;
+use crate::dma::{self, DmaRx, DmaTx};
+use crate::gpio::gpioa::{PA10, PA14, PA15, PA2, PA3, PA4, PA8, PA9};
+use crate::gpio::gpiob::{PB6, PB7};
+use crate::gpio::{AF0, AF4};
+use crate::rcc::{Clocks, APB1, APB2};
+use crate::time::{Bps, Hertz};
+
+/// Serial error
+#[derive(Debug)]
+pub enum Error {
+    /// Framing error
+    Framing,
+    /// Noise error
+    Noise,
+    /// RX buffer overrun
+    Overrun,
+    /// Parity check error
+    Parity,
+    /// Automatic baud rate detection failed
+    AutoBaudRate,
+    /// Smartcard did not acknowledge a character after all retries
+    Nack,
+    #[doc(hidden)]
+    _Extensible,
+}
+
+impl embedded_io::Error for Error {
+    fn kind(&self) -> embedded_io::ErrorKind {
+        match self {
+            Error::Framing | Error::Noise | Error::Parity => embedded_io::ErrorKind::InvalidData,
+            _ => embedded_io::ErrorKind::Other,
+        }
+    }
+}
+
+// FIXME these should be "closed" traits
+/// TX pin -- DO NOT IMPLEMENT THIS TRAIT
+pub unsafe trait TxPin<USART> {}
+
+/// RX pin -- DO NOT IMPLEMENT THIS TRAIT
+pub unsafe trait RxPin<USART> {}
+
+unsafe impl TxPin<USART1> for PA9<AF4> {}
+unsafe impl TxPin<USART1> for PB6<AF0> {}
+
+unsafe impl RxPin<USART1> for PA10<AF4> {}
+unsafe impl RxPin<USART1> for PB7<AF0> {}
+
+unsafe impl TxPin<USART2> for PA2<AF4> {}
+unsafe impl TxPin<USART2> for PA14<AF4> {}
+
+unsafe impl RxPin<USART2> for PA3<AF4> {}
+unsafe impl RxPin<USART2> for PA15<AF4> {}
+
+/// CK pin -- DO NOT IMPLEMENT THIS TRAIT
+pub unsafe trait CkPin<USART> {}
+
+unsafe impl CkPin<USART1> for PA8<AF4> {}
+
+unsafe impl CkPin<USART2> for PA4<AF4> {}
+
+/// Number of data bits per character, including the parity bit
+pub enum WordLength {
+    Word7Bits,
+    Word8Bits,
+    Word9Bits,
+}
+
+impl WordLength {
+    fn cr1_bits(&self) -> (bool, bool) {
+        match self {
+            WordLength::Word8Bits => (false, false),
+            WordLength::Word9Bits => (false, true),
+            WordLength::Word7Bits => (true, false),
+        }
+    }
+}
+
+/// Parity control
+pub enum Parity {
+    ParityNone,
+    ParityEven,
+    ParityOdd,
+}
+
+/// Number of stop bits
+pub enum StopBits {
+    StopBits1,
+    StopBits0_5,
+    StopBits2,
+    StopBits1_5,
+}
+
+impl StopBits {
+    fn cr2_bits(&self) -> u8 {
+        match self {
+            StopBits::StopBits1 => 0b00,
+            StopBits::StopBits0_5 => 0b01,
+            StopBits::StopBits2 => 0b10,
+            StopBits::StopBits1_5 => 0b11,
+        }
+    }
+}
+
+/// Receiver oversampling
+///
+/// Oversampling by 8 doubles the maximum baud rate to an eighth of the kernel clock
+/// (e.g. 4 Mbaud at 32 MHz), at the cost of a lower tolerance to clock deviation.
+pub enum Oversampling {
+    Oversampling16,
+    Oversampling8,
+}
+
+/// Computes the BRR value for a baud rate, checking USARTDIV stays in range
+fn brr_bits(clk: u32, baud_rate: u32, oversampling: &Oversampling) -> u32 {
+    match oversampling {
+        Oversampling::Oversampling16 => {
+            let usartdiv = (clk + baud_rate / 2) / baud_rate;
+            assert!(usartdiv >= 16 && usartdiv <= 0xFFFF);
+            usartdiv
+        }
+        Oversampling::Oversampling8 => {
+            let usartdiv = (2 * clk + baud_rate / 2) / baud_rate;
+            assert!(usartdiv >= 16 && usartdiv <= 0xFFFF);
+            // BRR[2:0] = USARTDIV[3:0] >> 1 and BRR[3] must be kept cleared
+            (usartdiv & !0xF) | ((usartdiv & 0xF) >> 1)
+        }
+    }
+}
+
+/// Computes the baud rate a BRR value results in
+fn baud_rate_from_brr(clk: u32, brr: u32, over8: bool) -> u32 {
+    if over8 {
+        let usartdiv = (brr & !0xF) | ((brr & 0x7) << 1);
+        2 * clk / usartdiv
+    } else {
+        clk / brr
+    }
+}
+
+/// Serial configuration
+pub struct Config {
+    baud_rate: Bps,
+    word_length: WordLength,
+    parity: Parity,
+    stop_bits: StopBits,
+    oversampling: Oversampling,
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Config {
+            baud_rate: Bps(115_200),
+            word_length: WordLength::Word8Bits,
+            parity: Parity::ParityNone,
+            stop_bits: StopBits::StopBits1,
+            oversampling: Oversampling::Oversampling16,
+        }
+    }
+
+    pub fn baud_rate(mut self, baud_rate: Bps) -> Self {
+        self.baud_rate = baud_rate;
+        self
+    }
+
+    pub fn word_length(mut self, word_length: WordLength) -> Self {
+        self.word_length = word_length;
+        self
+    }
+
+    pub fn parity(mut self, parity: Parity) -> Self {
+        self.parity = parity;
+        self
+    }
+
+    pub fn stop_bits(mut self, stop_bits: StopBits) -> Self {
+        self.stop_bits = stop_bits;
+        self
+    }
+
+    pub fn oversampling(mut self, oversampling: Oversampling) -> Self {
+        self.oversampling = oversampling;
+        self
+    }
+}
+
+/// Automatic baud rate detection mode
+///
+/// Selects which character the receiver expects in order to measure the baud rate
+pub enum AutoBaudRateMode {
+    /// Any character starting with a 1 bit; measures the start bit
+    StartBit,
+    /// Any character starting with 10xx; measures falling edge to falling edge
+    FallingEdge,
+    /// A 0x7F frame
+    Frame0x7F,
+    /// A 0x55 frame
+    Frame0x55,
+}
+
+impl AutoBaudRateMode {
+    fn cr2_bits(&self) -> (bool, bool) {
+        match self {
+            AutoBaudRateMode::StartBit => (false, false),
+            AutoBaudRateMode::FallingEdge => (false, true),
+            AutoBaudRateMode::Frame0x7F => (true, false),
+            AutoBaudRateMode::Frame0x55 => (true, true),
+        }
+    }
+}
+
+/// Nominal IrDA low-power mode pulse clock
+const IRDA_LP_FREQ: u32 = 1_843_200; // Hz
+
+/// IrDA SIR power mode
+pub enum IrdaMode {
+    /// 3/16 bit period pulses
+    Normal,
+    /// Pulses of 3 periods of the prescaled low-power clock
+    LowPower,
+}
+
+/// LIN break detection length
+pub enum LinBreakLength {
+    Bits10,
+    Bits11,
+}
+
+/// Synchronous mode configuration
+pub struct SyncConfig {
+    mode: Mode,
+    msb_first: bool,
+    last_bit_clock: bool,
+}
+
+impl SyncConfig {
+    pub fn new(mode: Mode) -> Self {
+        SyncConfig {
+            mode,
+            msb_first: true,
+            last_bit_clock: true,
+        }
+    }
+
+    /// Shifts the most significant bit out first (default) instead of the least
+    pub fn msb_first(mut self, msb_first: bool) -> Self {
+        self.msb_first = msb_first;
+        self
+    }
+
+    /// Outputs a clock pulse for the last data bit (default)
+    pub fn last_bit_clock(mut self, last_bit_clock: bool) -> Self {
+        self.last_bit_clock = last_bit_clock;
+        self
+    }
+}
+
+/// Serial abstraction
+pub struct Serial<USART, PINS> {
+    usart: USART,
+    pins: PINS,
+    clk: Hertz,
+}
+
+/// USART operating as a synchronous (clocked) master
+///
+/// Data is shifted out on TX and sampled on RX, which makes it usable as a simple SPI
+/// master for shift registers and similar peripherals.
+pub struct SyncSerial<USART, PINS, CK> {
+    serial: Serial<USART, PINS>,
+    ck: CK,
+}
+
+macro_rules! hal {
+    ($(
+        $USARTX:ident: ($usartX:ident, $APB:ident, $usartXen:ident, $usartXrst:ident, $pclkX:ident),
+    )+) => {
+        $(
+            impl<TX, RX> Serial<$USARTX, (TX, RX)> {
+                /// Configures a USART peripheral to provide serial communication
+                pub fn $usartX(
+                    usart: $USARTX,
+                    pins: (TX, RX),
+                    config: Config,
+                    clocks: &Clocks,
+                    apb: &mut $APB,
+                ) -> Self
+                where
+                    TX: TxPin<$USARTX>,
+                    RX: RxPin<$USARTX>,
+                {
+                    apb.enr().modify(|_, w| w.$usartXen().set_bit());
+                    apb.rstr().modify(|_, w| w.$usartXrst().set_bit());
+                    apb.rstr().modify(|_, w| w.$usartXrst().clear_bit());
+
+                    // The kernel clock defaults to the APB clock (CCIPR USARTxSEL = 00)
+                    let clk = clocks.$pclkX();
+
+                    let brr = brr_bits(clk.0, config.baud_rate.0, &config.oversampling);
+                    let over8 = match config.oversampling {
+                        Oversampling::Oversampling16 => false,
+                        Oversampling::Oversampling8 => true,
+                    };
+                    usart.brr.write(|w| unsafe { w.bits(brr) });
+
+                    let (m1, m0) = config.word_length.cr1_bits();
+                    let (pce, ps) = match config.parity {
+                        Parity::ParityNone => (false, false),
+                        Parity::ParityEven => (true, false),
+                        Parity::ParityOdd => (true, true),
+                    };
+                    usart
+                        .cr2
+                        .write(|w| unsafe { w.stop().bits(config.stop_bits.cr2_bits()) });
+                    usart.cr1.write(|w| {
+                        w.m1()
+                            .bit(m1)
+                            .m0()
+                            .bit(m0)
+                            .pce()
+                            .bit(pce)
+                            .ps()
+                            .bit(ps)
+                            .over8()
+                            .bit(over8)
+                            .ue()
+                            .set_bit()
+                            .re()
+                            .set_bit()
+                            .te()
+                            .set_bit()
+                    });
+
+                    Serial { usart, pins, clk }
+                }
+
+                /// Releases the USART peripheral and associated pins
+                pub fn free(self) -> ($USARTX, (TX, RX)) {
+                    (self.usart, self.pins)
+                }
+            }
+
+            impl<PINS> Serial<$USARTX, PINS> {
+                /// Returns the current baud rate
+                pub fn baud_rate(&self) -> Bps {
+                    let brr = self.usart.brr.read().bits();
+                    let over8 = self.usart.cr1.read().over8().bit_is_set();
+                    Bps(baud_rate_from_brr(self.clk.0, brr, over8))
+                }
+
+                /// Enables automatic baud rate detection using the given mode
+                ///
+                /// The measurement starts on the next received character. Poll
+                /// `auto_baud_rate` for the result.
+                pub fn enable_auto_baud_rate(&mut self, mode: AutoBaudRateMode) {
+                    let (abrmod1, abrmod0) = mode.cr2_bits();
+
+                    // ABREN and ABRMOD can only be written while the USART is disabled
+                    self.usart.cr1.modify(|_, w| w.ue().clear_bit());
+                    self.usart.cr2.modify(|_, w| {
+                        w.abren()
+                            .set_bit()
+                            .abrmod1()
+                            .bit(abrmod1)
+                            .abrmod0()
+                            .bit(abrmod0)
+                    });
+                    self.usart.cr1.modify(|_, w| w.ue().set_bit());
+                }
+
+                /// Disables automatic baud rate detection
+                pub fn disable_auto_baud_rate(&mut self) {
+                    self.usart.cr1.modify(|_, w| w.ue().clear_bit());
+                    self.usart.cr2.modify(|_, w| w.abren().clear_bit());
+                    self.usart.cr1.modify(|_, w| w.ue().set_bit());
+                }
+
+                /// Restarts the baud rate measurement on the next received character
+                pub fn request_auto_baud_rate(&mut self) {
+                    self.usart.rqr.write(|w| w.abrrq().set_bit());
+                }
+
+                /// Returns the detected baud rate once the measurement has completed
+                ///
+                /// The baud rate register is updated by hardware, so after a successful
+                /// measurement the USART is already running at the detected rate.
+                pub fn auto_baud_rate(&self) -> nb::Result<Bps, Error> {
+                    let isr = self.usart.isr.read();
+
+                    if isr.abre().bit_is_set() {
+                        Err(nb::Error::Other(Error::AutoBaudRate))
+                    } else if isr.abrf().bit_is_set() {
+                        Ok(self.baud_rate())
+                    } else {
+                        Err(nb::Error::WouldBlock)
+                    }
+                }
+
+                /// Enables the IrDA SIR encoder / decoder
+                ///
+                /// IrDA is limited to 115200 baud with 1 stop bit. In low-power mode the
+                /// pulse width is derived from the kernel clock divided by the prescaler,
+                /// which has to land between 1.42 MHz and 2.12 MHz.
+                pub fn enable_irda(&mut self, mode: IrdaMode) {
+                    // IrDA only supports oversampling by 16
+                    assert!(self.usart.cr1.read().over8().bit_is_clear());
+                    assert!(self.baud_rate().0 <= 115_200);
+
+                    let (irlp, psc) = match mode {
+                        // PSC must be 1 in normal mode
+                        IrdaMode::Normal => (false, 1),
+                        IrdaMode::LowPower => {
+                            let psc = (self.clk.0 + IRDA_LP_FREQ / 2) / IRDA_LP_FREQ;
+                            assert!(psc >= 1 && psc <= 255);
+                            let lp_freq = self.clk.0 / psc;
+                            assert!(lp_freq >= 1_420_000 && lp_freq <= 2_120_000);
+                            (true, psc as u8)
+                        }
+                    };
+
+                    // IrDA can only be configured while the USART is disabled
+                    self.usart.cr1.modify(|_, w| w.ue().clear_bit());
+                    self.usart.gtpr.modify(|_, w| unsafe { w.psc().bits(psc) });
+                    self.usart.cr2.modify(|_, w| unsafe {
+                        w.linen()
+                            .clear_bit()
+                            .clken()
+                            .clear_bit()
+                            .stop()
+                            .bits(0b00)
+                    });
+                    self.usart.cr3.modify(|_, w| {
+                        w.scen()
+                            .clear_bit()
+                            .hdsel()
+                            .clear_bit()
+                            .irlp()
+                            .bit(irlp)
+                            .iren()
+                            .set_bit()
+                    });
+                    self.usart.cr1.modify(|_, w| w.ue().set_bit());
+                }
+
+                /// Disables the IrDA SIR encoder / decoder
+                pub fn disable_irda(&mut self) {
+                    self.usart.cr1.modify(|_, w| w.ue().clear_bit());
+                    self.usart
+                        .cr3
+                        .modify(|_, w| w.iren().clear_bit().irlp().clear_bit());
+                    self.usart.cr1.modify(|_, w| w.ue().set_bit());
+                }
+
+                /// Enables LIN mode with the given break detection length
+                ///
+                /// LIN frames use 8 data bits, no parity and 1 stop bit.
+                pub fn enable_lin(&mut self, break_length: LinBreakLength) {
+                    let lbdl = match break_length {
+                        LinBreakLength::Bits10 => false,
+                        LinBreakLength::Bits11 => true,
+                    };
+
+                    // LIN can only be configured while the USART is disabled
+                    self.usart.cr1.modify(|_, w| w.ue().clear_bit());
+                    self.usart.cr1.modify(|_, w| {
+                        w.m1()
+                            .clear_bit()
+                            .m0()
+                            .clear_bit()
+                            .pce()
+                            .clear_bit()
+                    });
+                    self.usart.cr2.modify(|_, w| unsafe {
+                        w.clken()
+                            .clear_bit()
+                            .stop()
+                            .bits(0b00)
+                            .lbdl()
+                            .bit(lbdl)
+                            .linen()
+                            .set_bit()
+                    });
+                    self.usart.cr3.modify(|_, w| {
+                        w.scen()
+                            .clear_bit()
+                            .hdsel()
+                            .clear_bit()
+                            .iren()
+                            .clear_bit()
+                    });
+                    self.usart.cr1.modify(|_, w| w.ue().set_bit());
+                }
+
+                /// Disables LIN mode
+                pub fn disable_lin(&mut self) {
+                    self.usart.cr1.modify(|_, w| w.ue().clear_bit());
+                    self.usart
+                        .cr2
+                        .modify(|_, w| w.linen().clear_bit().lbdie().clear_bit());
+                    self.usart.cr1.modify(|_, w| w.ue().set_bit());
+                }
+
+                /// Sends a break character (13 dominant bits in LIN mode) after the
+                /// current transmission
+                pub fn send_break(&mut self) {
+                    self.usart.rqr.write(|w| w.sbkrq().set_bit());
+                }
+
+                /// Returns true while a break is still being transmitted
+                pub fn is_sending_break(&self) -> bool {
+                    self.usart.isr.read().sbkf().bit_is_set()
+                }
+
+                /// Returns true if a LIN break has been detected
+                pub fn is_break_detected(&self) -> bool {
+                    self.usart.isr.read().lbdf().bit_is_set()
+                }
+
+                /// Clears the LIN break detected flag
+                pub fn clear_break_detected(&mut self) {
+                    self.usart.icr.write(|w| w.lbdcf().set_bit());
+                }
+
+                pub fn enable_break_interrupt(&mut self) {
+                    self.usart.cr2.modify(|_, w| w.lbdie().set_bit());
+                }
+
+                pub fn disable_break_interrupt(&mut self) {
+                    self.usart.cr2.modify(|_, w| w.lbdie().clear_bit());
+                }
+
+                /// Enables the receiver timeout
+                ///
+                /// The timeout flag is raised when the RX line stays idle for `bits` bit
+                /// durations after the last received character, e.g. 39 bits for the
+                /// 3.5 character Modbus RTU inter-frame gap with 11-bit characters.
+                pub fn enable_receiver_timeout(&mut self, bits: u32) {
+                    assert!(bits <= 0x00FF_FFFF);
+                    self.usart.rtor.modify(|_, w| unsafe { w.rto().bits(bits) });
+                    self.usart.cr2.modify(|_, w| w.rtoen().set_bit());
+                }
+
+                /// Disables the receiver timeout
+                pub fn disable_receiver_timeout(&mut self) {
+                    self.usart.cr2.modify(|_, w| w.rtoen().clear_bit());
+                }
+
+                /// Returns true if the receiver timeout has elapsed
+                pub fn is_receiver_timeout(&self) -> bool {
+                    self.usart.isr.read().rtof().bit_is_set()
+                }
+
+                /// Clears the receiver timeout flag
+                pub fn clear_receiver_timeout(&mut self) {
+                    self.usart.icr.write(|w| w.rtocf().set_bit());
+                }
+
+                pub fn enable_receiver_timeout_interrupt(&mut self) {
+                    self.usart.cr1.modify(|_, w| w.rtoie().set_bit());
+                }
+
+                pub fn disable_receiver_timeout_interrupt(&mut self) {
+                    self.usart.cr1.modify(|_, w| w.rtoie().clear_bit());
+                }
+
+                pub fn enable_rx_interrupt(&mut self) {
+                    self.usart.cr1.modify(|_, w| w.rxneie().set_bit());
+                }
+
+                pub fn disable_rx_interrupt(&mut self) {
+                    self.usart.cr1.modify(|_, w| w.rxneie().clear_bit());
+                }
+
+                pub fn enable_tx_interrupt(&mut self) {
+                    self.usart.cr1.modify(|_, w| w.txeie().set_bit());
+                }
+
+                pub fn disable_tx_interrupt(&mut self) {
+                    self.usart.cr1.modify(|_, w| w.txeie().clear_bit());
+                }
+            }
+
+            impl<PINS> serial::Read<u8> for Serial<$USARTX, PINS> {
+                type Error = Error;
+
+                fn read(&mut self) -> nb::Result<u8, Error> {
+                    let isr = self.usart.isr.read();
+
+                    Err(if isr.pe().bit_is_set() {
+                        self.usart.icr.write(|w| w.pecf().set_bit());
+                        nb::Error::Other(Error::Parity)
+                    } else if isr.fe().bit_is_set() {
+                        self.usart.icr.write(|w| w.fecf().set_bit());
+                        nb::Error::Other(Error::Framing)
+                    } else if isr.nf().bit_is_set() {
+                        self.usart.icr.write(|w| w.ncf().set_bit());
+                        nb::Error::Other(Error::Noise)
+                    } else if isr.ore().bit_is_set() {
+                        self.usart.icr.write(|w| w.orecf().set_bit());
+                        nb::Error::Other(Error::Overrun)
+                    } else if isr.rxne().bit_is_set() {
+                        // NOTE(read_volatile) see `write_volatile` below
+                        return Ok(unsafe {
+                            ptr::read_volatile(&self.usart.rdr as *const _ as *const _)
+                        });
+                    } else {
+                        nb::Error::WouldBlock
+                    })
+                }
+            }
+
+            impl<PINS> BufferedUart for Serial<$USARTX, PINS> {
+                fn read_byte(&mut self) -> Option<u8> {
+                    serial::Read::read(self).ok()
+                }
+
+                fn write_byte(&mut self, byte: u8) {
+                    serial::Write::write(self, byte).ok();
+                }
+
+                fn is_tx_empty(&self) -> bool {
+                    self.usart.isr.read().txe().bit_is_set()
+                }
+
+                fn enable_rx_interrupt(&mut self) {
+                    self.enable_rx_interrupt();
+                }
+
+                fn disable_tx_interrupt(&mut self) {
+                    self.disable_tx_interrupt();
+                }
+
+                fn listen_tx() {
+                    // NOTE(unsafe) the read-modify-write is made atomic by the critical section
+                    interrupt::free(|_| unsafe {
+                        (*$USARTX::ptr()).cr1.modify(|_, w| w.txeie().set_bit())
+                    });
+                }
+
+                fn is_listening_tx() -> bool {
+                    unsafe { (*$USARTX::ptr()).cr1.read().txeie().bit_is_set() }
+                }
+
+                fn is_tx_complete() -> bool {
+                    unsafe { (*$USARTX::ptr()).isr.read().tc().bit_is_set() }
+                }
+            }
+
+            impl<PINS> serial::Write<u8> for Serial<$USARTX, PINS> {
+                type Error = Error;
+
+                fn flush(&mut self) -> nb::Result<(), Error> {
+                    if self.usart.isr.read().tc().bit_is_set() {
+                        Ok(())
+                    } else {
+                        Err(nb::Error::WouldBlock)
+                    }
+                }
+
+                fn write(&mut self, byte: u8) -> nb::Result<(), Error> {
+                    if self.usart.isr.read().txe().bit_is_set() {
+                        // NOTE(unsafe) atomic write to stateless register
+                        // NOTE(write_volatile) 8-bit write that's not possible through the svd2rust API
+                        unsafe { ptr::write_volatile(&self.usart.tdr as *const _ as *mut _, byte) }
+                        Ok(())
+                    } else {
+                        Err(nb::Error::WouldBlock)
+                    }
+                }
+            }
+
+            impl<PINS> Serial<$USARTX, PINS> {
+                /// Switches to synchronous master mode, driving the clock on the CK pin
+                pub fn synchronous<CK>(
+                    self,
+                    ck: CK,
+                    config: SyncConfig,
+                ) -> SyncSerial<$USARTX, PINS, CK>
+                where
+                    CK: CkPin<$USARTX>,
+                {
+                    let cpol = config.mode.polarity == Polarity::IdleHigh;
+                    let cpha = config.mode.phase == Phase::CaptureOnSecondTransition;
+
+                    // Synchronous mode can only be configured while the USART is disabled
+                    self.usart.cr1.modify(|_, w| w.ue().clear_bit());
+                    self.usart.cr2.modify(|_, w| {
+                        w.linen()
+                            .clear_bit()
+                            .msbfirst()
+                            .bit(config.msb_first)
+                            .cpol()
+                            .bit(cpol)
+                            .cpha()
+                            .bit(cpha)
+                            .lbcl()
+                            .bit(config.last_bit_clock)
+                            .clken()
+                            .set_bit()
+                    });
+                    self.usart.cr3.modify(|_, w| {
+                        w.scen()
+                            .clear_bit()
+                            .hdsel()
+                            .clear_bit()
+                            .iren()
+                            .clear_bit()
+                    });
+                    self.usart.cr1.modify(|_, w| w.ue().set_bit());
+
+                    SyncSerial { serial: self, ck }
+                }
+            }
+
+            impl<PINS, CK> SyncSerial<$USARTX, PINS, CK> {
+                /// Leaves synchronous mode, returning the serial interface and the CK pin
+                pub fn release(self) -> (Serial<$USARTX, PINS>, CK) {
+                    let usart = &self.serial.usart;
+                    usart.cr1.modify(|_, w| w.ue().clear_bit());
+                    usart.cr2.modify(|_, w| w.clken().clear_bit());
+                    usart.cr1.modify(|_, w| w.ue().set_bit());
+                    (self.serial, self.ck)
+                }
+            }
+
+            impl<PINS, CK> spi::FullDuplex<u8> for SyncSerial<$USARTX, PINS, CK> {
+                type Error = Error;
+
+                fn read(&mut self) -> nb::Result<u8, Error> {
+                    serial::Read::read(&mut self.serial)
+                }
+
+                fn send(&mut self, byte: u8) -> nb::Result<(), Error> {
+                    serial::Write::write(&mut self.serial, byte)
+                }
+            }
+
+            impl<PINS, CK> blocking::spi::transfer::Default<u8>
+                for SyncSerial<$USARTX, PINS, CK> {}
+
+            impl<PINS, CK> blocking::spi::write::Default<u8>
+                for SyncSerial<$USARTX, PINS, CK> {}
+
+            impl<PINS> embedded_io::ErrorType for Serial<$USARTX, PINS> {
+                type Error = Error;
+            }
+
+            impl<PINS> embedded_io::Read for Serial<$USARTX, PINS> {
+                fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+                    if buffer.is_empty() {
+                        return Ok(0);
+                    }
+
+                    buffer[0] = nb::block!(serial::Read::read(self))?;
+
+                    // Take whatever else is already available, leaving any reception
+                    // error to be reported by the next call
+                    let mut count = 1;
+                    while count < buffer.len() {
+                        let isr = self.usart.isr.read();
+                        let error = isr.pe().bit_is_set()
+                            || isr.fe().bit_is_set()
+                            || isr.nf().bit_is_set()
+                            || isr.ore().bit_is_set();
+                        if error || isr.rxne().bit_is_clear() {
+                            break;
+                        }
+                        buffer[count] = nb::block!(serial::Read::read(self))?;
+                        count += 1;
+                    }
+                    Ok(count)
+                }
+            }
+
+            impl<PINS> embedded_io::ReadReady for Serial<$USARTX, PINS> {
+                fn read_ready(&mut self) -> Result<bool, Error> {
+                    Ok(self.usart.isr.read().rxne().bit_is_set())
+                }
+            }
+
+            impl<PINS> embedded_io::Write for Serial<$USARTX, PINS> {
+                fn write(&mut self, bytes: &[u8]) -> Result<usize, Error> {
+                    if bytes.is_empty() {
+                        return Ok(0);
+                    }
+
+                    nb::block!(serial::Write::write(self, bytes[0]))?;
+
+                    let mut count = 1;
+                    while count < bytes.len() && self.usart.isr.read().txe().bit_is_set() {
+                        nb::block!(serial::Write::write(self, bytes[count]))?;
+                        count += 1;
+                    }
+                    Ok(count)
+                }
+
+                fn flush(&mut self) -> Result<(), Error> {
+                    nb::block!(serial::Write::flush(self))
+                }
+            }
+
+            impl<PINS> embedded_io::WriteReady for Serial<$USARTX, PINS> {
+                fn write_ready(&mut self) -> Result<bool, Error> {
+                    Ok(self.usart.isr.read().txe().bit_is_set())
+                }
+            }
+        )+
+    }
+}
+
+hal! {
+    USART1: (usart1, APB2, usart1en, usart1rst, pclk2),
+    USART2: (usart2, APB1, usart2en, usart2rst, pclk1),
+}
+
+macro_rules! dma {
+    ($(
+        $USARTX:ident: (
+            $tx_request:ident,
+            $rx_request:ident,
+            [$($TXCH:ident: $TXREQ:ident),+],
+            [$($RXCH:ident: $RXREQ:ident),+]
+        ),
+    )+) => {
+        $(
+            $(
+                unsafe impl<PINS> DmaTx<dma::$TXCH> for Serial<$USARTX, PINS> {
+                    type Word = u8;
+
+                    const REQUEST: dma::$TXREQ = dma::$TXREQ::$tx_request;
+
+                    fn tx_address(&self) -> u32 {
+                        &self.usart.tdr as *const _ as u32
+                    }
+
+                    fn enable_tx_dma(&mut self) {
+                        // clear TC, set again once the last byte is out
+                        self.usart.icr.write(|w| w.tccf().set_bit());
+                        self.usart.cr3.modify(|_, w| w.dmat().set_bit());
+                    }
+
+                    fn disable_tx_dma(&mut self) {
+                        self.usart.cr3.modify(|_, w| w.dmat().clear_bit());
+                    }
+                }
+            )+
+
+            $(
+                unsafe impl<PINS> DmaRx<dma::$RXCH> for Serial<$USARTX, PINS> {
+                    type Word = u8;
+
+                    const REQUEST: dma::$RXREQ = dma::$RXREQ::$rx_request;
+
+                    fn rx_address(&self) -> u32 {
+                        &self.usart.rdr as *const _ as u32
+                    }
+
+                    fn enable_rx_dma(&mut self) {
+                        self.usart.cr3.modify(|_, w| w.dmar().set_bit());
+                    }
+
+                    fn disable_rx_dma(&mut self) {
+                        self.usart.cr3.modify(|_, w| w.dmar().clear_bit());
+                    }
+                }
+            )+
+        )+
+    }
+}
+
+dma! {
+    USART1: (
+        Usart1Tx,
+        Usart1Rx,
+        [C2: C2Request, C4: C4Request],
+        [C3: C3Request, C5: C5Request]
+    ),
+    USART2: (
+        Usart2Tx,
+        Usart2Rx,
+        [C4: C4Request, C7: C7Request],
+        [C5: C5Request, C6: C6Request]
+    ),
+}
+
+/// Smartcard (ISO 7816-3) configuration
+pub struct SmartcardConfig {
+    clock: Hertz,
+    etu_clocks: u32,
+    guard_time: u8,
+    nack: bool,
+    retries: u8,
+}
+
+impl SmartcardConfig {
+    /// Creates a configuration providing the given clock to the card
+    ///
+    /// The card clock is the highest one not exceeding `clock`, from the kernel clock divided
+    /// by an even number up to 62.
+    ///
+    /// The baud rate defaults to the ISO 7816-3 initial rate of 372 card clocks per
+    /// elementary time unit (etu).
+    pub fn new<F>(clock: F) -> Self
+    where
+        F: Into<Hertz>,
+    {
+        SmartcardConfig {
+            clock: clock.into(),
+            etu_clocks: 372,
+            guard_time: 12,
+            nack: true,
+            retries: 3,
+        }
+    }
+
+    /// Sets the number of card clocks per etu (F / D)
+    pub fn etu_clocks(mut self, etu_clocks: u32) -> Self {
+        self.etu_clocks = etu_clocks;
+        self
+    }
+
+    /// Sets the guard time in etu
+    pub fn guard_time(mut self, guard_time: u8) -> Self {
+        self.guard_time = guard_time;
+        self
+    }
+
+    /// Enables / disables sending a NACK on parity errors
+    pub fn nack(mut self, nack: bool) -> Self {
+        self.nack = nack;
+        self
+    }
+
+    /// Sets how many times a NACKed character is retransmitted (0 to 7)
+    pub fn retries(mut self, retries: u8) -> Self {
+        self.retries = retries;
+        self
+    }
+}
+
+/// USART1 operating in smartcard mode
+pub struct Smartcard<PINS, CK> {
+    serial: Serial<USART1, PINS>,
+    ck: CK,
+}
+
+impl<PINS> Serial<USART1, PINS> {
+    /// Switches USART1 to smartcard mode, providing the card clock on the CK pin
+    ///
+    /// The TX pin is the bidirectional I/O line and should be configured as open
+    /// drain with a pull up.
+    pub fn smartcard<CK>(self, ck: CK, config: SmartcardConfig) -> Smartcard<PINS, CK>
+    where
+        CK: CkPin<USART1>,
+    {
+        assert!(config.retries <= 7);
+
+        // The card clock is the kernel clock divided by 2 * PSC, rounded up so that it doesn't
+        // exceed the requested frequency
+        assert!(config.clock.0 > 0);
+        let div = 2 * config.clock.0;
+        let psc = (self.clk.0 + div - 1) / div;
+        assert!(psc >= 1 && psc <= 0b1_1111);
+        let card_clock = self.clk.0 / (2 * psc);
+        let baud_rate = card_clock / config.etu_clocks;
+        // smartcard mode only supports oversampling by 16
+        let brr = brr_bits(self.clk.0, baud_rate, &Oversampling::Oversampling16);
+
+        let usart = &self.usart;
+        usart.cr1.modify(|_, w| w.ue().clear_bit());
+        usart.brr.write(|w| unsafe { w.bits(brr) });
+        usart
+            .gtpr
+            .write(|w| unsafe { w.psc().bits(psc as u8).gt().bits(config.guard_time) });
+        // 8 data bits plus even parity, 1.5 stop bits
+        usart.cr1.modify(|_, w| {
+            w.m1()
+                .clear_bit()
+                .m0()
+                .set_bit()
+                .pce()
+                .set_bit()
+                .ps()
+                .clear_bit()
+                .over8()
+                .clear_bit()
+        });
+        usart
+            .cr2
+            .modify(|_, w| unsafe { w.linen().clear_bit().stop().bits(0b11).clken().set_bit() });
+        usart.cr3.modify(|_, w| unsafe {
+            w.iren()
+                .clear_bit()
+                .hdsel()
+                .clear_bit()
+                .scarcnt()
+                .bits(config.retries)
+                .nack()
+                .bit(config.nack)
+                .scen()
+                .set_bit()
+        });
+        usart.cr1.modify(|_, w| w.ue().set_bit());
+
+        Smartcard { serial: self, ck }
+    }
+}
+
+impl<PINS, CK> Smartcard<PINS, CK> {
+    /// Transmits a T=0 block of bytes, blocking until the last character is acknowledged
+    ///
+    /// The receiver is turned off while transmitting so the half-duplex I/O line does
+    /// not echo the sent bytes back.
+    pub fn transmit(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let usart = &self.serial.usart;
+        usart.cr1.modify(|_, w| w.re().clear_bit());
+        usart.icr.write(|w| w.fecf().set_bit().tccf().set_bit());
+
+        let mut result = Ok(());
+        for byte in bytes.iter() {
+            while usart.isr.read().txe().bit_is_clear() {}
+            // NOTE(write_volatile) 8-bit write that's not possible through the svd2rust API
+            unsafe { ptr::write_volatile(&usart.tdr as *const _ as *mut _, *byte) }
+        }
+        loop {
+            let isr = usart.isr.read();
+            if isr.fe().bit_is_set() {
+                // the card NACKed a character more than SCARCNT times
+                usart.icr.write(|w| w.fecf().set_bit());
+                result = Err(Error::Nack);
+                break;
+            } else if isr.tc().bit_is_set() {
+                break;
+            }
+        }
+
+        usart.cr1.modify(|_, w| w.re().set_bit());
+        result
+    }
+
+    /// Receives a T=0 block of bytes, blocking until `buffer` is full
+    pub fn receive(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+        for byte in buffer.iter_mut() {
+            *byte = nb::block!(serial::Read::read(&mut self.serial))?;
+        }
+        Ok(())
+    }
+
+    /// Exchanges a T=0 command: transmits `command`, then receives the response into `response`
+    pub fn exchange(&mut self, command: &[u8], response: &mut [u8]) -> Result<(), Error> {
+        self.transmit(command)?;
+        self.receive(response)
+    }
+
+    /// Leaves smartcard mode, returning the serial interface and the CK pin
+    pub fn release(self) -> (Serial<USART1, PINS>, CK) {
+        let usart = &self.serial.usart;
+        usart.cr1.modify(|_, w| w.ue().clear_bit());
+        usart
+            .cr3
+            .modify(|_, w| w.scen().clear_bit().nack().clear_bit());
+        usart.cr2.modify(|_, w| w.clken().clear_bit());
+        usart.cr1.modify(|_, w| w.ue().set_bit());
+        (self.serial, self.ck)
+    }
+}
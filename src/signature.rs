@@ -0,0 +1,161 @@
+//! Device electronic signature
+//!
+//! Factory data stored in system memory: the unique device ID, e.g. for serial numbers or to
+//! diversify per-unit keys, the flash memory size, and the calibration values of the internal
+//! voltage reference and of the temperature sensor, used by the ADC helpers.
+//!
+//! The STM32L0x3 parts have no package type register; the part is identified by its device and
+//! revision IDs instead, see `Part`.
+
+use core::ptr;
+
+use stm32l0x3::DBG;
+
+const UID: *const u32 = 0x1FF8_0050 as *const u32;
+const FLASH_SIZE: *const u16 = 0x1FF8_007C as *const u16;
+const VREFINT_CAL: *const u16 = 0x1FF8_0078 as *const u16;
+const TS_CAL1: *const u16 = 0x1FF8_007A as *const u16;
+const TS_CAL2: *const u16 = 0x1FF8_007E as *const u16;
+
+/// VDDA at which the calibration values were measured, in millivolts
+pub const CALIBRATION_VDDA_MV: u16 = 3000;
+
+/// Temperature of `TemperatureCal::cal1`, in degrees Celsius
+pub const TS_CAL1_TEMP_C: i16 = 30;
+
+/// Temperature of `TemperatureCal::cal2`, in degrees Celsius
+pub const TS_CAL2_TEMP_C: i16 = 130;
+
+/// 96-bit unique device ID
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Uid([u32; 3]);
+
+impl Uid {
+    /// Reads the unique device ID
+    pub fn get() -> Self {
+        // NOTE(unsafe) read-only access to system memory; the third word is at offset 0x14
+        unsafe {
+            Uid([
+                ptr::read_volatile(UID),
+                ptr::read_volatile(UID.add(1)),
+                ptr::read_volatile(UID.add(5)),
+            ])
+        }
+    }
+
+    /// Returns the ID as words, bits 31:0 first
+    pub fn words(&self) -> [u32; 3] {
+        self.0
+    }
+
+    /// Returns the ID as little-endian bytes, bits 7:0 first
+    pub fn to_bytes(&self) -> [u8; 12] {
+        let mut bytes = [0; 12];
+        for (chunk, word) in bytes.chunks_mut(4).zip(self.0.iter()) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        bytes
+    }
+}
+
+/// Flash memory size
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FlashSize(u16);
+
+impl FlashSize {
+    /// Reads the flash memory size
+    pub fn get() -> Self {
+        // NOTE(unsafe) read-only access to system memory
+        FlashSize(unsafe { ptr::read_volatile(FLASH_SIZE) })
+    }
+
+    /// Returns the size in KiB
+    pub fn kibibytes(&self) -> u16 {
+        self.0
+    }
+
+    /// Returns the size in bytes
+    pub fn bytes(&self) -> u32 {
+        u32::from(self.0) * 1024
+    }
+}
+
+/// Device and revision IDs (DBG_IDCODE)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Part {
+    idcode: u32,
+}
+
+impl Part {
+    /// Reads the part IDs
+    ///
+    /// The register stays readable when the DBG peripheral is disabled.
+    pub fn get() -> Self {
+        // NOTE(unsafe) read-only access
+        Part {
+            idcode: unsafe { (*DBG::ptr()).idcode.read().bits() },
+        }
+    }
+
+    /// Returns the device ID, 0x417 for the STM32L0x3 parts (Category 3), 0x447 for the
+    /// Category 5 ones
+    pub fn device_id(&self) -> u16 {
+        (self.idcode & 0xFFF) as u16
+    }
+
+    /// Returns the revision ID of the silicon
+    pub fn revision_id(&self) -> u16 {
+        (self.idcode >> 16) as u16
+    }
+}
+
+/// Factory conversion result of the internal voltage reference (VREFINT_CAL)
+///
+/// Taken at `CALIBRATION_VDDA_MV` with 12-bit resolution, right aligned.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VRefintCal(u16);
+
+impl VRefintCal {
+    /// Reads the calibration value
+    pub fn get() -> Self {
+        // NOTE(unsafe) read-only access to system memory
+        VRefintCal(unsafe { ptr::read_volatile(VREFINT_CAL) })
+    }
+
+    /// Returns the conversion result
+    pub fn value(&self) -> u16 {
+        self.0
+    }
+}
+
+/// Factory conversion results of the temperature sensor (TS_CAL1, TS_CAL2)
+///
+/// Taken at `CALIBRATION_VDDA_MV` with 12-bit resolution, right aligned.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TemperatureCal {
+    cal1: u16,
+    cal2: u16,
+}
+
+impl TemperatureCal {
+    /// Reads the calibration values
+    pub fn get() -> Self {
+        // NOTE(unsafe) read-only access to system memory
+        unsafe {
+            TemperatureCal {
+                cal1: ptr::read_volatile(TS_CAL1),
+                cal2: ptr::read_volatile(TS_CAL2),
+            }
+        }
+    }
+
+    /// Returns the conversion result at `TS_CAL1_TEMP_C`
+    pub fn cal1(&self) -> u16 {
+        self.cal1
+    }
+
+    /// Returns the conversion result at `TS_CAL2_TEMP_C`
+    pub fn cal2(&self) -> u16 {
+        self.cal2
+    }
+}
@@ -0,0 +1,1391 @@
+//! Serial Peripheral Interface (SPI) bus
+
+use core::ptr;
+use core::sync::atomic::{self, Ordering};
+
+use embedded_hal::blocking;
+use embedded_hal::digital::OutputPin;
+use embedded_hal::spi::{FullDuplex, Mode, Phase, Polarity};
+use embedded_hal_1::spi::{ErrorKind, ErrorType, Operation, SpiBus, SpiDevice};
+use nb;
+use stm32l0x3::{RCC, SPI1, SPI2};
+
+use crate::dma::{self, Direction, WordSize};
+use crate::gpio::gpioa::{PA15, PA4, PA5, PA6, PA7};
+use crate::gpio::gpiob::{PB12, PB13, PB14, PB15, PB3, PB4, PB5};
+use crate::gpio::gpioc::PC6;
+use crate::gpio::AF0;
+use crate::rcc::{Clocks, APB1, APB2};
+use crate::time::Hertz;
+
+/// SPI error
+#[derive(Debug)]
+pub enum Error {
+    /// Overrun occurred
+    Overrun,
+    /// Mode fault occurred
+    ModeFault,
+    /// CRC error
+    Crc,
+    /// DMA transfer error
+    Dma,
+    /// I2S slave transmit underrun
+    Underrun,
+    #[doc(hidden)]
+    _Extensible,
+}
+
+impl embedded_hal_1::spi::Error for Error {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Overrun => ErrorKind::Overrun,
+            Error::ModeFault => ErrorKind::ModeFault,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+// FIXME these should be "closed" traits
+/// SCK pin -- DO NOT IMPLEMENT THIS TRAIT
+pub unsafe trait SckPin<SPI> {}
+
+/// MISO pin -- DO NOT IMPLEMENT THIS TRAIT
+pub unsafe trait MisoPin<SPI> {}
+
+/// MOSI pin -- DO NOT IMPLEMENT THIS TRAIT
+pub unsafe trait MosiPin<SPI> {}
+
+/// NSS pin -- DO NOT IMPLEMENT THIS TRAIT
+pub unsafe trait NssPin<SPI> {}
+
+/// Placeholder for an unused MISO pin, e.g. in half-duplex mode
+pub struct NoMiso;
+
+/// Placeholder for an unused MOSI pin, e.g. in receive-only mode
+pub struct NoMosi;
+
+unsafe impl<SPI> MisoPin<SPI> for NoMiso {}
+unsafe impl<SPI> MosiPin<SPI> for NoMosi {}
+
+unsafe impl SckPin<SPI1> for PA5<AF0> {}
+unsafe impl SckPin<SPI1> for PB3<AF0> {}
+
+unsafe impl MisoPin<SPI1> for PA6<AF0> {}
+unsafe impl MisoPin<SPI1> for PB4<AF0> {}
+
+unsafe impl MosiPin<SPI1> for PA7<AF0> {}
+unsafe impl MosiPin<SPI1> for PB5<AF0> {}
+
+unsafe impl NssPin<SPI1> for PA4<AF0> {}
+unsafe impl NssPin<SPI1> for PA15<AF0> {}
+
+unsafe impl SckPin<SPI2> for PB13<AF0> {}
+
+unsafe impl MisoPin<SPI2> for PB14<AF0> {}
+
+unsafe impl MosiPin<SPI2> for PB15<AF0> {}
+
+unsafe impl NssPin<SPI2> for PB12<AF0> {}
+
+/// Data frame size
+pub enum DataSize {
+    Bits8,
+    Bits16,
+}
+
+/// Frame format
+pub enum FrameFormat {
+    /// Motorola SPI frames
+    Motorola,
+    /// TI synchronous serial frames; clock polarity and phase are fixed and NSS is
+    /// driven by hardware
+    Ti,
+}
+
+/// SPI peripheral operating in full duplex master mode
+pub struct Spi<SPI, PINS> {
+    spi: SPI,
+    pins: PINS,
+}
+
+/// SPI peripheral operating in slave mode
+pub struct SpiSlave<SPI, PINS> {
+    spi: SPI,
+    pins: PINS,
+}
+
+macro_rules! read_data {
+    ($spi:expr, $T:ty) => {{
+        let sr = $spi.sr.read();
+
+        Err(if sr.ovr().bit_is_set() {
+            // OVR is cleared by reading DR followed by SR
+            let _ = $spi.dr.read();
+            let _ = $spi.sr.read();
+            nb::Error::Other(Error::Overrun)
+        } else if sr.modf().bit_is_set() {
+            nb::Error::Other(Error::ModeFault)
+        } else if sr.crcerr().bit_is_set() {
+            $spi.sr.modify(|_, w| w.crcerr().clear_bit());
+            nb::Error::Other(Error::Crc)
+        } else if sr.rxne().bit_is_set() {
+            // NOTE(read_volatile) the access width selects whether one or two data
+            // frames are read, which is not possible through the svd2rust API
+            return Ok(unsafe { ptr::read_volatile(&$spi.dr as *const _ as *const $T) });
+        } else {
+            nb::Error::WouldBlock
+        })
+    }};
+}
+
+macro_rules! send_data {
+    ($spi:expr, $T:ty, $word:expr) => {{
+        let sr = $spi.sr.read();
+
+        Err(if sr.ovr().bit_is_set() {
+            nb::Error::Other(Error::Overrun)
+        } else if sr.modf().bit_is_set() {
+            nb::Error::Other(Error::ModeFault)
+        } else if sr.crcerr().bit_is_set() {
+            nb::Error::Other(Error::Crc)
+        } else if sr.txe().bit_is_set() {
+            // NOTE(write_volatile) see note above
+            unsafe { ptr::write_volatile(&$spi.dr as *const _ as *mut $T, $word) }
+            return Ok(());
+        } else {
+            nb::Error::WouldBlock
+        })
+    }};
+}
+
+/// Returns the prescaler of the APB whose PPRE field is at `shift` in RCC_CFGR, which is the
+/// number of core cycles per APB clock cycle
+fn apb_prescaler(shift: u8) -> u32 {
+    // NOTE(unsafe) read-only access
+    let ppre = unsafe { ((*RCC::ptr()).cfgr.read().bits() >> shift) & 0b111 };
+    if ppre < 0b100 {
+        1
+    } else {
+        1 << (ppre - 0b011)
+    }
+}
+
+macro_rules! spi_bus {
+    ($SPIX:ident, $W:ty, $dff:ident) => {
+        /// Needs frames of the size of the words, see `set_data_size`
+        impl<SCK, MISO, MOSI> SpiBus<$W> for Spi<$SPIX, (SCK, MISO, MOSI)> {
+            fn read(&mut self, words: &mut [$W]) -> Result<(), Error> {
+                assert!(self.spi.cr1.read().dff().$dff());
+                for word in words.iter_mut() {
+                    nb::block!(FullDuplex::<$W>::send(self, 0))?;
+                    *word = nb::block!(FullDuplex::<$W>::read(self))?;
+                }
+                Ok(())
+            }
+
+            fn write(&mut self, words: &[$W]) -> Result<(), Error> {
+                assert!(self.spi.cr1.read().dff().$dff());
+                for word in words.iter() {
+                    nb::block!(FullDuplex::<$W>::send(self, *word))?;
+                    nb::block!(FullDuplex::<$W>::read(self))?;
+                }
+                Ok(())
+            }
+
+            fn transfer(&mut self, read: &mut [$W], write: &[$W]) -> Result<(), Error> {
+                assert!(self.spi.cr1.read().dff().$dff());
+                // the shorter buffer is padded with zeros on the way out and
+                // discarded on the way in
+                for i in 0..read.len().max(write.len()) {
+                    let word = write.get(i).cloned().unwrap_or(0);
+                    nb::block!(FullDuplex::<$W>::send(self, word))?;
+                    let word = nb::block!(FullDuplex::<$W>::read(self))?;
+                    if let Some(r) = read.get_mut(i) {
+                        *r = word;
+                    }
+                }
+                Ok(())
+            }
+
+            fn transfer_in_place(&mut self, words: &mut [$W]) -> Result<(), Error> {
+                assert!(self.spi.cr1.read().dff().$dff());
+                for word in words.iter_mut() {
+                    nb::block!(FullDuplex::<$W>::send(self, *word))?;
+                    *word = nb::block!(FullDuplex::<$W>::read(self))?;
+                }
+                Ok(())
+            }
+
+            fn flush(&mut self) -> Result<(), Error> {
+                while self.spi.sr.read().bsy().bit_is_set() {}
+                Ok(())
+            }
+        }
+    };
+}
+
+macro_rules! hal {
+    ($(
+        $SPIX:ident: (
+            $spiX:ident,
+            $spiX_slave:ident,
+            $spiX_slave_software_nss:ident,
+            $APB:ident,
+            $spiXen:ident,
+            $spiXrst:ident,
+            $pclkX:ident,
+            $ppre:expr,
+            $RXCH:ident,
+            $TXCH:ident,
+            $rx_request:expr,
+            $tx_request:expr
+        ),
+    )+) => {
+        $(
+            impl<SCK, MISO, MOSI> Spi<$SPIX, (SCK, MISO, MOSI)> {
+                /// Configures the SPI peripheral to operate in full duplex master mode
+                ///
+                /// The SPI clock is the highest frequency not exceeding `freq` that can be
+                /// derived from the APB clock, from PCLK / 2 down to PCLK / 256. Slave select is
+                /// managed in software.
+                pub fn $spiX<F>(
+                    spi: $SPIX,
+                    pins: (SCK, MISO, MOSI),
+                    mode: Mode,
+                    freq: F,
+                    clocks: &Clocks,
+                    apb: &mut $APB,
+                ) -> Self
+                where
+                    F: Into<Hertz>,
+                    SCK: SckPin<$SPIX>,
+                    MISO: MisoPin<$SPIX>,
+                    MOSI: MosiPin<$SPIX>,
+                {
+                    apb.enr().modify(|_, w| w.$spiXen().set_bit());
+                    apb.rstr().modify(|_, w| w.$spiXrst().set_bit());
+                    apb.rstr().modify(|_, w| w.$spiXrst().clear_bit());
+
+                    let freq = freq.into().0;
+                    assert!(freq > 0);
+                    // the smallest divider, 2 << BR, not below the ratio, from /2 to /256
+                    let ratio = (clocks.$pclkX().0 + freq - 1) / freq;
+                    let br = (0..0b111).find(|br| 2 << br >= ratio).unwrap_or(0b111);
+
+                    // 8-bit frames, MSB first, software slave select held high
+                    spi.cr1.write(|w| unsafe {
+                        w.cpha()
+                            .bit(mode.phase == Phase::CaptureOnSecondTransition)
+                            .cpol()
+                            .bit(mode.polarity == Polarity::IdleHigh)
+                            .mstr()
+                            .set_bit()
+                            .br()
+                            .bits(br)
+                            .lsbfirst()
+                            .clear_bit()
+                            .ssm()
+                            .set_bit()
+                            .ssi()
+                            .set_bit()
+                            .rxonly()
+                            .clear_bit()
+                            .dff()
+                            .clear_bit()
+                            .bidimode()
+                            .clear_bit()
+                            .spe()
+                            .set_bit()
+                    });
+
+                    Spi { spi, pins }
+                }
+
+                /// Releases the SPI peripheral and associated pins
+                pub fn free(self) -> ($SPIX, (SCK, MISO, MOSI)) {
+                    (self.spi, self.pins)
+                }
+            }
+
+            impl<SCK, MISO, MOSI> ErrorType for Spi<$SPIX, (SCK, MISO, MOSI)> {
+                type Error = Error;
+            }
+
+            spi_bus!($SPIX, u8, bit_is_clear);
+            spi_bus!($SPIX, u16, bit_is_set);
+
+            impl<SCK, MISO, MOSI> FullDuplex<u8> for Spi<$SPIX, (SCK, MISO, MOSI)> {
+                type Error = Error;
+
+                fn read(&mut self) -> nb::Result<u8, Error> {
+                    read_data!(self.spi, u8)
+                }
+
+                fn send(&mut self, byte: u8) -> nb::Result<(), Error> {
+                    send_data!(self.spi, u8, byte)
+                }
+            }
+
+            unsafe impl<SCK, MISO, MOSI> dma::DmaTx<dma::$TXCH> for Spi<$SPIX, (SCK, MISO, MOSI)> {
+                type Word = u8;
+
+                const REQUEST: <dma::$TXCH as dma::Channel>::Request = $tx_request;
+
+                fn tx_address(&self) -> u32 {
+                    &self.spi.dr as *const _ as u32
+                }
+
+                /// Enables the DMA requests, with 8-bit frames
+                fn enable_tx_dma(&mut self) {
+                    assert!(self.spi.cr1.read().dff().bit_is_clear());
+                    self.spi.cr2.modify(|_, w| w.txdmaen().set_bit());
+                }
+
+                fn disable_tx_dma(&mut self) {
+                    self.spi.cr2.modify(|_, w| w.txdmaen().clear_bit());
+                }
+            }
+
+            /// In full duplex, the reception is started before the transmission, which drives
+            /// the clock
+            unsafe impl<SCK, MISO, MOSI> dma::DmaRx<dma::$RXCH> for Spi<$SPIX, (SCK, MISO, MOSI)> {
+                type Word = u8;
+
+                const REQUEST: <dma::$RXCH as dma::Channel>::Request = $rx_request;
+
+                fn rx_address(&self) -> u32 {
+                    &self.spi.dr as *const _ as u32
+                }
+
+                /// Enables the DMA requests, with 8-bit frames
+                fn enable_rx_dma(&mut self) {
+                    assert!(self.spi.cr1.read().dff().bit_is_clear());
+                    self.spi.cr2.modify(|_, w| w.rxdmaen().set_bit());
+                }
+
+                fn disable_rx_dma(&mut self) {
+                    self.spi.cr2.modify(|_, w| w.rxdmaen().clear_bit());
+                }
+            }
+
+            impl<SCK, MISO, MOSI> Spi<$SPIX, (SCK, MISO, MOSI)> {
+                /// Transfers `words` in place using DMA, blocking until done
+                ///
+                /// The bytes of `words` are sent and replaced by the bytes received.
+                pub fn transfer_dma<'w>(
+                    &mut self,
+                    rx_channel: &mut dma::$RXCH,
+                    tx_channel: &mut dma::$TXCH,
+                    words: &'w mut [u8],
+                ) -> Result<&'w [u8], Error> {
+                    assert!(words.len() <= u16::max_value() as usize);
+                    // DMA transfers use 8-bit frames
+                    assert!(self.spi.cr1.read().dff().bit_is_clear());
+                    let dr = &self.spi.dr as *const _ as u32;
+                    let buffer = words.as_mut_ptr() as u32;
+
+                    rx_channel.route($rx_request);
+                    rx_channel.set_peripheral_address(dr);
+                    rx_channel.set_memory_address(buffer);
+                    rx_channel.set_transfer_length(words.len() as u16);
+
+                    tx_channel.route($tx_request);
+                    tx_channel.set_peripheral_address(dr);
+                    tx_channel.set_memory_address(buffer);
+                    tx_channel.set_transfer_length(words.len() as u16);
+
+                    // the buffer must be written before the DMA reads it
+                    atomic::compiler_fence(Ordering::Release);
+                    // RXDMAEN first, then the TX and RX channels, and TXDMAEN last as
+                    // it triggers the first request
+                    self.spi.cr2.modify(|_, w| w.rxdmaen().set_bit());
+                    tx_channel.start(Direction::MemoryToPeripheral, true, WordSize::Bits8);
+                    rx_channel.start(Direction::PeripheralToMemory, true, WordSize::Bits8);
+                    self.spi.cr2.modify(|_, w| w.txdmaen().set_bit());
+
+                    // The last byte has been shifted in once the RX channel completes
+                    let mut result = Ok(());
+                    while !rx_channel.is_complete() {
+                        if rx_channel.has_error() || tx_channel.has_error() {
+                            result = Err(Error::Dma);
+                            break;
+                        }
+                    }
+                    if result.is_ok() && self.spi.cr1.read().crcen().bit_is_set() {
+                        // The hardware sends the CRC after the last DMA request, but the
+                        // received CRC is not part of the DMA transfer
+                        while self.spi.sr.read().rxne().bit_is_clear() {}
+                        let _ = self.spi.dr.read();
+                        result = self.check_crc();
+                    }
+                    while self.spi.sr.read().bsy().bit_is_set() {}
+
+                    self.spi
+                        .cr2
+                        .modify(|_, w| w.txdmaen().clear_bit().rxdmaen().clear_bit());
+                    tx_channel.stop();
+                    rx_channel.stop();
+                    // the DMA writes must be visible before the buffer is read
+                    atomic::compiler_fence(Ordering::Acquire);
+
+                    result.map(|_| &*words)
+                }
+
+                /// Sends `words` using DMA, blocking until done
+                ///
+                /// Received bytes are discarded.
+                pub fn write_dma(
+                    &mut self,
+                    tx_channel: &mut dma::$TXCH,
+                    words: &[u8],
+                ) -> Result<(), Error> {
+                    assert!(words.len() <= u16::max_value() as usize);
+                    // DMA transfers use 8-bit frames
+                    assert!(self.spi.cr1.read().dff().bit_is_clear());
+
+                    tx_channel.route($tx_request);
+                    tx_channel.set_peripheral_address(&self.spi.dr as *const _ as u32);
+                    tx_channel.set_memory_address(words.as_ptr() as u32);
+                    tx_channel.set_transfer_length(words.len() as u16);
+
+                    // the buffer must be written before the DMA reads it
+                    atomic::compiler_fence(Ordering::Release);
+                    tx_channel.start(Direction::MemoryToPeripheral, true, WordSize::Bits8);
+                    self.spi.cr2.modify(|_, w| w.txdmaen().set_bit());
+
+                    let mut result = Ok(());
+                    while !tx_channel.is_complete() {
+                        if tx_channel.has_error() {
+                            result = Err(Error::Dma);
+                            break;
+                        }
+                    }
+                    while self.spi.sr.read().txe().bit_is_clear() {}
+                    while self.spi.sr.read().bsy().bit_is_set() {}
+
+                    self.spi.cr2.modify(|_, w| w.txdmaen().clear_bit());
+                    tx_channel.stop();
+                    // the DMA reads of the buffer must be done before it's reused
+                    atomic::compiler_fence(Ordering::Acquire);
+
+                    // Clear the overrun caused by the discarded bytes
+                    let _ = self.spi.dr.read();
+                    let _ = self.spi.sr.read();
+
+                    result
+                }
+            }
+
+            impl<SCK, MISO, MOSI> blocking::spi::transfer::Default<u8>
+                for Spi<$SPIX, (SCK, MISO, MOSI)>
+            {
+            }
+
+            impl<SCK, MISO, MOSI> blocking::spi::write::Default<u8>
+                for Spi<$SPIX, (SCK, MISO, MOSI)>
+            {
+            }
+
+            impl<SCK, MISO, MOSI> Spi<$SPIX, (SCK, MISO, MOSI)> {
+                /// Sets the data frame size
+                ///
+                /// Use the `u8` or `u16` trait implementations to match the frame size.
+                pub fn set_data_size(&mut self, data_size: DataSize) {
+                    let dff = match data_size {
+                        DataSize::Bits8 => false,
+                        DataSize::Bits16 => true,
+                    };
+
+                    // DFF can only be changed while the SPI is disabled
+                    while self.spi.sr.read().bsy().bit_is_set() {}
+                    self.spi.cr1.modify(|_, w| w.spe().clear_bit());
+                    self.spi.cr1.modify(|_, w| w.dff().bit(dff));
+                    self.spi.cr1.modify(|_, w| w.spe().set_bit());
+                }
+            }
+
+            impl<SCK, MISO, MOSI> Spi<$SPIX, (SCK, MISO, MOSI)> {
+                /// Enables hardware CRC calculation with the given polynomial
+                ///
+                /// The CRC is as wide as the data frame. Once enabled, `transfer_with_crc`
+                /// and the DMA transfers append the CRC and verify the received one.
+                pub fn enable_crc(&mut self, polynomial: u16) {
+                    // CRCEN can only be changed while the SPI is disabled
+                    while self.spi.sr.read().bsy().bit_is_set() {}
+                    self.spi.cr1.modify(|_, w| w.spe().clear_bit());
+                    self.spi.crcpr.write(|w| unsafe { w.crcpoly().bits(polynomial) });
+                    self.spi.cr1.modify(|_, w| w.crcen().set_bit());
+                    self.spi.cr1.modify(|_, w| w.spe().set_bit());
+                }
+
+                /// Disables hardware CRC calculation
+                pub fn disable_crc(&mut self) {
+                    while self.spi.sr.read().bsy().bit_is_set() {}
+                    self.spi.cr1.modify(|_, w| w.spe().clear_bit());
+                    self.spi.cr1.modify(|_, w| w.crcen().clear_bit());
+                    self.spi.cr1.modify(|_, w| w.spe().set_bit());
+                }
+
+                /// Resets the TX and RX CRC calculations
+                pub fn reset_crc(&mut self) {
+                    while self.spi.sr.read().bsy().bit_is_set() {}
+                    self.spi.cr1.modify(|_, w| w.spe().clear_bit());
+                    self.spi.cr1.modify(|_, w| w.crcen().clear_bit());
+                    self.spi.cr1.modify(|_, w| w.crcen().set_bit());
+                    self.spi.cr1.modify(|_, w| w.spe().set_bit());
+                }
+
+                /// Returns the CRC computed over the transmitted data
+                pub fn tx_crc(&self) -> u16 {
+                    self.spi.txcrcr.read().tx_crc().bits()
+                }
+
+                /// Returns the CRC computed over the received data
+                pub fn rx_crc(&self) -> u16 {
+                    self.spi.rxcrcr.read().rx_crc().bits()
+                }
+
+                /// Transfers `words` in place followed by the CRC, blocking until done
+                ///
+                /// The CRC is reset before the transfer. Returns `Error::Crc` if the CRC
+                /// received after the data does not match.
+                pub fn transfer_with_crc<'w>(
+                    &mut self,
+                    words: &'w mut [u8],
+                ) -> Result<&'w [u8], Error> {
+                    assert!(!words.is_empty());
+                    assert!(self.spi.cr1.read().crcen().bit_is_set());
+                    self.reset_crc();
+
+                    let len = words.len();
+                    for (i, word) in words.iter_mut().enumerate() {
+                        nb::block!(FullDuplex::<u8>::send(self, *word))?;
+                        if i == len - 1 {
+                            // CRCNEXT has to be set right after writing the last data
+                            self.spi.cr1.modify(|_, w| w.crcnext().set_bit());
+                        }
+                        *word = nb::block!(FullDuplex::<u8>::read(self))?;
+                    }
+
+                    // Receive the CRC, which is compared by hardware
+                    while self.spi.sr.read().rxne().bit_is_clear() {}
+                    let _ = self.spi.dr.read();
+                    self.check_crc()?;
+
+                    Ok(words)
+                }
+
+                fn check_crc(&mut self) -> Result<(), Error> {
+                    if self.spi.sr.read().crcerr().bit_is_set() {
+                        self.spi.sr.modify(|_, w| w.crcerr().clear_bit());
+                        Err(Error::Crc)
+                    } else {
+                        Ok(())
+                    }
+                }
+            }
+
+            impl<SCK, MISO, MOSI> Spi<$SPIX, (SCK, MISO, MOSI)> {
+                /// Selects the Motorola or TI frame format
+                pub fn set_frame_format(&mut self, format: FrameFormat) {
+                    let frf = match format {
+                        FrameFormat::Motorola => false,
+                        FrameFormat::Ti => true,
+                    };
+
+                    while self.spi.sr.read().bsy().bit_is_set() {}
+                    self.spi.cr1.modify(|_, w| w.spe().clear_bit());
+                    self.spi.cr2.modify(|_, w| w.frf().bit(frf));
+                    self.spi.cr1.modify(|_, w| w.spe().set_bit());
+                }
+
+                /// Enables / disables single-wire half-duplex mode on the MOSI pin
+                ///
+                /// Use `NoMiso` in place of the MISO pin when only the data line is
+                /// connected.
+                pub fn set_half_duplex(&mut self, half_duplex: bool) {
+                    while self.spi.sr.read().bsy().bit_is_set() {}
+                    self.spi.cr1.modify(|_, w| w.spe().clear_bit());
+                    self.spi.cr1.modify(|_, w| {
+                        w.bidimode()
+                            .bit(half_duplex)
+                            .bidioe()
+                            .bit(half_duplex)
+                            .rxonly()
+                            .clear_bit()
+                    });
+                    self.spi.cr1.modify(|_, w| w.spe().set_bit());
+                }
+
+                /// Sends `words` in half-duplex mode, blocking until done
+                pub fn half_duplex_write(&mut self, words: &[u8]) -> Result<(), Error> {
+                    assert!(self.spi.cr1.read().bidimode().bit_is_set());
+
+                    self.spi.cr1.modify(|_, w| w.bidioe().set_bit());
+                    for word in words.iter() {
+                        nb::block!(FullDuplex::<u8>::send(self, *word))?;
+                    }
+                    while self.spi.sr.read().txe().bit_is_clear() {}
+                    while self.spi.sr.read().bsy().bit_is_set() {}
+                    Ok(())
+                }
+
+                /// Receives into `buffer` in half-duplex mode, blocking until done
+                pub fn half_duplex_read(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+                    assert!(self.spi.cr1.read().bidimode().bit_is_set());
+
+                    // Switching to input starts the clock
+                    self.spi.cr1.modify(|_, w| w.spe().clear_bit());
+                    self.spi.cr1.modify(|_, w| w.bidioe().clear_bit());
+                    let result = self.receive_and_stop(buffer);
+                    self.spi.cr1.modify(|_, w| w.bidioe().set_bit());
+                    self.spi.cr1.modify(|_, w| w.spe().set_bit());
+                    result
+                }
+
+                /// Receives into `buffer` in receive-only (simplex) mode, blocking until
+                /// done
+                ///
+                /// Use `NoMosi` in place of the MOSI pin when it is not connected.
+                pub fn receive_only(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+                    while self.spi.sr.read().bsy().bit_is_set() {}
+                    self.spi.cr1.modify(|_, w| w.spe().clear_bit());
+                    self.spi.cr1.modify(|_, w| w.rxonly().set_bit());
+                    let result = self.receive_and_stop(buffer);
+                    self.spi.cr1.modify(|_, w| w.rxonly().clear_bit());
+                    self.spi.cr1.modify(|_, w| w.spe().set_bit());
+                    result
+                }
+
+                /// Runs the continuous receive clock until `buffer` is full
+                ///
+                /// The master keeps clocking while enabled in these modes, so the SPI is
+                /// disabled between the second to last and the last frame.
+                fn receive_and_stop(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+                    let len = buffer.len();
+                    if len == 0 {
+                        return Ok(());
+                    }
+
+                    // roughly one SPI clock period in core cycles
+                    let sck_cycles = (2 << self.spi.cr1.read().br().bits()) * apb_prescaler($ppre);
+
+                    self.spi.cr1.modify(|_, w| w.spe().set_bit());
+                    if len == 1 {
+                        cortex_m::asm::delay(sck_cycles);
+                        self.spi.cr1.modify(|_, w| w.spe().clear_bit());
+                    }
+                    for (i, word) in buffer.iter_mut().enumerate() {
+                        *word = nb::block!(FullDuplex::<u8>::read(self))?;
+                        if len > 1 && i == len - 2 {
+                            cortex_m::asm::delay(sck_cycles);
+                            self.spi.cr1.modify(|_, w| w.spe().clear_bit());
+                        }
+                    }
+                    while self.spi.sr.read().bsy().bit_is_set() {}
+                    Ok(())
+                }
+            }
+
+            impl<SCK, MISO, MOSI> Spi<$SPIX, (SCK, MISO, MOSI)> {
+                /// Hands the slave select over to hardware on the given NSS pin
+                ///
+                /// The NSS output is low while the SPI is enabled, so the SPI stays
+                /// disabled between transactions. Use `transaction` / `write_transaction`,
+                /// which pulse NSS around each transfer; the other transfers and the settings
+                /// are only available with software slave select.
+                pub fn with_hardware_nss<NSS>(
+                    self,
+                    nss: NSS,
+                ) -> Spi<$SPIX, (SCK, MISO, MOSI, NSS)>
+                where
+                    NSS: NssPin<$SPIX>,
+                {
+                    while self.spi.sr.read().bsy().bit_is_set() {}
+                    self.spi.cr1.modify(|_, w| w.spe().clear_bit());
+                    self.spi.cr1.modify(|_, w| w.ssm().clear_bit());
+                    self.spi.cr2.modify(|_, w| w.ssoe().set_bit());
+
+                    let (sck, miso, mosi) = self.pins;
+                    Spi {
+                        spi: self.spi,
+                        pins: (sck, miso, mosi, nss),
+                    }
+                }
+            }
+
+            impl<SCK, MISO, MOSI, NSS> Spi<$SPIX, (SCK, MISO, MOSI, NSS)> {
+                /// Transfers `words` in place with NSS held low, blocking until done
+                pub fn transaction<'w>(
+                    &mut self,
+                    words: &'w mut [u8],
+                ) -> Result<&'w [u8], Error> {
+                    self.spi.cr1.modify(|_, w| w.spe().set_bit());
+                    let mut result = Ok(());
+                    for word in words.iter_mut() {
+                        result = nb::block!(self.send_byte(*word))
+                            .and_then(|_| nb::block!(self.read_byte()))
+                            .map(|w| *word = w);
+                        if result.is_err() {
+                            break;
+                        }
+                    }
+                    self.end_transaction();
+                    result.map(|_| &*words)
+                }
+
+                /// Sends `words` with NSS held low, blocking until done
+                pub fn write_transaction(&mut self, words: &[u8]) -> Result<(), Error> {
+                    self.spi.cr1.modify(|_, w| w.spe().set_bit());
+                    let mut result = Ok(());
+                    for word in words.iter() {
+                        result = nb::block!(self.send_byte(*word))
+                            .and_then(|_| nb::block!(self.read_byte()))
+                            .map(|_| ());
+                        if result.is_err() {
+                            break;
+                        }
+                    }
+                    self.end_transaction();
+                    result
+                }
+
+                /// Returns to software slave select, giving back the NSS pin
+                pub fn without_hardware_nss(self) -> (Spi<$SPIX, (SCK, MISO, MOSI)>, NSS) {
+                    self.spi.cr2.modify(|_, w| w.ssoe().clear_bit());
+                    self.spi.cr1.modify(|_, w| w.ssm().set_bit().ssi().set_bit());
+                    self.spi.cr1.modify(|_, w| w.spe().set_bit());
+
+                    let (sck, miso, mosi, nss) = self.pins;
+                    (
+                        Spi {
+                            spi: self.spi,
+                            pins: (sck, miso, mosi),
+                        },
+                        nss,
+                    )
+                }
+
+                /// Disables the SPI once idle, releasing NSS
+                fn end_transaction(&mut self) {
+                    while self.spi.sr.read().txe().bit_is_clear() {}
+                    while self.spi.sr.read().bsy().bit_is_set() {}
+                    self.spi.cr1.modify(|_, w| w.spe().clear_bit());
+                }
+
+                fn read_byte(&mut self) -> nb::Result<u8, Error> {
+                    read_data!(self.spi, u8)
+                }
+
+                fn send_byte(&mut self, byte: u8) -> nb::Result<(), Error> {
+                    send_data!(self.spi, u8, byte)
+                }
+            }
+
+            /// Needs 16-bit frames, see `set_data_size`
+            impl<SCK, MISO, MOSI> FullDuplex<u16> for Spi<$SPIX, (SCK, MISO, MOSI)> {
+                type Error = Error;
+
+                fn read(&mut self) -> nb::Result<u16, Error> {
+                    assert!(self.spi.cr1.read().dff().bit_is_set());
+                    read_data!(self.spi, u16)
+                }
+
+                fn send(&mut self, word: u16) -> nb::Result<(), Error> {
+                    assert!(self.spi.cr1.read().dff().bit_is_set());
+                    send_data!(self.spi, u16, word)
+                }
+            }
+
+            impl<SCK, MISO, MOSI> blocking::spi::transfer::Default<u16>
+                for Spi<$SPIX, (SCK, MISO, MOSI)>
+            {
+            }
+
+            impl<SCK, MISO, MOSI> blocking::spi::write::Default<u16>
+                for Spi<$SPIX, (SCK, MISO, MOSI)>
+            {
+            }
+
+            impl<SCK, MISO, MOSI, NSS> SpiSlave<$SPIX, (SCK, MISO, MOSI, NSS)> {
+                /// Configures the SPI peripheral as a slave selected by the NSS pin
+                pub fn $spiX_slave(
+                    spi: $SPIX,
+                    pins: (SCK, MISO, MOSI, NSS),
+                    mode: Mode,
+                    apb: &mut $APB,
+                ) -> Self
+                where
+                    SCK: SckPin<$SPIX>,
+                    MISO: MisoPin<$SPIX>,
+                    MOSI: MosiPin<$SPIX>,
+                    NSS: NssPin<$SPIX>,
+                {
+                    apb.enr().modify(|_, w| w.$spiXen().set_bit());
+                    apb.rstr().modify(|_, w| w.$spiXrst().set_bit());
+                    apb.rstr().modify(|_, w| w.$spiXrst().clear_bit());
+
+                    spi.cr1.write(|w| {
+                        w.cpha()
+                            .bit(mode.phase == Phase::CaptureOnSecondTransition)
+                            .cpol()
+                            .bit(mode.polarity == Polarity::IdleHigh)
+                            .mstr()
+                            .clear_bit()
+                            .ssm()
+                            .clear_bit()
+                            .spe()
+                            .set_bit()
+                    });
+
+                    SpiSlave { spi, pins }
+                }
+
+                /// Releases the SPI peripheral and associated pins
+                pub fn free(self) -> ($SPIX, (SCK, MISO, MOSI, NSS)) {
+                    (self.spi, self.pins)
+                }
+            }
+
+            impl<SCK, MISO, MOSI> SpiSlave<$SPIX, (SCK, MISO, MOSI)> {
+                /// Configures the SPI peripheral as a slave selected in software
+                ///
+                /// The slave starts out deselected; call `select` to take part in transfers.
+                pub fn $spiX_slave_software_nss(
+                    spi: $SPIX,
+                    pins: (SCK, MISO, MOSI),
+                    mode: Mode,
+                    apb: &mut $APB,
+                ) -> Self
+                where
+                    SCK: SckPin<$SPIX>,
+                    MISO: MisoPin<$SPIX>,
+                    MOSI: MosiPin<$SPIX>,
+                {
+                    apb.enr().modify(|_, w| w.$spiXen().set_bit());
+                    apb.rstr().modify(|_, w| w.$spiXrst().set_bit());
+                    apb.rstr().modify(|_, w| w.$spiXrst().clear_bit());
+
+                    spi.cr1.write(|w| {
+                        w.cpha()
+                            .bit(mode.phase == Phase::CaptureOnSecondTransition)
+                            .cpol()
+                            .bit(mode.polarity == Polarity::IdleHigh)
+                            .mstr()
+                            .clear_bit()
+                            .ssm()
+                            .set_bit()
+                            .ssi()
+                            .set_bit()
+                            .spe()
+                            .set_bit()
+                    });
+
+                    SpiSlave { spi, pins }
+                }
+
+                /// Selects / deselects the slave through the internal SSI bit
+                pub fn select(&mut self, selected: bool) {
+                    self.spi.cr1.modify(|_, w| w.ssi().bit(!selected));
+                }
+
+                /// Releases the SPI peripheral and associated pins
+                pub fn free(self) -> ($SPIX, (SCK, MISO, MOSI)) {
+                    (self.spi, self.pins)
+                }
+            }
+
+            impl<PINS> SpiSlave<$SPIX, PINS> {
+                /// Returns the byte shifted in from the master, if any
+                pub fn read(&mut self) -> nb::Result<u8, Error> {
+                    read_data!(self.spi, u8)
+                }
+
+                /// Queues a byte to be shifted out on the next transfer from the master
+                pub fn write(&mut self, byte: u8) -> nb::Result<(), Error> {
+                    send_data!(self.spi, u8, byte)
+                }
+
+                /// Returns true while a transfer is ongoing
+                pub fn is_busy(&self) -> bool {
+                    self.spi.sr.read().bsy().bit_is_set()
+                }
+
+                pub fn enable_rx_interrupt(&mut self) {
+                    self.spi.cr2.modify(|_, w| w.rxneie().set_bit());
+                }
+
+                pub fn disable_rx_interrupt(&mut self) {
+                    self.spi.cr2.modify(|_, w| w.rxneie().clear_bit());
+                }
+
+                pub fn enable_tx_interrupt(&mut self) {
+                    self.spi.cr2.modify(|_, w| w.txeie().set_bit());
+                }
+
+                pub fn disable_tx_interrupt(&mut self) {
+                    self.spi.cr2.modify(|_, w| w.txeie().clear_bit());
+                }
+
+                pub fn enable_error_interrupt(&mut self) {
+                    self.spi.cr2.modify(|_, w| w.errie().set_bit());
+                }
+
+                pub fn disable_error_interrupt(&mut self) {
+                    self.spi.cr2.modify(|_, w| w.errie().clear_bit());
+                }
+            }
+        )+
+    }
+}
+
+hal! {
+    SPI1: (
+        spi1,
+        spi1_slave,
+        spi1_slave_software_nss,
+        APB2,
+        spi1en,
+        spi1rst,
+        pclk2,
+        11,
+        C2,
+        C3,
+        dma::C2Request::Spi1Rx,
+        dma::C3Request::Spi1Tx
+    ),
+    SPI2: (
+        spi2,
+        spi2_slave,
+        spi2_slave_software_nss,
+        APB1,
+        spi2en,
+        spi2rst,
+        pclk1,
+        8,
+        C4,
+        C5,
+        dma::C4Request::Spi2Rx,
+        dma::C5Request::Spi2Tx
+    ),
+}
+
+/// SPI device with exclusive access to a bus, managing its chip select pin
+///
+/// Implements the embedded-hal 1.0 `SpiDevice` trait on top of any `SpiBus`, so drivers
+/// written against it can use an `Spi` that is not shared. Shared buses can instead
+/// be wrapped with the devices from the `embedded-hal-bus` crate.
+pub struct ExclusiveDevice<BUS, CS> {
+    bus: BUS,
+    cs: CS,
+    sysclk: Hertz,
+}
+
+impl<BUS, CS> ExclusiveDevice<BUS, CS>
+where
+    CS: OutputPin,
+{
+    /// Creates a device from a bus and a chip select pin, deasserting the pin
+    ///
+    /// `clocks` are used to time the delays requested in transactions.
+    pub fn new(bus: BUS, mut cs: CS, clocks: &Clocks) -> Self {
+        cs.set_high();
+        ExclusiveDevice {
+            bus,
+            cs,
+            sysclk: clocks.sysclk(),
+        }
+    }
+
+    /// Returns a reference to the underlying bus
+    pub fn bus(&mut self) -> &mut BUS {
+        &mut self.bus
+    }
+
+    /// Releases the bus and chip select pin
+    pub fn free(self) -> (BUS, CS) {
+        (self.bus, self.cs)
+    }
+}
+
+impl<BUS, CS> ErrorType for ExclusiveDevice<BUS, CS>
+where
+    BUS: ErrorType,
+{
+    type Error = BUS::Error;
+}
+
+impl<BUS, CS, W> SpiDevice<W> for ExclusiveDevice<BUS, CS>
+where
+    BUS: SpiBus<W>,
+    CS: OutputPin,
+    W: Copy + 'static,
+{
+    fn transaction(&mut self, operations: &mut [Operation<'_, W>]) -> Result<(), BUS::Error> {
+        self.cs.set_low();
+
+        let mut result = Ok(());
+        for operation in operations.iter_mut() {
+            result = match operation {
+                Operation::Read(words) => self.bus.read(words),
+                Operation::Write(words) => self.bus.write(words),
+                Operation::Transfer(read, write) => self.bus.transfer(read, write),
+                Operation::TransferInPlace(words) => self.bus.transfer_in_place(words),
+                Operation::DelayNs(ns) => self.bus.flush().map(|_| {
+                    let cycles = (*ns as u64 * self.sysclk.0 as u64 + 999_999_999) / 1_000_000_000;
+                    cortex_m::asm::delay(cycles as u32);
+                }),
+            };
+            if result.is_err() {
+                break;
+            }
+        }
+
+        // the chip select is deasserted even if an operation failed
+        let flushed = self.bus.flush();
+        self.cs.set_high();
+
+        result.and(flushed)
+    }
+}
+
+/// I2S word select pin -- DO NOT IMPLEMENT THIS TRAIT
+pub unsafe trait WsPin<SPI> {}
+
+/// I2S serial data pin -- DO NOT IMPLEMENT THIS TRAIT
+pub unsafe trait SdPin<SPI> {}
+
+/// I2S master clock pin -- DO NOT IMPLEMENT THIS TRAIT
+pub unsafe trait MckPin<SPI> {
+    /// Whether the master clock is output
+    const ENABLED: bool;
+}
+
+/// Placeholder for an unused I2S master clock pin
+pub struct NoMck;
+
+unsafe impl WsPin<SPI2> for PB12<AF0> {}
+
+unsafe impl SdPin<SPI2> for PB15<AF0> {}
+
+unsafe impl MckPin<SPI2> for PC6<AF0> {
+    const ENABLED: bool = true;
+}
+
+unsafe impl MckPin<SPI2> for NoMck {
+    const ENABLED: bool = false;
+}
+
+/// I2S operating mode
+pub enum I2sMode {
+    MasterTransmit,
+    MasterReceive,
+}
+
+/// I2S standard
+pub enum I2sStandard {
+    /// I2S Philips standard
+    Philips,
+    /// MSB justified (left justified)
+    MsbJustified,
+    /// LSB justified (right justified)
+    LsbJustified,
+    /// PCM with short frame synchronization
+    PcmShort,
+    /// PCM with long frame synchronization
+    PcmLong,
+}
+
+/// I2S data length and channel length
+pub enum I2sDataFormat {
+    /// 16-bit data in a 16-bit channel
+    Data16Channel16,
+    /// 16-bit data in a 32-bit channel
+    Data16Channel32,
+    /// 24-bit data in a 32-bit channel
+    Data24Channel32,
+    /// 32-bit data in a 32-bit channel
+    Data32Channel32,
+}
+
+impl I2sDataFormat {
+    /// Returns the DATLEN and CHLEN bits
+    fn bits(&self) -> (u8, bool) {
+        match self {
+            I2sDataFormat::Data16Channel16 => (0b00, false),
+            I2sDataFormat::Data16Channel32 => (0b00, true),
+            I2sDataFormat::Data24Channel32 => (0b01, true),
+            I2sDataFormat::Data32Channel32 => (0b10, true),
+        }
+    }
+}
+
+/// I2S configuration
+pub struct I2sConfig {
+    mode: I2sMode,
+    standard: I2sStandard,
+    data_format: I2sDataFormat,
+    sample_rate: Hertz,
+    clock_idle_high: bool,
+}
+
+impl I2sConfig {
+    pub fn new<F>(mode: I2sMode, sample_rate: F) -> Self
+    where
+        F: Into<Hertz>,
+    {
+        I2sConfig {
+            mode,
+            standard: I2sStandard::Philips,
+            data_format: I2sDataFormat::Data16Channel16,
+            sample_rate: sample_rate.into(),
+            clock_idle_high: false,
+        }
+    }
+
+    pub fn standard(mut self, standard: I2sStandard) -> Self {
+        self.standard = standard;
+        self
+    }
+
+    pub fn data_format(mut self, data_format: I2sDataFormat) -> Self {
+        self.data_format = data_format;
+        self
+    }
+
+    /// Sets the clock idle level to high instead of low
+    pub fn clock_idle_high(mut self, clock_idle_high: bool) -> Self {
+        self.clock_idle_high = clock_idle_high;
+        self
+    }
+}
+
+/// SPI2 operating in I2S audio mode
+pub struct I2s<PINS> {
+    spi: SPI2,
+    pins: PINS,
+}
+
+impl<WS, CK, SD, MCK> I2s<(WS, CK, SD, MCK)> {
+    /// Configures SPI2 as an I2S master
+    ///
+    /// The I2S kernel clock is the system clock. Pass `NoMck` instead of a pin when the
+    /// master clock output is not needed; the achievable sample rates depend on it.
+    pub fn i2s2(
+        spi: SPI2,
+        pins: (WS, CK, SD, MCK),
+        config: I2sConfig,
+        clocks: &Clocks,
+        apb1: &mut APB1,
+    ) -> Self
+    where
+        WS: WsPin<SPI2>,
+        CK: SckPin<SPI2>,
+        SD: SdPin<SPI2>,
+        MCK: MckPin<SPI2>,
+    {
+        apb1.enr().modify(|_, w| w.spi2en().set_bit());
+        apb1.rstr().modify(|_, w| w.spi2rst().set_bit());
+        apb1.rstr().modify(|_, w| w.spi2rst().clear_bit());
+
+        let (datlen, chlen) = config.data_format.bits();
+
+        // Fs = I2SCLK / (256 * (2 * I2SDIV + ODD)) with the master clock output,
+        // Fs = I2SCLK / (32 * channel length factor * (2 * I2SDIV + ODD)) without
+        let frame_clocks = if MCK::ENABLED {
+            256
+        } else if chlen {
+            64
+        } else {
+            32
+        };
+        let fs = config.sample_rate.0;
+        let div = (clocks.sysclk().0 + (frame_clocks * fs) / 2) / (frame_clocks * fs);
+        let i2sdiv = div / 2;
+        let odd = div & 1 == 1;
+        assert!(i2sdiv >= 2 && i2sdiv <= 0xFF);
+
+        let (i2sstd, pcmsync) = match config.standard {
+            I2sStandard::Philips => (0b00, false),
+            I2sStandard::MsbJustified => (0b01, false),
+            I2sStandard::LsbJustified => (0b10, false),
+            I2sStandard::PcmShort => (0b11, false),
+            I2sStandard::PcmLong => (0b11, true),
+        };
+        let i2scfg = match config.mode {
+            I2sMode::MasterTransmit => 0b10,
+            I2sMode::MasterReceive => 0b11,
+        };
+
+        spi.i2spr.write(|w| unsafe {
+            w.i2sdiv()
+                .bits(i2sdiv as u8)
+                .odd()
+                .bit(odd)
+                .mckoe()
+                .bit(MCK::ENABLED)
+        });
+        spi.i2scfgr.write(|w| unsafe {
+            w.i2smod()
+                .set_bit()
+                .i2scfg()
+                .bits(i2scfg)
+                .pcmsync()
+                .bit(pcmsync)
+                .i2sstd()
+                .bits(i2sstd)
+                .ckpol()
+                .bit(config.clock_idle_high)
+                .datlen()
+                .bits(datlen)
+                .chlen()
+                .bit(chlen)
+        });
+        spi.i2scfgr.modify(|_, w| w.i2se().set_bit());
+
+        I2s { spi, pins }
+    }
+
+    /// Disables I2S and releases the SPI2 peripheral and associated pins
+    pub fn free(self) -> (SPI2, (WS, CK, SD, MCK)) {
+        self.spi.i2scfgr.modify(|_, w| w.i2se().clear_bit());
+        (self.spi, self.pins)
+    }
+}
+
+impl<PINS> I2s<PINS> {
+    fn is_wide(&self) -> bool {
+        // 24-bit and 32-bit data take two 16-bit DR accesses
+        self.spi.i2scfgr.read().datlen().bits() != 0b00
+    }
+
+    fn write_half_word(&mut self, half_word: u16) -> Result<(), Error> {
+        loop {
+            let sr = self.spi.sr.read();
+            if sr.udr().bit_is_set() {
+                return Err(Error::Underrun);
+            } else if sr.txe().bit_is_set() {
+                break;
+            }
+        }
+        self.spi.dr.write(|w| unsafe { w.dr().bits(half_word) });
+        Ok(())
+    }
+
+    fn read_half_word(&mut self) -> Result<(u16, bool), Error> {
+        loop {
+            let sr = self.spi.sr.read();
+            if sr.ovr().bit_is_set() {
+                // OVR is cleared by reading DR followed by SR
+                let _ = self.spi.dr.read();
+                let _ = self.spi.sr.read();
+                return Err(Error::Overrun);
+            } else if sr.rxne().bit_is_set() {
+                let right = sr.chside().bit_is_set();
+                return Ok((self.spi.dr.read().dr().bits(), right));
+            }
+        }
+    }
+
+    fn write_sample(&mut self, sample: u32) -> Result<(), Error> {
+        if self.is_wide() {
+            self.write_half_word((sample >> 16) as u16)?;
+        }
+        self.write_half_word(sample as u16)
+    }
+
+    fn read_sample(&mut self) -> Result<(u32, bool), Error> {
+        let (high, right) = self.read_half_word()?;
+        if self.is_wide() {
+            let (low, _) = self.read_half_word()?;
+            Ok((((high as u32) << 16) | low as u32, right))
+        } else {
+            Ok((high as u32, right))
+        }
+    }
+
+    /// Sends a stereo frame, blocking until both samples are queued
+    ///
+    /// Samples are left aligned to the data length, e.g. 24-bit samples occupy bits 31:8.
+    pub fn write_frame(&mut self, left: u32, right: u32) -> Result<(), Error> {
+        self.write_sample(left)?;
+        self.write_sample(right)
+    }
+
+    /// Receives a stereo frame, blocking until both samples have arrived
+    pub fn read_frame(&mut self) -> Result<(u32, u32), Error> {
+        // resynchronize on the left channel
+        let left = loop {
+            let (sample, right) = self.read_sample()?;
+            if !right {
+                break sample;
+            }
+        };
+        let (right, _) = self.read_sample()?;
+        Ok((left, right))
+    }
+
+    /// Sends 16-bit DR half-words using DMA, blocking until done
+    ///
+    /// Each sample takes one half-word with 16-bit data, or two (most significant
+    /// first) with 24-bit and 32-bit data.
+    pub fn write_dma(&mut self, tx_channel: &mut dma::C5, half_words: &[u16]) -> Result<(), Error> {
+        assert!(half_words.len() <= u16::max_value() as usize);
+
+        tx_channel.route(dma::C5Request::Spi2Tx);
+        tx_channel.set_peripheral_address(&self.spi.dr as *const _ as u32);
+        tx_channel.set_memory_address(half_words.as_ptr() as u32);
+        tx_channel.set_transfer_length(half_words.len() as u16);
+
+        // the buffer must be written before the DMA reads it
+        atomic::compiler_fence(Ordering::Release);
+        tx_channel.start(Direction::MemoryToPeripheral, true, WordSize::Bits16);
+        self.spi.cr2.modify(|_, w| w.txdmaen().set_bit());
+
+        let mut result = Ok(());
+        while !tx_channel.is_complete() {
+            if tx_channel.has_error() {
+                result = Err(Error::Dma);
+                break;
+            }
+        }
+
+        self.spi.cr2.modify(|_, w| w.txdmaen().clear_bit());
+        tx_channel.stop();
+        // the DMA reads of the buffer must be done before it's reused
+        atomic::compiler_fence(Ordering::Acquire);
+        result
+    }
+
+    /// Receives 16-bit DR half-words using DMA, blocking until `half_words` is full
+    pub fn read_dma(
+        &mut self,
+        rx_channel: &mut dma::C4,
+        half_words: &mut [u16],
+    ) -> Result<(), Error> {
+        assert!(half_words.len() <= u16::max_value() as usize);
+
+        rx_channel.route(dma::C4Request::Spi2Rx);
+        rx_channel.set_peripheral_address(&self.spi.dr as *const _ as u32);
+        rx_channel.set_memory_address(half_words.as_mut_ptr() as u32);
+        rx_channel.set_transfer_length(half_words.len() as u16);
+
+        atomic::compiler_fence(Ordering::Release);
+        rx_channel.start(Direction::PeripheralToMemory, true, WordSize::Bits16);
+        self.spi.cr2.modify(|_, w| w.rxdmaen().set_bit());
+
+        let mut result = Ok(());
+        while !rx_channel.is_complete() {
+            if rx_channel.has_error() {
+                result = Err(Error::Dma);
+                break;
+            }
+        }
+
+        self.spi.cr2.modify(|_, w| w.rxdmaen().clear_bit());
+        rx_channel.stop();
+        // the DMA writes must be visible before the buffer is read
+        atomic::compiler_fence(Ordering::Acquire);
+        result
+    }
+}
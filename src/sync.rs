@@ -0,0 +1,144 @@
+//! Timer synchronization
+//!
+//! A master timer drives its trigger output (TRGO) from one of its events, and the slave timers
+//! are reset, gated, started or clocked by it. For example, a 32-bit counter is made of TIM21
+//! as master in `MasterMode::Update`, clocking TIM2 as slave in `SlaveMode::ExternalClock`. TIM2
+//! can in turn clock TIM22 the same way, for a 48-bit counter.
+
+use stm32l0x3::{TIM2, TIM21, TIM22};
+
+use crate::capture::Capture;
+use crate::pwm::Pwm;
+use crate::timer::Timer;
+
+/// Event driving the trigger output of a master timer
+pub enum MasterMode {
+    /// Counter reset, by software or by a slave mode reset
+    Reset,
+    /// Counter enable
+    Enable,
+    /// Update event
+    Update,
+    /// Capture or compare match on channel 1
+    ComparePulse,
+    /// Output compare reference of channel 1
+    Oc1Ref,
+    /// Output compare reference of channel 2
+    Oc2Ref,
+    /// Output compare reference of channel 3
+    Oc3Ref,
+    /// Output compare reference of channel 4
+    Oc4Ref,
+}
+
+/// Slave timer response to the trigger input
+pub enum SlaveMode {
+    /// The counter is reset on the rising edge of the trigger
+    Reset,
+    /// The counter runs while the trigger is high
+    Gated,
+    /// The counter is stopped, and starts on the rising edge of the trigger
+    Trigger,
+    /// The counter is clocked by the rising edges of the trigger
+    ExternalClock,
+}
+
+/// Timer driver that can act as master of other timers
+pub trait Master {
+    /// Timer peripheral
+    type Timer;
+}
+
+/// Trigger output of a timer, connected to an internal trigger input of `SLAVE` -- DO NOT
+/// IMPLEMENT THIS TRAIT
+pub unsafe trait InternalTrigger<SLAVE> {
+    #[doc(hidden)]
+    const TS: u8;
+}
+
+unsafe impl InternalTrigger<TIM2> for TIM21 {
+    const TS: u8 = 0b000;
+}
+unsafe impl InternalTrigger<TIM2> for TIM22 {
+    const TS: u8 = 0b010;
+}
+
+unsafe impl InternalTrigger<TIM21> for TIM2 {
+    const TS: u8 = 0b000;
+}
+unsafe impl InternalTrigger<TIM21> for TIM22 {
+    const TS: u8 = 0b001;
+}
+
+unsafe impl InternalTrigger<TIM22> for TIM2 {
+    const TS: u8 = 0b000;
+}
+unsafe impl InternalTrigger<TIM22> for TIM21 {
+    const TS: u8 = 0b001;
+}
+
+macro_rules! sync {
+    ($($TIMX:ident: [$($DRIVER:ident),+],)+) => {
+        $(
+            $(
+                impl Master for $DRIVER<$TIMX> {
+                    type Timer = $TIMX;
+                }
+
+                impl $DRIVER<$TIMX> {
+                    /// Selects the event driving the trigger output
+                    pub fn set_master_mode(&mut self, mode: MasterMode) {
+                        let mms = match mode {
+                            MasterMode::Reset => 0b000,
+                            MasterMode::Enable => 0b001,
+                            MasterMode::Update => 0b010,
+                            MasterMode::ComparePulse => 0b011,
+                            MasterMode::Oc1Ref => 0b100,
+                            MasterMode::Oc2Ref => 0b101,
+                            MasterMode::Oc3Ref => 0b110,
+                            MasterMode::Oc4Ref => 0b111,
+                        };
+                        self.tim.cr2.modify(|_, w| unsafe { w.mms().bits(mms) });
+                    }
+
+                    /// Makes the timer a slave of `master`
+                    ///
+                    /// In `SlaveMode::Trigger`, the counter is stopped and reset until the
+                    /// master triggers it, which synchronizes the start of both timers.
+                    pub fn set_slave_mode<M>(&mut self, mode: SlaveMode, _master: &M)
+                    where
+                        M: Master,
+                        M::Timer: InternalTrigger<$TIMX>,
+                    {
+                        let sms = match mode {
+                            SlaveMode::Reset => 0b100,
+                            SlaveMode::Gated => 0b101,
+                            SlaveMode::Trigger => 0b110,
+                            SlaveMode::ExternalClock => 0b111,
+                        };
+                        if let SlaveMode::Trigger = mode {
+                            self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                            self.tim.cnt.write(|w| unsafe { w.bits(0) });
+                        }
+                        // the trigger selection is changed with the slave mode disabled
+                        self.tim.smcr.modify(|_, w| unsafe { w.sms().bits(0b000) });
+                        self.tim.smcr.modify(|_, w| unsafe {
+                            w.ts().bits(<M::Timer as InternalTrigger<$TIMX>>::TS).sms().bits(sms)
+                        });
+                    }
+
+                    /// Stops following the master timer
+                    pub fn disable_slave_mode(&mut self) {
+                        self.tim.smcr.modify(|_, w| unsafe { w.sms().bits(0b000) });
+                    }
+                }
+            )+
+        )+
+    }
+}
+
+sync! {
+    TIM2: [Timer, Pwm, Capture],
+    TIM21: [Timer, Pwm, Capture],
+    TIM22: [Timer, Pwm, Capture],
+}
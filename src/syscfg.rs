@@ -0,0 +1,232 @@
+//! System configuration controller (SYSCFG)
+//!
+//! The SYSCFG_COMP peripheral is shared with the comparators and the EXTI lines, which take it
+//! by reference as well, so this module extends it instead of owning it.
+//!
+//! The memory mapped at address 0x0000_0000, where the core fetches the vector table at reset,
+//! can be switched at run-time, e.g. to run an update stub from SRAM, or before jumping to the
+//! bootloader in system memory.
+//!
+//! The I2C pins need the 20 mA Fast-mode Plus drive to run above 400 kHz; it's enabled either
+//! for all the pins of an I2C peripheral, or for PB6 to PB9 one by one.
+//!
+//! The internal voltage reference (VREFINT) is buffered separately for each of its users: the
+//! ADC, the COMP2 scaler, the HSI48 oscillator, and the PB0 and PB1 outputs. Each buffer is
+//! enabled and disabled on its own, the reference staying up while any of them is enabled.
+
+use cortex_m::{asm, interrupt};
+use stm32l0x3::SYSCFG_COMP;
+
+use crate::rcc::APB2;
+
+// CFGR1 bits
+const MEM_MODE: u32 = 0b11;
+
+// CFGR2 bits
+pub(crate) const I2C_PB6_FMP: u32 = 1 << 8;
+pub(crate) const I2C_PB7_FMP: u32 = 1 << 9;
+pub(crate) const I2C_PB8_FMP: u32 = 1 << 10;
+pub(crate) const I2C_PB9_FMP: u32 = 1 << 11;
+pub(crate) const I2C1_FMP: u32 = 1 << 12;
+pub(crate) const I2C2_FMP: u32 = 1 << 13;
+pub(crate) const I2C3_FMP: u32 = 1 << 14;
+
+// CFGR3 bits
+const EN_VREFINT: u32 = 1 << 0;
+const SEL_VREF_OUT: u32 = 0b11 << 4;
+pub(crate) const ENBUF_VREFINT_ADC: u32 = 1 << 8;
+pub(crate) const ENBUF_SENSOR_ADC: u32 = 1 << 9;
+pub(crate) const ENBUF_VREFINT_COMP2: u32 = 1 << 12;
+pub(crate) const ENREF_HSI48: u32 = 1 << 13;
+const VREFINT_RDYF: u32 = 1 << 30;
+
+/// Memory mapped at address 0x0000_0000
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MemoryMode {
+    /// Main flash memory
+    Flash,
+    /// System memory, holding the bootloader
+    SystemFlash,
+    /// SRAM
+    Sram,
+}
+
+/// Pins driven with the Fast-mode Plus drive
+#[derive(Clone, Copy)]
+pub enum FastModePlus {
+    Pb6,
+    Pb7,
+    Pb8,
+    Pb9,
+    /// All the pins of I2C1
+    I2c1,
+    /// All the pins of I2C2
+    I2c2,
+    /// All the pins of I2C3
+    I2c3,
+}
+
+impl FastModePlus {
+    fn bit(self) -> u32 {
+        match self {
+            FastModePlus::Pb6 => I2C_PB6_FMP,
+            FastModePlus::Pb7 => I2C_PB7_FMP,
+            FastModePlus::Pb8 => I2C_PB8_FMP,
+            FastModePlus::Pb9 => I2C_PB9_FMP,
+            FastModePlus::I2c1 => I2C1_FMP,
+            FastModePlus::I2c2 => I2C2_FMP,
+            FastModePlus::I2c3 => I2C3_FMP,
+        }
+    }
+}
+
+/// Pins the internal voltage reference is output on
+#[derive(Clone, Copy)]
+pub enum VRefintOutput {
+    Pb0,
+    Pb1,
+    Pb0AndPb1,
+}
+
+impl VRefintOutput {
+    fn bits(self) -> u32 {
+        match self {
+            VRefintOutput::Pb0 => 0b01 << 4,
+            VRefintOutput::Pb1 => 0b10 << 4,
+            VRefintOutput::Pb0AndPb1 => 0b11 << 4,
+        }
+    }
+}
+
+/// Extension trait for the SYSCFG_COMP peripheral
+pub trait SysCfgExt {
+    /// Maps `mode` at address 0x0000_0000
+    ///
+    /// The memory stays accessible at its own address too. The vector table offset (VTOR)
+    /// isn't changed.
+    fn remap_memory(&mut self, mode: MemoryMode, apb2: &mut APB2);
+
+    /// Returns the memory mapped at address 0x0000_0000
+    ///
+    /// After a reset, that's the memory selected by the BOOT0 pin and the option bytes.
+    fn memory_mode(&self) -> MemoryMode;
+
+    /// Enables the 20 mA Fast-mode Plus drive of `pins`, needed by the I2C above 400 kHz
+    fn enable_fast_mode_plus(&mut self, pins: FastModePlus, apb2: &mut APB2);
+
+    /// Disables the Fast-mode Plus drive of `pins`
+    fn disable_fast_mode_plus(&mut self, pins: FastModePlus);
+
+    /// Outputs the internal voltage reference on `output`, and waits until it is ready
+    ///
+    /// The pins must be in analog mode. This also keeps the reference, and the COMP2 scaler,
+    /// enabled in the low-power modes.
+    fn enable_vrefint_output(&mut self, output: VRefintOutput, apb2: &mut APB2);
+
+    /// Disconnects the internal voltage reference from PB0 and PB1
+    fn disable_vrefint_output(&mut self);
+
+    /// Returns true if the internal voltage reference is ready (VREFINT_RDYF)
+    ///
+    /// The reference restarts after a wake up from Stop with `StopConfig::ultra_low_power` set.
+    fn is_vrefint_ready(&self) -> bool;
+}
+
+impl SysCfgExt for SYSCFG_COMP {
+    fn remap_memory(&mut self, mode: MemoryMode, apb2: &mut APB2) {
+        apb2.enr().modify(|_, w| w.syscfgen().set_bit());
+        let bits = match mode {
+            MemoryMode::Flash => 0b00,
+            MemoryMode::SystemFlash => 0b01,
+            MemoryMode::Sram => 0b11,
+        };
+        self.cfgr1
+            .modify(|r, w| unsafe { w.bits((r.bits() & !MEM_MODE) | bits) });
+        // the next instructions must see the new mapping
+        asm::dsb();
+        asm::isb();
+    }
+
+    fn memory_mode(&self) -> MemoryMode {
+        match self.cfgr1.read().bits() & MEM_MODE {
+            0b00 => MemoryMode::Flash,
+            0b01 => MemoryMode::SystemFlash,
+            _ => MemoryMode::Sram,
+        }
+    }
+
+    fn enable_fast_mode_plus(&mut self, pins: FastModePlus, apb2: &mut APB2) {
+        apb2.enr().modify(|_, w| w.syscfgen().set_bit());
+        self.cfgr2
+            .modify(|r, w| unsafe { w.bits(r.bits() | pins.bit()) });
+    }
+
+    fn disable_fast_mode_plus(&mut self, pins: FastModePlus) {
+        self.cfgr2
+            .modify(|r, w| unsafe { w.bits(r.bits() & !pins.bit()) });
+    }
+
+    fn enable_vrefint_output(&mut self, output: VRefintOutput, apb2: &mut APB2) {
+        apb2.enr().modify(|_, w| w.syscfgen().set_bit());
+        // NOTE(unsafe) the other CFGR3 bits are kept, atomically
+        interrupt::free(|_| {
+            self.cfgr3.modify(|r, w| unsafe {
+                w.bits((r.bits() & !SEL_VREF_OUT) | output.bits() | EN_VREFINT)
+            })
+        });
+        wait_for_vrefint();
+    }
+
+    fn disable_vrefint_output(&mut self) {
+        // NOTE(unsafe) see `enable_vrefint_output`
+        interrupt::free(|_| {
+            self.cfgr3
+                .modify(|r, w| unsafe { w.bits(r.bits() & !(SEL_VREF_OUT | EN_VREFINT)) })
+        });
+    }
+
+    fn is_vrefint_ready(&self) -> bool {
+        self.cfgr3.read().bits() & VREFINT_RDYF != 0
+    }
+}
+
+/// Returns true if all the `fmp` Fast-mode Plus drive bits are set
+pub(crate) fn is_fast_mode_plus_enabled(fmp: u32) -> bool {
+    // NOTE(unsafe) read-only access
+    unsafe { (*SYSCFG_COMP::ptr()).cfgr2.read().bits() & fmp == fmp }
+}
+
+/// Enables a buffer of the internal voltage reference in CFGR3, and waits until the reference
+/// is ready
+pub(crate) fn enable_buffer(enbuf: u32, apb2: &mut APB2) {
+    apb2.enr().modify(|_, w| w.syscfgen().set_bit());
+    // NOTE(unsafe) each ENBUF bit is only used by one driver, and the read-modify-write is
+    // done atomically
+    interrupt::free(|_| unsafe {
+        (*SYSCFG_COMP::ptr())
+            .cfgr3
+            .modify(|r, w| w.bits(r.bits() | enbuf))
+    });
+    wait_for_vrefint();
+}
+
+/// Disables a buffer of the internal voltage reference in CFGR3
+pub(crate) fn disable_buffer(enbuf: u32) {
+    // NOTE(unsafe) see `enable_buffer`
+    interrupt::free(|_| unsafe {
+        (*SYSCFG_COMP::ptr())
+            .cfgr3
+            .modify(|r, w| w.bits(r.bits() & !enbuf))
+    });
+}
+
+/// Returns true if the internal voltage reference is ready (VREFINT_RDYF)
+pub(crate) fn is_vrefint_ready() -> bool {
+    // NOTE(unsafe) read-only access
+    unsafe { (*SYSCFG_COMP::ptr()).cfgr3.read().bits() & VREFINT_RDYF != 0 }
+}
+
+/// Waits for the internal voltage reference to be ready
+pub(crate) fn wait_for_vrefint() {
+    while !is_vrefint_ready() {}
+}
@@ -0,0 +1,281 @@
+//! General purpose and basic timers
+
+use embedded_hal::timer::{CountDown, Periodic};
+use nb;
+use stm32l0x3::{TIM2, TIM21, TIM22, TIM6, TIM7};
+use void::Void;
+
+use crate::capture::Capture;
+use crate::pwm::Pwm;
+use crate::rcc::{Clocks, APB1, APB2};
+use crate::time::{Hertz, KiloHertz, MegaHertz, MicroSeconds, MilliSeconds};
+
+/// Input edge
+#[derive(Clone, Copy)]
+pub enum Edge {
+    Rising,
+    Falling,
+    /// Rising and falling edges
+    Both,
+}
+
+/// Interrupt events
+pub enum Event {
+    /// Timer timed out / count down ended
+    TimeOut,
+}
+
+/// Timer interrupt flags
+///
+/// The basic timers TIM6 and TIM7 only have the update flag.
+#[derive(Clone, Copy)]
+pub enum Flag {
+    /// Update: the counter overflowed or was reinitialized
+    Update,
+    /// Capture or compare on channel 1
+    Cc1,
+    /// Capture or compare on channel 2
+    Cc2,
+    /// Capture or compare on channel 3
+    Cc3,
+    /// Capture or compare on channel 4
+    Cc4,
+    /// Trigger input edge, in slave mode
+    Trigger,
+}
+
+impl Flag {
+    /// Returns the mask of the flag, in the SR register as well as the DIER register
+    fn mask(&self) -> u32 {
+        match self {
+            Flag::Update => 1 << 0,
+            Flag::Cc1 => 1 << 1,
+            Flag::Cc2 => 1 << 2,
+            Flag::Cc3 => 1 << 3,
+            Flag::Cc4 => 1 << 4,
+            Flag::Trigger => 1 << 6,
+        }
+    }
+}
+
+impl From<Event> for Flag {
+    fn from(event: Event) -> Flag {
+        match event {
+            Event::TimeOut => Flag::Update,
+        }
+    }
+}
+
+/// Time until a timer times out, as a frequency or a duration
+#[derive(Clone, Copy)]
+pub enum Timeout {
+    Frequency(Hertz),
+    Duration(MicroSeconds),
+}
+
+impl From<Hertz> for Timeout {
+    fn from(freq: Hertz) -> Timeout {
+        Timeout::Frequency(freq)
+    }
+}
+
+impl From<KiloHertz> for Timeout {
+    fn from(freq: KiloHertz) -> Timeout {
+        Timeout::Frequency(freq.into())
+    }
+}
+
+impl From<MegaHertz> for Timeout {
+    fn from(freq: MegaHertz) -> Timeout {
+        Timeout::Frequency(freq.into())
+    }
+}
+
+impl From<MicroSeconds> for Timeout {
+    fn from(duration: MicroSeconds) -> Timeout {
+        Timeout::Duration(duration)
+    }
+}
+
+impl From<MilliSeconds> for Timeout {
+    fn from(duration: MilliSeconds) -> Timeout {
+        Timeout::Duration(duration.into())
+    }
+}
+
+impl Timeout {
+    /// Returns the number of timer clock ticks until the timeout
+    pub(crate) fn ticks(&self, clk: Hertz) -> u64 {
+        match self {
+            Timeout::Frequency(freq) => {
+                assert!(freq.0 > 0);
+                (clk.0 / freq.0) as u64
+            }
+            Timeout::Duration(duration) => clk.0 as u64 * duration.0 as u64 / 1_000_000,
+        }
+    }
+}
+
+/// Returns the prescaler and auto-reload values counting `ticks` with a 16-bit timer, the
+/// auto-reload value staying at most `max_arr`
+pub(crate) fn psc_arr(ticks: u64, max_arr: u16) -> (u16, u16) {
+    let period = u64::from(max_arr) + 1;
+    let ticks = ticks.max(1).min(period << 16);
+    let psc = (ticks - 1) / period;
+    let arr = ticks / (psc + 1) - 1;
+    (psc as u16, arr as u16)
+}
+
+/// Hardware timer
+pub struct Timer<TIM> {
+    pub(crate) tim: TIM,
+    clk: Hertz,
+}
+
+macro_rules! hal {
+    ($(
+        $TIMX:ident: (
+            $timX:ident,
+            $APB:ident,
+            $timXen:ident,
+            $timXrst:ident,
+            $pclkX:ident,
+            $ppreX:ident
+        ),
+    )+) => {
+        $(
+            impl Timer<$TIMX> {
+                /// Configures the timer as a periodic count down timer, and starts it
+                ///
+                /// The timer kernel clock is the APB clock, doubled when the APB clock is
+                /// divided from the AHB clock.
+                pub fn $timX<T>(tim: $TIMX, timeout: T, clocks: &Clocks, apb: &mut $APB) -> Self
+                where
+                    T: Into<Timeout>,
+                {
+                    apb.enr().modify(|_, w| w.$timXen().set_bit());
+                    apb.rstr().modify(|_, w| w.$timXrst().set_bit());
+                    apb.rstr().modify(|_, w| w.$timXrst().clear_bit());
+
+                    let clk = if clocks.$ppreX() == 1 {
+                        clocks.$pclkX()
+                    } else {
+                        Hertz(clocks.$pclkX().0 * 2)
+                    };
+
+                    let mut timer = Timer { tim, clk };
+                    timer.start(timeout);
+                    timer
+                }
+
+                /// Returns the frequency of the timer kernel clock
+                pub fn clock(&self) -> Hertz {
+                    self.clk
+                }
+
+                /// Clears the update interrupt flag
+                pub fn clear_update(&mut self) {
+                    self.clear_interrupt(Flag::Update);
+                }
+
+                /// Stops the timer and releases the peripheral
+                pub fn release(self) -> $TIMX {
+                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    self.tim.dier.modify(|_, w| w.uie().clear_bit());
+                    self.tim
+                }
+            }
+
+            impl CountDown for Timer<$TIMX> {
+                type Time = Timeout;
+
+                fn start<T>(&mut self, timeout: T)
+                where
+                    T: Into<Timeout>,
+                {
+                    // pause
+                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+
+                    let (psc, arr) = psc_arr(timeout.into().ticks(self.clk), 0xFFFF);
+                    self.tim.psc.write(|w| unsafe { w.psc().bits(psc) });
+                    self.tim.arr.write(|w| unsafe { w.bits(arr as u32) });
+
+                    // load the new prescaler without raising the update interrupt
+                    self.tim.cr1.modify(|_, w| w.urs().set_bit());
+                    self.tim.egr.write(|w| w.ug().set_bit());
+                    self.clear_interrupt(Flag::Update);
+                    self.tim.cr1.modify(|_, w| w.urs().clear_bit());
+
+                    self.tim.cr1.modify(|_, w| w.cen().set_bit());
+                }
+
+                fn wait(&mut self) -> nb::Result<(), Void> {
+                    if self.tim.sr.read().uif().bit_is_clear() {
+                        Err(nb::Error::WouldBlock)
+                    } else {
+                        self.clear_interrupt(Flag::Update);
+                        Ok(())
+                    }
+                }
+            }
+
+            impl Periodic for Timer<$TIMX> {}
+        )+
+    }
+}
+
+macro_rules! interrupts {
+    ($($TIMX:ident: [$($DRIVER:ident),+],)+) => {
+        $(
+            $(
+                impl $DRIVER<$TIMX> {
+                    /// Enables the interrupt of a flag, e.g. `Flag::Update` or `Event::TimeOut`
+                    pub fn listen<F>(&mut self, flag: F)
+                    where
+                        F: Into<Flag>,
+                    {
+                        let mask = flag.into().mask();
+                        self.tim.dier.modify(|r, w| unsafe { w.bits(r.bits() | mask) });
+                    }
+
+                    /// Disables the interrupt of a flag
+                    pub fn unlisten<F>(&mut self, flag: F)
+                    where
+                        F: Into<Flag>,
+                    {
+                        let mask = flag.into().mask();
+                        self.tim.dier.modify(|r, w| unsafe { w.bits(r.bits() & !mask) });
+                    }
+
+                    /// Returns true if a flag is set
+                    pub fn is_pending(&self, flag: Flag) -> bool {
+                        self.tim.sr.read().bits() & flag.mask() != 0
+                    }
+
+                    /// Clears a flag
+                    pub fn clear_interrupt(&mut self, flag: Flag) {
+                        // the flags are cleared by writing 0, the other flags are unaffected by
+                        // writing 1, so flags set in the meantime aren't lost
+                        self.tim.sr.write(|w| unsafe { w.bits(!flag.mask()) });
+                    }
+                }
+            )+
+        )+
+    }
+}
+
+hal! {
+    TIM2: (tim2, APB1, tim2en, tim2rst, pclk1, ppre1),
+    TIM6: (tim6, APB1, tim6en, tim6rst, pclk1, ppre1),
+    TIM7: (tim7, APB1, tim7en, tim7rst, pclk1, ppre1),
+    TIM21: (tim21, APB2, tim21en, tim21rst, pclk2, ppre2),
+    TIM22: (tim22, APB2, tim22en, tim22rst, pclk2, ppre2),
+}
+
+interrupts! {
+    TIM2: [Timer, Pwm, Capture],
+    TIM6: [Timer],
+    TIM7: [Timer],
+    TIM21: [Timer, Pwm, Capture],
+    TIM22: [Timer, Pwm, Capture],
+}
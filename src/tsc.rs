@@ -0,0 +1,531 @@
+//! Touch Sensing Controller (TSC)
+//!
+//! The controller charges a sensing electrode, and transfers its charge into a sampling
+//! capacitor until the capacitor voltage reaches the I/O threshold. A finger on the electrode
+//! adds capacitance, so fewer transfers are needed: the count of a group drops on a touch.
+//!
+//! The I/Os are arranged in 8 groups of 4. Each group used needs one sampling capacitor I/O,
+//! and one of its other I/Os is acquired at a time, the groups being acquired in parallel.
+//!
+//! `Scanner` rotates through banks of channels, one channel per group, tracks the baseline
+//! count of each sensor, and reports touch and release events.
+
+use core::ptr;
+
+use cortex_m::interrupt;
+use nb;
+use stm32l0x3::{GPIOA, GPIOB, GPIOC, TSC};
+
+use crate::gpio::gpioa::{PA0, PA1, PA10, PA11, PA12, PA2, PA3, PA4, PA5, PA6, PA7, PA9};
+use crate::gpio::gpiob::{PB0, PB1, PB11, PB12, PB13, PB14, PB2, PB3, PB4, PB6, PB7};
+use crate::gpio::gpioc::{PC0, PC1, PC2, PC3, PC5, PC6, PC7, PC8, PC9};
+use crate::gpio::AF3;
+use crate::rcc::AHB;
+
+// CR bits
+const TSCE: u32 = 1 << 0;
+const START: u32 = 1 << 1;
+
+// ISR bits
+const EOAF: u32 = 1 << 0;
+const MCEF: u32 = 1 << 1;
+
+/// TSC error
+#[derive(Debug)]
+pub enum Error {
+    /// A group reached the maximum count before its sampling capacitor was charged, e.g.
+    /// because the sampling capacitor is missing
+    MaxCount,
+    #[doc(hidden)]
+    _Extensible,
+}
+
+/// Maximum count of an acquisition (MCV)
+#[derive(Clone, Copy)]
+pub enum MaxCount {
+    C255,
+    C511,
+    C1023,
+    C2047,
+    C4095,
+    C8191,
+    C16383,
+}
+
+/// TSC configuration
+pub struct Config {
+    pulse_prescaler: u8,
+    charge_transfer_high: u8,
+    charge_transfer_low: u8,
+    max_count: MaxCount,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            pulse_prescaler: 0b010,
+            charge_transfer_high: 2,
+            charge_transfer_low: 2,
+            max_count: MaxCount::C8191,
+        }
+    }
+}
+
+impl Config {
+    /// Divides the AHB clock by `2^prescaler` to clock the charge transfer pulses (PGPSC)
+    pub fn pulse_prescaler(mut self, prescaler: u8) -> Self {
+        assert!(prescaler <= 7);
+        self.pulse_prescaler = prescaler;
+        self
+    }
+
+    /// Sets the duration of the electrode charge (CTPH) and of the transfer (CTPL), in pulse
+    /// clock cycles, from 1 to 16
+    pub fn charge_transfer(mut self, high: u8, low: u8) -> Self {
+        assert!(high >= 1 && high <= 16 && low >= 1 && low <= 16);
+        self.charge_transfer_high = high;
+        self.charge_transfer_low = low;
+        self
+    }
+
+    /// Sets the count after which an acquisition fails
+    pub fn max_count(mut self, max_count: MaxCount) -> Self {
+        self.max_count = max_count;
+        self
+    }
+}
+
+/// TSC I/O pin -- DO NOT IMPLEMENT THIS TRAIT
+pub unsafe trait TscPin {
+    /// Group of the I/O, from 1 to 8
+    const GROUP: u8;
+    /// I/O in the group, from 1 to 4
+    const IO: u8;
+
+    #[doc(hidden)]
+    fn set_open_drain(&mut self, open_drain: bool);
+}
+
+/// Returns the bit of a pin in the I/O registers
+fn io_bit<P: TscPin>() -> u32 {
+    1 << (4 * (P::GROUP - 1) + (P::IO - 1))
+}
+
+macro_rules! tsc_pins {
+    ($($GPIOX:ident: [$($PIN:ident: ($i:expr, $group:expr, $io:expr),)+],)+) => {
+        $(
+            $(
+                unsafe impl TscPin for $PIN<AF3> {
+                    const GROUP: u8 = $group;
+                    const IO: u8 = $io;
+
+                    fn set_open_drain(&mut self, open_drain: bool) {
+                        // NOTE(unsafe) atomic read-modify-write of the bit of the owned pin
+                        interrupt::free(|_| unsafe {
+                            (*$GPIOX::ptr()).otyper.modify(|r, w| {
+                                w.bits(if open_drain {
+                                    r.bits() | (1 << $i)
+                                } else {
+                                    r.bits() & !(1 << $i)
+                                })
+                            })
+                        });
+                    }
+                }
+            )+
+        )+
+    };
+}
+
+tsc_pins! {
+    GPIOA: [
+        PA0: (0, 1, 1),
+        PA1: (1, 1, 2),
+        PA2: (2, 1, 3),
+        PA3: (3, 1, 4),
+        PA4: (4, 2, 1),
+        PA5: (5, 2, 2),
+        PA6: (6, 2, 3),
+        PA7: (7, 2, 4),
+        PA9: (9, 4, 1),
+        PA10: (10, 4, 2),
+        PA11: (11, 4, 3),
+        PA12: (12, 4, 4),
+    ],
+    GPIOB: [
+        PB0: (0, 3, 2),
+        PB1: (1, 3, 3),
+        PB2: (2, 3, 4),
+        PB3: (3, 5, 1),
+        PB4: (4, 5, 2),
+        PB6: (6, 5, 3),
+        PB7: (7, 5, 4),
+        PB11: (11, 6, 1),
+        PB12: (12, 6, 2),
+        PB13: (13, 6, 3),
+        PB14: (14, 6, 4),
+    ],
+    GPIOC: [
+        PC0: (0, 7, 1),
+        PC1: (1, 7, 2),
+        PC2: (2, 7, 3),
+        PC3: (3, 7, 4),
+        PC5: (5, 3, 1),
+        PC6: (6, 8, 1),
+        PC7: (7, 8, 2),
+        PC8: (8, 8, 3),
+        PC9: (9, 8, 4),
+    ],
+}
+
+/// Touch sensing controller
+pub struct Tsc {
+    tsc: TSC,
+}
+
+impl Tsc {
+    pub fn new(tsc: TSC, config: &Config, ahb: &mut AHB) -> Self {
+        // TSCEN, TSCRST
+        ahb.enr()
+            .modify(|r, w| unsafe { w.bits(r.bits() | (1 << 16)) });
+        ahb.rstr()
+            .modify(|r, w| unsafe { w.bits(r.bits() | (1 << 16)) });
+        ahb.rstr()
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << 16)) });
+
+        let mcv = match config.max_count {
+            MaxCount::C255 => 0b000,
+            MaxCount::C511 => 0b001,
+            MaxCount::C1023 => 0b010,
+            MaxCount::C2047 => 0b011,
+            MaxCount::C4095 => 0b100,
+            MaxCount::C8191 => 0b101,
+            MaxCount::C16383 => 0b110,
+        };
+        // CTPH, CTPL, PGPSC, MCV; the I/Os are driven low between acquisitions, discharging
+        // the electrodes and sampling capacitors (IODEF)
+        let cr = ((config.charge_transfer_high as u32 - 1) << 28)
+            | ((config.charge_transfer_low as u32 - 1) << 24)
+            | ((config.pulse_prescaler as u32) << 12)
+            | (mcv << 5)
+            | TSCE;
+        tsc.cr.write(|w| unsafe { w.bits(cr) });
+
+        Tsc { tsc }
+    }
+
+    /// Uses `pin` as the sampling capacitor I/O of its group, and enables the group
+    pub fn setup_sampling_capacitor<P>(&mut self, pin: &mut P)
+    where
+        P: TscPin,
+    {
+        pin.set_open_drain(true);
+        let bit = io_bit::<P>();
+        // the Schmitt trigger hysteresis is disabled on the sampling capacitor I/O
+        self.tsc
+            .iohcr
+            .modify(|r, w| unsafe { w.bits(r.bits() & !bit) });
+        self.tsc
+            .ioscr
+            .modify(|r, w| unsafe { w.bits(r.bits() | bit) });
+        self.tsc
+            .iogcsr
+            .modify(|r, w| unsafe { w.bits(r.bits() | (1 << (P::GROUP - 1))) });
+    }
+
+    /// Acquires `pin` as a channel of its group, instead of the previous one
+    ///
+    /// Only one channel of each group is acquired at a time.
+    pub fn select_channel<P>(&mut self, pin: &mut P)
+    where
+        P: TscPin,
+    {
+        pin.set_open_drain(false);
+        let group = 0b1111 << (4 * (P::GROUP - 1));
+        self.tsc
+            .ioccr
+            .modify(|r, w| unsafe { w.bits((r.bits() & !group) | io_bit::<P>()) });
+    }
+
+    /// Stops acquiring the channel of `pin`
+    pub fn deselect_channel<P>(&mut self, _pin: &P)
+    where
+        P: TscPin,
+    {
+        self.tsc
+            .ioccr
+            .modify(|r, w| unsafe { w.bits(r.bits() & !io_bit::<P>()) });
+    }
+
+    /// Acquires the channels of `bank`, instead of the previously selected ones
+    pub fn select_bank(&mut self, bank: &Bank) {
+        self.tsc.ioccr.write(|w| unsafe { w.bits(bank.channels) });
+    }
+
+    /// Starts an acquisition of the selected channels
+    pub fn start(&mut self) {
+        self.tsc.icr.write(|w| unsafe { w.bits(EOAF | MCEF) });
+        self.tsc
+            .cr
+            .modify(|r, w| unsafe { w.bits(r.bits() | START) });
+    }
+
+    /// Waits for the end of the acquisition
+    pub fn wait(&mut self) -> nb::Result<(), Error> {
+        let isr = self.tsc.isr.read().bits();
+        if isr & MCEF != 0 {
+            Err(nb::Error::Other(Error::MaxCount))
+        } else if isr & EOAF != 0 {
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+
+    /// Returns the count of the last acquisition of `group`, from 1 to 8
+    pub fn read_group(&self, group: u8) -> u16 {
+        assert!(group >= 1 && group <= 8);
+        // NOTE(read_volatile) the IOGxCR count registers follow each other
+        let count = unsafe {
+            let iog1cr = &self.tsc.iog1cr as *const _ as *const u32;
+            ptr::read_volatile(iog1cr.add(group as usize - 1))
+        };
+        (count & 0x3FFF) as u16
+    }
+
+    /// Returns the count of the last acquisition of the channel of `pin`
+    pub fn read<P>(&self, _pin: &P) -> u16
+    where
+        P: TscPin,
+    {
+        self.read_group(P::GROUP)
+    }
+
+    /// Releases the peripheral
+    pub fn release(self) -> TSC {
+        self.tsc.cr.write(|w| unsafe { w.bits(0) });
+        self.tsc
+    }
+}
+
+/// Channels acquired together, at most one per group
+#[derive(Clone, Copy, Default)]
+pub struct Bank {
+    channels: u32,
+}
+
+impl Bank {
+    pub fn new() -> Self {
+        Bank { channels: 0 }
+    }
+
+    /// Adds the channel of `pin`, whose group must not have a channel in the bank yet
+    pub fn channel<P>(mut self, pin: &mut P) -> Self
+    where
+        P: TscPin,
+    {
+        assert!(self.channels & (0b1111 << (4 * (P::GROUP - 1))) == 0);
+        pin.set_open_drain(false);
+        self.channels |= io_bit::<P>();
+        self
+    }
+}
+
+/// Touch detection configuration
+pub struct TouchConfig {
+    touch_threshold: u16,
+    release_threshold: u16,
+    drift_shift: u8,
+    calibration_samples: u8,
+}
+
+impl Default for TouchConfig {
+    fn default() -> TouchConfig {
+        TouchConfig {
+            touch_threshold: 40,
+            release_threshold: 20,
+            drift_shift: 6,
+            calibration_samples: 8,
+        }
+    }
+}
+
+impl TouchConfig {
+    /// Sets the count drop from the baseline detecting a touch, and the lower drop detecting
+    /// the release
+    ///
+    /// The gap between the two is the hysteresis, which keeps a light touch from bouncing.
+    pub fn thresholds(mut self, touch: u16, release: u16) -> Self {
+        assert!(release < touch);
+        self.touch_threshold = touch;
+        self.release_threshold = release;
+        self
+    }
+
+    /// Sets how slowly the baseline follows the count while untouched, as the baseline moves
+    /// by `1 / 2^shift` of the difference on each acquisition
+    ///
+    /// This compensates for the temperature and humidity drifts, and must be slow compared
+    /// to a touch.
+    pub fn drift_shift(mut self, shift: u8) -> Self {
+        assert!(shift <= 12);
+        self.drift_shift = shift;
+        self
+    }
+
+    /// Sets the number of acquisitions averaged into the initial baseline, during which no
+    /// sensor must be touched
+    pub fn calibration_samples(mut self, samples: u8) -> Self {
+        assert!(samples > 0 && samples < u8::max_value());
+        self.calibration_samples = samples;
+        self
+    }
+}
+
+/// Touch sensor, a channel of a bank
+pub struct Sensor {
+    bank: usize,
+    group: u8,
+    // fixed point, 4 fractional bits
+    baseline: u32,
+    samples: u8,
+    touched: bool,
+}
+
+impl Sensor {
+    /// Creates the sensor of `pin`, in the bank at index `bank` of the `Scanner`
+    pub fn new<P>(bank: usize, _pin: &P) -> Self
+    where
+        P: TscPin,
+    {
+        Sensor {
+            bank,
+            group: P::GROUP,
+            baseline: 0,
+            samples: 0,
+            touched: false,
+        }
+    }
+
+    /// Returns true while the sensor is touched
+    pub fn is_touched(&self) -> bool {
+        self.touched
+    }
+
+    /// Returns the baseline count, once calibrated
+    pub fn baseline(&self) -> Option<u16> {
+        if self.samples != u8::max_value() {
+            None
+        } else {
+            Some((self.baseline >> 4) as u16)
+        }
+    }
+
+    /// Updates the baseline and touch state with a new count, and returns the event
+    fn update(&mut self, count: u16, config: &TouchConfig) -> Option<bool> {
+        let count = (count as u32) << 4;
+
+        if self.samples != u8::max_value() {
+            // average the first acquisitions
+            self.samples += 1;
+            let baseline = self.baseline as i32;
+            self.baseline = (baseline + (count as i32 - baseline) / self.samples as i32) as u32;
+            if self.samples == config.calibration_samples {
+                self.samples = u8::max_value();
+            }
+            return None;
+        }
+
+        // a touch lowers the count
+        let drop = (self.baseline.saturating_sub(count) >> 4) as u16;
+        if self.touched {
+            if drop < config.release_threshold {
+                self.touched = false;
+                return Some(false);
+            }
+        } else if drop >= config.touch_threshold {
+            self.touched = true;
+            return Some(true);
+        } else if count > self.baseline {
+            // the baseline follows a rising count quickly, e.g. once a finger left during
+            // the calibration
+            self.baseline += (count - self.baseline) >> 2;
+        } else {
+            self.baseline -= (self.baseline - count) >> config.drift_shift;
+        }
+        None
+    }
+}
+
+/// Touch event of a sensor, given by its index in the `Scanner`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TouchEvent {
+    Touch(usize),
+    Release(usize),
+}
+
+/// Acquisition scheduler, rotating through banks of channels, and detecting touches
+pub struct Scanner<'a> {
+    banks: &'a [Bank],
+    sensors: &'a mut [Sensor],
+    config: TouchConfig,
+    bank: usize,
+}
+
+impl<'a> Scanner<'a> {
+    pub fn new(banks: &'a [Bank], sensors: &'a mut [Sensor], config: TouchConfig) -> Self {
+        assert!(!banks.is_empty());
+        assert!(sensors.iter().all(|sensor| sensor.bank < banks.len()));
+
+        Scanner {
+            banks,
+            sensors,
+            config,
+            bank: 0,
+        }
+    }
+
+    /// Starts acquiring the first bank
+    pub fn start(&mut self, tsc: &mut Tsc) {
+        self.bank = 0;
+        tsc.select_bank(&self.banks[0]);
+        tsc.start();
+    }
+
+    /// Waits for the end of the acquisition of the current bank, updates its sensors, calling
+    /// `f` on their events, and starts acquiring the next bank
+    ///
+    /// The sensors of a bank failing its acquisition are left untouched.
+    pub fn poll<F>(&mut self, tsc: &mut Tsc, mut f: F) -> nb::Result<(), Error>
+    where
+        F: FnMut(TouchEvent),
+    {
+        let result = tsc.wait();
+        if let Err(nb::Error::WouldBlock) = result {
+            return result;
+        }
+
+        if result.is_ok() {
+            for (i, sensor) in self.sensors.iter_mut().enumerate() {
+                if sensor.bank != self.bank {
+                    continue;
+                }
+                match sensor.update(tsc.read_group(sensor.group), &self.config) {
+                    Some(true) => f(TouchEvent::Touch(i)),
+                    Some(false) => f(TouchEvent::Release(i)),
+                    None => {}
+                }
+            }
+        }
+
+        self.bank = (self.bank + 1) % self.banks.len();
+        tsc.select_bank(&self.banks[self.bank]);
+        tsc.start();
+        result
+    }
+
+    /// Returns the sensors
+    pub fn sensors(&self) -> &[Sensor] {
+        self.sensors
+    }
+}
@@ -0,0 +1,334 @@
+//! USB full-speed device
+//!
+//! `UsbBus`, from the `stm32-usbd` crate, implements `usb_device::bus::UsbBus` over the
+//! peripheral: packet memory allocation, endpoint setup, and suspend and resume. The classes
+//! of the usb-device ecosystem (CDC-ACM, HID, DFU...) then run on top of it.
+//!
+//! The peripheral needs a 48 MHz clock, from the PLL at 96 MHz (`CFGR::usb_pll`) or from the
+//! HSI48 oscillator (`CRRCR::enable_hsi48`), selected with `CCIPR::set_clock48`. The HSI48
+//! isn't accurate enough for USB on its own: `Usb::enable_crs` lets the clock recovery system
+//! (CRS) trim it continuously on the start-of-frame packets sent by the host every 1 ms.
+//!
+//! A suspended device must draw less than 2.5 mA from VBUS: once `UsbDevice::state` is
+//! `Suspend`, `Usb::stop` enters the Stop mode until the host resumes the bus, and
+//! `Usb::remote_wakeup` lets the device resume it itself.
+
+use cortex_m::peripheral::SCB;
+use cortex_m::{asm, interrupt};
+use embedded_hal::blocking::delay::DelayMs;
+use stm32_usbd::UsbPeripheral;
+use stm32l0x3::{CRS, RCC, USB};
+
+pub use stm32_usbd::UsbBus;
+
+use crate::exti::{ExtiTrigger, Internal, InternalLine};
+use crate::gpio::gpioa::{PA11, PA12};
+use crate::gpio::Analog;
+use crate::pwr::{self, Pwr, StopConfig, WakeUp};
+use crate::rcc::{Clocks, APB1, CCIPR, CRRCR};
+
+/// USB peripheral, with its DM (PA11) and DP (PA12) pins
+pub struct Usb {
+    usb: USB,
+    dm: PA11<Analog>,
+    dp: PA12<Analog>,
+    crs: Option<CRS>,
+}
+
+/// `UsbBus` over the USB peripheral
+pub type UsbBusType = UsbBus<Usb>;
+
+impl Usb {
+    /// Takes the USB peripheral, once its 48 MHz clock is running
+    pub fn new(
+        usb: USB,
+        dm: PA11<Analog>,
+        dp: PA12<Analog>,
+        clocks: &Clocks,
+        apb1: &mut APB1,
+    ) -> Self {
+        if CCIPR::is_clock48_hsi48() {
+            assert!(CRRCR::is_hsi48_ready());
+        } else {
+            assert!(clocks.usb_pll());
+        }
+        apb1.enr().modify(|_, w| w.usben().set_bit());
+
+        Usb {
+            usb,
+            dm,
+            dp,
+            crs: None,
+        }
+    }
+
+    /// Detects the kind of port the device is plugged in, with the battery charging detection
+    /// (BCD) of the USB Battery Charging 1.2 specification
+    ///
+    /// This takes about 100 ms, and must be done once VBUS is present, before the `UsbBus` is
+    /// enabled and connects the DP pull-up. The device may then draw up to
+    /// `Port::max_current`.
+    pub fn detect_port<D>(&mut self, delay: &mut D) -> Port
+    where
+        D: DelayMs<u32>,
+    {
+        // power the transceiver up (PDWN), the `UsbBus` resets the peripheral when enabled
+        self.usb.cntr.write(|w| unsafe { w.bits(1) });
+        Usb::startup_delay();
+        self.usb.bcdr.write(|w| unsafe { w.bits(BCDEN) });
+
+        // data contact detection (DCD), until DP is connected, or a 1 s timeout after which
+        // the detection goes on anyway
+        self.usb.bcdr.write(|w| unsafe { w.bits(BCDEN | DCDEN) });
+        let mut timeout = 100;
+        while self.usb.bcdr.read().bits() & DCDET == 0 && timeout > 0 {
+            delay.delay_ms(10);
+            timeout -= 1;
+        }
+
+        // primary detection, tells a standard downstream port apart from a charging port
+        self.usb.bcdr.write(|w| unsafe { w.bits(BCDEN | PDEN) });
+        delay.delay_ms(50);
+        let bcdr = self.usb.bcdr.read().bits();
+        let port = if bcdr & PS2DET != 0 {
+            Port::Proprietary
+        } else if bcdr & PDET == 0 {
+            Port::StandardDownstream
+        } else {
+            // secondary detection, tells a charging downstream port apart from a charger
+            self.usb.bcdr.write(|w| unsafe { w.bits(BCDEN | SDEN) });
+            delay.delay_ms(50);
+            if self.usb.bcdr.read().bits() & SDET == 0 {
+                Port::ChargingDownstream
+            } else {
+                Port::DedicatedCharger
+            }
+        };
+
+        self.usb.bcdr.write(|w| unsafe { w.bits(0) });
+        // PDWN, FRES, the reset value
+        self.usb.cntr.write(|w| unsafe { w.bits(0b11) });
+        port
+    }
+
+    /// Trims the HSI48 oscillator on the USB start-of-frame packets, with the clock recovery
+    /// system (CRS)
+    ///
+    /// The CRS is owned by the driver from then on, its status is read with `Usb::crs_status`.
+    pub fn enable_crs(&mut self, crs: CRS, apb1: &mut APB1) {
+        assert!(CCIPR::is_clock48_hsi48());
+        apb1.enr().modify(|_, w| w.crsen().set_bit());
+
+        // SYNCSRC: USB SOF, FELIM: 34, RELOAD: 48 MHz / 1 kHz - 1, the reset values
+        crs.cfgr
+            .write(|w| unsafe { w.bits((0b10 << 28) | (0x22 << 16) | 0xBB7F) });
+        // CEN, AUTOTRIMEN
+        crs.cr
+            .modify(|r, w| unsafe { w.bits(r.bits() | (1 << 5) | (1 << 6)) });
+        self.crs = Some(crs);
+    }
+
+    /// Returns the CRS status since the flags were last cleared, or `None` if the driver
+    /// doesn't own the CRS
+    ///
+    /// This doesn't need the driver, which is owned by the `UsbBus`.
+    pub fn crs_status() -> Option<CrsStatus> {
+        // NOTE(unsafe) read-only access
+        let (cr, isr) = unsafe {
+            (
+                (*CRS::ptr()).cr.read().bits(),
+                (*CRS::ptr()).isr.read().bits(),
+            )
+        };
+        if cr & (1 << 5) == 0 {
+            return None;
+        }
+
+        // FECAP, counted from the reload value in the direction given by FEDIR
+        let fecap = (isr >> 16) as i32;
+        Some(CrsStatus {
+            sync_ok: isr & (1 << 0) != 0,
+            sync_warning: isr & (1 << 1) != 0,
+            sync_error: isr & (1 << 8) != 0,
+            sync_missed: isr & (1 << 9) != 0,
+            trim_overflow: isr & (1 << 10) != 0,
+            frequency_error: if isr & (1 << 15) == 0 { fecap } else { -fecap },
+        })
+    }
+
+    /// Clears the CRS flags
+    pub fn clear_crs_flags() {
+        // NOTE(unsafe) write-1-to-clear register of the CRS owned by the driver
+        unsafe { (*CRS::ptr()).icr.write(|w| w.bits(0b1111)) };
+    }
+
+    /// Returns true while the bus is suspended (FSUSP)
+    ///
+    /// This doesn't need the driver, which is owned by the `UsbBus`.
+    pub fn is_suspended() -> bool {
+        // NOTE(unsafe) read-only access
+        unsafe { (*USB::ptr()).cntr.read().bits() & FSUSP != 0 }
+    }
+
+    /// Starts listening for the USB wake up on its EXTI line (18), so that the bus activity
+    /// wakes the device up from the Stop mode
+    pub fn listen_wakeup(exti: &mut Internal) {
+        exti.listen(InternalLine::Usb, ExtiTrigger::Rising);
+    }
+
+    /// Stops listening for the USB wake up
+    pub fn unlisten_wakeup(exti: &mut Internal) {
+        exti.unlisten(InternalLine::Usb);
+    }
+
+    /// Enters the Stop mode while the bus is suspended, until woken up, e.g. by the host
+    /// resuming the bus, and restores the clocks
+    ///
+    /// The USB wake up must be listened to with `Usb::listen_wakeup`, and its interrupt
+    /// enabled in the NVIC for `WakeUp::Interrupt`. The 48 MHz clock, stopped in the Stop mode,
+    /// is restarted before returning. `UsbDevice::poll` then handles the resume.
+    pub fn stop(
+        pwr: &mut Pwr,
+        scb: &mut SCB,
+        config: &StopConfig,
+        clocks: &Clocks,
+        wake_up: WakeUp,
+    ) -> Result<(), pwr::Error> {
+        assert!(Usb::is_suspended());
+        pwr.stop(scb, config, clocks, wake_up)?;
+        Usb::restore_clock48();
+        Ok(())
+    }
+
+    /// Resumes the suspended bus (remote wakeup)
+    ///
+    /// The host must have allowed it, as told by `UsbDevice::remote_wakeup_enabled`, and the
+    /// bus must have been suspended for at least 5 ms. The resume signaling lasts 10 ms.
+    ///
+    /// The 48 MHz clock is restarted first if the device was in the Stop mode.
+    pub fn remote_wakeup<D>(delay: &mut D)
+    where
+        D: DelayMs<u32>,
+    {
+        assert!(Usb::is_suspended());
+        Usb::restore_clock48();
+        // NOTE(unsafe) atomic read-modify-writes, the `UsbBus` only writes CNTR from its
+        // methods, which can't run meanwhile
+        let usb = unsafe { &(*USB::ptr()) };
+        interrupt::free(|_| {
+            usb.cntr
+                .modify(|r, w| unsafe { w.bits((r.bits() & !(FSUSP | LPMODE)) | RESUME) })
+        });
+        delay.delay_ms(10);
+        interrupt::free(|_| {
+            usb.cntr
+                .modify(|r, w| unsafe { w.bits(r.bits() & !RESUME) })
+        });
+    }
+
+    /// Restarts the HSI48 oscillator, or waits for the PLL restarted by `Clocks::restore`,
+    /// whichever clocks the USB
+    fn restore_clock48() {
+        if CCIPR::is_clock48_hsi48() {
+            CRRCR::restore_hsi48();
+        } else {
+            // NOTE(unsafe) read-only access
+            let rcc = unsafe { &*RCC::ptr() };
+            while rcc.cr.read().pllrdy().bit_is_clear() {}
+        }
+    }
+
+    /// Releases the peripheral, its pins and the CRS
+    pub fn release(self) -> (USB, PA11<Analog>, PA12<Analog>, Option<CRS>) {
+        (self.usb, self.dm, self.dp, self.crs)
+    }
+}
+
+// CNTR bits
+const LPMODE: u32 = 1 << 2;
+const FSUSP: u32 = 1 << 3;
+const RESUME: u32 = 1 << 4;
+
+// BCDR bits
+const BCDEN: u32 = 1 << 0;
+const DCDEN: u32 = 1 << 1;
+const PDEN: u32 = 1 << 2;
+const SDEN: u32 = 1 << 3;
+const DCDET: u32 = 1 << 4;
+const PDET: u32 = 1 << 5;
+const SDET: u32 = 1 << 6;
+const PS2DET: u32 = 1 << 7;
+
+/// Kind of port the device is plugged in, see `Usb::detect_port`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Port {
+    /// A host or hub port, with data and up to 500 mA once configured (SDP)
+    StandardDownstream,
+    /// A host or hub port, with data and up to 1.5 A (CDP)
+    ChargingDownstream,
+    /// A wall adapter, without data and up to 1.5 A (DCP)
+    DedicatedCharger,
+    /// A PS/2 port or a proprietary charger, with the data lines pulled up
+    Proprietary,
+}
+
+impl Port {
+    /// Returns the current the device may draw from VBUS, in mA
+    ///
+    /// On a standard downstream port, this is only allowed once the device is configured, and
+    /// the configuration descriptor must request it. It is 100 mA before.
+    pub fn max_current(self) -> u16 {
+        match self {
+            Port::StandardDownstream => 500,
+            Port::ChargingDownstream | Port::DedicatedCharger => 1500,
+            // nothing is guaranteed, the charger's own signaling would tell
+            Port::Proprietary => 500,
+        }
+    }
+}
+
+/// Clock recovery system (CRS) status
+#[derive(Clone, Copy, Debug)]
+pub struct CrsStatus {
+    /// The last start-of-frame arrived within the trimming tolerance (SYNCOKF)
+    pub sync_ok: bool,
+    /// A start-of-frame arrived outside of the trimming tolerance, but within the error limit
+    /// (SYNCWARNF)
+    pub sync_warning: bool,
+    /// A start-of-frame arrived outside of the error limit (SYNCERR)
+    pub sync_error: bool,
+    /// A start-of-frame is missing (SYNCMISS)
+    pub sync_missed: bool,
+    /// The automatic trimming reached its limit (TRIMOVF)
+    pub trim_overflow: bool,
+    /// HSI48 cycles in excess on the last start-of-frame, negative if the oscillator is slow
+    pub frequency_error: i32,
+}
+
+// NOTE(unsafe) the registers are only accessed by `UsbBus`, which owns this value
+unsafe impl Sync for Usb {}
+
+unsafe impl UsbPeripheral for Usb {
+    const REGISTERS: *const () = USB::ptr() as *const ();
+    // the DP pull-up is built in, and switched by BCDR.DPPU
+    const DP_PULL_UP_FEATURE: bool = true;
+    // 1 KB of packet memory, accessed as 2 x 16 bits words
+    const EP_MEMORY: *const () = 0x4000_6000 as *const ();
+    const EP_MEMORY_SIZE: usize = 1024;
+    const EP_MEMORY_ACCESS_2X16: bool = true;
+
+    fn enable() {
+        // NOTE(unsafe) the clock is already enabled by `Usb::new`, and USBRST is only used
+        // here, atomically
+        cortex_m::interrupt::free(|_| unsafe {
+            let rcc = &(*RCC::ptr());
+            rcc.apb1rstr.modify(|_, w| w.usbrst().set_bit());
+            rcc.apb1rstr.modify(|_, w| w.usbrst().clear_bit());
+        });
+    }
+
+    fn startup_delay() {
+        // tSTARTUP, 1 µs
+        asm::delay(32);
+    }
+}
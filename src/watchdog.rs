@@ -0,0 +1,198 @@
+//! Watchdogs
+
+use embedded_hal::watchdog::{Watchdog, WatchdogEnable};
+use stm32l0x3::{IWDG, WWDG};
+
+use crate::rcc::{Clocks, APB1, LSI};
+use crate::time::{Hertz, MicroSeconds, MilliSeconds};
+
+/// Independent watchdog (IWDG), clocked by the LSI oscillator
+///
+/// Once started, the watchdog can't be stopped other than by a reset, and it keeps running in
+/// the Stop and Standby modes. The LSI frequency varies a lot between parts, so the actual
+/// timeout may be up to about 30% shorter than requested.
+pub struct IndependentWatchdog {
+    iwdg: IWDG,
+}
+
+impl IndependentWatchdog {
+    pub fn new(iwdg: IWDG) -> Self {
+        IndependentWatchdog { iwdg }
+    }
+
+    /// Returns the prescaler (PR) and reload value for the timeout, in milliseconds
+    fn config(timeout: u32) -> (u8, u16) {
+        assert!(timeout > 0 && timeout <= IndependentWatchdog::max_timeout().0);
+        let ticks = timeout * LSI / 1000;
+        // the prescaler divides the LSI clock by 4 << PR, up to 256
+        let mut pr = 0;
+        while pr < 6 && (ticks >> (pr + 2)) > 0x1000 {
+            pr += 1;
+        }
+        let reload = (ticks >> (pr + 2)).max(1) - 1;
+        assert!(reload <= 0xFFF);
+        (pr, reload as u16)
+    }
+
+    /// Returns the longest timeout, in milliseconds, about 28 s
+    pub fn max_timeout() -> MilliSeconds {
+        MilliSeconds(0x1000 * 256 * 1000 / LSI)
+    }
+
+    /// Releases the peripheral
+    ///
+    /// A started watchdog keeps running.
+    pub fn release(self) -> IWDG {
+        self.iwdg
+    }
+}
+
+impl WatchdogEnable for IndependentWatchdog {
+    type Time = MilliSeconds;
+
+    /// Starts the watchdog, or changes the timeout of a running watchdog
+    ///
+    /// The timeout must not exceed `max_timeout`, about 28 s.
+    fn start<T>(&mut self, timeout: T)
+    where
+        T: Into<MilliSeconds>,
+    {
+        let (pr, reload) = IndependentWatchdog::config(timeout.into().0);
+
+        // start first, which also starts the LSI oscillator clocking the registers
+        self.iwdg.kr.write(|w| unsafe { w.bits(0xCCCC) });
+        // unlock PR and RLR
+        self.iwdg.kr.write(|w| unsafe { w.bits(0x5555) });
+        // PVU, RVU, set while a previous update is in progress
+        while self.iwdg.sr.read().bits() & 0b11 != 0 {}
+        self.iwdg.pr.write(|w| unsafe { w.bits(pr as u32) });
+        self.iwdg.rlr.write(|w| unsafe { w.bits(reload as u32) });
+        while self.iwdg.sr.read().bits() & 0b11 != 0 {}
+        // reload the counter, which also locks the registers again
+        self.iwdg.kr.write(|w| unsafe { w.bits(0xAAAA) });
+    }
+}
+
+impl Watchdog for IndependentWatchdog {
+    fn feed(&mut self) {
+        self.iwdg.kr.write(|w| unsafe { w.bits(0xAAAA) });
+    }
+}
+
+/// Window watchdog (WWDG), clocked by PCLK1
+///
+/// The watchdog resets the device if it isn't fed before the timeout, or if it is fed too early,
+/// before the window opens. Once started, it can't be stopped other than by a reset, and it
+/// doesn't run in the Stop and Standby modes.
+pub struct WindowWatchdog {
+    wwdg: WWDG,
+    pclk1: Hertz,
+    window: Option<MicroSeconds>,
+    counter: u8,
+}
+
+impl WindowWatchdog {
+    pub fn new(wwdg: WWDG, clocks: &Clocks, apb1: &mut APB1) -> Self {
+        apb1.enr().modify(|_, w| w.wwdgen().set_bit());
+
+        WindowWatchdog {
+            wwdg,
+            pclk1: clocks.pclk1(),
+            window: None,
+            counter: 0x7F,
+        }
+    }
+
+    /// Only allows feeding the watchdog during the last `window` before the timeout
+    ///
+    /// Feeding it earlier resets the device. Applies on the next `start`.
+    pub fn set_window<T>(&mut self, window: T)
+    where
+        T: Into<MicroSeconds>,
+    {
+        self.window = Some(window.into());
+    }
+
+    /// Returns the longest timeout
+    pub fn max_timeout(&self) -> MicroSeconds {
+        MicroSeconds(WindowWatchdog::tick(self.pclk1, 3) * 64)
+    }
+
+    /// Returns the counter tick duration in microseconds for the WDGTB prescaler
+    fn tick(pclk1: Hertz, wdgtb: u8) -> u32 {
+        // PCLK1 / 4096 / 2^WDGTB, rounded up
+        let div = (4096 << wdgtb) as u64;
+        ((div * 1_000_000 + pclk1.0 as u64 - 1) / pclk1.0 as u64) as u32
+    }
+
+    /// Enables the early wakeup interrupt (EWI), raised one tick before the reset
+    ///
+    /// The interrupt handler may feed the watchdog, or save state before the reset. The
+    /// interrupt stays enabled until the next reset.
+    pub fn listen(&mut self) {
+        self.wwdg
+            .cfr
+            .modify(|r, w| unsafe { w.bits(r.bits() | (1 << 9)) });
+    }
+
+    /// Returns true if the early wakeup interrupt is pending
+    pub fn is_early_wakeup(&self) -> bool {
+        self.wwdg.sr.read().bits() & 1 != 0
+    }
+
+    /// Clears the early wakeup interrupt flag
+    pub fn clear_early_wakeup(&mut self) {
+        self.wwdg.sr.write(|w| unsafe { w.bits(0) });
+    }
+
+    /// Releases the peripheral
+    ///
+    /// A started watchdog keeps running.
+    pub fn release(self) -> WWDG {
+        self.wwdg
+    }
+}
+
+impl WatchdogEnable for WindowWatchdog {
+    type Time = MicroSeconds;
+
+    /// Starts the watchdog, or changes the timeout of a running watchdog
+    fn start<T>(&mut self, timeout: T)
+    where
+        T: Into<MicroSeconds>,
+    {
+        let timeout = timeout.into().0;
+        // the counter counts down from T[6:0] to 0x3F, so up to 64 ticks
+        let mut wdgtb = 0;
+        while wdgtb < 3 && timeout > WindowWatchdog::tick(self.pclk1, wdgtb) * 64 {
+            wdgtb += 1;
+        }
+        let tick = WindowWatchdog::tick(self.pclk1, wdgtb);
+        let ticks = (timeout / tick).max(1);
+        assert!(ticks <= 64);
+        self.counter = 0x3F + ticks as u8;
+
+        // feeding is allowed once the counter is at or below the window value
+        let window = match self.window {
+            Some(window) => (0x3F + (window.0 / tick).max(1)).min(0x7F),
+            None => 0x7F,
+        };
+
+        // keep EWI, which can't be cleared
+        let cfr = ((wdgtb as u32) << 7) | window;
+        self.wwdg
+            .cfr
+            .modify(|r, w| unsafe { w.bits((r.bits() & (1 << 9)) | cfr) });
+        self.feed();
+    }
+}
+
+impl Watchdog for WindowWatchdog {
+    fn feed(&mut self) {
+        // WDGA, T
+        let counter = self.counter as u32;
+        self.wwdg
+            .cr
+            .write(|w| unsafe { w.bits((1 << 7) | counter) });
+    }
+}